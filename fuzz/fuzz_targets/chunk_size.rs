@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use toot::protocol::parse_chunk_size;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(size) = parse_chunk_size(data) {
+        assert_eq!(Ok(size), parse_chunk_size(format!("{size:x}").as_bytes()));
+    }
+});
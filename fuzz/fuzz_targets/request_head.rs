@@ -0,0 +1,27 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use toot::protocol::{parse_request_head, parse_request_head_with, Leniency, RequestLimits};
+
+fuzz_target!(|data: &[u8]| {
+    // the first byte picks strict or lenient parsing
+    let Some((mode, data)) = data.split_first() else {
+        return;
+    };
+    let leniency = if mode & 1 == 0 { Leniency::STRICT } else { Leniency::LENIENT };
+    let limits = RequestLimits { leniency, ..Default::default() };
+    let Ok(Some((request_line, headers, len))) = parse_request_head_with(data, &limits) else {
+        return;
+    };
+    assert!(len <= data.len());
+
+    // a strictly parsed head is written as it was read
+    if leniency == Leniency::STRICT {
+        let head = format!("{}{}\r\n", request_line.to_http_message(), headers.to_http_message());
+        let (reparsed_line, reparsed_headers, reparsed_len) =
+            parse_request_head(head.as_bytes()).unwrap().unwrap();
+        assert_eq!(request_line.to_string(), reparsed_line.to_string());
+        assert_eq!(headers.to_http_message(), reparsed_headers.to_http_message());
+        assert_eq!(head.len(), reparsed_len);
+    }
+});
@@ -0,0 +1,33 @@
+#![no_main]
+
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use toot::protocol::HttpServerCodec;
+
+/// Requests decoded from `pieces` of the input arriving one after another, until an error
+fn decode(pieces: &[&[u8]]) -> (Vec<String>, bool) {
+    let mut codec = HttpServerCodec::default();
+    let mut buf = BytesMut::new();
+    let mut requests = Vec::new();
+    for piece in pieces {
+        buf.extend_from_slice(piece);
+        loop {
+            match codec.decode(&mut buf) {
+                Ok(Some(request)) => requests.push(format!("{:?}", request.into_vec())),
+                Ok(None) => break,
+                Err(_) => return (requests, false),
+            }
+        }
+    }
+    (requests, true)
+}
+
+fuzz_target!(|data: &[u8]| {
+    // the first byte says where the input is split, which mustn't change what's decoded
+    let Some((split, data)) = data.split_first() else {
+        return;
+    };
+    let split = *split as usize % (data.len() + 1);
+    let (first, second) = data.split_at(split);
+    assert_eq!(decode(&[data]), decode(&[first, second]));
+});
@@ -0,0 +1,226 @@
+//! `toot serve [DIR]` serves a directory, `toot proxy UPSTREAM` forwards every request,
+//! splicing connections which switch protocols, e.g. to WebSocket, with the upstream. The
+//! proxy adds itself to `Via` and `Forwarded`, answers loops and honours `Max-Forwards`.
+//!
+//! Settings start from the `TOOT_*` environment variables and are overridden by flags.
+//! On unix `SIGUSR2` replaces the running process with a fresh start of the binary without
+//! closing the listening sockets.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use tokio::net::TcpStream;
+use toot::protocol::{
+    Headers, HttpVersion, RawRequest, RawResponse, RequestLimits, StatusCode, StatusLine,
+};
+use toot::proxy::{forward, forward_upgrade, Forwarding, Hop, MaxForwards, Via};
+use toot::server::{Config, OnUpgrade, Server, StaticMount, TlsFiles};
+
+const USAGE: &str = "\
+usage: toot serve [DIR] [OPTIONS]
+       toot proxy UPSTREAM [OPTIONS]
+
+options:
+    --bind ADDR        listen address, may be repeated (default 127.0.0.1:8080)
+    --port PORT        replace the port of every listen address
+    --tls-cert FILE    PEM certificate chain, requires --tls-key
+    --tls-key FILE     PEM private key
+    --workers N        run N worker processes sharing the listen addresses
+    --via NAME         name of the proxy in Via, unique in a chain of proxies (default toot)
+    --json             write the access log as json lines
+    --quiet            no access log";
+
+enum Command {
+    Serve(PathBuf),
+    Proxy { upstream: Arc<str>, via: String },
+}
+
+struct Options {
+    command: Command,
+    workers: Option<usize>,
+}
+
+fn parse_args(args: Vec<String>, config: &mut Config) -> Result<Options, String> {
+    let mut args = args.into_iter();
+    let command = args.next().ok_or("missing command")?;
+    let mut positional = None;
+    let mut bind = Vec::<SocketAddr>::new();
+    let mut port = None;
+    let (mut cert, mut key) = (None, None);
+    let mut workers = None;
+    let mut via = None;
+    config.access_log = true;
+
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| args.next().ok_or(format!("{flag} needs a value"));
+        match arg.as_str() {
+            "--bind" => {
+                let addr = value("--bind")?;
+                bind.push(addr.parse().map_err(|_| format!("invalid address: {addr}"))?);
+            }
+            "--port" => {
+                let value = value("--port")?;
+                port = Some(value.parse::<u16>().map_err(|_| format!("invalid port: {value}"))?);
+            }
+            "--workers" => {
+                let value = value("--workers")?;
+                workers = Some(
+                    value
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|n| *n > 0)
+                        .ok_or(format!("invalid worker count: {value}"))?,
+                );
+            }
+            "--tls-cert" => cert = Some(value("--tls-cert")?),
+            "--tls-key" => key = Some(value("--tls-key")?),
+            "--via" => via = Some(value("--via")?),
+            "--json" => config.log_format = toot::server::LogFormat::Json,
+            "--quiet" => config.access_log = false,
+            flag if flag.starts_with("--") => return Err(format!("unknown option: {flag}")),
+            _ if positional.is_none() => positional = Some(arg),
+            _ => return Err(format!("unexpected argument: {arg}")),
+        }
+    }
+
+    if !bind.is_empty() {
+        config.bind = bind;
+    }
+    if let Some(port) = port {
+        config.bind.iter_mut().for_each(|addr| addr.set_port(port));
+    }
+    match (cert, key) {
+        (Some(cert), Some(key)) => {
+            config.tls = Some(TlsFiles { cert: cert.into(), key: key.into(), early_data: false })
+        }
+        (None, None) => {}
+        _ => return Err("--tls-cert and --tls-key go together".to_owned()),
+    }
+
+    let command = match command.as_str() {
+        "serve" => Command::Serve(positional.unwrap_or_else(|| ".".to_owned()).into()),
+        "proxy" => Command::Proxy {
+            upstream: positional.ok_or("missing upstream address")?.into(),
+            via: via.unwrap_or_else(|| "toot".to_owned()),
+        },
+        _ => return Err(format!("unknown command: {command}")),
+    };
+    Ok(Options { command, workers })
+}
+
+async fn run(server: Server, workers: Option<usize>) -> std::io::Result<()> {
+    match workers {
+        #[cfg(unix)]
+        Some(workers) => server.run_prefork(workers).await,
+        #[cfg(not(unix))]
+        Some(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "--workers needs SO_REUSEPORT",
+        )),
+        #[cfg(unix)]
+        None => server.run_upgradable().await,
+        #[cfg(not(unix))]
+        None => server.run_with_shutdown(async { _ = tokio::signal::ctrl_c().await }).await,
+    }
+}
+
+/// `forward` for requests switching protocols, the connections are spliced on a 101
+async fn upgrade(
+    upstream: &str,
+    request: RawRequest,
+    hop: &Hop,
+    limits: &RequestLimits,
+) -> std::io::Result<RawResponse> {
+    let mut request = request;
+    if let Some(response) = hop.on_request(&mut request) {
+        return Ok(response);
+    }
+    let stream = TcpStream::connect(upstream).await?;
+    let mut response = forward_upgrade(stream, request, limits).await?;
+    hop.on_response(&mut response);
+    Ok(response)
+}
+
+fn status_response(status: StatusCode) -> RawResponse {
+    RawResponse::new(
+        StatusLine::new(HttpVersion::Http1_1, status),
+        Headers::empty(),
+        Some(Vec::new()),
+    )
+}
+
+/// only the supervising process announces itself when running prefork workers
+fn is_supervisor(workers: Option<usize>) -> bool {
+    #[cfg(unix)]
+    return workers.is_none() || toot::server::worker_id().is_none();
+    #[cfg(not(unix))]
+    return workers.is_none();
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let mut config = match Config::from_env() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("toot: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let Options { command, workers } =
+        match parse_args(std::env::args().skip(1).collect(), &mut config) {
+            Ok(options) => options,
+            Err(err) => {
+                eprintln!("toot: {err}\n\n{USAGE}");
+                return ExitCode::from(2);
+            }
+        };
+
+    let listening = config.bind.iter().map(SocketAddr::to_string).collect::<Vec<_>>().join(", ");
+    let result = match command {
+        Command::Serve(dir) => {
+            if is_supervisor(workers) {
+                eprintln!("toot: serving {} on {listening}", dir.display());
+            }
+            config.static_mounts = vec![StaticMount { prefix: "/".to_owned(), dir }];
+            let not_found = |_: RawRequest| async { status_response(StatusCode::NOT_FOUND) };
+            run(Server::from_config(config, not_found), workers).await
+        }
+        Command::Proxy { upstream, via } => {
+            if is_supervisor(workers) {
+                eprintln!("toot: proxying {listening} to {upstream}");
+            }
+            let limits = config.limits.request_limits();
+            let proto = if config.tls.is_some() { "https" } else { "http" };
+            let hop = Arc::new(
+                Hop::new()
+                    .via(Via::new(&via))
+                    .max_forwards(MaxForwards::new())
+                    .forwarding(Forwarding::new(proto)),
+            );
+            let proxy = move |request: RawRequest| {
+                let (upstream, hop) = (upstream.clone(), hop.clone());
+                async move {
+                    let forwarded = match request.extensions.contains::<OnUpgrade>() {
+                        true => upgrade(&upstream, request, &hop, &limits).await,
+                        false => forward(&upstream, request, &hop, &limits).await,
+                    };
+                    match forwarded {
+                        Ok(response) => response,
+                        Err(_) => status_response(StatusCode::BAD_GATEWAY),
+                    }
+                }
+            };
+            run(Server::from_config(config, proxy), workers).await
+        }
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("toot: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
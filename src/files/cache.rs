@@ -0,0 +1,381 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::future::Future;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::task::JoinHandle;
+
+/// bytes of a file read at a time when streaming it
+const READ_CHUNK_LEN: usize = 64 * 1024;
+
+/// A file `StaticFiles` found for a request path, along with what answering it needs
+#[derive(Clone, Debug)]
+pub(crate) struct OpenFile {
+    /// the file served, below the directory asked for if that has an index
+    pub path: PathBuf,
+    pub len: u64,
+    pub etag: Option<String>,
+    pub file: Arc<File>,
+    /// the whole file, kept by a cache for files up to `FileCache::max_contents` bytes
+    pub contents: Option<Arc<[u8]>>,
+}
+
+impl OpenFile {
+    /// Reads the file through its handle, at most `len` bytes
+    pub async fn read(&self) -> io::Result<Vec<u8>> {
+        if let Some(ref contents) = self.contents {
+            return Ok(contents.to_vec());
+        }
+        read_handle(self).await
+    }
+
+    /// Reads `range` of the file, from the kept contents if there are any
+    pub fn reader(&self, range: Range<u64>) -> FileRange {
+        FileRange {
+            file: self.file.clone(),
+            contents: self.contents.clone(),
+            pos: range.start,
+            end: range.end,
+            chunk: Vec::new(),
+            chunk_pos: 0,
+            pending: None,
+        }
+    }
+}
+
+/// A range of a file, read a chunk at a time with positioned reads on the blocking pool, so the
+/// handle shared by every request for the file is never seeked. Ends early if the file shrank.
+pub(crate) struct FileRange {
+    file: Arc<File>,
+    contents: Option<Arc<[u8]>>,
+    pos: u64,
+    end: u64,
+    chunk: Vec<u8>,
+    chunk_pos: usize,
+    pending: Option<JoinHandle<io::Result<Vec<u8>>>>,
+}
+
+impl AsyncRead for FileRange {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        out: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.chunk_pos < this.chunk.len() {
+                let n = (this.chunk.len() - this.chunk_pos).min(out.remaining());
+                out.put_slice(&this.chunk[this.chunk_pos..this.chunk_pos + n]);
+                this.chunk_pos += n;
+                return Poll::Ready(Ok(()));
+            }
+            if this.pos >= this.end {
+                return Poll::Ready(Ok(()));
+            }
+            let len = (this.end - this.pos).min(READ_CHUNK_LEN as u64) as usize;
+            if let Some(ref contents) = this.contents {
+                let start = (this.pos as usize).min(contents.len());
+                let end = (start + len).min(contents.len());
+                this.chunk = contents[start..end].to_vec();
+            } else {
+                let (file, pos) = (this.file.clone(), this.pos);
+                let pending = this.pending.get_or_insert_with(|| {
+                    tokio::task::spawn_blocking(move || {
+                        let mut chunk = vec![0; len];
+                        let n = loop {
+                            match read_at(&file, &mut chunk, pos) {
+                                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                                read => break read?,
+                            }
+                        };
+                        chunk.truncate(n);
+                        Ok(chunk)
+                    })
+                });
+                let read = ready!(Pin::new(pending).poll(cx));
+                this.pending = None;
+                this.chunk = read.map_err(io::Error::other)??;
+            }
+            if this.chunk.is_empty() {
+                this.end = this.pos;
+            }
+            this.pos += this.chunk.len() as u64;
+            this.chunk_pos = 0;
+        }
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn read_at(_: &File, _: &mut [u8], _: u64) -> io::Result<usize> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(unix)]
+async fn read_handle(open: &OpenFile) -> io::Result<Vec<u8>> {
+    use std::os::unix::fs::FileExt;
+
+    let (file, len) = (open.file.clone(), open.len);
+    let read = tokio::task::spawn_blocking(move || {
+        // positioned reads, the handle is shared by every request for the file
+        let mut contents = vec![0; len as usize];
+        let mut filled = 0;
+        while filled < contents.len() {
+            match file.read_at(&mut contents[filled..], filled as u64) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        contents.truncate(filled);
+        Ok(contents)
+    });
+    read.await.map_err(io::Error::other)?
+}
+
+#[cfg(not(unix))]
+async fn read_handle(open: &OpenFile) -> io::Result<Vec<u8>> {
+    tokio::fs::read(&open.path).await
+}
+
+/// What a cache entry was looked up by: the path a request resolved to and the options of
+/// the `StaticFiles` which opened it
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct CacheKey {
+    pub path: PathBuf,
+    pub index: Option<String>,
+    pub confine_symlinks: bool,
+}
+
+/// Keeps the files `StaticFiles` opened, with their metadata and ETag and the contents of
+/// small ones, so hot assets are served without canonicalizing, stating and opening them
+/// again. Entries expire after `ttl`; on Linux they are also dropped once inotify reports a
+/// change in the directory of the file or of the path asked for. Clones share the entries.
+#[derive(Clone, Debug)]
+pub struct FileCache {
+    entries: Arc<Mutex<Entries>>,
+    ttl: Duration,
+    max_contents: u64,
+    capacity: usize,
+}
+
+#[derive(Debug)]
+struct Entries {
+    files: HashMap<CacheKey, Entry>,
+    #[cfg(target_os = "linux")]
+    watcher: Option<inotify::Watcher>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    file: OpenFile,
+    stored: Instant,
+    /// directories whose changes invalidate the entry
+    dirs: Vec<PathBuf>,
+}
+
+impl Default for FileCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FileCache {
+    /// Keeping up to 1024 files for 5 seconds, the contents of those up to 64 KiB
+    pub fn new() -> Self {
+        let entries = Entries {
+            files: HashMap::new(),
+            #[cfg(target_os = "linux")]
+            watcher: inotify::Watcher::new(),
+        };
+        Self {
+            entries: Arc::new(Mutex::new(entries)),
+            ttl: Duration::from_secs(5),
+            max_contents: 64 * 1024,
+            capacity: 1024,
+        }
+    }
+
+    /// How long an entry is used without checking the file again
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Largest file whose contents are kept, larger ones are read through the cached handle
+    pub fn max_contents(mut self, max: u64) -> Self {
+        self.max_contents = max;
+        self
+    }
+
+    /// Most files kept at once
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().files.clear();
+    }
+
+    pub(crate) fn max_contents_len(&self) -> u64 {
+        self.max_contents
+    }
+
+    pub(crate) fn get(&self, key: &CacheKey) -> Option<OpenFile> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.invalidate_changed();
+        match entries.files.get(key) {
+            Some(entry) if entry.stored.elapsed() < self.ttl => Some(entry.file.clone()),
+            Some(_) => {
+                entries.files.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub(crate) fn insert(&self, key: CacheKey, file: OpenFile) {
+        let mut dirs: Vec<PathBuf> =
+            [key.path.parent(), Some(key.path.as_path()), file.path.parent()]
+                .into_iter()
+                .flatten()
+                .map(Path::to_path_buf)
+                .collect();
+        dirs.dedup();
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.files.len() >= self.capacity && !entries.files.contains_key(&key) {
+            let ttl = self.ttl;
+            entries.files.retain(|_, entry| entry.stored.elapsed() < ttl);
+            if entries.files.len() >= self.capacity {
+                let Some(evicted) = entries.files.keys().next().cloned() else { return };
+                entries.files.remove(&evicted);
+            }
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(ref mut watcher) = entries.watcher {
+            dirs.iter().for_each(|dir| watcher.watch(dir));
+        }
+        entries.files.insert(key, Entry { file, stored: Instant::now(), dirs });
+    }
+}
+
+impl Entries {
+    #[cfg(target_os = "linux")]
+    fn invalidate_changed(&mut self) {
+        let Some(ref mut watcher) = self.watcher else { return };
+        match watcher.changed() {
+            Some(changed) if changed.is_empty() => {}
+            Some(changed) => {
+                self.files.retain(|_, entry| !entry.dirs.iter().any(|dir| changed.contains(dir)))
+            }
+            None => self.files.clear(),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn invalidate_changed(&mut self) {}
+}
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::collections::HashMap;
+    use std::ffi::CString;
+    use std::mem::size_of;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::{Path, PathBuf};
+
+    const MASK: u32 = libc::IN_MODIFY
+        | libc::IN_ATTRIB
+        | libc::IN_CLOSE_WRITE
+        | libc::IN_CREATE
+        | libc::IN_DELETE
+        | libc::IN_MOVED_FROM
+        | libc::IN_MOVED_TO
+        | libc::IN_DELETE_SELF
+        | libc::IN_MOVE_SELF;
+
+    /// Non-blocking inotify instance watching the directories of cached files, polled on
+    /// every lookup rather than by a task of its own
+    #[derive(Debug)]
+    pub struct Watcher {
+        fd: OwnedFd,
+        dirs: HashMap<i32, PathBuf>,
+    }
+
+    impl Watcher {
+        /// `None` if inotify is unavailable, leaving entries to expire
+        pub fn new() -> Option<Self> {
+            let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+            // SAFETY: a descriptor just created and owned by nothing else
+            (fd >= 0)
+                .then(|| Self { fd: unsafe { OwnedFd::from_raw_fd(fd) }, dirs: HashMap::new() })
+        }
+
+        /// Watching a path twice is harmless, a failed watch leaves its entries to expire
+        pub fn watch(&mut self, dir: &Path) {
+            let Ok(path) = CString::new(dir.as_os_str().as_bytes()) else { return };
+            let wd = unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), path.as_ptr(), MASK) };
+            if wd >= 0 {
+                self.dirs.insert(wd, dir.to_path_buf());
+            }
+        }
+
+        /// Directories changed since the last call, `None` if events were lost
+        pub fn changed(&mut self) -> Option<Vec<PathBuf>> {
+            let mut changed = Vec::new();
+            let mut overflowed = false;
+            let mut buf = [0u8; 4096];
+            loop {
+                let n =
+                    unsafe { libc::read(self.fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+                if n <= 0 {
+                    break;
+                }
+                let n = n as usize;
+                let mut offset = 0;
+                while offset + size_of::<libc::inotify_event>() <= n {
+                    // SAFETY: the kernel writes whole events, the buffer may be unaligned
+                    let event: libc::inotify_event = unsafe {
+                        buf.as_ptr().add(offset).cast::<libc::inotify_event>().read_unaligned()
+                    };
+                    offset += size_of::<libc::inotify_event>() + event.len as usize;
+                    overflowed |= event.mask & libc::IN_Q_OVERFLOW != 0;
+                    let dir = match event.mask & libc::IN_IGNORED {
+                        0 => self.dirs.get(&event.wd).cloned(),
+                        _ => self.dirs.remove(&event.wd),
+                    };
+                    changed.extend(dir);
+                }
+            }
+            (!overflowed).then_some(changed)
+        }
+    }
+}
@@ -0,0 +1,324 @@
+use std::io;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::protocol::{
+    content_range, percent_decode, Body, ByteRanges, ByteRangesBody, Headers, HttpVersion, Method,
+    RawRequest, RawResponse, StatusCode, StatusLine,
+};
+
+mod cache;
+#[cfg(test)]
+mod tests;
+
+pub use cache::FileCache;
+use cache::{CacheKey, OpenFile};
+
+/// Serves files below a root directory for `GET` and `HEAD` requests, and ranges of them for
+/// `GET` requests with `Range`. Responses carry an `ETag`, which `If-None-Match` and
+/// `If-Range` are compared with.
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+    index: Option<String>,
+    confine_symlinks: bool,
+    cache: Option<FileCache>,
+}
+
+impl StaticFiles {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self {
+            root: root.into(),
+            index: Some("index.html".to_owned()),
+            confine_symlinks: true,
+            cache: None,
+        }
+    }
+
+    /// File served for requests naming a directory, `None` answers those with 404
+    pub fn index(mut self, index: Option<&str>) -> Self {
+        self.index = index.map(str::to_owned);
+        self
+    }
+
+    /// Whether symlinks may only lead to files below the root, on by default
+    pub fn confine_symlinks(mut self, confine: bool) -> Self {
+        self.confine_symlinks = confine;
+        self
+    }
+
+    /// Where opened files are kept between requests, none are unless set
+    pub fn cache(mut self, cache: Option<FileCache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub async fn respond(&self, request: &RawRequest) -> RawResponse {
+        self.respond_path(request, request.request_line.target.path()).await
+    }
+
+    /// Same as `respond` with `uri_path` taken relative to the root, used below mount prefixes
+    pub async fn respond_path(&self, request: &RawRequest, uri_path: &str) -> RawResponse {
+        let method = request.request_line.method;
+        if !matches!(method, Method::GET | Method::HEAD) {
+            let mut headers = Headers::empty();
+            headers.set("Allow", "GET, HEAD".to_owned());
+            return response(StatusCode::METHOD_NOT_ALLOWED, headers, Some(Vec::new()));
+        }
+        let Some(path) = resolve_safe(&self.root, uri_path) else {
+            return response(StatusCode::NOT_FOUND, Headers::empty(), Some(Vec::new()));
+        };
+        let file = match self.cache {
+            Some(ref cache) => {
+                let key = CacheKey {
+                    path,
+                    index: self.index.clone(),
+                    confine_symlinks: self.confine_symlinks,
+                };
+                match cache.get(&key) {
+                    Some(file) => file,
+                    None => match self.open(key.path.clone(), cache.max_contents_len()).await {
+                        Ok(file) => {
+                            cache.insert(key, file.clone());
+                            file
+                        }
+                        Err(response) => return response,
+                    },
+                }
+            }
+            None => match self.open(path, 0).await {
+                Ok(file) => file,
+                Err(response) => return response,
+            },
+        };
+
+        let mut headers = Headers::empty();
+        let content_type = content_type(&file.path);
+        headers.set("Content-Type", content_type.to_owned());
+        headers.set("Accept-Ranges", "bytes".to_owned());
+        if let Some(ref etag) = file.etag {
+            headers.set("ETag", etag.clone());
+            let matches = request.headers.get("If-None-Match").is_some_and(|value| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag.as_str())
+            });
+            if matches {
+                return response(StatusCode::NOT_MODIFIED, headers, None);
+            }
+        }
+        if method == Method::HEAD {
+            headers.set("Content-Length", file.len.to_string());
+            return response(StatusCode::OK, headers, None);
+        }
+        // only the bytes sent are read, as they are sent
+        let len = file.len;
+        // ranges of another version than the one `If-Range` names aren't served
+        let current = |if_range: &str| file.etag.as_deref() == Some(if_range.trim());
+        let ranges = match request.headers.get("Range") {
+            Some(range) if request.headers.get("If-Range").is_none_or(current) => {
+                ByteRanges::parse(range, len)
+            }
+            _ => ByteRanges::Whole,
+        };
+        match ranges {
+            ByteRanges::Unsatisfiable => {
+                let mut headers = Headers::empty();
+                headers.set("Content-Range", format!("bytes */{len}"));
+                response(StatusCode::RANGE_NOT_SATISFIABLE, headers, Some(Vec::new()))
+            }
+            ByteRanges::Satisfiable(ranges) if ranges.len() == 1 => {
+                let range = ranges[0].clone();
+                headers.set("Content-Range", content_range(&range, len));
+                file_response(StatusCode::PARTIAL_CONTENT, headers, &file, range)
+            }
+            ByteRanges::Satisfiable(ranges) => {
+                let parts = ByteRangesBody::new();
+                headers.set("Content-Type", parts.content_type());
+                let mut body_len = 0;
+                let mut body: Pin<Box<dyn AsyncRead + Send>> = Box::pin(tokio::io::empty());
+                for range in ranges {
+                    let head = parts.part_head(&range, len, content_type);
+                    body_len += head.len() as u64 + (range.end - range.start) + 2;
+                    let part = io::Cursor::new(head).chain(file.reader(range)).chain(&b"\r\n"[..]);
+                    body = Box::pin(body.chain(part));
+                }
+                let closing = parts.closing();
+                body_len += closing.len() as u64;
+                let body = Body::from_reader(body.chain(io::Cursor::new(closing)), Some(body_len));
+                let status_line =
+                    StatusLine::new(HttpVersion::Http1_1, StatusCode::PARTIAL_CONTENT);
+                RawResponse::streaming(status_line, headers, body)
+            }
+            ByteRanges::Whole => file_response(StatusCode::OK, headers, &file, 0..len),
+        }
+    }
+
+    /// Finds the file `path` names, and opens it, reading its contents if they are at most
+    /// `max_contents` bytes
+    async fn open(&self, mut path: PathBuf, max_contents: u64) -> Result<OpenFile, RawResponse> {
+        if self.confine_symlinks {
+            path = match canonical_within(&self.root, &path).await {
+                Ok(Some(path)) => path,
+                Ok(None) => {
+                    return Err(response(StatusCode::NOT_FOUND, Headers::empty(), Some(Vec::new())))
+                }
+                Err(err) => return Err(error_response(err)),
+            };
+        }
+
+        let metadata = match tokio::fs::metadata(&path).await {
+            Ok(metadata) if metadata.is_dir() => match self.index {
+                Some(ref index) => {
+                    path.push(index);
+                    tokio::fs::metadata(&path).await
+                }
+                None => Err(io::ErrorKind::NotFound.into()),
+            },
+            result => result,
+        };
+        match metadata {
+            Ok(metadata) if metadata.is_file() => {}
+            Ok(_) => {
+                return Err(response(StatusCode::NOT_FOUND, Headers::empty(), Some(Vec::new())))
+            }
+            Err(err) => return Err(error_response(err)),
+        }
+
+        // metadata of the handle, the path may have been replaced since it was looked at
+        let opened = async {
+            let file = tokio::fs::File::open(&path).await?.into_std().await;
+            let metadata = file.metadata()?;
+            Ok::<_, io::Error>((file, metadata))
+        };
+        let (file, metadata) = match opened.await {
+            Ok(opened) => opened,
+            Err(err) => return Err(error_response(err)),
+        };
+        let mut file = OpenFile {
+            path,
+            len: metadata.len(),
+            etag: etag(&metadata),
+            file: Arc::new(file),
+            contents: None,
+        };
+        if file.len <= max_contents {
+            file.contents = match file.read().await {
+                Ok(contents) => Some(contents.into()),
+                Err(err) => return Err(error_response(err)),
+            };
+        }
+        Ok(file)
+    }
+}
+
+/// Maps a request path onto a path below `root`, `None` if it would leave `root`.
+///
+/// Query and fragment are dropped, segments are percent-decoded and `.` and `..` are resolved
+/// lexically, `..` never climbing above `root`. Segments decoding to a separator or containing
+/// NUL are rejected. Symlinks below `root` may still point elsewhere, `canonical_within` checks
+/// the target.
+pub fn resolve_safe(root: &Path, uri_path: &str) -> Option<PathBuf> {
+    let path = uri_path.split(['?', '#']).next().unwrap_or_default();
+    if path.contains(['\0', '\\']) {
+        return None;
+    }
+
+    let mut segments = Vec::new();
+    for segment in path.split('/').filter(|s| !s.is_empty()) {
+        // decoded separators would address another directory than the segment names
+        let segment = percent_decode(segment).ok()?;
+        if segment.contains(['/', '\\', '\0']) {
+            return None;
+        }
+        match Path::new(segment.as_ref()).components().next() {
+            Some(Component::Normal(_)) => segments.push(segment),
+            Some(Component::CurDir) => {}
+            Some(Component::ParentDir) => {
+                segments.pop()?;
+            }
+            _ => return None,
+        }
+    }
+
+    let mut resolved = root.to_path_buf();
+    resolved.extend(segments.iter().map(|segment| segment.as_ref()));
+    Some(resolved)
+}
+
+/// Canonical `path` if it, after following symlinks, is still below the canonical `root`
+pub async fn canonical_within(root: &Path, path: &Path) -> io::Result<Option<PathBuf>> {
+    let root = tokio::fs::canonicalize(root).await?;
+    let path = tokio::fs::canonicalize(path).await?;
+    Ok(path.starts_with(&root).then_some(path))
+}
+
+/// Strong validator from the size and modification time of a file, `None` where the
+/// platform doesn't record the latter
+pub fn etag(metadata: &std::fs::Metadata) -> Option<String> {
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!("\"{:x}-{:x}\"", metadata.len(), modified.as_nanos()))
+}
+
+/// Guesses the media type from the file extension
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+    match extension.to_ascii_lowercase().as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+/// `range` of `file`, copied from its kept contents or else streamed from the file
+fn file_response(
+    status: StatusCode,
+    headers: Headers,
+    file: &OpenFile,
+    range: Range<u64>,
+) -> RawResponse {
+    if let Some(ref contents) = file.contents {
+        let part = contents[range.start as usize..range.end as usize].to_vec();
+        return response(status, headers, Some(part));
+    }
+    let len = range.end - range.start;
+    let body = Body::from_reader(file.reader(range), Some(len));
+    RawResponse::streaming(StatusLine::new(HttpVersion::Http1_1, status), headers, body)
+}
+
+fn error_response(err: io::Error) -> RawResponse {
+    let status = match err.kind() {
+        io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+        io::ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    response(status, Headers::empty(), Some(Vec::new()))
+}
+
+fn response(status: StatusCode, headers: Headers, body: Option<Vec<u8>>) -> RawResponse {
+    RawResponse::new(StatusLine::new(HttpVersion::Http1_1, status), headers, body)
+}
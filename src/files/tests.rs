@@ -0,0 +1,269 @@
+use std::path::Path;
+use std::time::Duration;
+
+use super::*;
+
+fn request(method: &str, uri: &str) -> RawRequest {
+    RawRequest {
+        request_line: format!("{method} {uri} HTTP/1.1").parse().unwrap(),
+        headers: Headers::empty(),
+        body: None,
+        extensions: Default::default(),
+    }
+}
+
+async fn message(response: RawResponse) -> String {
+    let mut message = Vec::new();
+    crate::protocol::write_http_response(&mut message, response).await.unwrap();
+    String::from_utf8(message).unwrap()
+}
+
+/// Writes `contents` to `path`, modified a second after the epoch for a known ETag
+fn write(path: &Path, contents: &str) {
+    std::fs::write(path, contents).unwrap();
+    let file = std::fs::File::options().write(true).open(path).unwrap();
+    file.set_modified(UNIX_EPOCH + Duration::from_secs(1)).unwrap();
+}
+
+#[test]
+pub fn test_resolve() {
+    let root = Path::new("/srv/www");
+
+    assert_eq!(Some(root.join("a/b.txt")), resolve_safe(root, "/a/./b.txt?v=1"));
+    assert_eq!(Some(root.to_path_buf()), resolve_safe(root, "/"));
+    assert_eq!(None, resolve_safe(root, "/a/../../etc/passwd"));
+    assert_eq!(Some(root.join("b")), resolve_safe(root, "/a/../b/."));
+    assert_eq!(None, resolve_safe(root, "/..%00/x\0"));
+    assert_eq!(None, resolve_safe(root, "/a\\..\\b"));
+    assert_eq!(Some(root.join("my file")), resolve_safe(root, "/my%20file"));
+    assert_eq!(None, resolve_safe(root, "/%2e%2e/etc/passwd"));
+    assert_eq!(None, resolve_safe(root, "/a%2F..%2F..%2Fb"));
+}
+
+#[tokio::test]
+pub async fn test_static_files() {
+    let dir = std::env::temp_dir().join(format!("toot-files-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("docs")).unwrap();
+    write(&dir.join("index.html"), "<p>home</p>");
+    write(&dir.join("docs/a.css"), "p {}");
+    let files = StaticFiles::new(&dir);
+
+    let response = message(files.respond(&request("GET", "/")).await).await;
+    assert_eq!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nAccept-Ranges: bytes\r\n\
+         ETag: \"b-3b9aca00\"\r\nContent-Length: 11\r\n\r\n<p>home</p>",
+        response
+    );
+
+    let response = message(files.respond(&request("HEAD", "/docs/a.css")).await).await;
+    assert_eq!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/css; charset=utf-8\r\nAccept-Ranges: bytes\r\n\
+         ETag: \"4-3b9aca00\"\r\nContent-Length: 4\r\n\r\n",
+        response
+    );
+
+    let response = message(files.respond(&request("GET", "/missing")).await).await;
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    let response = message(files.clone().index(None).respond(&request("GET", "/docs")).await).await;
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    let response = message(files.respond(&request("POST", "/")).await).await;
+    assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\nAllow: GET, HEAD\r\n"));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+pub async fn test_static_files_confine_symlinks() {
+    let dir = std::env::temp_dir().join(format!("toot-symlinks-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("public")).unwrap();
+    std::fs::write(dir.join("secret.txt"), "secret").unwrap();
+    std::os::unix::fs::symlink(dir.join("secret.txt"), dir.join("public/leak.txt")).unwrap();
+
+    let files = StaticFiles::new(dir.join("public"));
+    let response = message(files.respond(&request("GET", "/leak.txt")).await).await;
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+    let response =
+        message(files.confine_symlinks(false).respond(&request("GET", "/leak.txt")).await).await;
+    assert!(response.ends_with("secret"));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+pub async fn test_static_file_ranges() {
+    let dir = std::env::temp_dir().join(format!("toot-ranges-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    write(&dir.join("a.txt"), "0123456789");
+    let files = StaticFiles::new(&dir);
+    let get = |range: &str, if_range: Option<&str>| {
+        let mut request = request("GET", "/a.txt");
+        request.headers.set("Range", range.to_owned());
+        if let Some(if_range) = if_range {
+            request.headers.set("If-Range", if_range.to_owned());
+        }
+        request
+    };
+
+    assert_eq!(
+        "HTTP/1.1 206 Partial Content\r\nContent-Type: text/plain; charset=utf-8\r\n\
+         Accept-Ranges: bytes\r\nETag: \"a-3b9aca00\"\r\nContent-Range: bytes 2-4/10\r\n\
+         Content-Length: 3\r\n\r\n234",
+        message(files.respond(&get("bytes=2-4", None)).await).await
+    );
+
+    let response = files.respond(&get("bytes=0-1,-2", None)).await;
+    assert_eq!(StatusCode::PARTIAL_CONTENT, response.status());
+    let content_type = response.headers().get("Content-Type").unwrap().to_owned();
+    let boundary = content_type.strip_prefix("multipart/byteranges; boundary=").unwrap();
+    let expected = format!(
+        "--{boundary}\r\nContent-Type: text/plain; charset=utf-8\r\n\
+         Content-Range: bytes 0-1/10\r\n\r\n01\r\n\
+         --{boundary}\r\nContent-Type: text/plain; charset=utf-8\r\n\
+         Content-Range: bytes 8-9/10\r\n\r\n89\r\n--{boundary}--\r\n"
+    );
+    assert!(message(response).await.ends_with(&format!("\r\n\r\n{expected}")));
+
+    assert_eq!(
+        "HTTP/1.1 416 Range Not Satisfiable\r\nContent-Range: bytes */10\r\n\
+         Content-Length: 0\r\n\r\n",
+        message(files.respond(&get("bytes=10-", None)).await).await
+    );
+    assert!(message(files.respond(&get("bytes=2-4", Some("\"v1\""))).await)
+        .await
+        .ends_with("0123456789"));
+    assert!(message(files.respond(&get("bytes=2-4", Some("\"a-3b9aca00\""))).await)
+        .await
+        .ends_with("234"));
+    assert!(message(files.respond(&get("lines=1-2", None)).await).await.ends_with("0123456789"));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+pub async fn test_static_file_ranges_streamed() {
+    let dir = std::env::temp_dir().join(format!("toot-streamed-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let contents = (0..200_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    std::fs::write(dir.join("big.bin"), &contents).unwrap();
+    let files = StaticFiles::new(&dir);
+    let get = |range: &str| {
+        let mut request = request("GET", "/big.bin");
+        request.headers.set("Range", range.to_owned());
+        request
+    };
+    let body = |message: Vec<u8>| {
+        let end = message.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        message[end + 4..].to_vec()
+    };
+
+    // across the chunks the file is read in, nothing outside the range is read
+    let response = files.respond(&get("bytes=60000-139999")).await;
+    assert!(response.is_streamed());
+    assert_eq!(Some("80000"), response.headers().get("Content-Length"));
+    let mut message = Vec::new();
+    crate::protocol::write_http_response(&mut message, response).await.unwrap();
+    assert_eq!(&contents[60000..140000], &body(message)[..]);
+
+    let response = files.respond(&get("bytes=10-19,199990-")).await;
+    assert!(response.is_streamed());
+    let content_type = response.headers().get("Content-Type").unwrap().to_owned();
+    let boundary = content_type.strip_prefix("multipart/byteranges; boundary=").unwrap();
+    let length = response.headers().get("Content-Length").unwrap().parse::<usize>().unwrap();
+    let mut message = Vec::new();
+    crate::protocol::write_http_response(&mut message, response).await.unwrap();
+    let mut expected = format!(
+        "--{boundary}\r\nContent-Type: application/octet-stream\r\n\
+         Content-Range: bytes 10-19/200000\r\n\r\n"
+    )
+    .into_bytes();
+    expected.extend_from_slice(&contents[10..20]);
+    expected.extend_from_slice(
+        format!(
+            "\r\n--{boundary}\r\nContent-Type: application/octet-stream\r\n\
+             Content-Range: bytes 199990-199999/200000\r\n\r\n"
+        )
+        .as_bytes(),
+    );
+    expected.extend_from_slice(&contents[199990..]);
+    expected.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+    assert_eq!(length, expected.len());
+    assert_eq!(expected, body(message));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+pub async fn test_not_modified() {
+    let dir = std::env::temp_dir().join(format!("toot-etag-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    write(&dir.join("a.txt"), "abc");
+    let files = StaticFiles::new(&dir);
+    let get = |if_none_match: &str| {
+        let mut request = request("GET", "/a.txt");
+        request.headers.set("If-None-Match", if_none_match.to_owned());
+        request
+    };
+
+    assert_eq!(
+        "HTTP/1.1 304 Not Modified\r\nContent-Type: text/plain; charset=utf-8\r\n\
+         Accept-Ranges: bytes\r\nETag: \"3-3b9aca00\"\r\n\r\n",
+        message(files.respond(&get("\"x\", W/\"3-3b9aca00\"")).await).await
+    );
+    assert!(message(files.respond(&get("*")).await)
+        .await
+        .starts_with("HTTP/1.1 304 Not Modified\r\n"));
+    assert!(message(files.respond(&get("\"x\"")).await).await.ends_with("abc"));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+pub async fn test_static_file_cache() {
+    let dir = std::env::temp_dir().join(format!("toot-cache-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("docs")).unwrap();
+    write(&dir.join("small.txt"), "small");
+    write(&dir.join("docs/index.html"), "<p>docs</p>");
+    let cache = FileCache::new().max_contents(8);
+    let files = StaticFiles::new(&dir).cache(Some(cache.clone()));
+
+    assert!(message(files.respond(&request("GET", "/small.txt")).await).await.ends_with("small"));
+    assert!(message(files.respond(&request("GET", "/docs")).await).await.ends_with("<p>docs</p>"));
+    assert_eq!(2, cache.len());
+    // another index is another entry
+    let response = files.clone().index(None).respond(&request("GET", "/docs")).await;
+    assert_eq!(StatusCode::NOT_FOUND, response.status());
+    assert!(message(files.respond(&request("GET", "/missing")).await)
+        .await
+        .starts_with("HTTP/1.1 404"));
+    assert_eq!(2, cache.len());
+
+    // inotify drops the entries of changed directories, elsewhere they expire
+    write(&dir.join("small.txt"), "changed");
+    if cfg!(not(target_os = "linux")) {
+        cache.clear();
+    }
+    assert!(message(files.respond(&request("GET", "/small.txt")).await).await.ends_with("changed"));
+    std::fs::remove_file(dir.join("docs/index.html")).unwrap();
+    if cfg!(not(target_os = "linux")) {
+        cache.clear();
+    }
+    let response = files.respond(&request("GET", "/docs")).await;
+    assert_eq!(StatusCode::NOT_FOUND, response.status());
+    assert_eq!(1, cache.len());
+
+    let files = StaticFiles::new(&dir).cache(Some(FileCache::new().ttl(Duration::ZERO)));
+    assert!(message(files.respond(&request("GET", "/small.txt")).await).await.ends_with("changed"));
+    write(&dir.join("small.txt"), "again");
+    assert!(message(files.respond(&request("GET", "/small.txt")).await).await.ends_with("again"));
+
+    write(&dir.join("other.txt"), "other");
+    let cache = FileCache::new().capacity(1);
+    let files = StaticFiles::new(&dir).cache(Some(cache.clone()));
+    files.respond(&request("GET", "/small.txt")).await;
+    files.respond(&request("HEAD", "/other.txt")).await;
+    assert_eq!(1, cache.len());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -1 +1,17 @@
-mod protocol;
+pub mod files;
+pub mod longpoll;
+pub mod metrics;
+pub mod middleware;
+#[cfg(feature = "oauth")]
+pub mod oauth;
+pub mod protocol;
+pub mod proxy;
+pub mod server;
+pub mod sse;
+#[cfg(unix)]
+pub mod systemd;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(feature = "webhook")]
+pub mod webhook;
+pub mod websocket;
@@ -0,0 +1,60 @@
+use std::future::Future;
+use std::time::Duration;
+
+use crate::protocol::{Headers, HttpVersion, RawRequest, RawResponse, StatusCode, StatusLine};
+use crate::server::ClientGone;
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum LongPoll<T> {
+    Ready(T),
+    TimedOut,
+    Disconnected,
+}
+
+impl<T> LongPoll<T> {
+    /// `204 No Content` on timeout, nothing when the client has already gone away
+    pub fn into_response<F>(self, respond: F) -> Option<RawResponse>
+    where
+        F: FnOnce(T) -> RawResponse,
+    {
+        match self {
+            LongPoll::Ready(value) => Some(respond(value)),
+            LongPoll::TimedOut => {
+                let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::NO_CONTENT);
+                Some(RawResponse::new(status_line, Headers::empty(), None))
+            }
+            LongPoll::Disconnected => None,
+        }
+    }
+}
+
+/// Parks a request until `ready` resolves, `timeout` elapses or `client_gone` resolves.
+///
+/// `ready` is usually `notify.notified()` or `receiver.recv()`; whichever loses the race is
+/// dropped, so it has to be cancel safe. `client_gone` is usually `client_gone(&request)`.
+pub async fn long_poll<T, F, C>(ready: F, timeout: Duration, client_gone: C) -> LongPoll<T>
+where
+    F: Future<Output = T>,
+    C: Future<Output = ()>,
+{
+    tokio::select! {
+        value = ready => LongPoll::Ready(value),
+        _ = tokio::time::sleep(timeout) => LongPoll::TimedOut,
+        _ = client_gone => LongPoll::Disconnected,
+    }
+}
+
+/// Resolves once the client of `request` is gone, see `ClientGone`. Never for requests which
+/// didn't come from a `Server`.
+pub fn client_gone(request: &RawRequest) -> impl Future<Output = ()> + Send + 'static {
+    let gone = request.extensions.get::<ClientGone>().cloned();
+    async move {
+        match gone {
+            Some(gone) => gone.wait().await,
+            None => std::future::pending().await,
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Notify;
+
+use super::*;
+use crate::server::{BoxFuture, Server};
+
+#[tokio::test]
+pub async fn test_long_poll_ready() {
+    let notify = Arc::new(Notify::new());
+    notify.notify_one();
+
+    let outcome =
+        long_poll(notify.notified(), Duration::from_secs(5), std::future::pending()).await;
+
+    assert_eq!(LongPoll::Ready(()), outcome);
+}
+
+#[tokio::test]
+pub async fn test_long_poll_timeout_responds_no_content() {
+    let notify = Notify::new();
+
+    let outcome =
+        long_poll(notify.notified(), Duration::from_millis(10), std::future::pending()).await;
+    assert_eq!(LongPoll::TimedOut, outcome);
+
+    let response = outcome.into_response(|_| unreachable!()).unwrap();
+    assert_eq!(b"HTTP/1.1 204 No Content\r\n\r\n".to_vec(), response.into_vec());
+}
+
+#[tokio::test]
+pub async fn test_long_poll_client_disconnect() {
+    let notify = Notify::new();
+
+    let outcome = long_poll(notify.notified(), Duration::from_secs(5), async {}).await;
+
+    assert_eq!(LongPoll::Disconnected, outcome);
+    assert!(outcome.into_response(|_| unreachable!()).is_none());
+}
+
+/// Long polls which never become ready, telling `outcomes` whether each ended because the
+/// client went away
+fn parked(
+    outcomes: tokio::sync::mpsc::UnboundedSender<bool>,
+) -> impl Fn(RawRequest) -> BoxFuture<'static, RawResponse> + Send + Sync + 'static {
+    move |request| {
+        let outcomes = outcomes.clone();
+        Box::pin(async move {
+            let notify = Notify::new();
+            let gone = client_gone(&request);
+            let outcome = long_poll(notify.notified(), Duration::from_secs(30), gone).await;
+            outcomes.send(outcome == LongPoll::Disconnected).unwrap();
+            let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::NO_CONTENT);
+            RawResponse::new(status_line, Headers::empty(), None)
+        })
+    }
+}
+
+#[tokio::test]
+pub async fn test_long_poll_server_client_disconnect() {
+    let (sender, mut outcomes) = tokio::sync::mpsc::unbounded_channel();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(Server::new(parked(sender)).serve(vec![listener]));
+
+    let mut client = TcpStream::connect(addr).await.unwrap();
+    client.write_all(b"GET /poll HTTP/1.1\r\n\r\n").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    drop(client);
+
+    let disconnected = tokio::time::timeout(Duration::from_secs(5), outcomes.recv()).await;
+    assert_eq!(Some(true), disconnected.unwrap());
+}
+
+#[cfg(feature = "tls")]
+#[tokio::test]
+pub async fn test_long_poll_server_client_disconnect_over_tls() {
+    use tokio_rustls::rustls::pki_types::ServerName;
+    use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+
+    use crate::server::{Config, TlsFiles};
+
+    let dir = std::env::temp_dir().join(format!("toot-long-poll-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let generated = rcgen::generate_simple_self_signed(vec!["a.test".to_owned()]).unwrap();
+    let tls = TlsFiles { cert: dir.join("cert.pem"), key: dir.join("key.pem"), early_data: false };
+    std::fs::write(&tls.cert, generated.cert.pem()).unwrap();
+    std::fs::write(&tls.key, generated.key_pair.serialize_pem()).unwrap();
+
+    let (sender, mut outcomes) = tokio::sync::mpsc::unbounded_channel();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = Config { tls: Some(tls), ..Config::default() };
+    tokio::spawn(Server::from_config(config, parked(sender)).serve(vec![listener]));
+
+    let mut roots = RootCertStore::empty();
+    roots.add(generated.cert.der().clone()).unwrap();
+    let client = ClientConfig::builder_with_provider(Arc::new(
+        tokio_rustls::rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .unwrap()
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+    let connector = tokio_rustls::TlsConnector::from(Arc::new(client));
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let name = ServerName::try_from("a.test").unwrap();
+    let mut client = connector.connect(name, tcp).await.unwrap();
+    client.write_all(b"GET /poll HTTP/1.1\r\n\r\n").await.unwrap();
+    client.flush().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    drop(client);
+
+    let disconnected = tokio::time::timeout(Duration::from_secs(5), outcomes.recv()).await;
+    assert_eq!(Some(true), disconnected.unwrap());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+pub async fn test_client_gone_outside_server_never_resolves() {
+    let mut source = &b"GET / HTTP/1.1\r\n\r\n"[..];
+    let request = crate::protocol::read_http_request(&mut source).await.unwrap();
+    let gone = client_gone(&request);
+    assert!(tokio::time::timeout(Duration::from_millis(10), gone).await.is_err());
+}
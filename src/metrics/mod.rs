@@ -0,0 +1,7 @@
+pub use self::stats::{RouteStats, StatsRegistry, UNROUTED};
+pub use self::statsd::StatsdExporter;
+
+mod stats;
+mod statsd;
+#[cfg(test)]
+mod tests;
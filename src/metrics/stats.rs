@@ -0,0 +1,187 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::server::{ConnectionRecord, RequestObserver, RequestRecord};
+
+/// route name of requests no route matched, or served without a `Router`
+pub const UNROUTED: &str = "*";
+
+/// values below this are counted exactly, above it in 64 buckets per power of two
+const LINEAR: u64 = 128;
+const SUB_BUCKETS: u64 = 64;
+
+/// Latency histogram in microseconds with a relative error below 1.6%
+#[derive(Debug, Clone)]
+struct Histogram {
+    counts: Vec<u64>,
+    total: u64,
+    max: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self { counts: vec![0; Self::index(u64::MAX) + 1], total: 0, max: 0 }
+    }
+}
+
+impl Histogram {
+    fn index(value: u64) -> usize {
+        if value < LINEAR {
+            return value as usize;
+        }
+        let shift = u64::from(63 - value.leading_zeros()) - 6;
+        (LINEAR + (shift - 1) * SUB_BUCKETS + ((value >> shift) - SUB_BUCKETS)) as usize
+    }
+
+    /// middle of the bucket at `index`
+    fn value(index: usize) -> u64 {
+        let index = index as u64;
+        if index < LINEAR {
+            return index;
+        }
+        let shift = (index - LINEAR) / SUB_BUCKETS + 1;
+        let low = ((index - LINEAR) % SUB_BUCKETS + SUB_BUCKETS) << shift;
+        low + (1 << shift) / 2
+    }
+
+    fn record(&mut self, value: u64) {
+        self.counts[Self::index(value)] += 1;
+        self.total += 1;
+        self.max = self.max.max(value);
+    }
+
+    fn quantile(&self, q: f64) -> u64 {
+        let rank = ((q * self.total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Self::value(index).min(self.max);
+            }
+        }
+        0
+    }
+}
+
+#[derive(Debug, Default)]
+struct Route {
+    active: u64,
+    statuses: BTreeMap<u16, u64>,
+    latency: Histogram,
+    bytes_read: u64,
+    bytes_written: u64,
+}
+
+/// Statistics of one route at the time of `StatsRegistry::snapshot`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RouteStats {
+    pub route: String,
+    /// answered requests
+    pub requests: u64,
+    /// requests currently being handled
+    pub active: u64,
+    /// answered requests per status code
+    pub statuses: BTreeMap<u16, u64>,
+    /// bytes of the answered requests, and of their responses
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl RouteStats {
+    /// share of answered requests with a 5xx status
+    pub fn error_rate(&self) -> f64 {
+        let errors = self.statuses.range(500..600).map(|(_, n)| n).sum::<u64>();
+        match self.requests {
+            0 => 0.0,
+            requests => errors as f64 / requests as f64,
+        }
+    }
+}
+
+/// In-process per-route latency, status and in-flight statistics, see `Server::stats`.
+///
+/// Routes are named by the pattern they were routed with, e.g. `/users/*`. Clones share
+/// the statistics.
+#[derive(Debug, Clone, Default)]
+pub struct StatsRegistry {
+    routes: Arc<Mutex<HashMap<String, Route>>>,
+    /// closed connections per `CloseReason::name`
+    closes: Arc<Mutex<BTreeMap<&'static str, u64>>>,
+    enabled: Arc<AtomicBool>,
+}
+
+impl StatsRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn enable(&self) {
+        self.enabled.store(true, Ordering::Release);
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Acquire)
+    }
+
+    pub fn route(&self, route: &str) -> Option<RouteStats> {
+        let routes = self.routes.lock().unwrap();
+        routes.get(route).map(|stats| snapshot(route, stats))
+    }
+
+    /// closed connections per `CloseReason::name`
+    pub fn close_reasons(&self) -> BTreeMap<&'static str, u64> {
+        self.closes.lock().unwrap().clone()
+    }
+
+    /// every route seen so far, by name
+    pub fn snapshot(&self) -> Vec<RouteStats> {
+        let routes = self.routes.lock().unwrap();
+        let mut snapshot =
+            routes.iter().map(|(route, stats)| snapshot(route, stats)).collect::<Vec<_>>();
+        snapshot.sort_by(|a, b| a.route.cmp(&b.route));
+        snapshot
+    }
+}
+
+fn snapshot(route: &str, stats: &Route) -> RouteStats {
+    let micros = |q| Duration::from_micros(stats.latency.quantile(q));
+    RouteStats {
+        route: route.to_owned(),
+        requests: stats.latency.total,
+        active: stats.active,
+        statuses: stats.statuses.clone(),
+        bytes_read: stats.bytes_read,
+        bytes_written: stats.bytes_written,
+        p50: micros(0.5),
+        p95: micros(0.95),
+        p99: micros(0.99),
+        max: Duration::from_micros(stats.latency.max),
+    }
+}
+
+impl RequestObserver for StatsRegistry {
+    fn started(&self, route: Option<&str>) {
+        let mut routes = self.routes.lock().unwrap();
+        routes.entry(route.unwrap_or(UNROUTED).to_owned()).or_default().active += 1;
+    }
+
+    fn observe(&self, record: &RequestRecord<'_>) {
+        let mut routes = self.routes.lock().unwrap();
+        let stats = routes.entry(record.route.unwrap_or(UNROUTED).to_owned()).or_default();
+        stats.active = stats.active.saturating_sub(1);
+        *stats.statuses.entry(*record.status).or_default() += 1;
+        stats.latency.record(record.elapsed.as_micros().try_into().unwrap_or(u64::MAX));
+        stats.bytes_read += record.size.total();
+        stats.bytes_written += record.written;
+    }
+
+    fn closed(&self, record: &ConnectionRecord<'_>) {
+        *self.closes.lock().unwrap().entry(record.reason.name()).or_default() += 1;
+    }
+}
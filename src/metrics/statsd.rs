@@ -0,0 +1,114 @@
+use std::fmt::Write;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+use crate::server::{ConnectionRecord, RequestObserver, RequestRecord};
+
+/// Pushes request metrics over UDP in StatsD format, one datagram per request:
+///
+/// `<prefix>.requests` counter, `<prefix>.responses.<class>` counter (e.g. `2xx`) and
+/// `<prefix>.request_time` timer in milliseconds. In DogStatsD format, which `tag` switches to,
+/// the status is a tag instead and every metric carries the configured tags plus `method` and
+/// `status`. Sending never blocks, datagrams the socket can't take right away are lost.
+///
+/// Closed connections are counted in `<prefix>.connections_closed.<reason>`, or with a `reason`
+/// tag in DogStatsD format, see `CloseReason::name`.
+#[derive(Debug)]
+pub struct StatsdExporter {
+    socket: UdpSocket,
+    prefix: String,
+    tags: Vec<(String, String)>,
+    dogstatsd: bool,
+}
+
+impl StatsdExporter {
+    pub fn new<A: ToSocketAddrs>(agent: A) -> io::Result<Self> {
+        let agent = agent
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no agent address"))?;
+        let local = match agent {
+            SocketAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], 0)),
+            SocketAddr::V6(_) => SocketAddr::from(([0u16; 8], 0)),
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(agent)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self { socket, prefix: "toot".to_owned(), tags: Vec::new(), dogstatsd: false })
+    }
+
+    /// metric name prefix, `toot` unless set
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_owned();
+        self
+    }
+
+    /// Adds a tag to every metric, switching to DogStatsD format
+    pub fn tag(mut self, key: &str, value: &str) -> Self {
+        self.tags.push((key.to_owned(), value.to_owned()));
+        self.dogstatsd = true;
+        self
+    }
+
+    pub fn dogstatsd(mut self, dogstatsd: bool) -> Self {
+        self.dogstatsd = dogstatsd;
+        self
+    }
+
+    /// The metric lines sent for `record`
+    pub fn format(&self, record: &RequestRecord<'_>) -> String {
+        let prefix = &self.prefix;
+        let millis = record.elapsed.as_secs_f64() * 1000.0;
+        let status = *record.status;
+        let (read, written) = (record.size.total(), record.written);
+
+        if !self.dogstatsd {
+            return format!(
+                "{prefix}.requests:1|c\n{prefix}.responses.{}xx:1|c\n{prefix}.request_time:{millis:.3}|ms\n\
+                 {prefix}.bytes_read:{read}|c\n{prefix}.bytes_written:{written}|c",
+                status / 100
+            );
+        }
+        let tags =
+            self.tags(&[("method", record.method.as_str()), ("status", &status.to_string())]);
+        format!(
+            "{prefix}.requests:1|c{tags}\n{prefix}.request_time:{millis:.3}|ms{tags}\n\
+             {prefix}.bytes_read:{read}|c{tags}\n{prefix}.bytes_written:{written}|c{tags}"
+        )
+    }
+
+    /// The metric line sent for a closed connection
+    pub fn format_closed(&self, record: &ConnectionRecord<'_>) -> String {
+        let (prefix, reason) = (&self.prefix, record.reason.name());
+        match self.dogstatsd {
+            false => format!("{prefix}.connections_closed.{reason}:1|c"),
+            true => format!("{prefix}.connections_closed:1|c{}", self.tags(&[("reason", reason)])),
+        }
+    }
+
+    /// The configured tags followed by `extra`, in DogStatsD format
+    fn tags(&self, extra: &[(&str, &str)]) -> String {
+        let mut tags = String::new();
+        let configured = self.tags.iter().map(|(k, v)| (k.as_str(), v.as_str()));
+        for (n, (key, value)) in configured.chain(extra.iter().copied()).enumerate() {
+            let separator = if n == 0 { "|#" } else { "," };
+            let _ = write!(tags, "{separator}{}:{}", sanitize(key), sanitize(value));
+        }
+        tags
+    }
+}
+
+impl RequestObserver for StatsdExporter {
+    fn observe(&self, record: &RequestRecord<'_>) {
+        let _ = self.socket.send(self.format(record).as_bytes());
+    }
+
+    fn closed(&self, record: &ConnectionRecord<'_>) {
+        let _ = self.socket.send(self.format_closed(record).as_bytes());
+    }
+}
+
+/// characters separating metrics, tags or fields are replaced
+fn sanitize(s: &str) -> String {
+    s.replace(['|', ',', '#', '\n'], "_")
+}
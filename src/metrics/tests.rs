@@ -0,0 +1,104 @@
+use std::net::UdpSocket;
+use std::time::Duration;
+
+use super::*;
+use crate::protocol::{Method, StatusCode};
+use crate::server::{
+    CloseReason, ConnectionRecord, PhaseTimings, RequestObserver, RequestRecord, RequestSeq,
+    RequestSize,
+};
+
+#[test]
+pub fn test_statsd_exporter() {
+    let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+    agent.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    let record = RequestRecord {
+        peer: None,
+        seq: RequestSeq { connection: 1, sequence: 1 },
+        method: Method::GET,
+        uri: "/items",
+        route: Some("/items"),
+        status: StatusCode::NOT_FOUND,
+        elapsed: Duration::from_micros(1500),
+        timings: PhaseTimings::default(),
+        size: RequestSize { head: 30, body: 0 },
+        written: 120,
+    };
+
+    let plain = StatsdExporter::new(agent.local_addr().unwrap()).unwrap().prefix("web");
+    assert_eq!(
+        "web.requests:1|c\nweb.responses.4xx:1|c\nweb.request_time:1.500|ms\n\
+         web.bytes_read:30|c\nweb.bytes_written:120|c",
+        plain.format(&record)
+    );
+
+    let dogstatsd = StatsdExporter::new(agent.local_addr().unwrap()).unwrap().tag("env", "prod|eu");
+    dogstatsd.observe(&record);
+    let mut datagram = [0u8; 512];
+    let n = agent.recv(&mut datagram).unwrap();
+    assert_eq!(
+        "toot.requests:1|c|#env:prod_eu,method:GET,status:404\n\
+         toot.request_time:1.500|ms|#env:prod_eu,method:GET,status:404\n\
+         toot.bytes_read:30|c|#env:prod_eu,method:GET,status:404\n\
+         toot.bytes_written:120|c|#env:prod_eu,method:GET,status:404",
+        std::str::from_utf8(&datagram[..n]).unwrap()
+    );
+
+    let reason = CloseReason::IdleTimeout;
+    let record = ConnectionRecord {
+        peer: None,
+        connection: 1,
+        requests: 1,
+        elapsed: Duration::from_secs(1),
+        reason: &reason,
+    };
+    assert_eq!("web.connections_closed.idle_timeout:1|c", plain.format_closed(&record));
+    assert_eq!(
+        "toot.connections_closed:1|c|#env:prod_eu,reason:idle_timeout",
+        dogstatsd.format_closed(&record)
+    );
+}
+
+#[test]
+pub fn test_stats_registry() {
+    let stats = StatsRegistry::new();
+    for millis in 1..=100 {
+        let status =
+            if millis % 10 == 0 { StatusCode::INTERNAL_SERVER_ERROR } else { StatusCode::OK };
+        stats.started(Some("/users/*"));
+        let record = RequestRecord {
+            peer: None,
+            seq: RequestSeq { connection: 1, sequence: millis },
+            method: Method::GET,
+            uri: "/users/1",
+            route: Some("/users/*"),
+            status,
+            elapsed: Duration::from_millis(millis),
+            timings: PhaseTimings::default(),
+            size: RequestSize { head: 40, body: 10 },
+            written: 100,
+        };
+        stats.observe(&record);
+    }
+    stats.started(Some("/users/*"));
+    stats.started(None);
+
+    let users = stats.route("/users/*").unwrap();
+    assert_eq!(100, users.requests);
+    assert_eq!(1, users.active);
+    assert_eq!(Some(&90), users.statuses.get(&200));
+    assert_eq!(0.1, users.error_rate());
+    assert_eq!((5000, 10_000), (users.bytes_read, users.bytes_written));
+    let close = |expected: u64, actual: Duration| {
+        let expected = Duration::from_millis(expected).as_secs_f64();
+        (actual.as_secs_f64() - expected).abs() <= expected * 0.016
+    };
+    assert!(close(50, users.p50), "{:?}", users.p50);
+    assert!(close(95, users.p95), "{:?}", users.p95);
+    assert!(close(99, users.p99), "{:?}", users.p99);
+    assert_eq!(Duration::from_millis(100), users.max);
+
+    let routes = stats.snapshot().into_iter().map(|s| s.route).collect::<Vec<_>>();
+    assert_eq!(vec![UNROUTED, "/users/*"], routes);
+    assert_eq!(0, stats.route(UNROUTED).unwrap().requests);
+}
@@ -0,0 +1,125 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use crate::protocol::{
+    query_param, Challenge, Headers, HttpVersion, RawRequest, RawResponse, StatusCode, StatusLine,
+};
+use crate::server::BoxFuture;
+
+/// Outcome of looking up an API key
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum KeyVerdict<P> {
+    /// the key is valid and belongs to this principal
+    Accept(P),
+    /// not a key this store knows, answered with 401
+    Unknown,
+    /// a known key which may not be used, e.g. revoked or suspended, answered with 403
+    Forbidden,
+}
+
+/// Resolves API keys, e.g. from a database or a secrets service
+pub trait ApiKeyStore: Send + Sync + 'static {
+    type Principal: Send + Sync + 'static;
+
+    fn lookup<'a>(&'a self, key: &'a str) -> BoxFuture<'a, KeyVerdict<Self::Principal>>;
+}
+
+/// Where the key is taken from, in the order given to `ApiKeyAuth`
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum KeySource {
+    Header(String),
+    Query(String),
+}
+
+/// Authenticates requests by API key.
+///
+/// The key is read from the configured headers and query parameters, the first one present
+/// wins. Accepted requests reach the handler with the principal in their extensions. Requests
+/// without a key or with an unknown one get 401 with an `ApiKey` challenge, forbidden keys
+/// get 403.
+pub struct ApiKeyAuth<S: ApiKeyStore> {
+    store: Arc<S>,
+    sources: Vec<KeySource>,
+    challenge: Challenge,
+}
+
+impl<S: ApiKeyStore> Clone for ApiKeyAuth<S> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            sources: self.sources.clone(),
+            challenge: self.challenge.clone(),
+        }
+    }
+}
+
+impl<S: ApiKeyStore> ApiKeyAuth<S> {
+    /// Reads the key from `X-Api-Key` unless other sources are added
+    pub fn new(store: S) -> Self {
+        Self { store: Arc::new(store), sources: Vec::new(), challenge: Challenge::new("ApiKey") }
+    }
+
+    /// The protection space named in the challenge of 401 responses
+    pub fn realm(mut self, realm: &str) -> Self {
+        self.challenge = self.challenge.realm(realm);
+        self
+    }
+
+    pub fn header(mut self, name: &str) -> Self {
+        self.sources.push(KeySource::Header(name.to_owned()));
+        self
+    }
+
+    pub fn query(mut self, parameter: &str) -> Self {
+        self.sources.push(KeySource::Query(parameter.to_owned()));
+        self
+    }
+
+    pub fn extract_key(&self, request: &RawRequest) -> Option<String> {
+        let default = [KeySource::Header("X-Api-Key".to_owned())];
+        let sources = if self.sources.is_empty() { &default[..] } else { &self.sources[..] };
+
+        sources.iter().find_map(|source| match source {
+            KeySource::Header(name) => request
+                .headers
+                .get(name)
+                .map(str::trim)
+                .filter(|k| !k.is_empty())
+                .map(str::to_owned),
+            KeySource::Query(parameter) => {
+                query_param(request.request_line.target.query().unwrap_or_default(), parameter)
+                    .filter(|k| !k.is_empty())
+                    .map(|k| k.into_owned())
+            }
+        })
+    }
+
+    pub async fn call<F, Fut>(&self, request: RawRequest, handler: F) -> RawResponse
+    where
+        F: FnOnce(RawRequest) -> Fut,
+        Fut: Future<Output = RawResponse>,
+    {
+        let Some(key) = self.extract_key(&request) else {
+            return self.unauthorized();
+        };
+        match self.store.lookup(&key).await {
+            KeyVerdict::Accept(principal) => {
+                let mut request = request;
+                request.extensions.insert(principal);
+                handler(request).await
+            }
+            KeyVerdict::Unknown => self.unauthorized(),
+            KeyVerdict::Forbidden => {
+                let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::FORBIDDEN);
+                RawResponse::new(status_line, Headers::empty(), Some(Vec::new()))
+            }
+        }
+    }
+
+    fn unauthorized(&self) -> RawResponse {
+        let mut headers = Headers::empty();
+        self.challenge.apply(&mut headers);
+        let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::UNAUTHORIZED);
+        RawResponse::new(status_line, headers, Some(Vec::new()))
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::protocol::{Headers, HttpVersion, RawResponse, RetryAfter, StatusCode, StatusLine};
+
+#[derive(Debug, Clone)]
+pub struct CircuitBreakerConfig {
+    /// how far back outcomes count towards the error rate
+    pub window: Duration,
+    /// fewer calls than this in the window never open the circuit
+    pub min_calls: usize,
+    /// error rate in `0.0..=1.0` which opens the circuit
+    pub failure_ratio: f64,
+    /// how long an open circuit fast-fails before letting probes through
+    pub open_for: Duration,
+    /// concurrent probe calls allowed while half-open
+    pub probes: usize,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(10),
+            min_calls: 20,
+            failure_ratio: 0.5,
+            open_for: Duration::from_secs(5),
+            probes: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum CircuitError<E> {
+    /// the call was not attempted
+    Open,
+    Upstream(E),
+}
+
+impl<E: Display> Display for CircuitError<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CircuitError::Open => write!(f, "circuit open"),
+            CircuitError::Upstream(err) => write!(f, "upstream failure: {err}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Circuit {
+    state: CircuitState,
+    /// bumped on every change of state, outcomes of calls admitted before are stale
+    generation: u64,
+    outcomes: VecDeque<(Instant, bool)>,
+    opened_at: Instant,
+    probes_in_flight: usize,
+}
+
+impl Circuit {
+    fn record(&mut self, config: &CircuitBreakerConfig, generation: u64, success: bool) {
+        // a call admitted in an earlier state, e.g. closed, says nothing about this one
+        if generation != self.generation {
+            return;
+        }
+        let now = Instant::now();
+        match self.state {
+            CircuitState::HalfOpen => {
+                self.probes_in_flight = self.probes_in_flight.saturating_sub(1);
+                if success {
+                    self.transition(CircuitState::Closed);
+                    self.outcomes.clear();
+                } else {
+                    self.open(now);
+                }
+            }
+            CircuitState::Closed => {
+                self.outcomes.push_back((now, success));
+                while self
+                    .outcomes
+                    .front()
+                    .is_some_and(|(at, _)| now.duration_since(*at) > config.window)
+                {
+                    self.outcomes.pop_front();
+                }
+
+                let calls = self.outcomes.len();
+                let failures = self.outcomes.iter().filter(|(_, ok)| !ok).count();
+                if calls >= config.min_calls
+                    && failures as f64 >= calls as f64 * config.failure_ratio
+                {
+                    self.open(now);
+                }
+            }
+            // no call is admitted while open
+            CircuitState::Open => {}
+        }
+    }
+
+    fn open(&mut self, now: Instant) {
+        self.transition(CircuitState::Open);
+        self.opened_at = now;
+        self.outcomes.clear();
+    }
+
+    fn transition(&mut self, state: CircuitState) {
+        self.state = state;
+        self.generation += 1;
+    }
+}
+
+/// Fast-fails calls to an unhealthy upstream.
+///
+/// Closed: calls go through and their outcomes are tracked over a rolling window.
+/// Open: calls fail immediately until `open_for` has passed.
+/// Half-open: a limited number of probe calls decide whether to close or reopen.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    config: Arc<CircuitBreakerConfig>,
+    circuit: Arc<Mutex<Circuit>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        let circuit = Circuit {
+            state: CircuitState::Closed,
+            generation: 0,
+            outcomes: VecDeque::new(),
+            opened_at: Instant::now(),
+            probes_in_flight: 0,
+        };
+        Self { config: Arc::new(config), circuit: Arc::new(Mutex::new(circuit)) }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        let mut circuit = self.circuit.lock().unwrap();
+        self.refresh(&mut circuit);
+        circuit.state
+    }
+
+    fn refresh(&self, circuit: &mut Circuit) {
+        if circuit.state == CircuitState::Open
+            && circuit.opened_at.elapsed() >= self.config.open_for
+        {
+            circuit.transition(CircuitState::HalfOpen);
+            circuit.probes_in_flight = 0;
+        }
+    }
+
+    /// The generation of the circuit the call is admitted in, `None` when it isn't
+    fn try_acquire(&self) -> Option<u64> {
+        let mut circuit = self.circuit.lock().unwrap();
+        self.refresh(&mut circuit);
+        match circuit.state {
+            CircuitState::Closed => Some(circuit.generation),
+            CircuitState::Open => None,
+            CircuitState::HalfOpen if circuit.probes_in_flight < self.config.probes => {
+                circuit.probes_in_flight += 1;
+                Some(circuit.generation)
+            }
+            CircuitState::HalfOpen => None,
+        }
+    }
+
+    /// Runs `call` unless the circuit is open, any `Err` counts as a failure
+    pub async fn call<F, Fut, T, E>(&self, call: F) -> Result<T, CircuitError<E>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let Some(generation) = self.try_acquire() else {
+            return Err(CircuitError::Open);
+        };
+
+        let mut guard = CallGuard { breaker: self, generation, success: false };
+        let result = call().await;
+        guard.success = result.is_ok();
+        drop(guard);
+        result.map_err(CircuitError::Upstream)
+    }
+
+    /// `503 Service Unavailable` with a `Retry-After` matching the open period
+    pub fn open_response(&self) -> RawResponse {
+        let mut headers = Headers::empty();
+        RetryAfter::Delay(self.config.open_for.max(Duration::from_secs(1))).apply(&mut headers);
+        let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::ServiceUnavailable);
+        RawResponse::new(status_line, headers, None)
+    }
+}
+
+/// Records the outcome, a call dropped before finishing counts as a failure
+struct CallGuard<'a> {
+    breaker: &'a CircuitBreaker,
+    generation: u64,
+    success: bool,
+}
+
+impl Drop for CallGuard<'_> {
+    fn drop(&mut self) {
+        let mut circuit = self.breaker.circuit.lock().unwrap();
+        circuit.record(&self.breaker.config, self.generation, self.success);
+    }
+}
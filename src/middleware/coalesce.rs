@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::broadcast;
+
+use crate::protocol::{RawRequest, RawResponse};
+
+type InFlight = Arc<Mutex<HashMap<String, broadcast::Sender<RawResponse>>>>;
+
+/// request headers a response may vary on, requests share a response only when they agree
+const KEY_HEADERS: [&str; 5] =
+    ["Accept", "Accept-Encoding", "Accept-Language", "Authorization", "Cookie"];
+
+/// Deduplicates concurrent identical `GET`/`HEAD` requests (singleflight).
+///
+/// The first request for a key runs the handler, requests arriving while it runs wait and get a
+/// copy of its response. Other methods always run their own handler, as do requests with
+/// credentials, whose responses may be personal, unless `share_credentials` is set, and the
+/// waiting requests when the response is streamed, its body can't be read twice.
+#[derive(Debug, Clone, Default)]
+pub struct Coalesce {
+    in_flight: InFlight,
+    share_credentials: bool,
+}
+
+impl Coalesce {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether requests with `Authorization` or `Cookie` are coalesced too, with those sending
+    /// the same credentials. Off by default.
+    pub fn share_credentials(mut self, share: bool) -> Self {
+        self.share_credentials = share;
+        self
+    }
+
+    /// Method, host, request target and the headers the response may vary on, `None` when the
+    /// request isn't shared
+    pub fn cache_key(&self, request: &RawRequest) -> Option<String> {
+        let line = &request.request_line;
+        let headers = &request.headers;
+        let credentials = headers.contains("Authorization") || headers.contains("Cookie");
+        if !line.method.is_cacheable() || (credentials && !self.share_credentials) {
+            return None;
+        }
+        let host = headers.get("Host").unwrap_or_default();
+        let mut key = format!("{} {host} {}", line.method.as_str(), line.target);
+        for field in KEY_HEADERS {
+            // header values can't hold a line break, so the fields can't run into each other
+            let values = headers.get_all(field).collect::<Vec<_>>().join(", ");
+            key.push_str(&format!("\n{field}: {values}"));
+        }
+        Some(key)
+    }
+
+    pub async fn call<F, Fut>(&self, request: &RawRequest, handler: F) -> RawResponse
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = RawResponse>,
+    {
+        let Some(key) = self.cache_key(request) else {
+            return handler().await;
+        };
+
+        let waiting = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            match in_flight.get(&key) {
+                Some(leader) => Some(leader.subscribe()),
+                None => {
+                    in_flight.insert(key.clone(), broadcast::channel(1).0);
+                    None
+                }
+            }
+        };
+
+        if let Some(mut waiting) = waiting {
+            return match waiting.recv().await {
+                Ok(response) => response,
+                // the leading request was cancelled before it produced a response
+                Err(_) => handler().await,
+            };
+        }
+
+        let guard = LeaderGuard { key, in_flight: self.in_flight.clone() };
+        let response = handler().await;
+        if let Some(followers) = guard.finish().filter(|_| !response.is_streamed()) {
+            let _ = followers.send(response.clone());
+        }
+        response
+    }
+}
+
+/// Removes the in-flight entry even when the leading request is dropped mid-way
+struct LeaderGuard {
+    key: String,
+    in_flight: InFlight,
+}
+
+impl LeaderGuard {
+    fn finish(self) -> Option<broadcast::Sender<RawResponse>> {
+        self.in_flight.lock().unwrap().remove(&self.key)
+    }
+}
+
+impl Drop for LeaderGuard {
+    fn drop(&mut self) {
+        self.in_flight.lock().unwrap().remove(&self.key);
+    }
+}
@@ -0,0 +1,85 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::Semaphore;
+use tokio::time::timeout;
+
+use crate::protocol::{Headers, HttpVersion, RawResponse, StatusCode, StatusLine};
+
+/// Caps how many requests run a handler at once, e.g. in front of a blocking resource.
+///
+/// Requests beyond the limit wait in a bounded queue for at most the queue timeout, requests
+/// finding the queue full or timing out get 503. Without a queue they get 503 right away. Wrap
+/// one handler for a per-route limit, or the whole router for a global one.
+#[derive(Debug, Clone)]
+pub struct ConcurrencyLimit {
+    max_concurrent: usize,
+    permits: Arc<Semaphore>,
+    waiting: Arc<AtomicUsize>,
+    queue_len: usize,
+    queue_timeout: Duration,
+}
+
+impl ConcurrencyLimit {
+    pub fn new(max_concurrent: usize) -> Self {
+        Self {
+            max_concurrent,
+            permits: Arc::new(Semaphore::new(max_concurrent)),
+            waiting: Arc::default(),
+            queue_len: 0,
+            queue_timeout: Duration::ZERO,
+        }
+    }
+
+    /// Lets up to `len` requests wait up to `timeout` for a slot
+    pub fn queue(mut self, len: usize, timeout: Duration) -> Self {
+        self.queue_len = len;
+        self.queue_timeout = timeout;
+        self
+    }
+
+    /// requests currently running the handler
+    pub fn in_flight(&self) -> usize {
+        self.max_concurrent - self.permits.available_permits()
+    }
+
+    pub fn waiting(&self) -> usize {
+        self.waiting.load(Ordering::Relaxed)
+    }
+
+    pub async fn call<F, Fut>(&self, handler: F) -> RawResponse
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = RawResponse>,
+    {
+        let permit = match self.permits.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                let queued = self.waiting.fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                    (n < self.queue_len).then_some(n + 1)
+                });
+                if queued.is_err() {
+                    return service_unavailable();
+                }
+                let acquired =
+                    timeout(self.queue_timeout, self.permits.clone().acquire_owned()).await;
+                self.waiting.fetch_sub(1, Ordering::AcqRel);
+                match acquired {
+                    Ok(Ok(permit)) => permit,
+                    _ => return service_unavailable(),
+                }
+            }
+        };
+
+        let response = handler().await;
+        drop(permit);
+        response
+    }
+}
+
+fn service_unavailable() -> RawResponse {
+    let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::ServiceUnavailable);
+    RawResponse::new(status_line, Headers::empty(), Some(Vec::new()))
+}
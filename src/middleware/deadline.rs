@@ -0,0 +1,118 @@
+use std::future::Future;
+use std::time::Duration;
+
+use tokio::time::{timeout_at, Instant};
+
+use crate::protocol::{Headers, HttpVersion, RawRequest, RawResponse, StatusCode, StatusLine};
+
+/// timeout in (fractional) seconds, as read and forwarded
+pub const REQUEST_TIMEOUT_HEADER: &str = "X-Request-Timeout";
+
+/// When the client stops waiting for a request, in its extensions once `DeadlinePropagation`
+/// has run. `proxy::forward` passes the remaining time on upstream.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Deadline(pub Instant);
+
+impl Deadline {
+    pub fn after(timeout: Duration) -> Self {
+        Self(Instant::now() + timeout)
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.0.saturating_duration_since(Instant::now())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining().is_zero()
+    }
+
+    /// The timeout a request asks for with `X-Request-Timeout` (seconds) or `grpc-timeout`
+    /// (e.g. `250m`), ignoring values which don't parse
+    pub fn requested(headers: &Headers) -> Option<Duration> {
+        if let Some(secs) = headers.get(REQUEST_TIMEOUT_HEADER) {
+            return secs
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .and_then(|s| Duration::try_from_secs_f64(s).ok());
+        }
+        let value = headers.get("grpc-timeout")?.trim();
+        let (amount, unit) = value.split_at(value.len().checked_sub(1)?);
+        if amount.is_empty() || amount.len() > 8 || !amount.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let amount = amount.parse::<u64>().ok()?;
+        match unit {
+            "H" => Some(Duration::from_secs(amount * 3600)),
+            "M" => Some(Duration::from_secs(amount * 60)),
+            "S" => Some(Duration::from_secs(amount)),
+            "m" => Some(Duration::from_millis(amount)),
+            "u" => Some(Duration::from_micros(amount)),
+            "n" => Some(Duration::from_nanos(amount)),
+            _ => None,
+        }
+    }
+
+    /// `X-Request-Timeout` value of the remaining time, in milliseconds precision
+    pub fn header_value(&self) -> String {
+        format!("{:.3}", self.remaining().as_secs_f64())
+    }
+}
+
+/// Enforces request deadlines server-side.
+///
+/// The deadline is the timeout the client asks for, capped at `max_timeout`, or
+/// `default_timeout` when it asks for none. Handlers find it as `Deadline` in the request
+/// extensions. Requests whose handler has not answered by then get 504.
+#[derive(Debug, Clone, Default)]
+pub struct DeadlinePropagation {
+    default_timeout: Option<Duration>,
+    max_timeout: Option<Duration>,
+}
+
+impl DeadlinePropagation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn default_timeout(mut self, timeout: Duration) -> Self {
+        self.default_timeout = Some(timeout);
+        self
+    }
+
+    pub fn max_timeout(mut self, timeout: Duration) -> Self {
+        self.max_timeout = Some(timeout);
+        self
+    }
+
+    pub fn deadline(&self, request: &RawRequest) -> Option<Deadline> {
+        let requested = Deadline::requested(&request.headers).or(self.default_timeout);
+        let timeout = match (requested, self.max_timeout) {
+            (Some(requested), Some(max)) => Some(requested.min(max)),
+            (requested, max) => requested.or(max),
+        }?;
+        let deadline = Deadline::after(timeout);
+        // an outer deadline, e.g. from another layer, is never extended
+        Some(request.extensions.get::<Deadline>().map_or(deadline, |outer| deadline.min(*outer)))
+    }
+
+    pub async fn call<F, Fut>(&self, request: RawRequest, handler: F) -> RawResponse
+    where
+        F: FnOnce(RawRequest) -> Fut,
+        Fut: Future<Output = RawResponse>,
+    {
+        let Some(deadline) = self.deadline(&request) else {
+            return handler(request).await;
+        };
+        let mut request = request;
+        request.extensions.insert(deadline);
+        match timeout_at(deadline.0, handler(request)).await {
+            Ok(response) => response,
+            Err(_) => {
+                let status_line =
+                    StatusLine::new(HttpVersion::Http1_1, StatusCode::GATEWAY_TIMEOUT);
+                RawResponse::new(status_line, Headers::empty(), Some(Vec::new()))
+            }
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use std::future::Future;
+
+use crate::protocol::{
+    Headers, HttpVersion, Method, RawRequest, RawResponse, StatusCode, StatusLine,
+};
+use crate::server::EarlyData;
+
+/// Answers requests sent in TLS 0-RTT data with 425 Too Early (RFC 8470) unless their method is
+/// safe, so an attacker replaying early data can't repeat their effects. Clients send them
+/// again once the handshake is complete.
+#[derive(Debug, Clone, Default)]
+pub struct EarlyDataGate {
+    idempotent: bool,
+}
+
+impl EarlyDataGate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also lets idempotent methods, `PUT` and `DELETE`, through
+    pub fn allow_idempotent(mut self) -> Self {
+        self.idempotent = true;
+        self
+    }
+
+    fn allows(&self, method: Method) -> bool {
+        method.is_safe() || (self.idempotent && method.is_idempotent())
+    }
+
+    pub async fn call<F, Fut>(&self, request: RawRequest, handler: F) -> RawResponse
+    where
+        F: FnOnce(RawRequest) -> Fut,
+        Fut: Future<Output = RawResponse>,
+    {
+        if request.extensions.get::<EarlyData>().is_none()
+            || self.allows(request.request_line.method)
+        {
+            return handler(request).await;
+        }
+        let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::TOO_EARLY);
+        RawResponse::new(status_line, Headers::empty(), Some(Vec::new()))
+    }
+}
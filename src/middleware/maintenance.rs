@@ -0,0 +1,134 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::protocol::{
+    Headers, HttpVersion, Method, RawRequest, RawResponse, RetryAfter, StatusCode, StatusLine,
+};
+use crate::server::{BoxFuture, Handler};
+
+/// Runtime switch answering requests with a 503 page while maintenance is under way.
+///
+/// Allowlisted paths, such as health checks, keep reaching the handler. Clones share the switch,
+/// which is flipped with `enable`/`disable` or over HTTP through `admin`.
+#[derive(Debug, Clone)]
+pub struct Maintenance {
+    enabled: Arc<AtomicBool>,
+    /// exact paths, or prefixes when they end with `*`
+    allowed: Vec<String>,
+    retry_after: Duration,
+    content_type: String,
+    page: Arc<Vec<u8>>,
+}
+
+impl Default for Maintenance {
+    fn default() -> Self {
+        Self {
+            enabled: Arc::default(),
+            allowed: Vec::new(),
+            retry_after: Duration::from_secs(300),
+            content_type: "text/plain; charset=utf-8".to_owned(),
+            page: Arc::new(b"Down for maintenance, back soon.\n".to_vec()),
+        }
+    }
+}
+
+impl Maintenance {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps serving `path` during maintenance, a trailing `*` matches any suffix
+    pub fn allow(mut self, path: &str) -> Self {
+        self.allowed.push(path.to_owned());
+        self
+    }
+
+    pub fn retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = retry_after;
+        self
+    }
+
+    pub fn page(mut self, content_type: &str, body: Vec<u8>) -> Self {
+        self.content_type = content_type.to_owned();
+        self.page = Arc::new(body);
+        self
+    }
+
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Release);
+    }
+
+    pub fn disable(&self) {
+        self.enabled.store(false, Ordering::Release);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Acquire)
+    }
+
+    fn allows(&self, request: &RawRequest) -> bool {
+        let path = request.request_line.target.path();
+        self.allowed.iter().any(|allowed| match allowed.strip_suffix('*') {
+            Some(prefix) => path.starts_with(prefix),
+            None => allowed == path,
+        })
+    }
+
+    pub async fn call<F, Fut>(&self, request: RawRequest, handler: F) -> RawResponse
+    where
+        F: FnOnce(RawRequest) -> Fut,
+        Fut: Future<Output = RawResponse>,
+    {
+        if !self.is_enabled() || self.allows(&request) {
+            return handler(request).await;
+        }
+        let mut headers = Headers::empty();
+        RetryAfter::Delay(self.retry_after).apply(&mut headers);
+        headers.set("Content-Type", self.content_type.clone());
+        let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::ServiceUnavailable);
+        let response = RawResponse::new(status_line, headers, Some(self.page.to_vec()));
+        match request.request_line.method {
+            Method::HEAD => response.without_body(),
+            _ => response,
+        }
+    }
+
+    /// Handler for an admin route: `GET` tells whether maintenance is on, `PUT` turns it on and
+    /// `DELETE` off. Guard its route, see `Router::guard`.
+    pub fn admin(&self) -> impl Handler {
+        MaintenanceAdmin(self.clone())
+    }
+}
+
+struct MaintenanceAdmin(Maintenance);
+
+impl Handler for MaintenanceAdmin {
+    fn call(&self, request: RawRequest) -> BoxFuture<'static, RawResponse> {
+        let status = match request.request_line.method {
+            Method::GET => StatusCode::OK,
+            Method::PUT => {
+                self.0.enable();
+                StatusCode::OK
+            }
+            Method::DELETE => {
+                self.0.disable();
+                StatusCode::OK
+            }
+            _ => StatusCode::METHOD_NOT_ALLOWED,
+        };
+        let mut headers = Headers::empty();
+        if status == StatusCode::METHOD_NOT_ALLOWED {
+            headers.set("Allow", "GET, PUT, DELETE".to_owned());
+        }
+        let body = match (status, self.0.is_enabled()) {
+            (StatusCode::METHOD_NOT_ALLOWED, _) => Vec::new(),
+            (_, true) => b"on\n".to_vec(),
+            (_, false) => b"off\n".to_vec(),
+        };
+        let response =
+            RawResponse::new(StatusLine::new(HttpVersion::Http1_1, status), headers, Some(body));
+        Box::pin(async { response })
+    }
+}
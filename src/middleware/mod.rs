@@ -0,0 +1,25 @@
+pub use self::apikey::{ApiKeyAuth, ApiKeyStore, KeyVerdict};
+pub use self::breaker::{CircuitBreaker, CircuitBreakerConfig, CircuitError, CircuitState};
+pub use self::coalesce::Coalesce;
+pub use self::concurrency::ConcurrencyLimit;
+pub use self::deadline::{Deadline, DeadlinePropagation, REQUEST_TIMEOUT_HEADER};
+pub use self::early::EarlyDataGate;
+pub use self::maintenance::Maintenance;
+pub use self::quota::{MemoryQuotaStore, Quota, QuotaStore, Usage};
+pub use self::throttle::CostThrottle;
+#[cfg(feature = "config")]
+pub use self::validate::Validate;
+pub use self::validate::{Rejection, Validation, Violation};
+
+mod apikey;
+mod breaker;
+mod coalesce;
+mod concurrency;
+mod deadline;
+mod early;
+mod maintenance;
+mod quota;
+#[cfg(test)]
+mod tests;
+mod throttle;
+mod validate;
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::protocol::{
+    Headers, HttpVersion, RateLimit, RawRequest, RawResponse, RetryAfter, StatusCode, StatusLine,
+};
+use crate::server::{BoxFuture, RequestSize};
+
+type ClientKey = Arc<dyn Fn(&RawRequest) -> Option<String> + Send + Sync>;
+
+/// What a key used in one period
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Usage {
+    pub requests: u64,
+    /// of requests and of response bodies
+    pub bytes: u64,
+}
+
+/// Keeps the usage of every key per period, e.g. in memory or in a store shared by several
+/// servers. Periods are numbered from the Unix epoch, so servers with the same period length
+/// agree on them.
+pub trait QuotaStore: Send + Sync + 'static {
+    /// Adds `usage` to what `key` used in `period`, returning the sum
+    fn add<'a>(
+        &'a self,
+        key: &'a str,
+        period: u64,
+        usage: Usage,
+    ) -> BoxFuture<'a, io::Result<Usage>>;
+}
+
+/// `QuotaStore` of one process, keeping only the current period of each key
+#[derive(Debug, Clone, Default)]
+pub struct MemoryQuotaStore {
+    keys: Arc<Mutex<HashMap<String, (u64, Usage)>>>,
+}
+
+impl MemoryQuotaStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl QuotaStore for MemoryQuotaStore {
+    fn add<'a>(
+        &'a self,
+        key: &'a str,
+        period: u64,
+        usage: Usage,
+    ) -> BoxFuture<'a, io::Result<Usage>> {
+        let mut keys = self.keys.lock().unwrap();
+        // keys of past periods are dropped once a new period starts
+        if keys.values().next().is_some_and(|(seen, _)| *seen < period) {
+            keys.retain(|_, (seen, _)| *seen >= period);
+        }
+        let (seen, used) = keys.entry(key.to_owned()).or_insert((period, Usage::default()));
+        if *seen != period {
+            (*seen, *used) = (period, Usage::default());
+        }
+        used.requests += usage.requests;
+        used.bytes += usage.bytes;
+        let used = *used;
+        Box::pin(async move { Ok(used) })
+    }
+}
+
+/// Usage quota: every key may make `requests` requests and transfer `bytes` bytes per
+/// `period`, requests beyond either get 429 with `Retry-After`.
+///
+/// Keys are taken from `X-Api-Key` unless `key` says otherwise, run `ApiKeyAuth` first so
+/// only known keys are counted. Requests without a key aren't limited, and neither are any
+/// while the store fails. Rejected requests count towards the request quota. Responses
+/// carry `RateLimit-*` headers for the request quota, or for the byte quota if that's the
+/// only one.
+pub struct Quota<S: QuotaStore> {
+    store: Arc<S>,
+    period: Duration,
+    requests: Option<u64>,
+    bytes: Option<u64>,
+    key: ClientKey,
+}
+
+impl<S: QuotaStore> Clone for Quota<S> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            period: self.period,
+            requests: self.requests,
+            bytes: self.bytes,
+            key: self.key.clone(),
+        }
+    }
+}
+
+impl<S: QuotaStore> Quota<S> {
+    /// Unlimited until `requests` or `bytes` is set
+    pub fn new(store: S, period: Duration) -> Self {
+        Self {
+            store: Arc::new(store),
+            period: period.max(Duration::from_secs(1)),
+            requests: None,
+            bytes: None,
+            key: Arc::new(|request| {
+                let key = request.headers.get("X-Api-Key").map(str::trim);
+                key.filter(|key| !key.is_empty()).map(str::to_owned)
+            }),
+        }
+    }
+
+    pub fn requests(mut self, requests: u64) -> Self {
+        self.requests = Some(requests);
+        self
+    }
+
+    /// Bytes of requests, as read from the connection, and of response bodies
+    pub fn bytes(mut self, bytes: u64) -> Self {
+        self.bytes = Some(bytes);
+        self
+    }
+
+    /// Identifies the key a request is counted for, e.g. an account owning several API keys
+    pub fn key<F>(mut self, key: F) -> Self
+    where
+        F: Fn(&RawRequest) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key = Arc::new(key);
+        self
+    }
+
+    pub async fn call<F, Fut>(&self, request: RawRequest, handler: F) -> RawResponse
+    where
+        F: FnOnce(RawRequest) -> Fut,
+        Fut: Future<Output = RawResponse>,
+    {
+        let Some(key) = (self.key)(&request) else {
+            return handler(request).await;
+        };
+        let (period, reset) = self.current_period();
+        let read = match request.extensions.get::<RequestSize>() {
+            Some(size) => size.total(),
+            None => request.body.as_ref().map_or(0, |body| body.len() as u64),
+        };
+        let Ok(used) = self.store.add(&key, period, Usage { requests: 1, bytes: read }).await
+        else {
+            return handler(request).await;
+        };
+
+        let over_requests = self.requests.is_some_and(|max| used.requests > max);
+        let over_bytes = self.bytes.is_some_and(|max| used.bytes - read >= max);
+        if over_requests || over_bytes {
+            let mut headers = Headers::empty();
+            RetryAfter::Delay(reset).apply(&mut headers);
+            if let Some(quota) = self.rate_limit(used, reset) {
+                quota.apply(&mut headers);
+            }
+            let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::TOO_MANY_REQUESTS);
+            return RawResponse::new(status_line, headers, Some(Vec::new()));
+        }
+
+        let mut response = handler(request).await;
+        let written = match response.body() {
+            Some(body) => body.len() as u64,
+            None => response.headers().get_parsed("Content-Length").unwrap_or(0),
+        };
+        let used = match written {
+            0 => used,
+            bytes => {
+                let usage = Usage { requests: 0, bytes };
+                self.store.add(&key, period, usage).await.unwrap_or(used)
+            }
+        };
+        if let Some(quota) = self.rate_limit(used, reset) {
+            quota.apply(response.headers_mut());
+        }
+        response
+    }
+
+    /// Number of the period now is in, and how long until it ends
+    fn current_period(&self) -> (u64, Duration) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let period = self.period.as_nanos();
+        let elapsed = now.as_nanos() % period;
+        let reset = Duration::from_nanos((period - elapsed).try_into().unwrap_or(u64::MAX));
+        ((now.as_nanos() / period) as u64, reset)
+    }
+
+    fn rate_limit(&self, used: Usage, reset: Duration) -> Option<RateLimit> {
+        let (limit, used) = match (self.requests, self.bytes) {
+            (Some(max), _) => (max, used.requests),
+            (None, Some(max)) => (max, used.bytes),
+            (None, None) => return None,
+        };
+        let saturate = |n: u64| u32::try_from(n).unwrap_or(u32::MAX);
+        Some(RateLimit {
+            limit: saturate(limit),
+            remaining: saturate(limit.saturating_sub(used)),
+            reset,
+            window: self.period,
+        })
+    }
+}
@@ -0,0 +1,532 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::*;
+use crate::protocol::{
+    read_http_request, Headers, HttpVersion, Method, RateLimit, RawRequest, RawResponse,
+    StatusCode, StatusLine,
+};
+
+async fn request(source: &str) -> RawRequest {
+    read_http_request(&mut source.as_bytes()).await.unwrap()
+}
+
+fn ok(body: &str) -> RawResponse {
+    let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::OK);
+    RawResponse::new(status_line, Headers::empty(), Some(body.as_bytes().to_vec()))
+}
+
+#[tokio::test]
+pub async fn test_coalesce_shares_concurrent_get() {
+    let coalesce = Coalesce::new();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let get = request("GET /expensive HTTP/1.1\r\nHost: a\r\n\r\n").await;
+
+    let handler = || async {
+        calls.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        ok("done")
+    };
+    let (first, second, third) = tokio::join!(
+        coalesce.call(&get, handler),
+        coalesce.call(&get, handler),
+        coalesce.call(&get, handler)
+    );
+
+    assert_eq!(1, calls.load(Ordering::SeqCst));
+    assert_eq!(first.into_vec(), second.clone().into_vec());
+    assert_eq!(second.into_vec(), third.into_vec());
+}
+
+#[tokio::test]
+pub async fn test_coalesce_skips_unsafe_methods() {
+    let coalesce = Coalesce::new();
+    let calls = AtomicUsize::new(0);
+    let post = request("POST /expensive HTTP/1.1\r\nHost: a\r\n\r\n").await;
+
+    let handler = || async {
+        calls.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        ok("done")
+    };
+    tokio::join!(coalesce.call(&post, handler), coalesce.call(&post, handler));
+
+    assert_eq!(2, calls.load(Ordering::SeqCst));
+    assert_eq!(None, coalesce.cache_key(&post));
+}
+
+#[tokio::test]
+pub async fn test_coalesce_skips_requests_with_credentials() {
+    let coalesce = Coalesce::new();
+    let alice = request("GET /me HTTP/1.1\r\nHost: a\r\nAuthorization: Bearer alice\r\n\r\n").await;
+    let bob = request("GET /me HTTP/1.1\r\nHost: a\r\nAuthorization: Bearer bob\r\n\r\n").await;
+
+    let handler = |request: &RawRequest| {
+        let user = request.headers.get("Authorization").unwrap().to_owned();
+        async move {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            ok(&user)
+        }
+    };
+    let (first, second) = tokio::join!(
+        coalesce.call(&alice, || handler(&alice)),
+        coalesce.call(&bob, || handler(&bob))
+    );
+
+    assert!(first.into_vec().ends_with(b"Bearer alice"));
+    assert!(second.into_vec().ends_with(b"Bearer bob"));
+    let cookie = request("GET /me HTTP/1.1\r\nHost: a\r\nCookie: session=1\r\n\r\n").await;
+    assert_eq!(None, coalesce.cache_key(&cookie));
+
+    // opted in, only requests with the same credentials share a response
+    let coalesce = Coalesce::new().share_credentials(true);
+    let calls = AtomicUsize::new(0);
+    let handler = |request: &RawRequest| {
+        calls.fetch_add(1, Ordering::SeqCst);
+        let user = request.headers.get("Authorization").unwrap().to_owned();
+        async move {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            ok(&user)
+        }
+    };
+    let (first, second, third) = tokio::join!(
+        coalesce.call(&alice, || handler(&alice)),
+        coalesce.call(&alice, || handler(&alice)),
+        coalesce.call(&bob, || handler(&bob))
+    );
+    assert_eq!(2, calls.load(Ordering::SeqCst));
+    assert!(first.into_vec().ends_with(b"Bearer alice"));
+    assert!(second.into_vec().ends_with(b"Bearer alice"));
+    assert!(third.into_vec().ends_with(b"Bearer bob"));
+}
+
+#[tokio::test]
+pub async fn test_coalesce_key_varies_on_negotiation_headers() {
+    let coalesce = Coalesce::new();
+    let json = request("GET /a HTTP/1.1\r\nHost: a\r\nAccept: application/json\r\n\r\n").await;
+    let html = request("GET /a HTTP/1.1\r\nHost: a\r\nAccept: text/html\r\n\r\n").await;
+    let gzip = request("GET /a HTTP/1.1\r\nHost: a\r\nAccept-Encoding: gzip\r\n\r\n").await;
+    let german = request("GET /a HTTP/1.1\r\nHost: a\r\nAccept-Language: de\r\n\r\n").await;
+    let plain = request("GET /a HTTP/1.1\r\nHost: a\r\n\r\n").await;
+
+    let keys = [&json, &html, &gzip, &german, &plain].map(|r| coalesce.cache_key(r).unwrap());
+    for (i, key) in keys.iter().enumerate() {
+        assert!(keys[i + 1..].iter().all(|other| other != key));
+    }
+    assert_eq!(keys[0], coalesce.cache_key(&json).unwrap());
+}
+
+#[tokio::test]
+pub async fn test_circuit_breaker_opens_and_recovers() {
+    let config = CircuitBreakerConfig {
+        min_calls: 2,
+        failure_ratio: 0.5,
+        open_for: Duration::from_millis(20),
+        ..Default::default()
+    };
+    let breaker = CircuitBreaker::new(config);
+
+    let failing = || async { Err::<(), _>("refused") };
+    assert_eq!(Err(CircuitError::Upstream("refused")), breaker.call(failing).await);
+    assert_eq!(CircuitState::Closed, breaker.state());
+    assert_eq!(Err(CircuitError::Upstream("refused")), breaker.call(failing).await);
+    assert_eq!(CircuitState::Open, breaker.state());
+
+    let calls = AtomicUsize::new(0);
+    let succeeding = || async {
+        calls.fetch_add(1, Ordering::SeqCst);
+        Ok::<_, &str>(())
+    };
+    assert_eq!(Err(CircuitError::Open), breaker.call(succeeding).await);
+    assert_eq!(0, calls.load(Ordering::SeqCst));
+    assert!(breaker
+        .open_response()
+        .into_vec()
+        .starts_with(b"HTTP/1.1 503 Service Unavailable\r\n"));
+
+    tokio::time::sleep(Duration::from_millis(25)).await;
+    assert_eq!(CircuitState::HalfOpen, breaker.state());
+    assert_eq!(Ok(()), breaker.call(succeeding).await);
+    assert_eq!(CircuitState::Closed, breaker.state());
+}
+
+#[tokio::test]
+pub async fn test_circuit_breaker_ignores_calls_admitted_before_opening() {
+    let config = CircuitBreakerConfig {
+        min_calls: 2,
+        failure_ratio: 0.5,
+        open_for: Duration::from_millis(20),
+        ..Default::default()
+    };
+    let breaker = CircuitBreaker::new(config);
+    let (release, released) = tokio::sync::oneshot::channel::<()>();
+
+    // admitted while closed, finishing only once the circuit is half-open
+    let slow = breaker.call(|| async {
+        released.await.unwrap();
+        Ok::<_, &str>(())
+    });
+    let opening = async {
+        let failing = || async { Err::<(), _>("refused") };
+        let _ = breaker.call(failing).await;
+        let _ = breaker.call(failing).await;
+        assert_eq!(CircuitState::Open, breaker.state());
+        tokio::time::sleep(Duration::from_millis(25)).await;
+        assert_eq!(CircuitState::HalfOpen, breaker.state());
+        release.send(()).unwrap();
+    };
+    let (slow, ()) = tokio::join!(slow, opening);
+    assert_eq!(Ok(()), slow);
+
+    // not taken for a probe: the circuit is still half-open and the probe slot still free
+    assert_eq!(CircuitState::HalfOpen, breaker.state());
+    assert_eq!(
+        Err(CircuitError::Upstream("refused")),
+        breaker.call(|| async { Err::<(), _>("refused") }).await
+    );
+    assert_eq!(CircuitState::Open, breaker.state());
+}
+
+struct Keys;
+
+impl ApiKeyStore for Keys {
+    type Principal = String;
+
+    fn lookup<'a>(&'a self, key: &'a str) -> crate::server::BoxFuture<'a, KeyVerdict<String>> {
+        Box::pin(async move {
+            match key {
+                "k-alice" => KeyVerdict::Accept("alice".to_owned()),
+                "k-revoked" => KeyVerdict::Forbidden,
+                _ => KeyVerdict::Unknown,
+            }
+        })
+    }
+}
+
+#[tokio::test]
+pub async fn test_api_key_auth() {
+    let auth = ApiKeyAuth::new(Keys).header("X-Api-Key").query("api_key");
+    let call = |source: &'static str| {
+        let auth = auth.clone();
+        async move {
+            let whoami = |request: RawRequest| async move {
+                ok(request.extensions.get::<String>().map(String::as_str).unwrap_or("nobody"))
+            };
+            let response = auth.call(request(source).await, whoami).await;
+            String::from_utf8(response.into_vec()).unwrap()
+        }
+    };
+
+    let accepted = "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nalice";
+    assert_eq!(accepted, call("GET / HTTP/1.1\r\nX-Api-Key: k-alice\r\n\r\n").await);
+    assert_eq!(accepted, call("GET /?x=1&api_key=k%2Dalice HTTP/1.1\r\n\r\n").await);
+
+    let unauthorized =
+        "HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: ApiKey\r\nContent-Length: 0\r\n\r\n";
+    assert_eq!(unauthorized, call("GET / HTTP/1.1\r\n\r\n").await);
+    assert_eq!(unauthorized, call("GET / HTTP/1.1\r\nX-Api-Key: k-bob\r\n\r\n").await);
+    assert_eq!(
+        "HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n",
+        call("GET /?api_key=k-revoked HTTP/1.1\r\n\r\n").await
+    );
+
+    let auth = ApiKeyAuth::new(Keys).realm("orders");
+    let response = auth.call(request("GET / HTTP/1.1\r\n\r\n").await, |_| async { ok("") }).await;
+    assert_eq!(Some("ApiKey realm=\"orders\""), response.headers().get("WWW-Authenticate"));
+}
+
+#[test]
+pub fn test_extensions() {
+    let mut extensions = crate::protocol::Extensions::new();
+    assert_eq!(None, extensions.insert(1u32));
+    assert_eq!(Some(1), extensions.insert(2u32));
+    extensions.insert("principal");
+    assert_eq!(Some(&2u32), extensions.get::<u32>());
+    *extensions.get_mut::<u32>().unwrap() += 1;
+    assert_eq!(Some(3u32), extensions.remove::<u32>());
+    assert!(!extensions.contains::<u32>());
+    assert_eq!(1, extensions.len());
+}
+
+#[tokio::test]
+pub async fn test_validation() {
+    let validation = Validation::new()
+        .require_header("X-Request-Id")
+        .content_type("application/json")
+        .max_body_len(16)
+        .require_query("page")
+        .rule(|request| match request.headers.get("X-Request-Id") {
+            Some(id) if id.len() > 8 => vec![Violation::new("X-Request-Id", "too long")],
+            _ => vec![],
+        });
+    let check = |source: &'static str| {
+        let validation = validation.clone();
+        async move { validation.check(&request(source).await).map_err(|r| *r.status) }
+    };
+
+    assert_eq!(Ok(()), check("GET /?page=1 HTTP/1.1\r\nX-Request-Id: a\r\n\r\n").await);
+    assert_eq!(Err(400), check("GET /?page=1 HTTP/1.1\r\n\r\n").await);
+    assert_eq!(
+        Err(415),
+        check("POST /?page=1 HTTP/1.1\r\nX-Request-Id: a\r\nContent-Length: 2\r\n\r\n{}").await
+    );
+    assert_eq!(
+        Ok(()),
+        check(
+            "POST /?page=1 HTTP/1.1\r\nX-Request-Id: a\r\nContent-Type: application/json; \
+             charset=utf-8\r\nContent-Length: 2\r\n\r\n{}"
+        )
+        .await
+    );
+    assert_eq!(
+        Err(413),
+        check(
+            "POST /?page=1 HTTP/1.1\r\nX-Request-Id: a\r\nContent-Type: application/json\r\n\
+             Content-Length: 17\r\n\r\n{\"a\":\"123456789\"}"
+        )
+        .await
+    );
+    assert_eq!(Err(422), check("GET / HTTP/1.1\r\nX-Request-Id: a\r\n\r\n").await);
+    assert_eq!(Err(422), check("GET /?page=1 HTTP/1.1\r\nX-Request-Id: 123456789\r\n\r\n").await);
+
+    let response = validation.call(request("GET / HTTP/1.1\r\n\r\n").await, |_| async { ok("") });
+    let expected = "HTTP/1.1 400 Bad Request\r\nContent-Type: application/json\r\n\
+                    Content-Length: 93\r\n\r\n{\"status\":400,\"violations\":[{\"field\":\
+                    \"X-Request-Id\",\"message\":\"required header is missing\"}]}";
+    assert_eq!(expected, String::from_utf8(response.await.into_vec()).unwrap());
+}
+
+#[cfg(feature = "config")]
+#[tokio::test]
+pub async fn test_validation_json_body() {
+    #[derive(serde::Deserialize)]
+    struct Signup {
+        name: String,
+    }
+
+    impl Validate for Signup {
+        fn validate(&self) -> Vec<Violation> {
+            match self.name.is_empty() {
+                true => vec![Violation::new("name", "must not be empty")],
+                false => vec![],
+            }
+        }
+    }
+
+    let validation = Validation::new().json_body::<Signup>();
+    let check = |body: &str| {
+        let source = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}", body.len());
+        let validation = validation.clone();
+        async move {
+            let request = read_http_request(&mut source.as_bytes()).await.unwrap();
+            validation.check(&request).map_err(|r| *r.status)
+        }
+    };
+
+    assert_eq!(Ok(()), check(r#"{"name":"toot"}"#).await);
+    assert_eq!(Err(422), check(r#"{"name":""}"#).await);
+    assert_eq!(Err(400), check(r#"{"nom":"toot"}"#).await);
+}
+
+#[tokio::test]
+pub async fn test_cost_throttle() {
+    let throttle = CostThrottle::new(10, Duration::from_secs(60))
+        .cost(Method::POST, "/orders", 6)
+        .cost(Method::GET, "/search*", 3);
+    let from = |source: &'static str, ip: [u8; 4]| async move {
+        let mut request = request(source).await;
+        request.extensions.insert(crate::server::PeerAddr((ip, 4000).into()));
+        request
+    };
+
+    assert_eq!(6, throttle.cost_of(&from("POST /orders HTTP/1.1\r\n\r\n", [10, 0, 0, 1]).await));
+    assert_eq!(3, throttle.cost_of(&from("GET /search?q=a HTTP/1.1\r\n\r\n", [10, 0, 0, 1]).await));
+    assert_eq!(1, throttle.cost_of(&from("GET /orders HTTP/1.1\r\n\r\n", [10, 0, 0, 1]).await));
+
+    let post = "POST /orders HTTP/1.1\r\n\r\n";
+    assert_eq!(Ok(()), throttle.admit(&from(post, [10, 0, 0, 1]).await));
+    assert_eq!(Ok(()), throttle.admit(&from("GET /search HTTP/1.1\r\n\r\n", [10, 0, 0, 1]).await));
+    let retry_after = throttle.admit(&from(post, [10, 0, 0, 1]).await).unwrap_err();
+    assert!(retry_after > Duration::from_secs(59));
+    // the cheap request still fits into the remaining budget of 1
+    assert_eq!(Ok(()), throttle.admit(&from("GET /items HTTP/1.1\r\n\r\n", [10, 0, 0, 1]).await));
+    assert_eq!(Ok(()), throttle.admit(&from(post, [10, 0, 0, 2]).await));
+
+    let response = throttle.call(from(post, [10, 0, 0, 1]).await, |_| async { ok("") }).await;
+    assert_eq!(
+        "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 60\r\nRateLimit-Limit: 10\r\n\
+         RateLimit-Remaining: 0\r\nRateLimit-Reset: 60\r\nRateLimit-Policy: 10;w=60\r\n\
+         Content-Length: 0\r\n\r\n",
+        String::from_utf8(response.into_vec()).unwrap()
+    );
+    let get = from("GET /search HTTP/1.1\r\n\r\n", [10, 0, 0, 3]).await;
+    let response = throttle.call(get, |_| async { ok("") }).await;
+    let quota = RateLimit::from_headers(response.headers()).unwrap();
+    assert_eq!((10, 7, Duration::from_secs(60)), (quota.limit, quota.remaining, quota.window));
+    let unkeyed = request(post).await;
+    assert_eq!(Ok(()), throttle.admit(&unkeyed));
+}
+
+#[tokio::test]
+pub async fn test_concurrency_limit() {
+    let limit = ConcurrencyLimit::new(1).queue(1, Duration::from_millis(200));
+    let slow = || async {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        ok("done")
+    };
+    let status = |response: RawResponse| {
+        String::from_utf8(response.into_vec()).unwrap().split(' ').nth(1).unwrap().to_owned()
+    };
+
+    // one runs, one waits for it and the third finds the queue full
+    let (first, second, third) = tokio::join!(limit.call(slow), limit.call(slow), async {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!((1, 1), (limit.in_flight(), limit.waiting()));
+        limit.call(slow).await
+    });
+    assert_eq!(("200", "200", "503"), (&*status(first), &*status(second), &*status(third)));
+    assert_eq!((0, 0), (limit.in_flight(), limit.waiting()));
+
+    let limit = ConcurrencyLimit::new(1).queue(1, Duration::from_millis(10));
+    let (first, second) = tokio::join!(limit.call(slow), limit.call(slow));
+    assert_eq!(("200", "503"), (&*status(first), &*status(second)));
+}
+
+#[tokio::test]
+pub async fn test_deadline_propagation() {
+    let mut headers = Headers::empty();
+    headers.set("grpc-timeout", "250m".to_owned());
+    assert_eq!(Some(Duration::from_millis(250)), Deadline::requested(&headers));
+    headers.set("X-Request-Timeout", "1.5".to_owned());
+    assert_eq!(Some(Duration::from_millis(1500)), Deadline::requested(&headers));
+    headers.set("X-Request-Timeout", "soon".to_owned());
+    assert_eq!(None, Deadline::requested(&headers));
+
+    let deadlines = DeadlinePropagation::new().max_timeout(Duration::from_millis(50));
+    let remaining = |request: RawRequest| async move {
+        let remaining = request.extensions.get::<Deadline>().unwrap().remaining();
+        ok(&(remaining <= Duration::from_millis(50)).to_string())
+    };
+    let get = request("GET / HTTP/1.1\r\nX-Request-Timeout: 10\r\n\r\n").await;
+    let response = deadlines.call(get, remaining).await;
+    assert!(String::from_utf8(response.into_vec()).unwrap().ends_with("true"));
+
+    let get = request("GET / HTTP/1.1\r\nX-Request-Timeout: 0.01\r\n\r\n").await;
+    let slow = |_| async {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        ok("late")
+    };
+    let response = deadlines.call(get, slow).await;
+    assert_eq!(
+        "HTTP/1.1 504 Gateway Timeout\r\nContent-Length: 0\r\n\r\n",
+        String::from_utf8(response.into_vec()).unwrap()
+    );
+    let get = request("GET / HTTP/1.1\r\n\r\n").await;
+    assert!(DeadlinePropagation::new().deadline(&get).is_none());
+}
+
+#[tokio::test]
+pub async fn test_maintenance_mode() {
+    let maintenance = Maintenance::new()
+        .allow("/healthz")
+        .allow("/admin/*")
+        .retry_after(Duration::from_secs(120))
+        .page("text/html", b"<h1>brb</h1>".to_vec());
+    let admin = maintenance.admin();
+    let call = |source: &'static str| {
+        let maintenance = maintenance.clone();
+        async move {
+            let response = maintenance.call(request(source).await, |_| async { ok("up") }).await;
+            String::from_utf8(response.into_vec()).unwrap()
+        }
+    };
+
+    assert!(call("GET /items HTTP/1.1\r\n\r\n").await.ends_with("up"));
+    let response = crate::server::Handler::call(
+        &admin,
+        request("PUT /admin/maintenance HTTP/1.1\r\n\r\n").await,
+    )
+    .await;
+    assert!(String::from_utf8(response.into_vec()).unwrap().ends_with("on\n"));
+    assert!(maintenance.is_enabled());
+
+    assert_eq!(
+        "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 120\r\nContent-Type: text/html\r\n\
+         Content-Length: 12\r\n\r\n<h1>brb</h1>",
+        call("GET /items HTTP/1.1\r\n\r\n").await
+    );
+    assert!(call("HEAD /items HTTP/1.1\r\n\r\n").await.ends_with("Content-Length: 12\r\n\r\n"));
+    assert!(call("GET /healthz HTTP/1.1\r\n\r\n").await.ends_with("up"));
+    assert!(call("GET /admin/maintenance HTTP/1.1\r\n\r\n").await.ends_with("up"));
+
+    maintenance.disable();
+    assert!(call("GET /items HTTP/1.1\r\n\r\n").await.ends_with("up"));
+}
+
+#[tokio::test]
+pub async fn test_early_data_gate() {
+    let call = |gate: EarlyDataGate, source: &'static str, early: bool| async move {
+        let mut request = request(source).await;
+        if early {
+            request.extensions.insert(crate::server::EarlyData);
+        }
+        gate.call(request, |_| async { ok("done") }).await.status()
+    };
+
+    assert_eq!(StatusCode::OK, call(EarlyDataGate::new(), "GET / HTTP/1.1\r\n\r\n", true).await);
+    assert_eq!(StatusCode::OK, call(EarlyDataGate::new(), "POST / HTTP/1.1\r\n\r\n", false).await);
+    let post = "POST / HTTP/1.1\r\n\r\n";
+    assert_eq!(StatusCode::TOO_EARLY, call(EarlyDataGate::new(), post, true).await);
+    let put = "PUT / HTTP/1.1\r\n\r\n";
+    assert_eq!(StatusCode::TOO_EARLY, call(EarlyDataGate::new(), put, true).await);
+    assert_eq!(StatusCode::OK, call(EarlyDataGate::new().allow_idempotent(), put, true).await);
+}
+
+#[tokio::test]
+pub async fn test_quota() {
+    let quota = Quota::new(MemoryQuotaStore::new(), Duration::from_secs(3600)).requests(2);
+    let keyed = |key: &'static str| request_with_key(key);
+
+    let response = quota.call(keyed("a").await, |_| async { ok("one") }).await;
+    let limit = RateLimit::from_headers(response.headers()).unwrap();
+    assert_eq!((2, 1, Duration::from_secs(3600)), (limit.limit, limit.remaining, limit.window));
+    assert!(limit.reset <= Duration::from_secs(3600));
+    quota.call(keyed("a").await, |_| async { ok("two") }).await;
+    let response = quota.call(keyed("a").await, |_| async { ok("three") }).await;
+    assert_eq!(StatusCode::TOO_MANY_REQUESTS, response.status());
+    assert!(response.headers().get("Retry-After").is_some());
+    assert_eq!(Some("0"), response.headers().get("RateLimit-Remaining"));
+    // other keys and requests without one have their own quota
+    let response = quota.call(keyed("b").await, |_| async { ok("b") }).await;
+    assert_eq!(StatusCode::OK, response.status());
+    let unkeyed = request("GET / HTTP/1.1\r\n\r\n").await;
+    let response = quota.call(unkeyed, |_| async { ok("anonymous") }).await;
+    assert_eq!(
+        (StatusCode::OK, None),
+        (response.status(), response.headers().get("RateLimit-Limit"))
+    );
+
+    let quota = Quota::new(MemoryQuotaStore::new(), Duration::from_secs(60)).bytes(100);
+    let upload = format!(
+        "POST /up HTTP/1.1\r\nX-Api-Key: c\r\nContent-Length: 40\r\n\r\n{}",
+        "x".repeat(40)
+    );
+    let upload = request(&upload).await;
+    let response = quota.call(upload, |_| async { ok(&"y".repeat(50)) }).await;
+    assert_eq!(Some("10"), response.headers().get("RateLimit-Remaining"));
+    // the last bytes may be exceeded, the next request is rejected
+    let response = quota.call(keyed("c").await, |_| async { ok(&"z".repeat(50)) }).await;
+    assert_eq!(
+        (StatusCode::OK, Some("0")),
+        (response.status(), response.headers().get("RateLimit-Remaining"))
+    );
+    let response = quota.call(keyed("c").await, |_| async { ok("") }).await;
+    assert_eq!(StatusCode::TOO_MANY_REQUESTS, response.status());
+}
+
+async fn request_with_key(key: &str) -> RawRequest {
+    let mut request = request("GET /items HTTP/1.1\r\n\r\n").await;
+    request.headers.set("X-Api-Key", key.to_owned());
+    request
+}
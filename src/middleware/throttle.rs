@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::protocol::{
+    Headers, HttpVersion, Method, RateLimit, RawRequest, RawResponse, RetryAfter, StatusCode,
+    StatusLine,
+};
+use crate::server::PeerAddr;
+
+/// clients tracked before windows which have run out are pruned
+const PRUNE_AT: usize = 1024;
+
+type ClientKey = Arc<dyn Fn(&RawRequest) -> Option<String> + Send + Sync>;
+
+#[derive(Clone)]
+struct RouteCost {
+    method: Method,
+    /// exact path, or a prefix when it ends with `*`
+    path: String,
+    cost: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    started: Instant,
+    spent: u32,
+}
+
+/// Weighted throttle: every client has `budget` units per `window`, and each request spends the
+/// cost of its route.
+///
+/// Requests over budget get 429 with `Retry-After`. Clients are told apart by `PeerAddr`
+/// unless `key` says otherwise, requests without a key are not throttled.
+#[derive(Clone)]
+pub struct CostThrottle {
+    budget: u32,
+    window: Duration,
+    default_cost: u32,
+    costs: Vec<RouteCost>,
+    key: ClientKey,
+    clients: Arc<Mutex<HashMap<String, Window>>>,
+}
+
+impl CostThrottle {
+    pub fn new(budget: u32, window: Duration) -> Self {
+        Self {
+            budget,
+            window,
+            default_cost: 1,
+            costs: Vec::new(),
+            key: Arc::new(|request| {
+                request.extensions.get::<PeerAddr>().map(|peer| peer.0.ip().to_string())
+            }),
+            clients: Arc::default(),
+        }
+    }
+
+    /// Cost of `method` requests for `path`, a trailing `*` matches any suffix.
+    /// Earlier declarations take precedence.
+    pub fn cost(mut self, method: Method, path: &str, cost: u32) -> Self {
+        let route = RouteCost { method, path: path.to_owned(), cost };
+        self.costs.push(route);
+        self
+    }
+
+    /// Cost of requests matching no declared route, 1 unless set
+    pub fn default_cost(mut self, cost: u32) -> Self {
+        self.default_cost = cost;
+        self
+    }
+
+    /// Identifies the client of a request, e.g. by an authenticated principal
+    pub fn key<F>(mut self, key: F) -> Self
+    where
+        F: Fn(&RawRequest) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key = Arc::new(key);
+        self
+    }
+
+    pub fn cost_of(&self, request: &RawRequest) -> u32 {
+        let line = &request.request_line;
+        let path = line.target.path();
+        self.costs
+            .iter()
+            .find(|route| {
+                route.method == line.method
+                    && match route.path.strip_suffix('*') {
+                        Some(prefix) => path.starts_with(prefix),
+                        None => route.path == path,
+                    }
+            })
+            .map_or(self.default_cost, |route| route.cost)
+    }
+
+    /// Spends the cost of `request` from its client's budget, or tells how long until the
+    /// budget is renewed
+    pub fn admit(&self, request: &RawRequest) -> Result<(), Duration> {
+        match self.spend(request) {
+            Some((false, quota)) => Err(quota.reset),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the cost of `request` could be spent, and the quota left to its client
+    fn spend(&self, request: &RawRequest) -> Option<(bool, RateLimit)> {
+        let key = (self.key)(request)?;
+        let cost = self.cost_of(request);
+        let now = Instant::now();
+
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() >= PRUNE_AT {
+            clients.retain(|_, window| now.duration_since(window.started) < self.window);
+        }
+        let window = clients.entry(key).or_insert(Window { started: now, spent: 0 });
+        if now.duration_since(window.started) >= self.window {
+            *window = Window { started: now, spent: 0 };
+        }
+        let admitted = window.spent.saturating_add(cost) <= self.budget;
+        if admitted {
+            window.spent += cost;
+        }
+        let quota = RateLimit {
+            limit: self.budget,
+            remaining: self.budget - window.spent,
+            reset: self.window.saturating_sub(now.duration_since(window.started)),
+            window: self.window,
+        };
+        Some((admitted, quota))
+    }
+
+    /// Throttled responses, and those to requests within budget, carry `RateLimit-*` headers
+    pub async fn call<F, Fut>(&self, request: RawRequest, handler: F) -> RawResponse
+    where
+        F: FnOnce(RawRequest) -> Fut,
+        Fut: Future<Output = RawResponse>,
+    {
+        match self.spend(&request) {
+            None => handler(request).await,
+            Some((true, quota)) => {
+                let mut response = handler(request).await;
+                quota.apply(response.headers_mut());
+                response
+            }
+            Some((false, quota)) => {
+                let mut headers = Headers::empty();
+                RetryAfter::Delay(quota.reset).apply(&mut headers);
+                quota.apply(&mut headers);
+                let status_line =
+                    StatusLine::new(HttpVersion::Http1_1, StatusCode::TOO_MANY_REQUESTS);
+                RawResponse::new(status_line, headers, Some(Vec::new()))
+            }
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use crate::protocol::{
+    query_param, Headers, HttpVersion, MediaType, RawRequest, RawResponse, StatusCode, StatusLine,
+};
+use crate::server::json_escape;
+
+/// One failed constraint, `field` names the header, query parameter or body field
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Violation {
+    pub field: String,
+    pub message: String,
+}
+
+impl Violation {
+    pub fn new(field: &str, message: &str) -> Self {
+        Self { field: field.to_owned(), message: message.to_owned() }
+    }
+}
+
+/// Why a request was refused, answered as
+/// `{"status":422,"violations":[{"field":"...","message":"..."}]}`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Rejection {
+    pub status: StatusCode,
+    pub violations: Vec<Violation>,
+}
+
+impl Rejection {
+    pub fn into_response(self) -> RawResponse {
+        let violations = self
+            .violations
+            .iter()
+            .map(|v| {
+                let (field, message) = (json_escape(&v.field), json_escape(&v.message));
+                format!("{{\"field\":\"{field}\",\"message\":\"{message}\"}}")
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        let body = format!("{{\"status\":{},\"violations\":[{violations}]}}", *self.status);
+
+        let mut headers = Headers::empty();
+        headers.set("Content-Type", "application/json".to_owned());
+        let status_line = StatusLine::new(HttpVersion::Http1_1, self.status);
+        RawResponse::new(status_line, headers, Some(body.into_bytes()))
+    }
+}
+
+/// Checks of a decoded body beyond what deserializing it ensures
+#[cfg(feature = "config")]
+pub trait Validate {
+    fn validate(&self) -> Vec<Violation> {
+        Vec::new()
+    }
+}
+
+type Rule = Arc<dyn Fn(&RawRequest) -> Result<(), Rejection> + Send + Sync>;
+
+/// Constraints a route declares for its requests, checked before the handler runs.
+///
+/// Checks run in order: required headers (400), the `Content-Type` allowlist (415), the body
+/// size (413), required query parameters (422), then rules in the order they were added. The
+/// first failing check answers the request with all of its violations.
+#[derive(Clone, Default)]
+pub struct Validation {
+    required_headers: Vec<String>,
+    content_types: Vec<String>,
+    max_body_len: Option<usize>,
+    required_query: Vec<String>,
+    rules: Vec<Rule>,
+}
+
+impl Validation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn require_header(mut self, name: &str) -> Self {
+        self.required_headers.push(name.to_owned());
+        self
+    }
+
+    /// Allows a media type or range, e.g. `application/json` or `text/*`, parameters such as
+    /// `charset` are ignored.
+    /// Requests with a body need an allowed `Content-Type` once any is given.
+    pub fn content_type(mut self, media_type: &str) -> Self {
+        self.content_types.push(media_type.to_ascii_lowercase());
+        self
+    }
+
+    pub fn max_body_len(mut self, len: usize) -> Self {
+        self.max_body_len = Some(len);
+        self
+    }
+
+    pub fn require_query(mut self, parameter: &str) -> Self {
+        self.required_query.push(parameter.to_owned());
+        self
+    }
+
+    /// Custom check, any violations it returns are answered with 422
+    pub fn rule<F>(mut self, rule: F) -> Self
+    where
+        F: Fn(&RawRequest) -> Vec<Violation> + Send + Sync + 'static,
+    {
+        self.rules.push(Arc::new(move |request| {
+            rejection(StatusCode::UNPROCESSABLE_ENTITY, rule(request))
+        }));
+        self
+    }
+
+    /// Requires a JSON body deserializing to `T` (400 otherwise) and passing its `Validate`
+    /// checks (422 otherwise)
+    #[cfg(feature = "config")]
+    pub fn json_body<T>(mut self) -> Self
+    where
+        T: serde::de::DeserializeOwned + Validate + 'static,
+    {
+        self.rules.push(Arc::new(|request| {
+            let body = request.body.as_deref().unwrap_or_default();
+            match serde_json::from_slice::<T>(body) {
+                Ok(value) => rejection(StatusCode::UNPROCESSABLE_ENTITY, value.validate()),
+                Err(err) => {
+                    let violation = Violation::new("body", &err.to_string());
+                    rejection(StatusCode::BAD_REQUEST, vec![violation])
+                }
+            }
+        }));
+        self
+    }
+
+    pub fn check(&self, request: &RawRequest) -> Result<(), Rejection> {
+        let missing = self
+            .required_headers
+            .iter()
+            .filter(|name| request.headers.get(name).is_none())
+            .map(|name| Violation::new(name, "required header is missing"))
+            .collect();
+        rejection(StatusCode::BAD_REQUEST, missing)?;
+
+        let body_len = request.body.as_ref().map_or(0, Vec::len);
+        if !self.content_types.is_empty() && request.body.is_some() {
+            let media_type = request.media_type();
+            let allowed = |media_type: &MediaType| {
+                self.content_types.iter().any(|allowed| media_type.matches(allowed))
+            };
+            if !media_type.as_ref().is_some_and(allowed) {
+                let message = format!("expected one of {}", self.content_types.join(", "));
+                let violation = Violation::new("Content-Type", &message);
+                rejection(StatusCode::UNSUPPORTED_MEDIA_TYPE, vec![violation])?;
+            }
+        }
+        if let Some(max) = self.max_body_len.filter(|max| body_len > *max) {
+            let violation = Violation::new("body", &format!("larger than {max} bytes"));
+            rejection(StatusCode::PAYLOAD_TOO_LARGE, vec![violation])?;
+        }
+
+        let query = request.request_line.target.query().unwrap_or_default();
+        let missing = self
+            .required_query
+            .iter()
+            .filter(|name| query_param(query, name).is_none())
+            .map(|name| Violation::new(name, "required query parameter is missing"))
+            .collect();
+        rejection(StatusCode::UNPROCESSABLE_ENTITY, missing)?;
+
+        self.rules.iter().try_for_each(|rule| rule(request))
+    }
+
+    pub async fn call<F, Fut>(&self, request: RawRequest, handler: F) -> RawResponse
+    where
+        F: FnOnce(RawRequest) -> Fut,
+        Fut: Future<Output = RawResponse>,
+    {
+        match self.check(&request) {
+            Ok(()) => handler(request).await,
+            Err(rejection) => rejection.into_response(),
+        }
+    }
+}
+
+fn rejection(status: StatusCode, violations: Vec<Violation>) -> Result<(), Rejection> {
+    match violations.is_empty() {
+        true => Ok(()),
+        false => Err(Rejection { status, violations }),
+    }
+}
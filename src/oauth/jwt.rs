@@ -0,0 +1,176 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::{hmac, signature};
+use serde::Deserialize;
+
+use super::OAuthError;
+
+/// clock skew tolerated for `exp` and `iat`
+const LEEWAY_SECS: u64 = 60;
+
+/// JSON Web Key Set, as served from the provider's `jwks_uri`
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+impl Jwks {
+    pub fn from_json(s: &str) -> Result<Self, OAuthError> {
+        serde_json::from_str(s).map_err(|err| OAuthError::InvalidResponse(err.to_string()))
+    }
+}
+
+/// Public key of a `Jwks`, RSA (`n`, `e`) and P-256 (`x`, `y`) keys are supported
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Jwk {
+    pub kty: String,
+    pub kid: Option<String>,
+    pub alg: Option<String>,
+    pub crv: Option<String>,
+    pub n: Option<String>,
+    pub e: Option<String>,
+    pub x: Option<String>,
+    pub y: Option<String>,
+}
+
+/// Claims of a validated ID token, those not listed here are kept in `extra`
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    #[serde(deserialize_with = "one_or_many")]
+    pub aud: Vec<String>,
+    pub exp: u64,
+    pub iat: u64,
+    pub nonce: Option<String>,
+    pub azp: Option<String>,
+    pub email: Option<String>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct JoseHeader {
+    alg: String,
+    kid: Option<String>,
+}
+
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(aud) => vec![aud],
+        OneOrMany::Many(aud) => aud,
+    })
+}
+
+/// What an ID token has to match besides a valid signature
+pub(super) struct Expected<'a> {
+    pub issuer: &'a str,
+    pub client_id: &'a str,
+    /// shared secret for `HS256` tokens
+    pub client_secret: Option<&'a str>,
+    pub nonce: &'a str,
+}
+
+/// Checks the signature of a compact JWS against `jwks` (or the client secret for `HS256`),
+/// then issuer, audience, expiry and nonce of its claims
+pub(super) fn validate(
+    token: &str,
+    jwks: &Jwks,
+    expected: &Expected<'_>,
+) -> Result<IdTokenClaims, OAuthError> {
+    let invalid = |reason: &str| OAuthError::InvalidToken(reason.to_owned());
+
+    let Some((signed, sig)) = token.rsplit_once('.') else {
+        return Err(invalid("not a compact JWS"));
+    };
+    let Some((header, payload)) = signed.split_once('.').filter(|(_, p)| !p.contains('.')) else {
+        return Err(invalid("not a compact JWS"));
+    };
+    let decode = |part: &str| URL_SAFE_NO_PAD.decode(part).map_err(|_| invalid("bad base64url"));
+    let header: JoseHeader =
+        serde_json::from_slice(&decode(header)?).map_err(|_| invalid("bad header"))?;
+    let sig = decode(sig)?;
+
+    let verified = match header.alg.as_str() {
+        "HS256" => {
+            let secret = expected.client_secret.ok_or_else(|| invalid("HS256 without a secret"))?;
+            let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+            hmac::verify(&key, signed.as_bytes(), &sig).is_ok()
+        }
+        "RS256" | "ES256" => {
+            candidates(jwks, &header).any(|jwk| verify(jwk, &header.alg, signed, &sig))
+        }
+        _ => return Err(invalid("unsupported alg")),
+    };
+    if !verified {
+        return Err(invalid("signature mismatch"));
+    }
+
+    let claims: IdTokenClaims = serde_json::from_slice(&decode(payload)?)
+        .map_err(|err| OAuthError::InvalidToken(err.to_string()))?;
+    if claims.iss != expected.issuer {
+        return Err(invalid("issuer mismatch"));
+    }
+    if !claims.aud.iter().any(|aud| aud == expected.client_id) {
+        return Err(invalid("audience mismatch"));
+    }
+    if claims.aud.len() > 1 && claims.azp.as_deref().is_some_and(|azp| azp != expected.client_id) {
+        return Err(invalid("authorized party mismatch"));
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    if claims.exp + LEEWAY_SECS < now {
+        return Err(invalid("expired"));
+    }
+    if claims.iat > now + LEEWAY_SECS {
+        return Err(invalid("issued in the future"));
+    }
+    if claims.nonce.as_deref() != Some(expected.nonce) {
+        return Err(invalid("nonce mismatch"));
+    }
+    Ok(claims)
+}
+
+/// the key named by `kid`, or every key when the header names none
+fn candidates<'a>(jwks: &'a Jwks, header: &'a JoseHeader) -> impl Iterator<Item = &'a Jwk> {
+    jwks.keys.iter().filter(move |jwk| match header.kid {
+        Some(ref kid) => jwk.kid.as_ref() == Some(kid),
+        None => true,
+    })
+}
+
+fn verify(jwk: &Jwk, alg: &str, signed: &str, sig: &[u8]) -> bool {
+    if jwk.alg.as_deref().is_some_and(|a| a != alg) {
+        return false;
+    }
+    let decode = |part: &Option<String>| part.as_ref().and_then(|p| URL_SAFE_NO_PAD.decode(p).ok());
+    match (alg, jwk.kty.as_str()) {
+        ("RS256", "RSA") => {
+            let (Some(n), Some(e)) = (decode(&jwk.n), decode(&jwk.e)) else {
+                return false;
+            };
+            let key = signature::RsaPublicKeyComponents { n, e };
+            key.verify(&signature::RSA_PKCS1_2048_8192_SHA256, signed.as_bytes(), sig).is_ok()
+        }
+        ("ES256", "EC") if jwk.crv.as_deref() == Some("P-256") => {
+            let (Some(x), Some(y)) = (decode(&jwk.x), decode(&jwk.y)) else {
+                return false;
+            };
+            let point = [&[0x04][..], &x, &y].concat();
+            let key = signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, point);
+            key.verify(signed.as_bytes(), sig).is_ok()
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,343 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::sync::Arc;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Deserialize;
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::ClientConfig;
+use tokio_rustls::TlsConnector;
+
+pub use self::jwt::{IdTokenClaims, Jwk, Jwks};
+use crate::protocol::{
+    percent_encode, query_param, EncodeSet, Headers, HttpVersion, Location, Method, RawRequest,
+    RequestLimits, RequestLine, RequestTarget, StatusCode,
+};
+use crate::proxy::send;
+
+mod jwt;
+#[cfg(test)]
+mod tests;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum OAuthError {
+    Io(io::ErrorKind),
+    InvalidUrl(String),
+    /// `error` and `error_description` the provider redirected back with
+    Provider(String, Option<String>),
+    /// the callback's `state` is not the one of the pending login
+    StateMismatch,
+    MissingCode,
+    /// status and body of a failed token or JWKS request
+    Endpoint(StatusCode, String),
+    InvalidResponse(String),
+    InvalidToken(String),
+}
+
+impl Display for OAuthError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OAuthError::Io(err) => write!(f, "oauth request failure: {err}"),
+            OAuthError::InvalidUrl(url) => write!(f, "invalid endpoint url: {url}"),
+            OAuthError::Provider(error, Some(description)) => {
+                write!(f, "provider error {error}: {description}")
+            }
+            OAuthError::Provider(error, None) => write!(f, "provider error {error}"),
+            OAuthError::StateMismatch => write!(f, "callback state mismatch"),
+            OAuthError::MissingCode => write!(f, "callback without code"),
+            OAuthError::Endpoint(status, body) => {
+                write!(f, "endpoint answered {}: {body}", **status)
+            }
+            OAuthError::InvalidResponse(reason) => write!(f, "invalid endpoint response: {reason}"),
+            OAuthError::InvalidToken(reason) => write!(f, "invalid id token: {reason}"),
+        }
+    }
+}
+
+impl From<io::Error> for OAuthError {
+    fn from(value: io::Error) -> Self {
+        OAuthError::Io(value.kind())
+    }
+}
+
+/// Registration of the app with a provider, the endpoints are found in the provider's
+/// `/.well-known/openid-configuration`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OAuthConfig {
+    pub client_id: String,
+    /// sent with the token request, and the key of `HS256` ID tokens
+    pub client_secret: Option<String>,
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: Option<String>,
+    /// the app's callback, as registered with the provider
+    pub redirect_uri: String,
+    /// requested besides `openid`
+    pub scopes: Vec<String>,
+}
+
+/// A started login, to be kept by the app until the callback arrives
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PendingLogin {
+    /// where to redirect the user agent to
+    pub url: String,
+    pub state: String,
+    pub nonce: String,
+    pub pkce_verifier: String,
+}
+
+/// Response of the token endpoint
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: Option<u64>,
+    pub refresh_token: Option<String>,
+    pub id_token: Option<String>,
+    pub scope: Option<String>,
+}
+
+/// A completed login
+#[derive(Debug, Clone)]
+pub struct Login {
+    pub tokens: TokenResponse,
+    pub claims: IdTokenClaims,
+}
+
+/// Authorization code flow with PKCE for "login with ..." against OAuth 2.0 / OpenID Connect
+/// providers.
+///
+/// `authorize` starts a login and yields the provider URL to redirect to along with a
+/// `PendingLogin`, which the app keeps (e.g. in a session) until the provider redirects back.
+/// `complete` then checks the callback, exchanges the code and validates the ID token.
+pub struct OAuthClient {
+    config: OAuthConfig,
+    tls: Option<Arc<ClientConfig>>,
+    limits: RequestLimits,
+}
+
+impl OAuthClient {
+    pub fn new(config: OAuthConfig) -> Self {
+        Self { config, tls: None, limits: RequestLimits::default() }
+    }
+
+    /// Needed for `https` endpoints, with the roots trusted for the provider
+    pub fn tls_config(mut self, tls: Arc<ClientConfig>) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    pub fn config(&self) -> &OAuthConfig {
+        &self.config
+    }
+
+    /// Starts a login with fresh `state`, `nonce` and PKCE verifier
+    pub fn authorize(&self) -> PendingLogin {
+        let state = random_token();
+        let nonce = random_token();
+        let pkce_verifier = random_token();
+
+        let scope = std::iter::once("openid")
+            .chain(self.config.scopes.iter().map(String::as_str).filter(|s| *s != "openid"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let url = Location::new(&self.config.authorization_endpoint)
+            .query("response_type", "code")
+            .query("client_id", &self.config.client_id)
+            .query("redirect_uri", &self.config.redirect_uri)
+            .query("scope", &scope)
+            .query("state", &state)
+            .query("nonce", &nonce)
+            .query("code_challenge", &pkce_challenge(&pkce_verifier))
+            .query("code_challenge_method", "S256")
+            .to_string();
+        PendingLogin { url, state, nonce, pkce_verifier }
+    }
+
+    /// The authorization code of the provider's redirect back to `redirect_uri`
+    pub fn callback_code(
+        &self,
+        request: &RawRequest,
+        pending: &PendingLogin,
+    ) -> Result<String, OAuthError> {
+        let query = request.request_line.target.query().unwrap_or_default();
+        if let Some(error) = query_param(query, "error") {
+            let description = query_param(query, "error_description").map(|d| d.into_owned());
+            return Err(OAuthError::Provider(error.into_owned(), description));
+        }
+        let state = query_param(query, "state").unwrap_or_default();
+        if !constant_time_eq(state.as_bytes(), pending.state.as_bytes()) {
+            return Err(OAuthError::StateMismatch);
+        }
+        match query_param(query, "code") {
+            Some(code) if !code.is_empty() => Ok(code.into_owned()),
+            _ => Err(OAuthError::MissingCode),
+        }
+    }
+
+    /// Redeems an authorization code at the token endpoint
+    pub async fn exchange_code(
+        &self,
+        code: &str,
+        pending: &PendingLogin,
+    ) -> Result<TokenResponse, OAuthError> {
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.config.redirect_uri),
+            ("client_id", &self.config.client_id),
+            ("code_verifier", &pending.pkce_verifier),
+        ];
+        if let Some(ref secret) = self.config.client_secret {
+            form.push(("client_secret", secret));
+        }
+        let body = form
+            .iter()
+            .map(|(key, value)| {
+                format!("{key}={}", percent_encode(value, EncodeSet::QueryComponent))
+            })
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let endpoint = Endpoint::parse(&self.config.token_endpoint)?;
+        let mut request = endpoint.request(Method::POST);
+        request.headers.set("Content-Type", "application/x-www-form-urlencoded".to_owned());
+        request.headers.set("Content-Length", body.len().to_string());
+        request.body = Some(body.into_bytes());
+        self.fetch_json(&endpoint, request).await
+    }
+
+    /// Fetches the provider's signing keys from `jwks_uri`
+    pub async fn fetch_jwks(&self) -> Result<Jwks, OAuthError> {
+        let uri = self.config.jwks_uri.as_deref().unwrap_or_default();
+        let endpoint = Endpoint::parse(uri)?;
+        let request = endpoint.request(Method::GET);
+        self.fetch_json(&endpoint, request).await
+    }
+
+    /// Validates an ID token issued for this client in the login started as `pending`
+    pub fn validate_id_token(
+        &self,
+        id_token: &str,
+        jwks: &Jwks,
+        pending: &PendingLogin,
+    ) -> Result<IdTokenClaims, OAuthError> {
+        let expected = jwt::Expected {
+            issuer: &self.config.issuer,
+            client_id: &self.config.client_id,
+            client_secret: self.config.client_secret.as_deref(),
+            nonce: &pending.nonce,
+        };
+        jwt::validate(id_token, jwks, &expected)
+    }
+
+    /// Callback handling, code exchange and ID token validation in one go
+    pub async fn complete(
+        &self,
+        request: &RawRequest,
+        pending: &PendingLogin,
+        jwks: &Jwks,
+    ) -> Result<Login, OAuthError> {
+        let code = self.callback_code(request, pending)?;
+        let tokens = self.exchange_code(&code, pending).await?;
+        let Some(ref id_token) = tokens.id_token else {
+            return Err(OAuthError::InvalidResponse("no id_token".to_owned()));
+        };
+        let claims = self.validate_id_token(id_token, jwks, pending)?;
+        Ok(Login { tokens, claims })
+    }
+
+    async fn fetch_json<T>(&self, endpoint: &Endpoint, request: RawRequest) -> Result<T, OAuthError>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let stream = TcpStream::connect((endpoint.host.as_str(), endpoint.port));
+        let response = if endpoint.https {
+            let Some(ref tls) = self.tls else {
+                return Err(OAuthError::InvalidUrl(format!(
+                    "{} needs a tls config",
+                    endpoint.host
+                )));
+            };
+            let name = ServerName::try_from(endpoint.host.clone())
+                .map_err(|_| OAuthError::InvalidUrl(endpoint.host.clone()))?;
+            let stream = TlsConnector::from(tls.clone()).connect(name, stream.await?).await?;
+            send(stream, request, &self.limits).await?
+        } else {
+            send(stream.await?, request, &self.limits).await?
+        };
+
+        let body = response.body().unwrap_or_default();
+        if response.status() != StatusCode::OK {
+            let body = String::from_utf8_lossy(body).into_owned();
+            return Err(OAuthError::Endpoint(response.status(), body));
+        }
+        serde_json::from_slice(body).map_err(|err| OAuthError::InvalidResponse(err.to_string()))
+    }
+}
+
+/// `http(s)://host[:port]/path` of a provider endpoint
+#[derive(Debug)]
+struct Endpoint {
+    https: bool,
+    host: String,
+    port: u16,
+    target: String,
+}
+
+impl Endpoint {
+    fn parse(url: &str) -> Result<Self, OAuthError> {
+        let invalid = || OAuthError::InvalidUrl(url.to_owned());
+        let (https, rest) = match url.split_once("://") {
+            Some(("https", rest)) => (true, rest),
+            Some(("http", rest)) => (false, rest),
+            _ => return Err(invalid()),
+        };
+        let (authority, target) = match rest.find('/') {
+            Some(at) => rest.split_at(at),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+            None => (authority, if https { 443 } else { 80 }),
+        };
+        if host.is_empty() || authority.contains('@') {
+            return Err(invalid());
+        }
+        Ok(Self { https, host: host.to_owned(), port, target: target.to_owned() })
+    }
+
+    fn request(&self, method: Method) -> RawRequest {
+        let mut headers = Headers::empty();
+        match (self.https, self.port) {
+            (true, 443) | (false, 80) => headers.set("Host", self.host.clone()),
+            _ => headers.set("Host", format!("{}:{}", self.host, self.port)),
+        }
+        headers.set("Accept", "application/json".to_owned());
+        let target = RequestTarget::origin(&self.target);
+        let request_line = RequestLine { method, target, version: HttpVersion::Http1_1 };
+        RawRequest { request_line, headers, body: None, extensions: Default::default() }
+    }
+}
+
+/// 32 random bytes, base64url encoded
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new().fill(&mut bytes).expect("system randomness");
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// `S256` code challenge of a PKCE verifier
+pub fn pkce_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(digest(&SHA256, verifier.as_bytes()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -0,0 +1,186 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use super::*;
+
+fn config(token_endpoint: &str) -> OAuthConfig {
+    OAuthConfig {
+        client_id: "app".to_owned(),
+        client_secret: None,
+        issuer: "https://id.example.com".to_owned(),
+        authorization_endpoint: "https://id.example.com/authorize".to_owned(),
+        token_endpoint: token_endpoint.to_owned(),
+        jwks_uri: None,
+        redirect_uri: "https://app.example.com/callback".to_owned(),
+        scopes: vec!["email".to_owned()],
+    }
+}
+
+fn callback(uri: &str) -> RawRequest {
+    let target = RequestTarget::origin(uri);
+    let request_line = RequestLine { method: Method::GET, target, version: HttpVersion::Http1_1 };
+    RawRequest {
+        request_line,
+        headers: Headers::empty(),
+        body: None,
+        extensions: Default::default(),
+    }
+}
+
+/// ES256 signing key and the `Jwks` publishing it
+fn signing_key() -> (EcdsaKeyPair, Jwks) {
+    let rng = SystemRandom::new();
+    let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+    let key =
+        EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng).unwrap();
+    let point = key.public_key().as_ref();
+    let jwk = Jwk {
+        kty: "EC".to_owned(),
+        kid: Some("k1".to_owned()),
+        crv: Some("P-256".to_owned()),
+        x: Some(URL_SAFE_NO_PAD.encode(&point[1..33])),
+        y: Some(URL_SAFE_NO_PAD.encode(&point[33..])),
+        ..Default::default()
+    };
+    (key, Jwks { keys: vec![jwk] })
+}
+
+fn sign(key: &EcdsaKeyPair, claims: serde_json::Value) -> String {
+    let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"ES256","kid":"k1"}"#);
+    let payload = URL_SAFE_NO_PAD.encode(claims.to_string());
+    let signed = format!("{header}.{payload}");
+    let sig = key.sign(&SystemRandom::new(), signed.as_bytes()).unwrap();
+    format!("{signed}.{}", URL_SAFE_NO_PAD.encode(sig))
+}
+
+fn claims(nonce: &str) -> serde_json::Value {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    serde_json::json!({
+        "iss": "https://id.example.com", "sub": "u1", "aud": "app",
+        "exp": now + 300, "iat": now, "nonce": nonce, "email": "u1@example.com",
+    })
+}
+
+#[test]
+pub fn test_pkce_challenge() {
+    // RFC 7636, appendix B
+    let challenge = pkce_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk");
+    assert_eq!("E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM", challenge);
+}
+
+#[test]
+pub fn test_authorize_and_callback() {
+    let client = OAuthClient::new(config("http://127.0.0.1:1/token"));
+    let pending = client.authorize();
+    assert_ne!(pending.state, client.authorize().state);
+    assert!(pending.url.starts_with("https://id.example.com/authorize?response_type=code&"));
+    assert!(pending.url.contains("&scope=openid%20email&"));
+    let challenge = pkce_challenge(&pending.pkce_verifier);
+    assert!(pending
+        .url
+        .ends_with(&format!("code_challenge={challenge}&code_challenge_method=S256")));
+
+    let uri = format!("/callback?code=c1&state={}", pending.state);
+    assert_eq!(Ok("c1".to_owned()), client.callback_code(&callback(&uri), &pending));
+    assert_eq!(
+        Err(OAuthError::StateMismatch),
+        client.callback_code(&callback("/callback?code=c1&state=forged"), &pending)
+    );
+    assert_eq!(
+        Err(OAuthError::Provider("access_denied".to_owned(), Some("no thanks".to_owned()))),
+        client.callback_code(
+            &callback("/callback?error=access_denied&error_description=no%20thanks"),
+            &pending
+        )
+    );
+}
+
+#[test]
+pub fn test_validate_id_token() {
+    let client = OAuthClient::new(config("http://127.0.0.1:1/token"));
+    let pending = client.authorize();
+    let (key, jwks) = signing_key();
+
+    let token = sign(&key, claims(&pending.nonce));
+    let claims_ok = client.validate_id_token(&token, &jwks, &pending).unwrap();
+    assert_eq!(
+        ("u1", Some("u1@example.com")),
+        (claims_ok.sub.as_str(), claims_ok.email.as_deref())
+    );
+
+    let invalid = |reason: &str| Err(OAuthError::InvalidToken(reason.to_owned()));
+    let token = sign(&key, claims("other"));
+    assert_eq!(
+        invalid("nonce mismatch"),
+        client.validate_id_token(&token, &jwks, &pending).map(|_| ())
+    );
+    let mut expired = claims(&pending.nonce);
+    expired["exp"] = serde_json::json!(1);
+    let token = sign(&key, expired);
+    assert_eq!(invalid("expired"), client.validate_id_token(&token, &jwks, &pending).map(|_| ()));
+    let mut audience = claims(&pending.nonce);
+    audience["aud"] = serde_json::json!(["other", "app"]);
+    audience["azp"] = serde_json::json!("other");
+    let token = sign(&key, audience);
+    assert_eq!(
+        invalid("authorized party mismatch"),
+        client.validate_id_token(&token, &jwks, &pending).map(|_| ())
+    );
+
+    let (other_key, _) = signing_key();
+    let token = sign(&other_key, claims(&pending.nonce));
+    assert_eq!(
+        invalid("signature mismatch"),
+        client.validate_id_token(&token, &jwks, &pending).map(|_| ())
+    );
+    let unsigned = format!(
+        "{}.{}.",
+        URL_SAFE_NO_PAD.encode(r#"{"alg":"none"}"#),
+        URL_SAFE_NO_PAD.encode(claims(&pending.nonce).to_string())
+    );
+    assert_eq!(
+        invalid("unsupported alg"),
+        client.validate_id_token(&unsigned, &jwks, &pending).map(|_| ())
+    );
+}
+
+#[tokio::test]
+pub async fn test_complete_login() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = OAuthClient::new(config(&format!("http://{addr}/token")));
+    let pending = client.authorize();
+    let (key, jwks) = signing_key();
+
+    let id_token = sign(&key, claims(&pending.nonce));
+    let verifier = pending.pkce_verifier.clone();
+    let provider = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = vec![0u8; 4096];
+        let n = stream.read(&mut request).await.unwrap();
+        let request = String::from_utf8_lossy(&request[..n]).into_owned();
+        let body =
+            format!(r#"{{"access_token":"at","token_type":"Bearer","id_token":"{id_token}"}}"#);
+        let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{body}", body.len());
+        stream.write_all(response.as_bytes()).await.unwrap();
+        request
+    });
+
+    let uri = format!("/callback?code=c1&state={}", pending.state);
+    let login = client.complete(&callback(&uri), &pending, &jwks).await.unwrap();
+    assert_eq!(("at", "u1"), (login.tokens.access_token.as_str(), login.claims.sub.as_str()));
+
+    let request = provider.await.unwrap();
+    assert!(request.starts_with("POST /token HTTP/1.1\r\n"));
+    assert!(request.contains("Content-Type: application/x-www-form-urlencoded\r\n"));
+    assert!(request.ends_with(&format!(
+        "grant_type=authorization_code&code=c1&redirect_uri=https:\
+         //app.example.com/callback&client_id=app&code_verifier={verifier}"
+    )));
+}
@@ -0,0 +1,148 @@
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+
+use super::inflate::{gunzip, zlib_decompress};
+use super::{Charset, CharsetError, Headers, MediaType, RawRequest, RawResponse, RequestLimits};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum BodyError {
+    /// a `Content-Encoding` other than `gzip`, `deflate` and `identity`
+    UnsupportedEncoding(String),
+    /// compressed data which doesn't decode, or decodes to more than allowed
+    Corrupt(String),
+    Charset(CharsetError),
+    Json(String),
+}
+
+impl Display for BodyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BodyError::UnsupportedEncoding(coding) => {
+                write!(f, "unsupported content coding: {coding}")
+            }
+            BodyError::Corrupt(reason) => write!(f, "corrupt body: {reason}"),
+            BodyError::Charset(err) => write!(f, "{err}"),
+            BodyError::Json(err) => write!(f, "invalid json body: {err}"),
+        }
+    }
+}
+
+impl From<CharsetError> for BodyError {
+    fn from(value: CharsetError) -> Self {
+        BodyError::Charset(value)
+    }
+}
+
+/// A request body as it was received, only its transfer coding undone, for what needs the
+/// exact bytes sent after the body was decoded or replaced: signatures, audit logs, replays.
+///
+/// In the extensions of requests the server reads with bodies up to `Limits::max_raw_body`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RawBody(pub Arc<[u8]>);
+
+impl RawBody {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// The `Content-Encoding` codings of a message other than `identity`
+fn content_codings(headers: &Headers) -> Vec<String> {
+    headers
+        .get_all("Content-Encoding")
+        .flat_map(|v| v.split(','))
+        .map(|v| v.trim().to_ascii_lowercase())
+        .filter(|v| !v.is_empty() && v != "identity")
+        .collect()
+}
+
+fn decode(codings: &[String], mut body: Vec<u8>, max_len: usize) -> Result<Vec<u8>, BodyError> {
+    // applied in the order listed, so undone from the last
+    for coding in codings.iter().rev() {
+        body = match coding.as_str() {
+            "gzip" | "x-gzip" => gunzip(&body, max_len),
+            "deflate" => zlib_decompress(&body, max_len),
+            _ => return Err(BodyError::UnsupportedEncoding(coding.clone())),
+        }
+        .map_err(|err| BodyError::Corrupt(err.0.to_owned()))?;
+    }
+    Ok(body)
+}
+
+impl RawRequest {
+    /// The body as received, if it was kept as `RawBody`
+    pub fn raw_body(&self) -> Option<&[u8]> {
+        self.extensions.get::<RawBody>().map(RawBody::as_bytes)
+    }
+
+    /// Keeps the body as `RawBody` if it has at most `max_len` bytes, unless one is kept already
+    pub fn keep_raw_body(&mut self, max_len: usize) {
+        if self.extensions.contains::<RawBody>() {
+            return;
+        }
+        if let Some(body) = self.body.as_deref().filter(|body| body.len() <= max_len) {
+            self.extensions.insert(RawBody(Arc::from(body)));
+        }
+    }
+
+    /// The request with its `Content-Encoding` undone as `RawResponse::decoded` does, and its
+    /// `Content-Length` updated. A `RawBody` kept before still holds the body received.
+    pub fn decoded(mut self, max_len: usize) -> Result<Self, BodyError> {
+        let codings = content_codings(&self.headers);
+        if codings.is_empty() {
+            return Ok(self);
+        }
+
+        let body = decode(&codings, self.body.take().unwrap_or_default(), max_len)?;
+        self.headers.retain(|field, _| !field.eq_ignore_ascii_case("Content-Encoding"));
+        self.headers.set("Content-Length", body.len().to_string());
+        self.body = Some(body);
+        Ok(self)
+    }
+}
+
+impl RawResponse {
+    /// The response with its `Content-Encoding` undone, decoding to at most `max_len` bytes
+    pub fn decoded(self, max_len: usize) -> Result<Self, BodyError> {
+        let codings = content_codings(self.headers());
+        if codings.is_empty() {
+            return Ok(self);
+        }
+
+        let mut response = self;
+        let body = response.body_mut().map(std::mem::take).unwrap_or_default();
+        let body = decode(&codings, body, max_len)?;
+        response.headers_mut().retain(|field, _| !field.eq_ignore_ascii_case("Content-Encoding"));
+        response.set_body(body);
+        Ok(response)
+    }
+
+    /// The decoded body, see `decoded`, up to the default `max_body_len` of `RequestLimits`
+    pub fn into_bytes(self) -> Result<Vec<u8>, BodyError> {
+        let mut response = self.decoded(RequestLimits::default().max_body_len)?;
+        Ok(response.body_mut().map(std::mem::take).unwrap_or_default())
+    }
+
+    /// The decoded body as text in the `charset` of its `Content-Type`, UTF-8 when none is
+    /// declared
+    pub fn into_string(self) -> Result<String, BodyError> {
+        let media_type = self.headers().get("Content-Type").and_then(|v| MediaType::parse(v).ok());
+        let charset = match media_type.as_ref().and_then(MediaType::charset) {
+            Some(label) => Charset::from_label(label)
+                .ok_or_else(|| CharsetError::Unsupported(label.to_owned()))?,
+            None => Charset::Utf8,
+        };
+        let body = self.into_bytes()?;
+        Ok(charset.decode(&body)?.into_owned())
+    }
+
+    /// The decoded body deserialized from JSON
+    #[cfg(feature = "config")]
+    pub fn json<T>(self) -> Result<T, BodyError>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let body = self.into_bytes()?;
+        serde_json::from_slice(&body).map_err(|err| BodyError::Json(err.to_string()))
+    }
+}
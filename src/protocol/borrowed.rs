@@ -0,0 +1,102 @@
+use super::request::{ascii_line, split_request_line, BufferLines};
+use super::{is_token, HttpVersion, Method, ParseRequestError, RequestLimits};
+
+/// A header borrowed from the buffer it was parsed from, for `parse_request_head_borrowed`
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct HeaderRef<'buf> {
+    pub name: &'buf str,
+    /// without surrounding whitespace, latin-1 only when `Leniency::latin1_header_values` allows
+    pub value: &'buf [u8],
+}
+
+/// Initializes the header slice handed to `parse_request_head_borrowed`
+pub const EMPTY_HEADER: HeaderRef<'static> = HeaderRef { name: "", value: b"" };
+
+/// A request head borrowed from the buffer it was parsed from
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct RequestHeadRef<'h, 'buf> {
+    pub method: Method,
+    /// as sent, its form checked against the method, see `RequestTarget` for the rest
+    pub target: &'buf str,
+    pub version: HttpVersion,
+    pub headers: &'h [HeaderRef<'buf>],
+}
+
+impl<'h, 'buf> RequestHeadRef<'h, 'buf> {
+    /// The value of the first header named `name`, ignoring case
+    pub fn header(&self, name: &str) -> Option<&'buf [u8]> {
+        self.headers.iter().find(|header| header.name.eq_ignore_ascii_case(name)).map(|h| h.value)
+    }
+}
+
+/// `parse_request_head_with` without allocating: the target and headers borrow from `buf`, and
+/// the headers are stored in `headers`, at most as many as it holds or `max_headers` allows.
+///
+/// Errors are those of the owned parser, only building them allocates.
+pub fn parse_request_head_borrowed<'h, 'buf>(
+    buf: &'buf [u8],
+    headers: &'h mut [HeaderRef<'buf>],
+    limits: &RequestLimits,
+) -> Result<Option<(RequestHeadRef<'h, 'buf>, usize)>, ParseRequestError> {
+    let mut lines = BufferLines::new(buf, limits);
+    let Some(line) = lines.next_line()? else {
+        return Ok(None);
+    };
+    let line = ascii_line(line)?;
+    let (method, target, version) = split_request_line(line, &limits.leniency)?;
+    check_target_form(line, method, target)?;
+
+    let max_headers = headers.len().min(limits.max_headers);
+    let mut count = 0;
+    loop {
+        let Some(line) = lines.next_line()? else {
+            return Ok(None);
+        };
+        if line.is_empty() {
+            let head = RequestHeadRef { method, target, version, headers: &headers[..count] };
+            return Ok(Some((head, lines.pos)));
+        }
+        if count == max_headers {
+            return Err(ParseRequestError::TooManyHeaders);
+        }
+        headers[count] = parse_header(line, limits.leniency.latin1_header_values)?;
+        count += 1;
+    }
+}
+
+/// The checks `RequestLine::parse_with` makes of the target's form, by its first characters
+fn check_target_form(line: &str, method: Method, target: &str) -> Result<(), ParseRequestError> {
+    if target.contains('#') {
+        return Err(ParseRequestError::RequestLine(target.to_owned()));
+    }
+    let suits_method = match target {
+        "*" => method == Method::OPTIONS,
+        _ if target.starts_with('/') || target.contains("://") => method != Method::CONNECT,
+        _ => method == Method::CONNECT,
+    };
+    match suits_method {
+        true => Ok(()),
+        false => Err(ParseRequestError::RequestLine(line.to_owned())),
+    }
+}
+
+/// `field-name ":" OWS field-value OWS`, like `Header::from_str`
+fn parse_header(line: &[u8], latin1: bool) -> Result<HeaderRef<'_>, ParseRequestError> {
+    if !latin1 || line.is_ascii() {
+        ascii_line(line)?;
+    } else if let Some(b) = line.iter().find(|b| b.is_ascii_control() && **b != b'\t') {
+        return Err(ParseRequestError::InvalidByte(*b));
+    }
+    let Some(colon) = line.iter().position(|b| *b == b':') else {
+        return Err(ParseRequestError::InvalidHeader(String::from_utf8_lossy(line).into_owned()));
+    };
+    let name = match std::str::from_utf8(&line[..colon]) {
+        Ok(name) if is_token(name) => name,
+        _ => {
+            let name = String::from_utf8_lossy(&line[..colon]).into_owned();
+            return Err(ParseRequestError::InvalidHeaderName(name));
+        }
+    };
+    // only spaces and tabs are left to trim, the rest of the whitespace is control characters
+    Ok(HeaderRef { name, value: line[colon + 1..].trim_ascii() })
+}
@@ -0,0 +1,88 @@
+use std::fmt::{Display, Formatter};
+
+use super::{Header, Headers};
+
+/// An authentication challenge of a `WWW-Authenticate` or `Proxy-Authenticate` header,
+/// `scheme param="value", ...`.
+///
+/// Parameter values are always sent as quoted strings, with control characters replaced by
+/// spaces.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Challenge {
+    scheme: String,
+    params: Vec<(String, String)>,
+}
+
+impl Challenge {
+    pub fn new(scheme: &str) -> Self {
+        Self { scheme: scheme.to_owned(), params: Vec::new() }
+    }
+
+    /// `Basic` (RFC 7617) asking for UTF-8 credentials
+    pub fn basic(realm: &str) -> Self {
+        Challenge::new("Basic").realm(realm).charset("UTF-8")
+    }
+
+    /// `Bearer` (RFC 6750), with `error` and friends for requests which carried a token
+    pub fn bearer(realm: &str) -> Self {
+        Challenge::new("Bearer").realm(realm)
+    }
+
+    pub fn realm(self, realm: &str) -> Self {
+        self.param("realm", realm)
+    }
+
+    pub fn charset(self, charset: &str) -> Self {
+        self.param("charset", charset)
+    }
+
+    /// e.g. `invalid_token` or `insufficient_scope`
+    pub fn error(self, error: &str) -> Self {
+        self.param("error", error)
+    }
+
+    pub fn error_description(self, description: &str) -> Self {
+        self.param("error_description", description)
+    }
+
+    /// space separated scopes the resource requires
+    pub fn scope(self, scope: &str) -> Self {
+        self.param("scope", scope)
+    }
+
+    /// Adds a parameter, or replaces the value of one with the same name
+    pub fn param(mut self, name: &str, value: &str) -> Self {
+        let value = value.chars().map(|c| if c.is_control() { ' ' } else { c }).collect();
+        match self.params.iter_mut().find(|(n, _)| n.eq_ignore_ascii_case(name)) {
+            Some(param) => param.1 = value,
+            None => self.params.push((name.to_owned(), value)),
+        }
+        self
+    }
+
+    pub fn scheme(&self) -> &str {
+        &self.scheme
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        let mut params = self.params.iter();
+        params.find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    /// Adds a `WWW-Authenticate` header, one per challenge a response offers
+    pub fn apply(&self, headers: &mut Headers) {
+        headers.push(Header::new("WWW-Authenticate", self));
+    }
+}
+
+impl Display for Challenge {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.scheme)?;
+        for (i, (name, value)) in self.params.iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            let escaped = value.replace('\\', "\\\\").replace('"', "\\\"");
+            write!(f, "{separator}{name}=\"{escaped}\"")?;
+        }
+        Ok(())
+    }
+}
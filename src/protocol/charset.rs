@@ -0,0 +1,151 @@
+use std::borrow::Cow;
+use std::fmt::{Display, Formatter};
+
+use super::{Headers, HttpVersion, MediaType, RawRequest, RawResponse, StatusCode, StatusLine};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CharsetError {
+    /// a charset label none of `Charset` stands for
+    Unsupported(String),
+    /// bytes which aren't valid in the charset, at this offset
+    Malformed(usize),
+    /// a character the charset can't encode
+    Unmappable(char),
+}
+
+impl Display for CharsetError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CharsetError::Unsupported(label) => write!(f, "unsupported charset: {label}"),
+            CharsetError::Malformed(offset) => write!(f, "malformed text at byte {offset}"),
+            CharsetError::Unmappable(c) => write!(f, "unmappable character {c:?}"),
+        }
+    }
+}
+
+/// Character encodings of text bodies
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Charset {
+    Utf8,
+    Ascii,
+    /// ISO-8859-1, each byte the code point of the same value
+    Latin1,
+}
+
+impl Charset {
+    /// The charset a label such as `UTF-8` or `latin1` names
+    pub fn from_label(label: &str) -> Option<Self> {
+        match label.trim().to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Charset::Utf8),
+            "us-ascii" | "ascii" => Some(Charset::Ascii),
+            "iso-8859-1" | "iso8859-1" | "latin1" | "l1" => Some(Charset::Latin1),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Charset::Utf8 => "utf-8",
+            Charset::Ascii => "us-ascii",
+            Charset::Latin1 => "iso-8859-1",
+        }
+    }
+
+    pub fn decode(self, bytes: &[u8]) -> Result<Cow<'_, str>, CharsetError> {
+        match self {
+            Charset::Utf8 => std::str::from_utf8(bytes)
+                .map(Cow::Borrowed)
+                .map_err(|err| CharsetError::Malformed(err.valid_up_to())),
+            Charset::Ascii => match bytes.iter().position(|b| !b.is_ascii()) {
+                Some(offset) => Err(CharsetError::Malformed(offset)),
+                None => Ok(Cow::Borrowed(std::str::from_utf8(bytes).expect("ASCII is UTF-8"))),
+            },
+            Charset::Latin1 if bytes.is_ascii() => {
+                Ok(Cow::Borrowed(std::str::from_utf8(bytes).expect("ASCII is UTF-8")))
+            }
+            Charset::Latin1 => Ok(Cow::Owned(bytes.iter().map(|b| char::from(*b)).collect())),
+        }
+    }
+
+    pub fn encode(self, text: &str) -> Result<Cow<'_, [u8]>, CharsetError> {
+        let max = match self {
+            Charset::Utf8 => return Ok(Cow::Borrowed(text.as_bytes())),
+            _ if text.is_ascii() => return Ok(Cow::Borrowed(text.as_bytes())),
+            Charset::Ascii => 0x7f,
+            Charset::Latin1 => 0xff,
+        };
+        let encode = |c: char| u8::try_from(c).ok().filter(|b| *b <= max);
+        let bytes = text.chars().map(|c| encode(c).ok_or(CharsetError::Unmappable(c)));
+        bytes.collect::<Result<Vec<_>, _>>().map(Cow::Owned)
+    }
+
+    /// The supported charset the `Accept-Charset` header ranks highest, UTF-8 when it's
+    /// missing or ranks none of them
+    pub fn preferred(headers: &Headers) -> Self {
+        let accept = headers.get_all("Accept-Charset").collect::<Vec<_>>().join(",");
+        if accept.trim().is_empty() {
+            return Charset::Utf8;
+        }
+        let ranked = accept.split(',').map(|item| {
+            let mut params = item.split(';');
+            let label = params.next().unwrap_or_default().trim();
+            let q = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            (label, q)
+        });
+        let ranked = ranked.collect::<Vec<_>>();
+        // a listed charset takes its own rank, any other that of `*`, if given
+        let quality = |charset: Charset| {
+            let listed =
+                ranked.iter().find(|(label, _)| Charset::from_label(label) == Some(charset));
+            let wildcard = || ranked.iter().find(|(label, _)| *label == "*");
+            listed.or_else(wildcard).map_or(0.0, |(_, q)| *q)
+        };
+        let mut best = (Charset::Utf8, 0.0);
+        for charset in [Charset::Utf8, Charset::Latin1, Charset::Ascii] {
+            let q = quality(charset);
+            if q > best.1 {
+                best = (charset, q);
+            }
+        }
+        best.0
+    }
+}
+
+impl RawRequest {
+    /// The body decoded per the `charset` of its `Content-Type`, UTF-8 when none is declared
+    pub fn text_body(&self) -> Result<Cow<'_, str>, CharsetError> {
+        let media_type = self.media_type();
+        let charset = match media_type.as_ref().and_then(MediaType::charset) {
+            Some(label) => Charset::from_label(label)
+                .ok_or_else(|| CharsetError::Unsupported(label.to_owned()))?,
+            None => Charset::Utf8,
+        };
+        charset.decode(self.body.as_deref().unwrap_or_default())
+    }
+}
+
+impl RawResponse {
+    /// A text response of `media_type`, e.g. `text/plain`, encoded in its `charset` parameter,
+    /// which is added as `utf-8` when missing
+    pub fn text(
+        status: StatusCode,
+        media_type: MediaType,
+        text: &str,
+    ) -> Result<Self, CharsetError> {
+        let (charset, media_type) = match media_type.charset() {
+            Some(label) => {
+                let charset = Charset::from_label(label)
+                    .ok_or_else(|| CharsetError::Unsupported(label.to_owned()))?;
+                (charset, media_type)
+            }
+            None => (Charset::Utf8, media_type.with_param("charset", Charset::Utf8.name())),
+        };
+        let body = charset.encode(text)?.into_owned();
+        let mut headers = Headers::empty();
+        headers.set("Content-Type", media_type.to_string());
+        Ok(RawResponse::new(StatusLine::new(HttpVersion::Http1_1, status), headers, Some(body)))
+    }
+}
@@ -0,0 +1,95 @@
+use std::io;
+
+use bytes::{Buf, BufMut, BytesMut};
+
+use super::request::{body_len, parse_request_head_with};
+use super::response::parse_chunked_body;
+use super::{Extensions, ParseRequestError, RawRequest, RawResponse, RequestLimits};
+
+/// Server side of HTTP/1.1 on a buffer: decodes requests out of the bytes read so far and
+/// encodes responses into the bytes to write, for connection handling other than the server's.
+///
+/// With the `codec` feature it implements the `Decoder` and `Encoder` traits of `tokio-util`,
+/// to be used with `Framed`.
+#[derive(Debug, Clone, Default)]
+pub struct HttpServerCodec {
+    limits: RequestLimits,
+}
+
+impl HttpServerCodec {
+    pub fn new(limits: RequestLimits) -> Self {
+        Self { limits }
+    }
+
+    /// The next complete request taken off the front of `buf`, `None` until there is one
+    pub fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<RawRequest>, ParseRequestError> {
+        let Some((request_line, headers, head_len)) = parse_request_head_with(buf, &self.limits)?
+        else {
+            return Ok(None);
+        };
+        let mut headers = headers;
+        if let Some(coding) = headers.get_all("Transfer-Encoding").flat_map(|v| v.split(',')).last()
+        {
+            // the server closes the connection after such requests, which a codec can't do
+            if !coding.trim().eq_ignore_ascii_case("chunked") || headers.contains("Content-Length")
+            {
+                let codings = headers.get_all("Transfer-Encoding").collect::<Vec<_>>().join(", ");
+                let message = format!("Transfer-Encoding: {codings}");
+                return Err(ParseRequestError::InvalidHeader(message));
+            }
+            let Some((body, body_len)) = parse_chunked_body(&buf[head_len..], &self.limits)? else {
+                return Ok(None);
+            };
+            buf.advance(head_len + body_len);
+            headers.retain(|field, _| !field.eq_ignore_ascii_case("Transfer-Encoding"));
+            headers.set("Content-Length", body.len().to_string());
+            let body = Some(body);
+            return Ok(Some(RawRequest {
+                request_line,
+                headers,
+                body,
+                extensions: Extensions::new(),
+            }));
+        }
+
+        let body_len = body_len(&headers, &self.limits)?;
+        let message_len = head_len + body_len.unwrap_or(0);
+        if buf.len() < message_len {
+            buf.reserve(message_len - buf.len());
+            return Ok(None);
+        }
+
+        let message = buf.split_to(message_len);
+        let body = body_len.map(|_| message[head_len..].to_vec());
+        Ok(Some(RawRequest { request_line, headers, body, extensions: Extensions::new() }))
+    }
+
+    /// Appends `response` to `buf`, which can't hold a streamed body
+    pub fn encode(&mut self, response: RawResponse, buf: &mut BytesMut) -> io::Result<()> {
+        if response.is_streamed() {
+            let message = "streamed bodies can't be encoded into a buffer";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+        buf.put_slice(&response.into_vec());
+        Ok(())
+    }
+}
+
+#[cfg(feature = "codec")]
+impl tokio_util::codec::Decoder for HttpServerCodec {
+    type Item = RawRequest;
+    type Error = ParseRequestError;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<RawRequest>, ParseRequestError> {
+        HttpServerCodec::decode(self, buf)
+    }
+}
+
+#[cfg(feature = "codec")]
+impl tokio_util::codec::Encoder<RawResponse> for HttpServerCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, response: RawResponse, buf: &mut BytesMut) -> io::Result<()> {
+        HttpServerCodec::encode(self, response, buf)
+    }
+}
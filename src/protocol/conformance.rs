@@ -0,0 +1,266 @@
+use bytes::BytesMut;
+use proptest::collection::vec;
+use proptest::option;
+use proptest::prelude::*;
+use proptest::sample::select;
+
+use super::*;
+
+const TCHAR: &[u8] =
+    b"!#$%&'*+-.^_`|~0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const PCHAR: &[u8] =
+    b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789-._~!$&'()*+,;=:@%";
+
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(future)
+}
+
+fn string(alphabet: &'static [u8], len: std::ops::RangeInclusive<usize>) -> BoxedStrategy<String> {
+    vec(select(alphabet), len).prop_map(|bytes| bytes.into_iter().map(char::from).collect()).boxed()
+}
+
+prop_compose! {
+    fn generated_request()(
+        method in select(vec![
+            Method::GET,
+            Method::HEAD,
+            Method::POST,
+            Method::PUT,
+            Method::DELETE,
+            Method::PATCH,
+            Method::OPTIONS,
+            Method::TRACE,
+        ]),
+        segments in vec(string(PCHAR, 0..=8), 0..4),
+        query in option::of(string(PCHAR, 0..=12)),
+        version in select(vec![HttpVersion::Http1_0, HttpVersion::Http1_1]),
+        // visible characters with single spaces between them, which survive trimming
+        fields in vec((string(TCHAR, 1..=10), vec(string(&TCHAR[..60], 1..=6), 1..=3)), 0..6),
+        body in option::of(vec(any::<u8>(), 0..64)),
+    ) -> RawRequest {
+        let mut target = format!("/{}", segments.join("/"));
+        if let Some(query) = query {
+            target.push('?');
+            target.push_str(&query);
+        }
+        let request_line = RequestLine { method, target: RequestTarget::origin(&target), version };
+        let mut headers = Headers::empty();
+        for (field, words) in fields {
+            headers.push(Header::new(format!("X-{field}"), words.join(" ")));
+        }
+        if let Some(ref body) = body {
+            headers.set("Content-Length", body.len().to_string());
+        }
+        RawRequest { request_line, headers, body, extensions: Extensions::new() }
+    }
+}
+
+/// Every request of a message, decoded by the codec from `message` arriving in pieces split at
+/// `splits`
+fn decode_in_pieces(
+    message: &[u8],
+    splits: &[usize],
+) -> Result<Vec<RawRequest>, ParseRequestError> {
+    let mut codec = HttpServerCodec::default();
+    let mut buf = BytesMut::new();
+    let mut splits = splits.iter().map(|split| split % (message.len() + 1)).collect::<Vec<_>>();
+    splits.push(message.len());
+    splits.sort_unstable();
+    let (mut decoded, mut start) = (Vec::new(), 0);
+    for end in splits {
+        buf.extend_from_slice(&message[start..end]);
+        start = end;
+        while let Some(request) = codec.decode(&mut buf)? {
+            decoded.push(request);
+        }
+    }
+    assert!(buf.is_empty(), "left undecoded: {buf:?}");
+    Ok(decoded)
+}
+
+proptest! {
+    /// Generated requests parse back to what was serialized
+    #[test]
+    fn test_generated_requests_round_trip(request in generated_request()) {
+        let (line, head) = (request.request_line.to_string(), request.headers.to_http_message());
+        let body = request.body.clone();
+        let message = request.into_vec();
+
+        let parsed = block_on(read_http_request(&mut &message[..])).unwrap();
+        prop_assert_eq!(&line, &parsed.request_line.to_string());
+        prop_assert_eq!(&head, &parsed.headers.to_http_message());
+        prop_assert_eq!(&body, &parsed.body);
+
+        let (parsed_line, parsed_headers, len) = parse_request_head(&message).unwrap().unwrap();
+        prop_assert_eq!(&line, &parsed_line.to_string());
+        prop_assert_eq!(&head, &parsed_headers.to_http_message());
+        prop_assert_eq!(message.len() - body.as_ref().map_or(0, Vec::len), len);
+
+        let decoded = decode_in_pieces(&message, &[message.len() / 2]).unwrap();
+        prop_assert_eq!(1, decoded.len());
+        prop_assert_eq!(&body, &decoded[0].body);
+    }
+
+    /// `Content-Length` values as one list or repeated fields, some signed, padded or off by
+    /// some: the body is framed only when every value is the same plain number (RFC 9112
+    /// section 6.3), otherwise the request is rejected
+    #[test]
+    fn test_content_length_framing(
+        len in 0usize..16,
+        values in vec((select(vec!["", "0", "+", "-", "0x"]), 0usize..3), 1..4),
+        listed in any::<bool>(),
+    ) {
+        // the first value matches the body, the others are off by their second element
+        let framed = values.iter().enumerate().all(|(i, (prefix, off))| {
+            ["", "0"].contains(prefix) && (i == 0 || *off == 0)
+        });
+        let values = values
+            .iter()
+            .enumerate()
+            .map(|(i, (prefix, off))| format!("{prefix}{}", if i == 0 { len } else { len + off }))
+            .collect::<Vec<_>>();
+        let fields = match listed {
+            true => format!("Content-Length: {}\r\n", values.join(", ")),
+            false => values.iter().map(|value| format!("Content-Length: {value}\r\n")).collect(),
+        };
+        let body = "b".repeat(len);
+        let message = format!("POST / HTTP/1.1\r\n{fields}\r\n{body}");
+
+        let read = block_on(read_http_request(&mut message.as_bytes()));
+        let decoded = decode_in_pieces(message.as_bytes(), &[]);
+        if framed {
+            prop_assert_eq!(Some(body.clone().into_bytes()), read.unwrap().body);
+            prop_assert_eq!(Some(body.into_bytes()), decoded.unwrap().remove(0).body);
+        } else {
+            prop_assert!(matches!(read, Err(ParseRequestError::InvalidHeader(_))), "{read:?}");
+            prop_assert!(
+                matches!(decoded, Err(ParseRequestError::InvalidHeader(_))),
+                "{decoded:?}"
+            );
+        }
+    }
+
+    /// Chunked bodies cut into chunks of any size, with extensions and trailers, decoded by the
+    /// codec from any split of the message, the request behind them included
+    #[test]
+    fn test_chunked_bodies_through_codec(
+        body in vec(any::<u8>(), 0..200),
+        sizes in vec(1usize..64, 1..8),
+        extension in option::of(string(TCHAR, 1..=8)),
+        trailer in option::of(string(TCHAR, 1..=8)),
+        splits in vec(any::<usize>(), 0..6),
+    ) {
+        let mut message = b"POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+        let mut rest = &body[..];
+        for size in sizes.iter().cycle() {
+            if rest.is_empty() {
+                break;
+            }
+            let (chunk, remaining) = rest.split_at((*size).min(rest.len()));
+            message.extend_from_slice(format!("{:X}", chunk.len()).as_bytes());
+            if let Some(ref extension) = extension {
+                message.extend_from_slice(format!(";{extension}=1").as_bytes());
+            }
+            message.extend_from_slice(b"\r\n");
+            message.extend_from_slice(chunk);
+            message.extend_from_slice(b"\r\n");
+            rest = remaining;
+        }
+        message.extend_from_slice(b"0\r\n");
+        if let Some(ref trailer) = trailer {
+            message.extend_from_slice(format!("X-{trailer}: 1\r\n").as_bytes());
+        }
+        message.extend_from_slice(b"\r\nGET /next HTTP/1.1\r\n\r\n");
+
+        let decoded = decode_in_pieces(&message, &splits).unwrap();
+        prop_assert_eq!(2, decoded.len());
+        prop_assert_eq!(Some(&body), decoded[0].body.as_ref());
+        let length = body.len().to_string();
+        prop_assert_eq!(Some(length.as_str()), decoded[0].headers.get("Content-Length"));
+        prop_assert!(decoded[0].headers.get("Transfer-Encoding").is_none());
+        prop_assert_eq!("GET /next HTTP/1.1", decoded[1].request_line.to_string());
+    }
+}
+
+/// Messages the RFC allows, parsed strictly
+const VALID: &[&str] = &[
+    "GET / HTTP/1.1\r\nHost: a\r\n\r\n",
+    "GET /p?q=1&r HTTP/1.0\r\n\r\n",
+    "GET http://example.com/p?q HTTP/1.1\r\nHost: example.com\r\n\r\n",
+    "OPTIONS * HTTP/1.1\r\nHost: a\r\n\r\n",
+    "CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n",
+    // optional whitespace around field values, empty values
+    "GET / HTTP/1.1\r\nHost:a\r\nX-Empty:\r\nX-Ows: \t v \t\r\n\r\n",
+    "POST / HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc",
+];
+
+/// Messages the RFC rejects, with the error a strict parser rejects them with
+fn invalid() -> Vec<(&'static str, ParseRequestError)> {
+    let request_line = |line: &str| ParseRequestError::RequestLine(line.to_owned());
+    vec![
+        ("GET / HTTP/1.1\nHost: a\n\n", ParseRequestError::BareLineFeed),
+        ("GET  / HTTP/1.1\r\n\r\n", request_line("GET  / HTTP/1.1")),
+        ("GET / HTTP/1.1 \r\n\r\n", request_line("GET / HTTP/1.1 ")),
+        ("GET\t/ HTTP/1.1\r\n\r\n", request_line("GET\t/ HTTP/1.1")),
+        ("GET / HTTP/1.10\r\n\r\n", request_line("GET / HTTP/1.10")),
+        ("GET / http/1.1\r\n\r\n", request_line("GET / http/1.1")),
+        ("GET / HTTP/2.0\r\n\r\n", ParseRequestError::UnknownHttpVersion("HTTP/2.0".to_owned())),
+        ("get / HTTP/1.1\r\n\r\n", ParseRequestError::UnknownMethod("get".to_owned())),
+        ("GET /a#f HTTP/1.1\r\n\r\n", request_line("/a#f")),
+        ("GET * HTTP/1.1\r\n\r\n", request_line("GET * HTTP/1.1")),
+        ("CONNECT /a HTTP/1.1\r\n\r\n", request_line("CONNECT /a HTTP/1.1")),
+        // whitespace between the field name and colon (section 5.1)
+        (
+            "GET / HTTP/1.1\r\nHost : a\r\n\r\n",
+            ParseRequestError::InvalidHeaderName("Host ".to_owned()),
+        ),
+        // obsolete line folding (section 5.2)
+        (
+            "GET / HTTP/1.1\r\nX-A: b\r\n c\r\n\r\n",
+            ParseRequestError::InvalidHeader(" c".to_owned()),
+        ),
+        ("GET / HTTP/1.1\r\nX-A: b\0\r\n\r\n", ParseRequestError::InvalidByte(0)),
+        ("GET / HTTP/1.1\r\nX-A: b\rc\r\n\r\n", ParseRequestError::InvalidByte(b'\r')),
+    ]
+}
+
+/// Both `Content-Length` and `Transfer-Encoding` (RFC 9112 section 6.3): chunked wins when
+/// reading from a connection, which the server then closes; the codec, which can't close it,
+/// rejects the message
+#[tokio::test]
+pub async fn test_content_length_with_transfer_encoding() {
+    let message = "POST / HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n\
+                   5\r\nhello\r\n0\r\n\r\n";
+    let request = read_http_request(&mut message.as_bytes()).await.unwrap();
+    assert_eq!(Some(&b"hello"[..]), request.body.as_deref());
+    assert_eq!(vec!["5"], request.headers.get_all("Content-Length").collect::<Vec<_>>());
+
+    let decoded = decode_in_pieces(message.as_bytes(), &[]);
+    assert!(matches!(decoded, Err(ParseRequestError::InvalidHeader(_))), "{decoded:?}");
+}
+
+/// Message syntax of RFC 9112, the parsers agree on each message
+#[tokio::test]
+pub async fn test_rfc9112_corpus() {
+    for message in VALID {
+        let request = read_http_request(&mut message.as_bytes()).await;
+        assert!(request.is_ok(), "{message:?}: {request:?}");
+        let head = parse_request_head(message.as_bytes());
+        assert!(matches!(head, Ok(Some(_))), "{message:?}: {head:?}");
+        let mut headers = [EMPTY_HEADER; 8];
+        let limits = RequestLimits::default();
+        let head = parse_request_head_borrowed(message.as_bytes(), &mut headers, &limits);
+        assert!(matches!(head, Ok(Some(_))), "{message:?}: {head:?}");
+    }
+
+    for (message, expected) in invalid() {
+        let request = read_http_request(&mut message.as_bytes()).await;
+        assert_eq!(Some(&expected), request.as_ref().err(), "{message:?}");
+        let head = parse_request_head(message.as_bytes());
+        assert_eq!(Some(&expected), head.as_ref().err(), "{message:?}");
+        let mut headers = [EMPTY_HEADER; 8];
+        let limits = RequestLimits::default();
+        let head = parse_request_head_borrowed(message.as_bytes(), &mut headers, &limits);
+        assert_eq!(Some(&expected), head.as_ref().err(), "{message:?}");
+    }
+}
@@ -0,0 +1,122 @@
+use super::inflate::{DIST_BASE, DIST_EXTRA, LENGTH_BASE, LENGTH_EXTRA};
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// match candidates looked at for each position, more compress better but slower
+const MAX_CHAIN: usize = 32;
+const HASH_BITS: u32 = 15;
+
+struct BitWriter {
+    out: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    /// Writes the low `n` bits of `value`, least significant first
+    fn put(&mut self, value: u32, n: u32) {
+        self.buffer |= value << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Writes a Huffman code, which is stored most significant bit first
+    fn put_code(&mut self, code: u32, len: u32) {
+        self.put(code.reverse_bits() >> (32 - len), len);
+    }
+
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.put(0, 8 - self.count);
+        }
+    }
+
+    fn literal(&mut self, symbol: u16) {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.put_code(0x30 + symbol, 8),
+            144..=255 => self.put_code(0x190 + symbol - 144, 9),
+            256..=279 => self.put_code(symbol - 256, 7),
+            _ => self.put_code(0xC0 + symbol - 280, 8),
+        }
+    }
+
+    fn copy(&mut self, len: usize, distance: usize) {
+        let index = LENGTH_BASE.iter().rposition(|&base| base as usize <= len).unwrap();
+        self.literal(257 + index as u16);
+        self.put((len - LENGTH_BASE[index] as usize) as u32, LENGTH_EXTRA[index] as u32);
+        let index = DIST_BASE.iter().rposition(|&base| base as usize <= distance).unwrap();
+        self.put_code(index as u32, 5);
+        self.put((distance - DIST_BASE[index] as usize) as u32, DIST_EXTRA[index] as u32);
+    }
+}
+
+fn hash(data: &[u8]) -> usize {
+    let value = u32::from_le_bytes([data[0], data[1], data[2], 0]);
+    (value.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+}
+
+/// Makes `position` the latest candidate for matches of the bytes it starts
+fn insert(data: &[u8], position: usize, head: &mut [usize], previous: &mut [usize]) {
+    if position + MIN_MATCH <= data.len() {
+        let slot = &mut head[hash(&data[position..])];
+        previous[position] = *slot;
+        *slot = position;
+    }
+}
+
+/// Compresses `data` into one fixed Huffman block followed by a sync flush, the empty stored
+/// block ending in `00 00 FF FF`. No match reaches back further than `max_distance` bytes.
+pub(crate) fn deflate_sync(data: &[u8], max_distance: usize) -> Vec<u8> {
+    let mut bits = BitWriter { out: Vec::with_capacity(data.len() / 2 + 8), buffer: 0, count: 0 };
+    // not the last block, fixed codes
+    bits.put(0b010, 3);
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut previous = vec![usize::MAX; data.len()];
+    let mut position = 0;
+    while position < data.len() {
+        let mut best = (0, 0);
+        if position + MIN_MATCH <= data.len() {
+            let mut candidate = head[hash(&data[position..])];
+            let longest = (data.len() - position).min(MAX_MATCH);
+            for _ in 0..MAX_CHAIN {
+                if candidate == usize::MAX || position - candidate > max_distance {
+                    break;
+                }
+                let len = data[candidate..]
+                    .iter()
+                    .zip(&data[position..position + longest])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best.0 {
+                    best = (len, position - candidate);
+                }
+                candidate = previous[candidate];
+            }
+        }
+
+        if best.0 >= MIN_MATCH {
+            bits.copy(best.0, best.1);
+            for skipped in position..position + best.0 {
+                insert(data, skipped, &mut head, &mut previous);
+            }
+            position += best.0;
+        } else {
+            bits.literal(data[position] as u16);
+            insert(data, position, &mut head, &mut previous);
+            position += 1;
+        }
+    }
+    bits.literal(256);
+
+    // sync flush
+    bits.put(0b000, 3);
+    bits.align();
+    bits.out.extend_from_slice(&[0x00, 0x00, 0xFF, 0xFF]);
+    bits.out
+}
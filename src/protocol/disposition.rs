@@ -0,0 +1,216 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use super::media::{unquote, write_param};
+use super::{is_token, percent_decode_bytes, percent_encode, Charset, EncodeSet};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ContentDispositionError(pub String);
+
+impl Display for ContentDispositionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid content disposition: {}", self.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DispositionType {
+    /// shown by the browser where it can
+    Inline,
+    /// saved as a download, also what unknown types are taken for (RFC 6266, section 4.2)
+    Attachment,
+    /// a field of a `multipart/form-data` body (RFC 7578)
+    FormData,
+}
+
+impl DispositionType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DispositionType::Inline => "inline",
+            DispositionType::Attachment => "attachment",
+            DispositionType::FormData => "form-data",
+        }
+    }
+}
+
+/// A `Content-Disposition` header of a response (RFC 6266) or of a multipart part (RFC 7578).
+///
+/// Parameter names are lowercased and values stored without quotes. Of `filename` and
+/// `filename*` only the name they stand for is kept, preferring the latter.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ContentDisposition {
+    kind: DispositionType,
+    filename: Option<String>,
+    params: Vec<(String, String)>,
+}
+
+impl ContentDisposition {
+    pub fn new(kind: DispositionType) -> Self {
+        Self { kind, filename: None, params: Vec::new() }
+    }
+
+    pub fn inline() -> Self {
+        Self::new(DispositionType::Inline)
+    }
+
+    /// A download saved as `filename`
+    pub fn attachment(filename: &str) -> Self {
+        Self::new(DispositionType::Attachment).with_filename(filename)
+    }
+
+    /// The form field `name`
+    pub fn form_data(name: &str) -> Self {
+        Self::new(DispositionType::FormData).with_param("name", name)
+    }
+
+    /// Sent as `filename` and, for names which aren't ASCII, also as `filename*` in UTF-8,
+    /// except in form data, where it's sent as is
+    pub fn with_filename(mut self, filename: &str) -> Self {
+        self.filename = Some(filename.to_owned());
+        self
+    }
+
+    /// Adds a parameter, or replaces the value of one with the same name
+    pub fn with_param(mut self, name: &str, value: &str) -> Self {
+        let name = name.to_ascii_lowercase();
+        if name == "filename" || name == "filename*" {
+            return self.with_filename(value);
+        }
+        match self.params.iter_mut().find(|(n, _)| *n == name) {
+            Some(param) => param.1 = value.to_owned(),
+            None => self.params.push((name, value.to_owned())),
+        }
+        self
+    }
+
+    pub fn kind(&self) -> DispositionType {
+        self.kind
+    }
+
+    pub fn is_attachment(&self) -> bool {
+        self.kind == DispositionType::Attachment
+    }
+
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    /// The name of a form field
+    pub fn name(&self) -> Option<&str> {
+        self.param("name")
+    }
+
+    /// The value of the parameter `name`, compared case-insensitively
+    pub fn param(&self, name: &str) -> Option<&str> {
+        if name.eq_ignore_ascii_case("filename") || name.eq_ignore_ascii_case("filename*") {
+            return self.filename();
+        }
+        let mut params = self.params.iter();
+        params.find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    pub fn parse(s: &str) -> Result<Self, ContentDispositionError> {
+        let invalid = || ContentDispositionError(s.to_owned());
+        let (kind, mut rest) = s.split_once(';').unwrap_or((s, ""));
+        let kind = kind.trim_matches([' ', '\t']);
+        let kind = match kind.to_ascii_lowercase().as_str() {
+            "inline" => DispositionType::Inline,
+            "form-data" => DispositionType::FormData,
+            _ if is_token(kind) => DispositionType::Attachment,
+            _ => return Err(invalid()),
+        };
+
+        let mut disposition = Self::new(kind);
+        let mut extended = None;
+        loop {
+            rest = rest.trim_start_matches([' ', '\t', ';']);
+            if rest.is_empty() {
+                break;
+            }
+            let (name, after) = rest.split_once('=').ok_or_else(invalid)?;
+            let name = name.trim_end_matches([' ', '\t']).to_ascii_lowercase();
+            if !is_token(&name) {
+                return Err(invalid());
+            }
+            let after = after.trim_start_matches([' ', '\t']);
+            let (value, after) = match after.strip_prefix('"') {
+                Some(quoted) => unquote(quoted).ok_or_else(invalid)?,
+                None => {
+                    let end = after.find(';').unwrap_or(after.len());
+                    (after[..end].trim_end_matches([' ', '\t']).to_owned(), &after[end..])
+                }
+            };
+            match name.as_str() {
+                // an ext-value in a charset other than the two required ones is ignored
+                "filename*" => extended = extended.or(decode_ext_value(&value)),
+                "filename" if disposition.filename.is_none() => disposition.filename = Some(value),
+                "filename" => {}
+                _ if disposition.param(&name).is_none() => disposition.params.push((name, value)),
+                _ => {}
+            }
+            rest = after;
+        }
+        if extended.is_some() {
+            disposition.filename = extended;
+        }
+        Ok(disposition)
+    }
+}
+
+/// `charset'language'percent-encoded` (RFC 8187), in UTF-8 or ISO-8859-1
+fn decode_ext_value(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let (charset, _language, encoded) = (parts.next()?, parts.next()?, parts.next()?);
+    let charset = match Charset::from_label(charset)? {
+        Charset::Ascii => Charset::Utf8,
+        charset => charset,
+    };
+    let bytes = percent_decode_bytes(encoded).ok()?;
+    charset.decode(&bytes).ok().map(|decoded| decoded.into_owned())
+}
+
+/// Percent-encodes what would end a quoted name, as browsers do
+fn escape_form_data(value: &str) -> String {
+    value.replace('"', "%22").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+impl Display for ContentDisposition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.kind.as_str())?;
+        if self.kind == DispositionType::FormData {
+            // quoted even when a token, as browsers and servers expect in form data
+            for (name, value) in &self.params {
+                write!(f, "; {name}=\"{}\"", escape_form_data(value))?;
+            }
+            if let Some(ref filename) = self.filename {
+                write!(f, "; filename=\"{}\"", escape_form_data(filename))?;
+            }
+            return Ok(());
+        }
+
+        for (name, value) in &self.params {
+            write_param(f, name, value)?;
+        }
+        if let Some(ref filename) = self.filename {
+            // recipients without RFC 8187 support see the name with `_` for what isn't ASCII
+            let fallback: String = filename
+                .chars()
+                .map(|c| if c.is_ascii() && !c.is_ascii_control() { c } else { '_' })
+                .collect();
+            write_param(f, "filename", &fallback)?;
+            if fallback != *filename {
+                let encoded = percent_encode(filename, EncodeSet::ExtValue);
+                write!(f, "; filename*=UTF-8''{encoded}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ContentDisposition {
+    type Err = ContentDispositionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ContentDisposition::parse(s)
+    }
+}
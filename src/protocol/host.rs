@@ -0,0 +1,252 @@
+use std::fmt::{Display, Formatter};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use super::RawRequest;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum HostError {
+    /// the request has no `Host` header
+    Missing,
+    Invalid(String),
+}
+
+impl Display for HostError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HostError::Missing => write!(f, "missing host"),
+            HostError::Invalid(host) => write!(f, "invalid host: {host}"),
+        }
+    }
+}
+
+/// Name part of a `Host`
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum HostName {
+    /// lowercase, without a trailing dot, non-ASCII labels punycode encoded
+    Domain(String),
+    Ip(IpAddr),
+}
+
+/// `host[:port]` as in the `Host` header or a URI authority, with IPv6 literals in brackets.
+///
+/// Domain names are validated as LDH labels and compared case-insensitively. With the `idna`
+/// feature, Unicode labels are lowercased and converted to their `xn--` form, without the
+/// further mappings of UTS #46; without it they are rejected.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Host {
+    name: HostName,
+    port: Option<u16>,
+}
+
+impl Host {
+    pub fn parse(s: &str) -> Result<Self, HostError> {
+        let invalid = || HostError::Invalid(s.to_owned());
+        let (name, port) = match s.strip_prefix('[') {
+            Some(rest) => {
+                let (literal, rest) = rest.split_once(']').ok_or_else(invalid)?;
+                let ip = literal.parse::<Ipv6Addr>().map_err(|_| invalid())?;
+                let port = match rest {
+                    "" => None,
+                    _ => rest.strip_prefix(':').ok_or_else(invalid)?.into(),
+                };
+                (HostName::Ip(IpAddr::V6(ip)), port)
+            }
+            None => {
+                let (name, port) = match s.rsplit_once(':') {
+                    Some((name, port)) => (name, Some(port)),
+                    None => (s, None),
+                };
+                (domain_or_ipv4(name).ok_or_else(invalid)?, port)
+            }
+        };
+
+        let port = match port {
+            // an empty port is allowed by the URI grammar and means the default one
+            None | Some("") => None,
+            Some(port) if port.len() <= 5 && port.bytes().all(|b| b.is_ascii_digit()) => {
+                Some(port.parse::<u16>().map_err(|_| invalid())?)
+            }
+            Some(_) => return Err(invalid()),
+        };
+        Ok(Self { name, port })
+    }
+
+    pub fn name(&self) -> &HostName {
+        &self.name
+    }
+
+    /// The domain or IP address as written in a URI, IPv6 literals without brackets
+    pub fn hostname(&self) -> String {
+        match self.name {
+            HostName::Domain(ref domain) => domain.clone(),
+            HostName::Ip(ip) => ip.to_string(),
+        }
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    pub fn port_or(&self, default: u16) -> u16 {
+        self.port.unwrap_or(default)
+    }
+
+    pub fn is_ip(&self) -> bool {
+        matches!(self.name, HostName::Ip(_))
+    }
+
+    /// Whether `name`, without a port, names this host, ignoring case and a trailing dot
+    pub fn matches(&self, name: &str) -> bool {
+        match (&self.name, domain_or_ipv4(name)) {
+            (own, Some(other)) => *own == other,
+            (HostName::Ip(IpAddr::V6(ip)), None) => {
+                let literal =
+                    name.strip_prefix('[').and_then(|n| n.strip_suffix(']')).unwrap_or(name);
+                literal.parse::<Ipv6Addr>().is_ok_and(|other| other == *ip)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Display for Host {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.name {
+            HostName::Domain(ref domain) => write!(f, "{domain}")?,
+            HostName::Ip(IpAddr::V4(ip)) => write!(f, "{ip}")?,
+            HostName::Ip(IpAddr::V6(ip)) => write!(f, "[{ip}]")?,
+        }
+        match self.port {
+            Some(port) => write!(f, ":{port}"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FromStr for Host {
+    type Err = HostError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Host::parse(s)
+    }
+}
+
+impl RawRequest {
+    /// The parsed `Host` header
+    pub fn host(&self) -> Result<Host, HostError> {
+        let mut hosts = self.headers.get_all("Host");
+        match (hosts.next(), hosts.next()) {
+            (Some(host), None) => Host::parse(host),
+            (None, _) => Err(HostError::Missing),
+            // a request with several hosts is ambiguous (RFC 9112, section 3.2)
+            (Some(host), Some(_)) => Err(HostError::Invalid(host.to_owned())),
+        }
+    }
+}
+
+/// A dotted-quad IPv4 address, or a domain name when the last label isn't numeric
+fn domain_or_ipv4(name: &str) -> Option<HostName> {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    let last = name.rsplit('.').next()?;
+    if !last.is_empty() && last.bytes().all(|b| b.is_ascii_digit()) {
+        return name.parse::<Ipv4Addr>().ok().map(|ip| HostName::Ip(IpAddr::V4(ip)));
+    }
+    if name.is_empty() || name.len() > 253 {
+        return None;
+    }
+    let labels = name.split('.').map(label).collect::<Option<Vec<_>>>()?;
+    let domain = labels.join(".");
+    (domain.len() <= 253).then_some(HostName::Domain(domain))
+}
+
+/// A lowercase letter-digit-hyphen label of at most 63 characters
+fn label(label: &str) -> Option<String> {
+    let label = match label.is_ascii() {
+        true => label.to_ascii_lowercase(),
+        false => idna_label(label)?,
+    };
+    let valid = (1..=63).contains(&label.len())
+        && !label.starts_with('-')
+        && !label.ends_with('-')
+        && label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-');
+    valid.then_some(label)
+}
+
+#[cfg(feature = "idna")]
+fn idna_label(label: &str) -> Option<String> {
+    let lowercase = label.chars().flat_map(char::to_lowercase).collect::<String>();
+    punycode_encode(&lowercase).map(|encoded| format!("xn--{encoded}"))
+}
+
+#[cfg(not(feature = "idna"))]
+fn idna_label(_label: &str) -> Option<String> {
+    None
+}
+
+/// Punycode (RFC 3492) of one label, without the `xn--` prefix
+#[cfg(feature = "idna")]
+pub(crate) fn punycode_encode(input: &str) -> Option<String> {
+    const BASE: u32 = 36;
+    const T_MIN: u32 = 1;
+    const T_MAX: u32 = 26;
+
+    fn adapt(delta: u32, points: u32, first: bool) -> u32 {
+        let mut delta = if first { delta / 700 } else { delta / 2 };
+        delta += delta / points;
+        let mut k = 0;
+        while delta > ((BASE - T_MIN) * T_MAX) / 2 {
+            delta /= BASE - T_MIN;
+            k += BASE;
+        }
+        k + (BASE - T_MIN + 1) * delta / (delta + 38)
+    }
+    fn digit(d: u32) -> char {
+        char::from(if d < 26 { b'a' + d as u8 } else { b'0' + (d - 26) as u8 })
+    }
+
+    let chars = input.chars().map(u32::from).collect::<Vec<_>>();
+    let mut output = input.chars().filter(char::is_ascii).collect::<String>();
+    let basic = output.len() as u32;
+    if basic > 0 {
+        output.push('-');
+    }
+
+    let (mut n, mut delta, mut bias, mut handled) = (0x80u32, 0u32, 72u32, basic);
+    while (handled as usize) < chars.len() {
+        let m = chars.iter().copied().filter(|c| *c >= n).min()?;
+        delta = delta.checked_add((m - n).checked_mul(handled + 1)?)?;
+        n = m;
+        for &c in &chars {
+            if c < n {
+                delta = delta.checked_add(1)?;
+            }
+            if c == n {
+                let mut q = delta;
+                let mut k = BASE;
+                loop {
+                    let t = if k <= bias {
+                        T_MIN
+                    } else if k >= bias + T_MAX {
+                        T_MAX
+                    } else {
+                        k - bias
+                    };
+                    if q < t {
+                        break;
+                    }
+                    output.push(digit(t + (q - t) % (BASE - t)));
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+                output.push(digit(q));
+                bias = adapt(delta, handled + 1, handled == basic);
+                delta = 0;
+                handled += 1;
+            }
+        }
+        delta += 1;
+        n += 1;
+    }
+    Some(output)
+}
@@ -0,0 +1,330 @@
+/// What is wrong with compressed data
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct InflateError(pub &'static str);
+
+pub(crate) const TOO_LARGE: InflateError = InflateError("decoded data too large");
+
+const MAX_BITS: usize = 15;
+
+pub(super) const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+pub(super) const LENGTH_EXTRA: [u8; 29] =
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+pub(super) const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+pub(super) const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// order in which the code length code lengths of a dynamic block are stored
+const CODE_LENGTH_ORDER: [usize; 19] =
+    [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl Bits<'_> {
+    fn take(&mut self, n: u32) -> Result<u32, InflateError> {
+        while self.count < n {
+            let byte = *self.data.get(self.pos).ok_or(InflateError("truncated data"))?;
+            self.buffer |= (byte as u32) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1u32 << n) - 1);
+        self.buffer = self.buffer.checked_shr(n).unwrap_or(0);
+        self.count -= n;
+        Ok(value)
+    }
+
+    /// Drops the bits left of the current byte
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+/// Canonical Huffman code, symbols ordered by code
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self, InflateError> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(InflateError("over-subscribed code"));
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 1];
+        for length in 1..MAX_BITS {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        counts[0] = 0;
+        Ok(Self { counts, symbols })
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, InflateError> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= bits.take(1)? as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(InflateError("invalid code"))
+    }
+}
+
+/// Decodes raw DEFLATE data into at most `max_len` bytes, returns them with the number of
+/// input bytes used
+pub(crate) fn inflate(data: &[u8], max_len: usize) -> Result<(Vec<u8>, usize), InflateError> {
+    inflate_after(&[], data, max_len)
+}
+
+/// Like `inflate`, for `data` which may refer back into `window`, the data decoded before it
+pub(crate) fn inflate_after(
+    window: &[u8],
+    data: &[u8],
+    max_len: usize,
+) -> Result<(Vec<u8>, usize), InflateError> {
+    let mut bits = Bits { data, pos: 0, buffer: 0, count: 0 };
+    let mut out = window.to_vec();
+    let max_len = max_len.saturating_add(window.len());
+    loop {
+        let last = bits.take(1)? == 1;
+        match bits.take(2)? {
+            0 => stored(&mut bits, &mut out, max_len)?,
+            1 => {
+                let (lengths, distances) = fixed_codes()?;
+                codes(&mut bits, &mut out, &lengths, &distances, max_len)?
+            }
+            2 => {
+                let (lengths, distances) = dynamic_codes(&mut bits)?;
+                codes(&mut bits, &mut out, &lengths, &distances, max_len)?
+            }
+            _ => return Err(InflateError("invalid block type")),
+        }
+        if last {
+            return Ok((out.split_off(window.len()), bits.pos));
+        }
+    }
+}
+
+fn stored(bits: &mut Bits, out: &mut Vec<u8>, max_len: usize) -> Result<(), InflateError> {
+    bits.align();
+    let header = bits.data.get(bits.pos..bits.pos + 4).ok_or(InflateError("truncated data"))?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    if len != !u16::from_le_bytes([header[2], header[3]]) {
+        return Err(InflateError("stored block length mismatch"));
+    }
+    let start = bits.pos + 4;
+    let block = bits.data.get(start..start + len as usize).ok_or(InflateError("truncated data"))?;
+    if out.len() + block.len() > max_len {
+        return Err(TOO_LARGE);
+    }
+    out.extend_from_slice(block);
+    bits.pos = start + block.len();
+    Ok(())
+}
+
+fn fixed_codes() -> Result<(Huffman, Huffman), InflateError> {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    Ok((Huffman::new(&lengths)?, Huffman::new(&[5; 30])?))
+}
+
+fn dynamic_codes(bits: &mut Bits) -> Result<(Huffman, Huffman), InflateError> {
+    let literals = bits.take(5)? as usize + 257;
+    let distances = bits.take(5)? as usize + 1;
+    let code_lengths = bits.take(4)? as usize + 4;
+    if literals > 286 || distances > 30 {
+        return Err(InflateError("too many codes"));
+    }
+
+    let mut lengths = [0u8; 19];
+    for &index in &CODE_LENGTH_ORDER[..code_lengths] {
+        lengths[index] = bits.take(3)? as u8;
+    }
+    let code = Huffman::new(&lengths)?;
+
+    let mut lengths = Vec::with_capacity(literals + distances);
+    while lengths.len() < literals + distances {
+        let (length, repeat) = match code.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths.last().ok_or(InflateError("repeat without length"))?;
+                (previous, 3 + bits.take(2)?)
+            }
+            17 => (0, 3 + bits.take(3)?),
+            _ => (0, 11 + bits.take(7)?),
+        };
+        if lengths.len() + repeat as usize > literals + distances {
+            return Err(InflateError("too many lengths"));
+        }
+        lengths.extend(std::iter::repeat_n(length, repeat as usize));
+    }
+    if lengths[256] == 0 {
+        return Err(InflateError("no end of block code"));
+    }
+    Ok((Huffman::new(&lengths[..literals])?, Huffman::new(&lengths[literals..])?))
+}
+
+fn codes(
+    bits: &mut Bits,
+    out: &mut Vec<u8>,
+    lengths: &Huffman,
+    distances: &Huffman,
+    max_len: usize,
+) -> Result<(), InflateError> {
+    loop {
+        let symbol = lengths.decode(bits)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let symbol = symbol - 257;
+                if symbol >= LENGTH_BASE.len() {
+                    return Err(InflateError("invalid length code"));
+                }
+                let extra = bits.take(LENGTH_EXTRA[symbol] as u32)? as usize;
+                let len = LENGTH_BASE[symbol] as usize + extra;
+                let symbol = distances.decode(bits)? as usize;
+                if symbol >= DIST_BASE.len() {
+                    return Err(InflateError("invalid distance code"));
+                }
+                let extra = bits.take(DIST_EXTRA[symbol] as u32)? as usize;
+                let distance = DIST_BASE[symbol] as usize + extra;
+                if distance > out.len() {
+                    return Err(InflateError("distance too far back"));
+                }
+                let start = out.len() - distance;
+                for i in 0..len {
+                    out.push(out[start + i]);
+                }
+            }
+        }
+        if out.len() > max_len {
+            return Err(TOO_LARGE);
+        }
+    }
+}
+
+/// `deflate` content coding: zlib data, or raw DEFLATE as some servers send it
+pub(crate) fn zlib_decompress(data: &[u8], max_len: usize) -> Result<Vec<u8>, InflateError> {
+    let zlib = data.len() >= 2
+        && data[0] & 0x0f == 8
+        && u16::from_be_bytes([data[0], data[1]]).is_multiple_of(31)
+        && data[1] & 0x20 == 0;
+    if !zlib {
+        return inflate(data, max_len).map(|(out, _)| out);
+    }
+    let (out, used) = inflate(&data[2..], max_len)?;
+    let trailer = data.get(2 + used..2 + used + 4).ok_or(InflateError("truncated data"))?;
+    if u32::from_be_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]) != adler32(&out) {
+        return Err(InflateError("adler-32 mismatch"));
+    }
+    Ok(out)
+}
+
+/// `gzip` content coding, concatenated members decode to their concatenation
+pub(crate) fn gunzip(data: &[u8], max_len: usize) -> Result<Vec<u8>, InflateError> {
+    let mut out = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let truncated = InflateError("truncated data");
+        if rest.len() < 10 || rest[0] != 0x1f || rest[1] != 0x8b || rest[2] != 8 {
+            return Err(InflateError("not gzip data"));
+        }
+        let flags = rest[3];
+        let mut pos = 10;
+        if flags & 0x04 != 0 {
+            let extra = rest.get(pos..pos + 2).ok_or(truncated)?;
+            pos += 2 + u16::from_le_bytes([extra[0], extra[1]]) as usize;
+        }
+        // file name and comment, zero terminated
+        for flag in [0x08, 0x10] {
+            if flags & flag != 0 {
+                let end = rest.get(pos..).and_then(|r| r.iter().position(|&b| b == 0));
+                pos += end.ok_or(truncated)? + 1;
+            }
+        }
+        if flags & 0x02 != 0 {
+            pos += 2;
+        }
+
+        let (member, used) = inflate(rest.get(pos..).ok_or(truncated)?, max_len - out.len())?;
+        let trailer = rest.get(pos + used..pos + used + 8).ok_or(truncated)?;
+        let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        if crc != crc32(&member) || size != member.len() as u32 {
+            return Err(InflateError("crc-32 mismatch"));
+        }
+        out.extend_from_slice(&member);
+        rest = &rest[pos + used + 8..];
+    }
+    Ok(out)
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+};
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |c, &b| CRC_TABLE[((c ^ b as u32) & 0xff) as usize] ^ (c >> 8))
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
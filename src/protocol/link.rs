@@ -0,0 +1,201 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use super::media::{unquote, write_param};
+use super::{is_token, Headers, HttpVersion, RawResponse, StatusCode, StatusLine};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LinkError(pub String);
+
+impl Display for LinkError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid link: {}", self.0)
+    }
+}
+
+/// One link of a `Link` header (RFC 8288), `<target>; rel=next; ...`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Link {
+    target: String,
+    /// names lowercased, values unquoted
+    params: Vec<(String, String)>,
+}
+
+impl Link {
+    pub fn new(target: &str) -> Self {
+        Self { target: target.to_owned(), params: Vec::new() }
+    }
+
+    /// `rel=preload` of a resource fetched `as` e.g. `style` or `script`
+    pub fn preload(target: &str, as_: &str) -> Self {
+        Link::new(target).rel("preload").param("as", as_)
+    }
+
+    pub fn rel(self, rel: &str) -> Self {
+        self.param("rel", rel)
+    }
+
+    /// Adds a target attribute, or replaces the value of one with the same name
+    pub fn param(mut self, name: &str, value: &str) -> Self {
+        let name = name.to_ascii_lowercase();
+        match self.params.iter_mut().find(|(n, _)| *n == name) {
+            Some(param) => param.1 = value.to_owned(),
+            None => self.params.push((name, value.to_owned())),
+        }
+        self
+    }
+
+    /// the URI reference, relative ones are resolved against the request URI
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// The first value of the attribute `name`, later ones are ignored as RFC 8288 asks
+    pub fn get(&self, name: &str) -> Option<&str> {
+        let mut params = self.params.iter();
+        params.find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    /// Relation types of the space separated `rel` attribute
+    pub fn rels(&self) -> impl Iterator<Item = &str> {
+        self.get("rel").unwrap_or_default().split_ascii_whitespace()
+    }
+
+    pub fn has_rel(&self, rel: &str) -> bool {
+        self.rels().any(|r| r.eq_ignore_ascii_case(rel))
+    }
+}
+
+impl Display for Link {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<{}>", self.target)?;
+        for (name, value) in &self.params {
+            write_param(f, name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// The links of one or several `Link` headers, e.g. pagination or an Early Hints preload list
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Links(pub Vec<Link>);
+
+impl Links {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn link(mut self, link: Link) -> Self {
+        self.0.push(link);
+        self
+    }
+
+    /// `rel=first` pagination link
+    pub fn first(self, target: &str) -> Self {
+        self.link(Link::new(target).rel("first"))
+    }
+
+    pub fn prev(self, target: &str) -> Self {
+        self.link(Link::new(target).rel("prev"))
+    }
+
+    pub fn next(self, target: &str) -> Self {
+        self.link(Link::new(target).rel("next"))
+    }
+
+    pub fn last(self, target: &str) -> Self {
+        self.link(Link::new(target).rel("last"))
+    }
+
+    /// The first link with the relation type `rel`
+    pub fn find(&self, rel: &str) -> Option<&Link> {
+        self.0.iter().find(|link| link.has_rel(rel))
+    }
+
+    /// The comma separated links of a header value
+    pub fn parse(s: &str) -> Result<Self, LinkError> {
+        const OWS: [char; 2] = [' ', '\t'];
+        let invalid = || LinkError(s.to_owned());
+        let mut links = Vec::new();
+        let mut rest = s;
+        loop {
+            rest = rest.trim_start_matches([' ', '\t', ',']);
+            if rest.is_empty() {
+                return Ok(Self(links));
+            }
+            let (target, after) =
+                rest.strip_prefix('<').and_then(|r| r.split_once('>')).ok_or_else(invalid)?;
+            let mut link = Link::new(target);
+            rest = after.trim_start_matches(OWS);
+            while let Some(param) = rest.strip_prefix(';') {
+                let param = param.trim_start_matches(OWS);
+                let end = param.find(['=', ';', ',']).unwrap_or(param.len());
+                let name = param[..end].trim_end_matches(OWS).to_ascii_lowercase();
+                if !is_token(&name) {
+                    return Err(invalid());
+                }
+                let (value, after) = match param[end..].strip_prefix('=') {
+                    Some(value) => match value.trim_start_matches(OWS).strip_prefix('"') {
+                        Some(quoted) => unquote(quoted).ok_or_else(invalid)?,
+                        None => {
+                            let value = value.trim_start_matches(OWS);
+                            let end = value.find([';', ',']).unwrap_or(value.len());
+                            (value[..end].trim_end_matches(OWS).to_owned(), &value[end..])
+                        }
+                    },
+                    None => (String::new(), &param[end..]),
+                };
+                // the first occurrence of an attribute counts
+                if link.get(&name).is_none() {
+                    link.params.push((name, value));
+                }
+                rest = after.trim_start_matches(OWS);
+            }
+            if !rest.is_empty() && !rest.starts_with(',') {
+                return Err(invalid());
+            }
+            links.push(link);
+        }
+    }
+
+    /// The links of every `Link` header
+    pub fn from_headers(headers: &Headers) -> Result<Self, LinkError> {
+        let mut links = Links::new();
+        for value in headers.get_all("Link") {
+            links.0.extend(Links::parse(value)?.0);
+        }
+        Ok(links)
+    }
+
+    /// A `103 Early Hints` interim response carrying these links, to be written ahead of the
+    /// final response
+    pub fn early_hints(&self) -> RawResponse {
+        let mut headers = Headers::empty();
+        headers.set("Link", self.to_string());
+        RawResponse::new(
+            StatusLine::new(HttpVersion::Http1_1, StatusCode::EARLY_HINTS),
+            headers,
+            None,
+        )
+    }
+}
+
+impl Display for Links {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, link) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{link}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Links {
+    type Err = LinkError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Links::parse(s)
+    }
+}
@@ -0,0 +1,181 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use super::{is_token, RawRequest};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MediaTypeError(pub String);
+
+impl Display for MediaTypeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid media type: {}", self.0)
+    }
+}
+
+/// `type/subtype` with parameters, as in `Content-Type` and `Accept`.
+///
+/// Type, subtype and parameter names are lowercased, and so is the `charset` value; other values
+/// keep their case and are stored without quotes.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MediaType {
+    essence: String,
+    slash: usize,
+    params: Vec<(String, String)>,
+}
+
+impl MediaType {
+    pub fn parse(s: &str) -> Result<Self, MediaTypeError> {
+        let invalid = || MediaTypeError(s.to_owned());
+        let (essence, mut rest) = s.split_once(';').unwrap_or((s, ""));
+        let essence = essence.trim_matches([' ', '\t']).to_ascii_lowercase();
+        let slash = essence.find('/').ok_or_else(invalid)?;
+        if !is_token(&essence[..slash]) || !is_token(&essence[slash + 1..]) {
+            return Err(invalid());
+        }
+
+        let mut params = Vec::new();
+        loop {
+            rest = rest.trim_start_matches([' ', '\t', ';']);
+            if rest.is_empty() {
+                break;
+            }
+            let (name, after) = rest.split_once('=').ok_or_else(invalid)?;
+            let name = name.trim_end_matches([' ', '\t']).to_ascii_lowercase();
+            if !is_token(&name) {
+                return Err(invalid());
+            }
+            let (mut value, after) = match after.strip_prefix('"') {
+                Some(quoted) => unquote(quoted).ok_or_else(invalid)?,
+                None => {
+                    let end = after.find(';').unwrap_or(after.len());
+                    let value = after[..end].trim_end_matches([' ', '\t']);
+                    if !is_token(value) {
+                        return Err(invalid());
+                    }
+                    (value.to_owned(), &after[end..])
+                }
+            };
+            if name == "charset" {
+                value.make_ascii_lowercase();
+            }
+            params.push((name, value));
+            rest = after;
+        }
+        Ok(Self { essence, slash, params })
+    }
+
+    /// `type/subtype`, without parameters
+    pub fn essence(&self) -> &str {
+        &self.essence
+    }
+
+    /// the top-level type, e.g. `text`
+    pub fn kind(&self) -> &str {
+        &self.essence[..self.slash]
+    }
+
+    pub fn subtype(&self) -> &str {
+        &self.essence[self.slash + 1..]
+    }
+
+    /// The structured syntax suffix, e.g. `json` for `application/problem+json`
+    pub fn suffix(&self) -> Option<&str> {
+        self.subtype().rsplit_once('+').map(|(_, suffix)| suffix)
+    }
+
+    /// The first value of the parameter `name`, compared case-insensitively
+    pub fn param(&self, name: &str) -> Option<&str> {
+        let mut params = self.params.iter();
+        params.find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    pub fn params(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.params.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn charset(&self) -> Option<&str> {
+        self.param("charset")
+    }
+
+    /// Adds a parameter, or replaces the value of one with the same name
+    pub fn with_param(mut self, name: &str, value: &str) -> Self {
+        let name = name.to_ascii_lowercase();
+        let value = match name == "charset" {
+            true => value.to_ascii_lowercase(),
+            false => value.to_owned(),
+        };
+        match self.params.iter_mut().find(|(n, _)| *n == name) {
+            Some(param) => param.1 = value,
+            None => self.params.push((name, value)),
+        }
+        self
+    }
+
+    /// `application/json` or any `+json` type
+    pub fn is_json(&self) -> bool {
+        self.essence == "application/json" || self.suffix() == Some("json")
+    }
+
+    /// Whether this type falls in `range`, e.g. `text/*` or `*/*`, ignoring parameters
+    pub fn matches(&self, range: &str) -> bool {
+        let range = range.split(';').next().unwrap_or_default().trim_matches([' ', '\t']);
+        let Some((kind, subtype)) = range.split_once('/') else {
+            return false;
+        };
+        match (kind, subtype) {
+            ("*", "*") => true,
+            (kind, "*") => kind.eq_ignore_ascii_case(self.kind()),
+            _ => range.eq_ignore_ascii_case(&self.essence),
+        }
+    }
+}
+
+/// A quoted-string after its opening quote, and what follows the closing one
+pub(super) fn unquote(s: &str) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &s[i + 1..])),
+            '\\' => value.push(chars.next()?.1),
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+impl Display for MediaType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.essence)?;
+        for (name, value) in &self.params {
+            write_param(f, name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// `; name=value`, quoting the value unless it's a token
+pub(super) fn write_param(f: &mut Formatter<'_>, name: &str, value: &str) -> std::fmt::Result {
+    match is_token(value) {
+        true => write!(f, "; {name}={value}"),
+        false => {
+            let escaped = value.replace('\\', "\\\\").replace('"', "\\\"");
+            write!(f, "; {name}=\"{escaped}\"")
+        }
+    }
+}
+
+impl FromStr for MediaType {
+    type Err = MediaTypeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        MediaType::parse(s)
+    }
+}
+
+impl RawRequest {
+    /// The parsed `Content-Type` header, `None` when missing or malformed
+    pub fn media_type(&self) -> Option<MediaType> {
+        self.headers.get("Content-Type").and_then(|value| MediaType::parse(value).ok())
+    }
+}
@@ -3,23 +3,95 @@ use std::io;
 use std::ops::{Deref, DerefMut};
 use std::str::FromStr;
 
-pub use self::request::{read_http_request, RawRequest, RequestLine};
-pub use self::response::{write_http_response, RawResponse, StatusLine};
-
+pub use self::body::{BodyError, RawBody};
+pub use self::borrowed::{parse_request_head_borrowed, HeaderRef, RequestHeadRef, EMPTY_HEADER};
+pub use self::challenge::Challenge;
+pub use self::charset::{Charset, CharsetError};
+pub use self::codec::HttpServerCodec;
+#[cfg(feature = "acme")]
+pub(crate) use self::date::days_from_civil;
+pub use self::date::{format_http_date, parse_http_date};
+pub(crate) use self::deflate::deflate_sync;
+pub use self::disposition::{ContentDisposition, ContentDispositionError, DispositionType};
+pub use self::extensions::Extensions;
+pub use self::host::{Host, HostError, HostName};
+pub(crate) use self::inflate::{inflate_after, TOO_LARGE};
+pub use self::link::{Link, LinkError, Links};
+pub use self::media::{MediaType, MediaTypeError};
+pub(crate) use self::percent::query_param;
+pub use self::percent::{
+    percent_decode, percent_decode_bytes, percent_encode, EncodeSet, Location, PercentDecodeError,
+};
+pub use self::range::{content_range, ByteRanges, ByteRangesBody};
+pub use self::request::{
+    parse_request_head, parse_request_head_with, read_http_request, read_http_request_with,
+    Leniency, RawRequest, RequestLimits, RequestLine,
+};
+pub(crate) use self::request::{read_request_body, read_request_head};
+pub use self::response::{
+    parse_chunk_size, read_http_response, read_http_response_head, write_http_response,
+    write_streaming_response, BodyStream, RawResponse, StatusLine,
+};
+pub use self::retry::{RateLimit, RetryAfter};
+pub use self::streaming::Body;
+pub use self::target::{RequestTarget, Uri};
+
+mod body;
+mod borrowed;
+mod challenge;
+mod charset;
+mod codec;
+#[cfg(test)]
+mod conformance;
+mod date;
+mod deflate;
+mod disposition;
+mod extensions;
+mod host;
+mod inflate;
+mod link;
+mod media;
+mod percent;
+mod range;
 mod request;
 mod response;
+mod retry;
+mod streaming;
+mod target;
 #[cfg(test)]
 mod tests;
 
 pub const CRLF: &str = "\r\n";
 
+/// Headers a proxy must not forward (RFC 9110, section 7.6.1), including the non-standard
+/// `Proxy-Connection`
+pub const HOP_BY_HOP_HEADERS: [&str; 9] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Connection",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+    "Proxy-Authorization",
+    "Proxy-Authenticate",
+];
+
 #[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
 pub enum ParseRequestError {
     Io(io::ErrorKind),
     UnknownMethod(String),
     UnknownHttpVersion(String),
     RequestLine(String),
+    StatusLine(String),
     InvalidHeader(String),
+    InvalidHeaderName(String),
+    /// control characters, or bytes above 0x7f where only ASCII is allowed
+    InvalidByte(u8),
+    LineTooLong,
+    BareLineFeed,
+    TooManyHeaders,
+    BodyTooLarge(usize),
 }
 
 impl Display for ParseRequestError {
@@ -29,9 +101,35 @@ impl Display for ParseRequestError {
             ParseRequestError::UnknownMethod(m) => write!(f, "unknown http method: {m}"),
             ParseRequestError::UnknownHttpVersion(v) => write!(f, "unknown http version: {v}"),
             ParseRequestError::RequestLine(src) => write!(f, "invalid request line: {src}"),
+            ParseRequestError::StatusLine(src) => write!(f, "invalid status line: {src}"),
             ParseRequestError::InvalidHeader(src) => {
                 write!(f, "invalid characters in header content: {src}")
             }
+            ParseRequestError::InvalidHeaderName(name) => write!(f, "invalid header name: {name}"),
+            ParseRequestError::LineTooLong => write!(f, "request line or header too long"),
+            ParseRequestError::InvalidByte(b) => write!(f, "invalid byte 0x{b:02x}"),
+            ParseRequestError::BareLineFeed => write!(f, "line terminated by a bare LF"),
+            ParseRequestError::TooManyHeaders => write!(f, "too many headers"),
+            ParseRequestError::BodyTooLarge(len) => write!(f, "body of {len} bytes is too large"),
+        }
+    }
+}
+
+impl ParseRequestError {
+    /// The status to answer a request which failed to parse with, before closing the
+    /// connection, none for read failures
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            ParseRequestError::Io(_) => None,
+            ParseRequestError::UnknownMethod(_) => Some(StatusCode::NOT_IMPLEMENTED),
+            ParseRequestError::UnknownHttpVersion(_) => {
+                Some(StatusCode::HTTP_VERSION_NOT_SUPPORTED)
+            }
+            ParseRequestError::LineTooLong | ParseRequestError::TooManyHeaders => {
+                Some(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE)
+            }
+            ParseRequestError::BodyTooLarge(_) => Some(StatusCode::PAYLOAD_TOO_LARGE),
+            _ => Some(StatusCode::BAD_REQUEST),
         }
     }
 }
@@ -42,6 +140,35 @@ impl From<io::Error> for ParseRequestError {
     }
 }
 
+/// A response whose framing would corrupt the connection if written
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FramingError {
+    /// the declared `Content-Length` and the actual body size
+    ContentLengthMismatch(String, usize),
+    /// 1xx, 204 and 304 responses (and responses to `HEAD`) carry no body
+    BodyNotAllowed(StatusCode),
+    /// 1xx and 204 responses carry no `Content-Length`
+    ContentLengthNotAllowed(StatusCode),
+    ChunkedWithContentLength,
+}
+
+impl Display for FramingError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FramingError::ContentLengthMismatch(declared, actual) => {
+                write!(f, "Content-Length {declared} declared for a body of {actual} bytes")
+            }
+            FramingError::BodyNotAllowed(status) => write!(f, "{} response with a body", **status),
+            FramingError::ContentLengthNotAllowed(status) => {
+                write!(f, "{} response with Content-Length", **status)
+            }
+            FramingError::ChunkedWithContentLength => {
+                write!(f, "both Transfer-Encoding: chunked and Content-Length set")
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Hash, PartialOrd, Eq, PartialEq)]
 pub enum Method {
     /// HTTP GET
@@ -60,6 +187,8 @@ pub enum Method {
     OPTIONS,
     /// HTTP TRACE
     TRACE,
+    /// HTTP CONNECT
+    CONNECT,
 }
 
 impl FromStr for Method {
@@ -75,6 +204,7 @@ impl FromStr for Method {
             "PATCH" => Ok(Method::PATCH),
             "OPTIONS" => Ok(Method::OPTIONS),
             "TRACE" => Ok(Method::TRACE),
+            "CONNECT" => Ok(Method::CONNECT),
             _ => Err(ParseRequestError::UnknownMethod(s.to_owned())),
         }
     }
@@ -91,8 +221,30 @@ impl Method {
             Method::PATCH => "PATCH",
             Method::OPTIONS => "OPTIONS",
             Method::TRACE => "TRACE",
+            Method::CONNECT => "CONNECT",
         }
     }
+
+    // the properties are listed for the methods that have them, so a method added later, e.g.
+    // for an extension, lacks them until its definition says otherwise
+
+    /// Methods which are read-only by definition (RFC 9110, section 9.2.1), so they may be
+    /// prefetched, retried and sent without protection against cross-site requests
+    pub fn is_safe(&self) -> bool {
+        matches!(self, Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE)
+    }
+
+    /// Methods a request may be repeated with, to the same effect as sending it once
+    pub fn is_idempotent(&self) -> bool {
+        self.is_safe() || matches!(self, Method::PUT | Method::DELETE)
+    }
+
+    /// Methods whose responses may be stored and reused by caches. POST responses are only
+    /// reusable with explicit freshness and a matching `Content-Location`, which isn't
+    /// supported here, so it's not one of them.
+    pub fn is_cacheable(&self) -> bool {
+        matches!(self, Method::GET | Method::HEAD)
+    }
 }
 
 #[derive(Debug, Clone, Copy, Hash, PartialOrd, Eq, PartialEq)]
@@ -131,7 +283,20 @@ impl HttpVersion {
     }
 }
 
-#[derive(Debug)]
+/// `Headers` of `field => value` pairs, checked as by `Headers::from_pairs`, panics when one is
+/// invalid
+#[macro_export]
+macro_rules! headers {
+    () => {
+        $crate::protocol::Headers::empty()
+    };
+    ($($field:expr => $value:expr),* $(,)?) => {
+        $crate::protocol::Headers::from_pairs([$(($field.to_string(), $value.to_string())),*])
+            .expect("invalid header")
+    };
+}
+
+#[derive(Debug, Clone)]
 pub struct Headers(Vec<Header>);
 
 impl Headers {
@@ -140,6 +305,28 @@ impl Headers {
         Self(inner)
     }
 
+    /// Headers of the pairs in order, names must be tokens and values free of control
+    /// characters but tab, values are trimmed like parsed ones
+    pub fn from_pairs<I, K, V>(pairs: I) -> Result<Self, ParseRequestError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: ToString,
+        V: ToString,
+    {
+        let mut headers = Headers::empty();
+        for (field, value) in pairs {
+            let (field, value) = (field.to_string(), value.to_string());
+            if !is_token(&field) {
+                return Err(ParseRequestError::InvalidHeaderName(field));
+            }
+            if let Some(b) = value.bytes().find(|&b| (b < 0x20 && b != b'\t') || b == 0x7f) {
+                return Err(ParseRequestError::InvalidByte(b));
+            }
+            headers.push(Header::new(field, value.trim_matches([' ', '\t'])));
+        }
+        Ok(headers)
+    }
+
     pub fn set(&mut self, field: &str, value: String) {
         if let Some(h) = self.iter_mut().find(|h| h.field.eq_ignore_ascii_case(field)) {
             h.value = value;
@@ -152,11 +339,90 @@ impl Headers {
         self.iter().find(|h| h.field.eq_ignore_ascii_case(field)).map(|h| h.value.as_ref())
     }
 
+    pub fn get_all<'a>(&'a self, field: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.iter().filter(move |h| h.field.eq_ignore_ascii_case(field)).map(|h| h.value.as_ref())
+    }
+
+    pub fn contains(&self, field: &str) -> bool {
+        self.iter().any(|h| h.field.eq_ignore_ascii_case(field))
+    }
+
+    /// The first value of `field`, parsed, `None` when missing or malformed
+    pub fn get_parsed<T: FromStr>(&self, field: &str) -> Option<T> {
+        self.get(field).and_then(|value| value.parse().ok())
+    }
+
+    /// The first value of `field`, after adding it with `default` when missing
+    pub fn get_or_insert_with<F>(&mut self, field: &str, default: F) -> &mut String
+    where
+        F: FnOnce() -> String,
+    {
+        let index = match self.iter().position(|h| h.field.eq_ignore_ascii_case(field)) {
+            Some(index) => index,
+            None => {
+                self.push(Header::new(field, default()));
+                self.len() - 1
+            }
+        };
+        &mut self.0[index].value
+    }
+
+    /// Keeps the headers for which `keep` holds, given their field and value
+    pub fn retain<F>(&mut self, mut keep: F)
+    where
+        F: FnMut(&str, &str) -> bool,
+    {
+        self.0.retain(|h| keep(&h.field, &h.value));
+    }
+
+    /// Whether the comma separated list of `field` holds `token`, ignoring case
+    pub fn has_token(&self, field: &str, token: &str) -> bool {
+        self.get_all(field).flat_map(|v| v.split(',')).any(|v| v.trim().eq_ignore_ascii_case(token))
+    }
+
+    /// Appends `value` to the comma separated list of `field`, merging its headers into one
+    pub fn append_list(&mut self, field: &str, value: &str) {
+        let mut values = self.get_all(field).collect::<Vec<_>>();
+        values.push(value);
+        let list = values.join(", ");
+        self.retain(|name, _| !name.eq_ignore_ascii_case(field));
+        self.push(Header::new(field, list));
+    }
+
+    /// Removes the headers which only concern the current connection, those of
+    /// `HOP_BY_HOP_HEADERS` and those named in `Connection`, before a message is forwarded
+    pub fn remove_hop_by_hop(&mut self) {
+        let listed = self
+            .get_all("Connection")
+            .flat_map(|v| v.split(','))
+            .map(|v| v.trim().to_ascii_lowercase())
+            .collect::<Vec<_>>();
+        self.retain(|field, _| {
+            !HOP_BY_HOP_HEADERS.iter().any(|h| h.eq_ignore_ascii_case(field))
+                && !listed.iter().any(|l| l.eq_ignore_ascii_case(field))
+        });
+    }
+
     pub fn to_http_message(&self) -> String {
         self.iter().map(Header::to_http_message).collect::<Vec<_>>().concat()
     }
 }
 
+impl<K: ToString, V: ToString> FromIterator<(K, V)> for Headers {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut headers = Headers::empty();
+        headers.extend(iter);
+        headers
+    }
+}
+
+/// Appends, keeping headers of the same field already present
+impl<K: ToString, V: ToString> Extend<(K, V)> for Headers {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        self.0.extend(iter.into_iter().map(|(field, value)| Header::new(field, value)));
+    }
+}
+
 impl Deref for Headers {
     type Target = Vec<Header>;
 
@@ -180,7 +446,7 @@ impl Display for Headers {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Header {
     field: String,
     value: String,
@@ -214,24 +480,25 @@ impl Header {
 impl FromStr for Header {
     type Err = ParseRequestError;
 
+    /// `field-name ":" OWS field-value OWS`, the name must be a token
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut parts = s.split(": ");
-
-        let field = parts
-            .next()
-            .map(|w| w.to_owned())
-            .ok_or(ParseRequestError::InvalidHeader(s.to_owned()))?;
-        let value = parts
-            .next()
-            .map(|w| w.trim())
-            .map(|w| w.to_owned())
-            .ok_or(ParseRequestError::InvalidHeader(s.to_owned()))?;
-
-        let header = Header { field, value };
+        let (field, value) =
+            s.split_once(':').ok_or_else(|| ParseRequestError::InvalidHeader(s.to_owned()))?;
+        if !is_token(field) {
+            return Err(ParseRequestError::InvalidHeaderName(field.to_owned()));
+        }
+        let value = value.trim_matches([' ', '\t']);
+
+        let header = Header { field: field.to_owned(), value: value.to_owned() };
         Ok(header)
     }
 }
 
+/// `token` of RFC 9110, as used for field names and methods
+pub(crate) fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
 // TODO: user std::num::NonZeroU16
 #[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
 pub struct StatusCode(u16);
@@ -256,6 +523,56 @@ impl From<u16> for StatusCode {
     }
 }
 
+/// `200 OK`, with the default reason phrase
+impl Display for StatusCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.0, self.default_reason_phrase())
+    }
+}
+
+/// Three digits from `100` to `599`, a reason phrase following them as in `Display` is ignored
+impl FromStr for StatusCode {
+    type Err = ParseRequestError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code = s.split_once(' ').map_or(s, |(code, _)| code);
+        Some(code)
+            .filter(|code| code.len() == 3 && code.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|code| code.parse::<u16>().ok())
+            .filter(|code| (100..600).contains(code))
+            .map(StatusCode)
+            .ok_or_else(|| ParseRequestError::StatusLine(s.to_owned()))
+    }
+}
+
+/// What the first digit of a status code tells (RFC 9110, section 15)
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum StatusClass {
+    /// 1xx
+    Informational,
+    /// 2xx
+    Success,
+    /// 3xx
+    Redirection,
+    /// 4xx
+    ClientError,
+    /// 5xx
+    ServerError,
+}
+
+impl StatusClass {
+    /// Reason phrase for codes of the class without one of their own
+    pub fn reason_phrase(&self) -> &'static str {
+        match self {
+            StatusClass::Informational => "Informational",
+            StatusClass::Success => "Success",
+            StatusClass::Redirection => "Redirection",
+            StatusClass::ClientError => "Client Error",
+            StatusClass::ServerError => "Server Error",
+        }
+    }
+}
+
 impl StatusCode {
     /// 100 Continue
     pub const CONTINUE: StatusCode = StatusCode(100);
@@ -342,9 +659,12 @@ impl StatusCode {
     /// 422 Unprocessable Entity
     pub const UNPROCESSABLE_ENTITY: StatusCode = StatusCode(422);
     /// 423 Locked
+    #[allow(non_upper_case_globals)]
     pub const Locked: StatusCode = StatusCode(423);
     /// 424 Failed Dependency
     pub const FAILED_DEPENDENCY: StatusCode = StatusCode(424);
+    /// 425 Too Early
+    pub const TOO_EARLY: StatusCode = StatusCode(425);
     /// 426 Upgrade Required
     pub const UPGRADE_REQUIRED: StatusCode = StatusCode(426);
     /// 428 Precondition Required
@@ -362,6 +682,7 @@ impl StatusCode {
     /// 502 Bad Gateway
     pub const BAD_GATEWAY: StatusCode = StatusCode(502);
     /// 503 Service Unavailable
+    #[allow(non_upper_case_globals)]
     pub const ServiceUnavailable: StatusCode = StatusCode(503);
     /// 504 Gateway Timeout
     pub const GATEWAY_TIMEOUT: StatusCode = StatusCode(504);
@@ -378,6 +699,19 @@ impl StatusCode {
     /// 511 Network Authentication Required
     pub const NETWORK_AUTHENTICATION_REQUIRED: StatusCode = StatusCode(511);
 
+    /// `None` for codes outside `100..600`, which `From<u16>` doesn't rule out
+    pub fn class(&self) -> Option<StatusClass> {
+        match self.0 {
+            100..=199 => Some(StatusClass::Informational),
+            200..=299 => Some(StatusClass::Success),
+            300..=399 => Some(StatusClass::Redirection),
+            400..=499 => Some(StatusClass::ClientError),
+            500..=599 => Some(StatusClass::ServerError),
+            _ => None,
+        }
+    }
+
+    /// The phrase of the code, or else of its class
     pub fn default_reason_phrase(&self) -> &'static str {
         match *self {
             StatusCode::CONTINUE => "Continue",
@@ -424,6 +758,7 @@ impl StatusCode {
             StatusCode::UNPROCESSABLE_ENTITY => "Unprocessable Entity",
             StatusCode::Locked => "Locked",
             StatusCode::FAILED_DEPENDENCY => "Failed Dependency",
+            StatusCode::TOO_EARLY => "Too Early",
             StatusCode::UPGRADE_REQUIRED => "Upgrade Required",
             StatusCode::PRECONDITION_REQUIRED => "Precondition Required",
             StatusCode::TOO_MANY_REQUESTS => "Too Many Requests",
@@ -440,7 +775,7 @@ impl StatusCode {
             StatusCode::LOOP_DETECTED => "Loop Detected",
             StatusCode::NOT_EXTENDED => "Not Extended",
             StatusCode::NETWORK_AUTHENTICATION_REQUIRED => "Network Authentication Required",
-            _ => "Unknown",
+            _ => self.class().map_or("Unknown", |class| class.reason_phrase()),
         }
     }
 }
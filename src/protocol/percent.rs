@@ -0,0 +1,142 @@
+use std::borrow::Cow;
+use std::fmt::{Display, Formatter};
+
+/// Which bytes `percent_encode` leaves alone, beyond the unreserved `A-Z a-z 0-9 - . _ ~`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum EncodeSet {
+    /// one path segment: sub-delims, `:` and `@` stay, `/ ? # %` are encoded
+    PathSegment,
+    /// a query key or value: `& = + #` and `%` are encoded
+    QueryComponent,
+    /// a whole URI reference placed in a header: everything a URI may contain stays, including
+    /// existing `%` escapes, while controls, spaces and non-ASCII bytes are encoded
+    HeaderValue,
+    /// the value of an RFC 8187 ext-value such as `filename*`: only `attr-char` stays
+    ExtValue,
+}
+
+impl EncodeSet {
+    fn keeps(self, b: u8) -> bool {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            return true;
+        }
+        match self {
+            EncodeSet::PathSegment => b"!$&'()*+,;=:@".contains(&b),
+            EncodeSet::QueryComponent => b"!$'()*,;:@/?".contains(&b),
+            EncodeSet::HeaderValue => b"!$&'()*+,;=:@/?#[]%".contains(&b),
+            EncodeSet::ExtValue => b"!#$&+^`|".contains(&b),
+        }
+    }
+}
+
+pub fn percent_encode(s: &str, set: EncodeSet) -> Cow<'_, str> {
+    if s.bytes().all(|b| set.keeps(b)) {
+        return Cow::Borrowed(s);
+    }
+    let mut encoded = String::with_capacity(s.len() + 8);
+    for b in s.bytes() {
+        if set.keeps(b) {
+            encoded.push(char::from(b));
+        } else {
+            encoded.push_str(&format!("%{b:02X}"));
+        }
+    }
+    Cow::Owned(encoded)
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum PercentDecodeError {
+    /// byte offset of a `%` not followed by two hex digits
+    InvalidEscape(usize),
+    NotUtf8,
+}
+
+impl Display for PercentDecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PercentDecodeError::InvalidEscape(at) => write!(f, "invalid percent escape at {at}"),
+            PercentDecodeError::NotUtf8 => write!(f, "decoded bytes are not UTF-8"),
+        }
+    }
+}
+
+pub fn percent_decode_bytes(s: &str) -> Result<Cow<'_, [u8]>, PercentDecodeError> {
+    if !s.contains('%') {
+        return Ok(Cow::Borrowed(s.as_bytes()));
+    }
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'%' {
+            decoded.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+        let b = hex
+            .filter(|h| h.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|h| u8::from_str_radix(h, 16).ok())
+            .ok_or(PercentDecodeError::InvalidEscape(i))?;
+        decoded.push(b);
+        i += 3;
+    }
+    Ok(Cow::Owned(decoded))
+}
+
+pub fn percent_decode(s: &str) -> Result<Cow<'_, str>, PercentDecodeError> {
+    match percent_decode_bytes(s)? {
+        Cow::Borrowed(_) => Ok(Cow::Borrowed(s)),
+        Cow::Owned(bytes) => {
+            String::from_utf8(bytes).map(Cow::Owned).map_err(|_| PercentDecodeError::NotUtf8)
+        }
+    }
+}
+
+/// Value of the first `name` parameter in `query`, percent-decoded
+pub(crate) fn query_param<'a>(query: &'a str, name: &str) -> Option<Cow<'a, str>> {
+    query.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        (key == name).then(|| percent_decode(value).ok()).flatten()
+    })
+}
+
+/// Builds a `Location` (or any URI reference) from untrusted parts, each encoded for its position
+///
+/// e.g. `Location::new("/users").segment("a b").query("next", "/x?y").to_string()` is
+/// `/users/a%20b?next=/x?y`
+#[derive(Debug, Clone)]
+pub struct Location {
+    path: String,
+    query: String,
+}
+
+impl Location {
+    /// `base` is trusted, e.g. `https://example.com/app`, and only made header safe
+    pub fn new(base: &str) -> Self {
+        let path = percent_encode(base, EncodeSet::HeaderValue).into_owned();
+        Self { path, query: String::new() }
+    }
+
+    pub fn segment(mut self, segment: &str) -> Self {
+        if !self.path.ends_with('/') {
+            self.path.push('/');
+        }
+        self.path.push_str(&percent_encode(segment, EncodeSet::PathSegment));
+        self
+    }
+
+    pub fn query(mut self, key: &str, value: &str) -> Self {
+        self.query.push(if self.query.is_empty() { '?' } else { '&' });
+        self.query.push_str(&percent_encode(key, EncodeSet::QueryComponent));
+        self.query.push('=');
+        self.query.push_str(&percent_encode(value, EncodeSet::QueryComponent));
+        self
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.path, self.query)
+    }
+}
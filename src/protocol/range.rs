@@ -0,0 +1,138 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::ops::Range;
+
+use super::CRLF;
+
+/// ranges served at most, requests for more get the whole representation
+const MAX_RANGES: usize = 16;
+
+/// The parts of a representation a `Range` header asks for (RFC 9110, section 14.2)
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ByteRanges {
+    /// a range unit other than bytes, malformed, or more or overlapping ranges than are worth
+    /// serving separately; answered as if there was no `Range`
+    Whole,
+    /// the ranges which overlap the representation, in the order asked for and cut to its end
+    Satisfiable(Vec<Range<u64>>),
+    /// none overlaps the representation, answered with 416
+    Unsatisfiable,
+}
+
+impl ByteRanges {
+    /// `bytes=` followed by `first-last`, `first-` and `-suffix` ranges, of a representation of
+    /// `len` bytes
+    pub fn parse(value: &str, len: u64) -> Self {
+        let value = value.trim();
+        let Some(unit) = value.get(..6).filter(|unit| unit.eq_ignore_ascii_case("bytes=")) else {
+            return ByteRanges::Whole;
+        };
+        let mut satisfiable = Vec::new();
+        let mut count = 0;
+        for range in value[unit.len()..].split(',').map(str::trim).filter(|r| !r.is_empty()) {
+            count += 1;
+            let Some((first, last)) = range.split_once('-') else {
+                return ByteRanges::Whole;
+            };
+            let (first, last) = (parse_pos(first), parse_pos(last));
+            let range = match (first, last) {
+                (Some(Some(first)), Some(last)) => match last {
+                    Some(last) if last < first => return ByteRanges::Whole,
+                    Some(last) => first..last.saturating_add(1).min(len),
+                    None => first..len,
+                },
+                (Some(None), Some(Some(suffix))) => len.saturating_sub(suffix)..len,
+                _ => return ByteRanges::Whole,
+            };
+            if range.start < range.end {
+                satisfiable.push(range);
+            }
+        }
+        if count == 0 || count > MAX_RANGES {
+            return ByteRanges::Whole;
+        }
+
+        let mut sorted = satisfiable.clone();
+        sorted.sort_unstable_by_key(|range| range.start);
+        if sorted.windows(2).any(|pair| pair[1].start < pair[0].end) {
+            return ByteRanges::Whole;
+        }
+        match satisfiable.is_empty() {
+            true => ByteRanges::Unsatisfiable,
+            false => ByteRanges::Satisfiable(satisfiable),
+        }
+    }
+}
+
+/// `Some(None)` for an empty position, `None` for one that isn't a number
+fn parse_pos(s: &str) -> Option<Option<u64>> {
+    match s {
+        "" => Some(None),
+        _ if s.bytes().all(|b| b.is_ascii_digit()) => s.parse().ok().map(Some),
+        _ => None,
+    }
+}
+
+/// `Content-Range` value of `range` of a representation of `len` bytes
+pub fn content_range(range: &Range<u64>, len: u64) -> String {
+    format!("bytes {}-{}/{len}", range.start, range.end - 1)
+}
+
+/// Encodes several ranges of a representation as a `multipart/byteranges` body (RFC 9110,
+/// section 14.6)
+#[derive(Clone, Debug)]
+pub struct ByteRangesBody {
+    boundary: String,
+}
+
+impl Default for ByteRangesBody {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ByteRangesBody {
+    /// With a random boundary, which is not checked against the contents
+    pub fn new() -> Self {
+        let random = || RandomState::new().hash_one(0u8);
+        Self { boundary: format!("toot-{:016x}{:016x}", random(), random()) }
+    }
+
+    pub fn boundary(mut self, boundary: &str) -> Self {
+        self.boundary = boundary.to_owned();
+        self
+    }
+
+    /// Value of the `Content-Type` header of the response
+    pub fn content_type(&self) -> String {
+        format!("multipart/byteranges; boundary={}", self.boundary)
+    }
+
+    /// One part per range of `contents`, each with `content_type` and its `Content-Range`
+    pub fn encode(&self, contents: &[u8], ranges: &[Range<u64>], content_type: &str) -> Vec<u8> {
+        let len = contents.len() as u64;
+        let mut body = Vec::new();
+        for range in ranges {
+            body.extend_from_slice(self.part_head(range, len, content_type).as_bytes());
+            body.extend_from_slice(&contents[range.start as usize..range.end as usize]);
+            body.extend_from_slice(CRLF.as_bytes());
+        }
+        body.extend_from_slice(self.closing().as_bytes());
+        body
+    }
+
+    /// What precedes the bytes of `range` of a representation `len` bytes long, which are
+    /// followed by a CRLF, for bodies written a part at a time
+    pub fn part_head(&self, range: &Range<u64>, len: u64, content_type: &str) -> String {
+        format!(
+            "--{}{CRLF}Content-Type: {content_type}{CRLF}Content-Range: {}{CRLF}{CRLF}",
+            self.boundary,
+            content_range(range, len)
+        )
+    }
+
+    /// What follows the last part
+    pub fn closing(&self) -> String {
+        format!("--{}--{CRLF}", self.boundary)
+    }
+}
@@ -1,44 +1,281 @@
+use std::borrow::Cow;
 use std::fmt::{Display, Formatter};
-use std::io;
 use std::str::FromStr;
 
 use tokio::io::{AsyncRead, AsyncReadExt};
 
-use super::{Headers, HttpVersion, Method, ParseRequestError};
+use super::response::read_chunked_body;
+use super::{
+    is_token, Extensions, Headers, HttpVersion, Method, ParseRequestError, RequestTarget, CRLF,
+};
+
+/// Upper bounds applied while reading a request
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct RequestLimits {
+    /// request line and each header line, without the `CRLF`
+    pub max_line_len: usize,
+    pub max_headers: usize,
+    pub max_body_len: usize,
+    pub leniency: Leniency,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self {
+            max_line_len: 8 * 1024,
+            max_headers: 100,
+            max_body_len: 2 * 1024 * 1024,
+            leniency: Leniency::STRICT,
+        }
+    }
+}
+
+/// Deviations from the message syntax tolerated for legacy clients and embedded devices
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default, deny_unknown_fields))]
+pub struct Leniency {
+    /// request line parts separated by runs of spaces or tabs
+    pub request_line_whitespace: bool,
+    /// status lines ending right after the status code, without the space before the reason
+    pub missing_reason_phrase: bool,
+    /// lines terminated by `LF` alone
+    pub bare_lf: bool,
+    /// header bytes above 0x7f read as latin-1 instead of rejecting them
+    pub latin1_header_values: bool,
+}
+
+impl Leniency {
+    pub const STRICT: Leniency = Leniency {
+        request_line_whitespace: false,
+        missing_reason_phrase: false,
+        bare_lf: false,
+        latin1_header_values: false,
+    };
+    pub const LENIENT: Leniency = Leniency {
+        request_line_whitespace: true,
+        missing_reason_phrase: true,
+        bare_lf: true,
+        latin1_header_values: true,
+    };
+}
 
 pub async fn read_http_request<R>(reader: &mut R) -> Result<RawRequest, ParseRequestError>
 where
     R: AsyncRead + ?Sized + Unpin,
 {
-    let line = read_next_line(reader).await?;
-    let request_line = String::from_utf8_lossy(&line).parse::<RequestLine>()?;
+    read_http_request_with(reader, &RequestLimits::default()).await
+}
+
+pub async fn read_http_request_with<R>(
+    reader: &mut R,
+    limits: &RequestLimits,
+) -> Result<RawRequest, ParseRequestError>
+where
+    R: AsyncRead + ?Sized + Unpin,
+{
+    let (request_line, mut headers) = read_request_head(reader, limits).await?;
+    let body = read_request_body(reader, &mut headers, limits).await?;
+
+    let request = RawRequest { request_line, headers, body, extensions: Extensions::new() };
+    Ok(request)
+}
+
+/// The request line and headers of `read_http_request_with`
+pub(crate) async fn read_request_head<R>(
+    reader: &mut R,
+    limits: &RequestLimits,
+) -> Result<(RequestLine, Headers), ParseRequestError>
+where
+    R: AsyncRead + ?Sized + Unpin,
+{
+    let line = read_next_line(reader, limits.max_line_len, limits.leniency.bare_lf).await?;
+    let request_line = RequestLine::parse_with(ascii_line(&line)?, &limits.leniency)?;
 
     let mut headers = Headers::empty();
     loop {
-        let line = read_next_line(reader).await?;
+        let line = read_next_line(reader, limits.max_line_len, limits.leniency.bare_lf).await?;
         if line.is_empty() {
             break;
         }
-        let header = String::from_utf8_lossy(&line).parse()?;
+        if headers.len() == limits.max_headers {
+            return Err(ParseRequestError::TooManyHeaders);
+        }
+        let header = text_line(&line, limits.leniency.latin1_header_values)?.parse()?;
         headers.push(header);
     }
+    Ok((request_line, headers))
+}
 
-    let body = {
-        if let Some(length) = headers.get("Content-Length").and_then(|v| v.parse::<usize>().ok()) {
+/// The body following a head read by `read_request_head`.
+///
+/// A chunked body is decoded, its `Transfer-Encoding` replaced by the `Content-Length` of the
+/// result. Other transfer codings leave the body's length unknown and fail.
+pub(crate) async fn read_request_body<R>(
+    reader: &mut R,
+    headers: &mut Headers,
+    limits: &RequestLimits,
+) -> Result<Option<Vec<u8>>, ParseRequestError>
+where
+    R: AsyncRead + ?Sized + Unpin,
+{
+    if let Some(coding) = headers.get_all("Transfer-Encoding").flat_map(|v| v.split(',')).last() {
+        // chunked must come last, anything else can't be told apart from the next request
+        if !coding.trim().eq_ignore_ascii_case("chunked") {
+            let codings = headers.get_all("Transfer-Encoding").collect::<Vec<_>>().join(", ");
+            return Err(ParseRequestError::InvalidHeader(format!("Transfer-Encoding: {codings}")));
+        }
+        let body = read_chunked_body(reader, limits).await?;
+        headers.retain(|field, _| {
+            !field.eq_ignore_ascii_case("Transfer-Encoding")
+                && !field.eq_ignore_ascii_case("Content-Length")
+        });
+        headers.set("Content-Length", body.len().to_string());
+        return Ok(Some(body));
+    }
+    match body_len(headers, limits)? {
+        Some(length) => {
             let mut body = vec![0; length];
             reader.read_exact(&mut body).await?;
-            Some(body)
-        } else {
-            None
+            Ok(Some(body))
         }
+        None => Ok(None),
+    }
+}
+
+/// `parse_request_head_with` the default `RequestLimits`
+pub fn parse_request_head(
+    buf: &[u8],
+) -> Result<Option<(RequestLine, Headers, usize)>, ParseRequestError> {
+    parse_request_head_with(buf, &RequestLimits::default())
+}
+
+/// Parses the request line and headers at the start of `buf` as `read_http_request_with`
+/// reads them, `None` while they're incomplete. Returns them with the length of the head.
+pub fn parse_request_head_with(
+    buf: &[u8],
+    limits: &RequestLimits,
+) -> Result<Option<(RequestLine, Headers, usize)>, ParseRequestError> {
+    let mut lines = BufferLines::new(buf, limits);
+    let Some(line) = lines.next_line()? else {
+        return Ok(None);
     };
+    let request_line = RequestLine::parse_with(ascii_line(line)?, &limits.leniency)?;
 
-    let request = RawRequest { request_line, headers, body };
-    Ok(request)
+    let mut headers = Headers::empty();
+    loop {
+        let Some(line) = lines.next_line()? else {
+            return Ok(None);
+        };
+        if line.is_empty() {
+            return Ok(Some((request_line, headers, lines.pos)));
+        }
+        if headers.len() == limits.max_headers {
+            return Err(ParseRequestError::TooManyHeaders);
+        }
+        let header = text_line(line, limits.leniency.latin1_header_values)?.parse()?;
+        headers.push(header);
+    }
+}
+
+/// The `Content-Length` of a request body, if within the limit
+pub(crate) fn body_len(
+    headers: &Headers,
+    limits: &RequestLimits,
+) -> Result<Option<usize>, ParseRequestError> {
+    match content_length(headers)? {
+        Some(length) if length > limits.max_body_len as u64 => {
+            Err(ParseRequestError::BodyTooLarge(length.try_into().unwrap_or(usize::MAX)))
+        }
+        length => Ok(length.map(|length| length as usize)),
+    }
+}
+
+/// The `Content-Length` of a message, `1*DIGIT` only. Repeated fields and lists are accepted as
+/// long as all of them agree (RFC 9112, section 6.3), anything else frames the body in a way
+/// another reader may not and fails.
+pub(crate) fn content_length(headers: &Headers) -> Result<Option<u64>, ParseRequestError> {
+    let invalid = || {
+        let values = headers.get_all("Content-Length").collect::<Vec<_>>().join(", ");
+        ParseRequestError::InvalidHeader(format!("Content-Length: {values}"))
+    };
+    let mut length = None;
+    for value in headers.get_all("Content-Length").flat_map(|v| v.split(',')) {
+        let value = value.trim();
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        let value = value.parse::<u64>().map_err(|_| invalid())?;
+        if length.is_some_and(|length| length != value) {
+            return Err(invalid());
+        }
+        length = Some(value);
+    }
+    Ok(length)
+}
+
+/// Lines of a buffer, checked like those of `read_next_line`
+pub(crate) struct BufferLines<'a> {
+    buf: &'a [u8],
+    /// where the next line starts
+    pub(crate) pos: usize,
+    max_len: usize,
+    bare_lf: bool,
+}
+
+impl<'a> BufferLines<'a> {
+    pub(crate) fn new(buf: &'a [u8], limits: &RequestLimits) -> Self {
+        Self { buf, pos: 0, max_len: limits.max_line_len, bare_lf: limits.leniency.bare_lf }
+    }
+
+    pub(crate) fn next_line(&mut self) -> Result<Option<&'a [u8]>, ParseRequestError> {
+        let rest = &self.buf[self.pos..];
+        let Some(end) = rest.iter().position(|b| *b == b'\n') else {
+            // one extra byte for a `CR` which may still be followed by `LF`
+            return match rest.len() > self.max_len.saturating_add(1) {
+                true => Err(ParseRequestError::LineTooLong),
+                false => Ok(None),
+            };
+        };
+        if end > self.max_len.saturating_add(1) {
+            return Err(ParseRequestError::LineTooLong);
+        }
+        self.pos += end + 1;
+        match rest[..end].strip_suffix(b"\r") {
+            Some(line) => Ok(Some(line)),
+            None if self.bare_lf => Ok(Some(&rest[..end])),
+            None => Err(ParseRequestError::BareLineFeed),
+        }
+    }
 }
 
-/// Reads until `CRLF` is reached
-async fn read_next_line<R>(reader: &mut R) -> io::Result<Vec<u8>>
+/// Visible ASCII, `SP` and `HTAB` only
+pub(crate) fn ascii_line(line: &[u8]) -> Result<&str, ParseRequestError> {
+    match line.iter().find(|b| !(b.is_ascii_graphic() || **b == b' ' || **b == b'\t')) {
+        Some(b) => Err(ParseRequestError::InvalidByte(*b)),
+        None => Ok(std::str::from_utf8(line).expect("ASCII is valid UTF-8")),
+    }
+}
+
+/// Like `ascii_line`, with bytes above 0x7f (`obs-text`) decoded as latin-1 when `latin1` is set
+pub(crate) fn text_line(line: &[u8], latin1: bool) -> Result<Cow<'_, str>, ParseRequestError> {
+    if !latin1 || line.is_ascii() {
+        return ascii_line(line).map(Cow::Borrowed);
+    }
+    match line.iter().find(|b| b.is_ascii_control() && **b != b'\t') {
+        Some(b) => Err(ParseRequestError::InvalidByte(*b)),
+        None => Ok(Cow::Owned(line.iter().map(|b| char::from(*b)).collect())),
+    }
+}
+
+/// Reads until `CRLF` is reached, or a bare `LF` when `bare_lf` is set.
+///
+/// Without `bare_lf` an `LF` not preceded by `CR` fails right away, rather than waiting for a
+/// `CRLF` an LF-only client never sends.
+pub(crate) async fn read_next_line<R>(
+    reader: &mut R,
+    max_len: usize,
+    bare_lf: bool,
+) -> Result<Vec<u8>, ParseRequestError>
 where
     R: AsyncRead + ?Sized + Unpin,
 {
@@ -48,36 +285,150 @@ where
     loop {
         let byte = reader.read_u8().await?;
 
-        if byte == b'\n' && prev_byte_was_cr {
-            line.pop();
-            return Ok(line);
+        if byte == b'\n' {
+            if prev_byte_was_cr {
+                line.pop();
+                return Ok(line);
+            }
+            if bare_lf {
+                return Ok(line);
+            }
+            return Err(ParseRequestError::BareLineFeed);
         }
 
+        // one extra byte for a `CR` which may still be followed by `LF`
+        if line.len() > max_len {
+            return Err(ParseRequestError::LineTooLong);
+        }
         prev_byte_was_cr = byte == b'\r';
         line.push(byte);
     }
 }
 
+#[derive(Debug)]
 pub struct RawRequest {
     pub request_line: RequestLine,
     pub headers: Headers,
     pub body: Option<Vec<u8>>,
+    /// not part of the message, see `Extensions`
+    pub extensions: Extensions,
 }
 
-#[derive(Debug)]
+impl RawRequest {
+    /// Replaces the body and its framing: a `Content-Length` for a body, even an empty one, and
+    /// none for no body at all
+    pub fn set_body(&mut self, body: Option<Vec<u8>>) {
+        self.headers.retain(|field, _| {
+            !field.eq_ignore_ascii_case("Transfer-Encoding")
+                && !field.eq_ignore_ascii_case("Content-Length")
+        });
+        if let Some(ref body) = body {
+            self.headers.set("Content-Length", body.len().to_string());
+        }
+        self.body = body;
+    }
+
+    /// The message as written. A body, even an empty one, goes with its `Content-Length`;
+    /// without one the framing headers are left as they are, e.g. for a body streamed after
+    /// the head.
+    pub fn into_vec(self) -> Vec<u8> {
+        let Self { request_line, mut headers, body, .. } = self;
+        if let Some(ref body) = body {
+            let length = body.len().to_string();
+            let framed = headers.get_all("Content-Length").eq([length.as_str()])
+                && headers.get("Transfer-Encoding").is_none();
+            if !framed {
+                headers.retain(|field, _| !field.eq_ignore_ascii_case("Transfer-Encoding"));
+                headers.set("Content-Length", body.len().to_string());
+            }
+        }
+        let mut buffer = Vec::<u8>::with_capacity(512);
+
+        buffer.extend_from_slice(request_line.to_http_message().as_bytes());
+        buffer.extend_from_slice(headers.to_http_message().as_bytes());
+        buffer.extend_from_slice(CRLF.as_bytes());
+        if let Some(body) = body {
+            buffer.extend_from_slice(&body);
+        }
+
+        buffer
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct RequestLine {
     pub method: Method,
-    pub uri: String,
+    pub target: RequestTarget,
     pub version: HttpVersion,
 }
 
+impl RequestLine {
+    /// `method SP request-target SP HTTP-version`, or with any whitespace runs when lenient
+    pub fn parse_with(s: &str, leniency: &Leniency) -> Result<Self, ParseRequestError> {
+        let invalid = || ParseRequestError::RequestLine(s.to_owned());
+        let (method, target, version) = split_request_line(s, leniency)?;
+        // the authority form is for CONNECT only, which takes no other, the asterisk for OPTIONS
+        let target = target.parse::<RequestTarget>()?;
+        let suits_method = match target {
+            RequestTarget::AuthorityForm(..) => method == Method::CONNECT,
+            _ if method == Method::CONNECT => false,
+            RequestTarget::AsteriskForm => method == Method::OPTIONS,
+            _ => true,
+        };
+        if !suits_method {
+            return Err(invalid());
+        }
+        let request_line = RequestLine { method, target, version };
+        Ok(request_line)
+    }
+
+    pub fn to_http_message(&self) -> String {
+        format!("{self}{CRLF}")
+    }
+}
+
+/// The method, target and version of a request line, the target not yet parsed
+pub(crate) fn split_request_line<'a>(
+    s: &'a str,
+    leniency: &Leniency,
+) -> Result<(Method, &'a str, HttpVersion), ParseRequestError> {
+    let invalid = || ParseRequestError::RequestLine(s.to_owned());
+    let lenient = leniency.request_line_whitespace;
+    let mut parts =
+        s.split(|c| c == ' ' || lenient && c == '\t').filter(|part| !lenient || !part.is_empty());
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+    if target.is_empty() {
+        return Err(invalid());
+    }
+
+    // well-formed but unsupported methods and versions are told apart, for 501 and 505
+    let method = match is_token(method) {
+        true => method.parse::<Method>()?,
+        false => return Err(invalid()),
+    };
+    let version = match version.strip_prefix("HTTP/").is_some_and(is_version_number) {
+        true => version.parse::<HttpVersion>()?,
+        false => return Err(invalid()),
+    };
+    Ok((method, target, version))
+}
+
+/// `DIGIT "." DIGIT`
+fn is_version_number(s: &str) -> bool {
+    matches!(s.as_bytes(), [major, b'.', minor] if major.is_ascii_digit() && minor.is_ascii_digit())
+}
+
 impl Display for RequestLine {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let method = self.method.as_str();
-        let uri = self.uri.as_str();
+        let target = &self.target;
         let version = self.version.as_str();
 
-        write!(f, "{method} {uri} {version}")
+        write!(f, "{method} {target} {version}")
     }
 }
 
@@ -85,17 +436,6 @@ impl FromStr for RequestLine {
     type Err = ParseRequestError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut parts = s.split(' ');
-
-        let method = parts.next().and_then(|w| w.parse::<Method>().ok());
-        let uri = parts.next().map(|w| w.to_owned());
-        let version = parts.next().and_then(|w| w.parse::<HttpVersion>().ok());
-
-        let request_line = RequestLine {
-            method: method.ok_or(ParseRequestError::RequestLine(s.to_owned()))?,
-            uri: uri.ok_or(ParseRequestError::RequestLine(s.to_owned()))?,
-            version: version.ok_or(ParseRequestError::RequestLine(s.to_owned()))?,
-        };
-        Ok(request_line)
+        RequestLine::parse_with(s, &Leniency::STRICT)
     }
 }
@@ -1,26 +1,423 @@
-use std::fmt::{Display, Formatter};
 use std::io;
 use std::io::Cursor;
 use std::io::Write;
+use std::str::FromStr;
 
-use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
-use super::{Headers, HttpVersion, StatusCode, CRLF};
+use super::percent::{percent_encode, EncodeSet};
+use super::request::{content_length, read_next_line, text_line, BufferLines};
+use super::{
+    Body, FramingError, Headers, HttpVersion, Leniency, Method, ParseRequestError, RequestLimits,
+    StatusCode, CRLF,
+};
 
 pub async fn write_http_response<W>(writer: &mut W, response: RawResponse) -> io::Result<()>
 where
     W: AsyncWrite + ?Sized + Unpin,
 {
-    let message = response.into_vec();
-    writer.write_all(&message).await?;
+    // the body is written from its own buffer instead of being copied behind the head
+    let chunked = is_chunked(&response.headers);
+    let RawResponse { status_line, headers, body, stream } = response;
+    let mut head = Vec::<u8>::with_capacity(256);
+    head.extend_from_slice(status_line.to_http_message().as_bytes());
+    head.extend_from_slice(headers.to_http_message().as_bytes());
+    head.extend_from_slice(CRLF.as_bytes());
+
+    writer.write_all(&head).await?;
+    if let Some(body) = body {
+        writer.write_all(&body).await?;
+    }
+    if let Some(stream) = stream {
+        stream.write_to(writer, chunked).await?;
+    }
     Ok(())
 }
 
+/// Writes `response` and relays its streamed `body` as it arrives, e.g. from an upstream to a
+/// client speaking `version`.
+///
+/// A body of known length keeps its `Content-Length`. Any other is sent in chunked transfer
+/// coding with the trailers it came with to HTTP/1.1 clients, and until the connection closes
+/// to older ones. Returns the length of the body.
+pub async fn write_streaming_response<W, R>(
+    writer: &mut W,
+    response: RawResponse,
+    body: &mut BodyStream<R>,
+    version: HttpVersion,
+) -> Result<u64, ParseRequestError>
+where
+    W: AsyncWrite + ?Sized + Unpin,
+    R: AsyncRead + Unpin,
+{
+    let mut response = response;
+    response.body = None;
+    response.stream = None;
+    response.headers.retain(|field, _| !field.eq_ignore_ascii_case("Transfer-Encoding"));
+    let chunked = !body.done && body.framing != Framing::Length && version == HttpVersion::Http1_1;
+    // without a body, e.g. to HEAD, `Content-Length` is that of the body it would have had
+    if !body.done && body.framing == Framing::Length {
+        response.headers.set("Content-Length", body.remaining.to_string());
+    } else if !body.done {
+        response.headers.retain(|field, _| !field.eq_ignore_ascii_case("Content-Length"));
+        if chunked {
+            response.headers.set("Transfer-Encoding", "chunked".to_owned());
+        }
+    }
+    write_http_response(writer, response).await?;
+
+    let mut written = 0;
+    while let Some(chunk) = body.chunk().await? {
+        if chunked {
+            writer.write_all(format!("{:X}\r\n", chunk.len()).as_bytes()).await?;
+            writer.write_all(&chunk).await?;
+            writer.write_all(CRLF.as_bytes()).await?;
+        } else {
+            writer.write_all(&chunk).await?;
+        }
+        written += chunk.len() as u64;
+    }
+    if chunked {
+        let trailers = body.trailers.to_http_message();
+        writer.write_all(format!("0\r\n{trailers}\r\n").as_bytes()).await?;
+    }
+    writer.flush().await?;
+    Ok(written)
+}
+
+/// Reads a response to a `method` request, the body is framed by `Content-Length`, chunked
+/// transfer coding or the end of the stream, whichever the response declares
+pub async fn read_http_response<R>(
+    reader: &mut R,
+    method: Method,
+    limits: &RequestLimits,
+) -> Result<RawResponse, ParseRequestError>
+where
+    R: AsyncRead + ?Sized + Unpin,
+{
+    let (status_line, mut headers) = read_response_head(reader, limits).await?;
+    if !has_body(method, status_line.status) {
+        return Ok(RawResponse { status_line, headers, body: None, stream: None });
+    }
+
+    let body = if is_chunked(&headers) {
+        let body = read_chunked_body(reader, limits).await?;
+        headers.retain(|field, _| !field.eq_ignore_ascii_case("Transfer-Encoding"));
+        body
+    } else if let Some(length) = content_length(&headers)? {
+        if length > limits.max_body_len as u64 {
+            return Err(ParseRequestError::BodyTooLarge(length.try_into().unwrap_or(usize::MAX)));
+        }
+        let mut body = vec![0; length as usize];
+        reader.read_exact(&mut body).await?;
+        body
+    } else {
+        let mut body = Vec::new();
+        let max = limits.max_body_len as u64;
+        reader.take(max + 1).read_to_end(&mut body).await?;
+        if body.len() as u64 > max {
+            return Err(ParseRequestError::BodyTooLarge(body.len()));
+        }
+        // delimited by the upstream closing, which the `Content-Length` set below would hide
+        headers.set("Connection", "close".to_owned());
+        body
+    };
+
+    Ok(RawResponse::new(status_line, headers, Some(body)))
+}
+
+/// Reads the head of a response to a `method` request and leaves its body, framed as for
+/// `read_http_response`, to be read from the returned `BodyStream` as it arrives.
+///
+/// The response keeps its framing headers and has no body.
+pub async fn read_http_response_head<R>(
+    reader: R,
+    method: Method,
+    limits: &RequestLimits,
+) -> Result<(RawResponse, BodyStream<R>), ParseRequestError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut reader = reader;
+    let (status_line, headers) = read_response_head(&mut reader, limits).await?;
+    let (framing, remaining) = if !has_body(method, status_line.status) {
+        (Framing::Length, 0)
+    } else if is_chunked(&headers) {
+        (Framing::Chunked, 0)
+    } else if let Some(length) = content_length(&headers)? {
+        (Framing::Length, length)
+    } else {
+        (Framing::Close, 0)
+    };
+
+    let body = BodyStream {
+        reader,
+        limits: *limits,
+        framing,
+        remaining,
+        started: false,
+        done: !has_body(method, status_line.status),
+        trailers: Headers::empty(),
+    };
+    Ok((RawResponse { status_line, headers, body: None, stream: None }, body))
+}
+
+async fn read_response_head<R>(
+    reader: &mut R,
+    limits: &RequestLimits,
+) -> Result<(StatusLine, Headers), ParseRequestError>
+where
+    R: AsyncRead + ?Sized + Unpin,
+{
+    let line = read_next_line(reader, limits.max_line_len, limits.leniency.bare_lf).await?;
+    // the reason phrase may carry `obs-text`, it is dropped anyway
+    let status_line = StatusLine::parse_with(&text_line(&line, true)?, &limits.leniency)?;
+
+    let mut headers = Headers::empty();
+    loop {
+        let line = read_next_line(reader, limits.max_line_len, limits.leniency.bare_lf).await?;
+        if line.is_empty() {
+            break;
+        }
+        if headers.len() == limits.max_headers {
+            return Err(ParseRequestError::TooManyHeaders);
+        }
+        headers.push(text_line(&line, limits.leniency.latin1_header_values)?.parse()?);
+    }
+    Ok((status_line, headers))
+}
+
+fn has_body(method: Method, status: StatusCode) -> bool {
+    let status = *status;
+    !(method == Method::HEAD || (100..200).contains(&status) || status == 204 || status == 304)
+}
+
+fn is_chunked(headers: &Headers) -> bool {
+    headers
+        .get_all("Transfer-Encoding")
+        .flat_map(|v| v.split(','))
+        .any(|v| v.trim().eq_ignore_ascii_case("chunked"))
+}
+
+/// The size of a chunk from its size line without the `CRLF`, `1*HEXDIG` followed by optional
+/// whitespace and chunk extensions, which are ignored
+pub fn parse_chunk_size(line: &[u8]) -> Result<usize, ParseRequestError> {
+    let invalid = || ParseRequestError::InvalidHeader(String::from_utf8_lossy(line).into_owned());
+    let end = line.iter().position(|b| *b == b';').unwrap_or(line.len());
+    let size = line[..end].trim_ascii_end();
+    if size.is_empty() || !size.iter().all(u8::is_ascii_hexdigit) {
+        return Err(invalid());
+    }
+    let size = std::str::from_utf8(size).expect("hex digits are valid UTF-8");
+    usize::from_str_radix(size, 16).map_err(|_| invalid())
+}
+
+pub(crate) async fn read_chunked_body<R>(
+    reader: &mut R,
+    limits: &RequestLimits,
+) -> Result<Vec<u8>, ParseRequestError>
+where
+    R: AsyncRead + ?Sized + Unpin,
+{
+    let mut body = Vec::new();
+    loop {
+        let line = read_next_line(reader, limits.max_line_len, limits.leniency.bare_lf).await?;
+        let size = parse_chunk_size(&line)?;
+        if size == 0 {
+            break;
+        }
+        let len = body.len().saturating_add(size);
+        if len > limits.max_body_len {
+            return Err(ParseRequestError::BodyTooLarge(len));
+        }
+
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..]).await?;
+        if !read_next_line(reader, 0, limits.leniency.bare_lf).await?.is_empty() {
+            return Err(ParseRequestError::InvalidHeader(String::from_utf8_lossy(&line).into()));
+        }
+    }
+    // trailers are discarded
+    while !read_next_line(reader, limits.max_line_len, limits.leniency.bare_lf).await?.is_empty() {}
+    Ok(body)
+}
+
+/// The chunked body at the start of `buf` as `read_chunked_body` reads it, `None` while it's
+/// incomplete. Returns it with the length it took up in `buf`.
+pub(crate) fn parse_chunked_body(
+    buf: &[u8],
+    limits: &RequestLimits,
+) -> Result<Option<(Vec<u8>, usize)>, ParseRequestError> {
+    let mut lines = BufferLines::new(buf, limits);
+    let mut body = Vec::new();
+    loop {
+        let Some(line) = lines.next_line()? else {
+            return Ok(None);
+        };
+        let size = parse_chunk_size(line)?;
+        if size == 0 {
+            break;
+        }
+        let len = body.len().saturating_add(size);
+        if len > limits.max_body_len {
+            return Err(ParseRequestError::BodyTooLarge(len));
+        }
+        let Some(chunk) = buf.get(lines.pos..lines.pos + size) else {
+            return Ok(None);
+        };
+        body.extend_from_slice(chunk);
+        lines.pos += size;
+        match lines.next_line()? {
+            None => return Ok(None),
+            Some([]) => {}
+            Some(_) => {
+                return Err(ParseRequestError::InvalidHeader(String::from_utf8_lossy(line).into()))
+            }
+        }
+    }
+    // trailers are discarded
+    loop {
+        match lines.next_line()? {
+            None => return Ok(None),
+            Some([]) => return Ok(Some((body, lines.pos))),
+            Some(_) => {}
+        }
+    }
+}
+
+/// pieces of a streamed body are at most this large
+const STREAM_CHUNK_LEN: u64 = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Framing {
+    Length,
+    Chunked,
+    Close,
+}
+
+/// Response body read piece by piece, see `read_http_response_head`.
+///
+/// Unlike with `read_http_response`, `max_body_len` doesn't apply, nothing is kept in memory.
 #[derive(Debug)]
+pub struct BodyStream<R> {
+    reader: R,
+    limits: RequestLimits,
+    framing: Framing,
+    /// left of the body or of the current chunk
+    remaining: u64,
+    /// a chunk was read, whose `CRLF` comes before the next chunk size
+    started: bool,
+    done: bool,
+    trailers: Headers,
+}
+
+impl<R> BodyStream<R>
+where
+    R: AsyncRead + Unpin,
+{
+    /// The next piece of the body, `None` once all of it was read
+    pub async fn chunk(&mut self) -> Result<Option<Vec<u8>>, ParseRequestError> {
+        if self.done {
+            return Ok(None);
+        }
+        let (max_line_len, bare_lf) = (self.limits.max_line_len, self.limits.leniency.bare_lf);
+        if self.framing == Framing::Chunked && self.remaining == 0 {
+            let line = match self.started {
+                true => read_next_line(&mut self.reader, 0, bare_lf).await?,
+                false => Vec::new(),
+            };
+            if !line.is_empty() {
+                return Err(ParseRequestError::InvalidHeader(
+                    String::from_utf8_lossy(&line).into(),
+                ));
+            }
+            self.started = true;
+            let line = read_next_line(&mut self.reader, max_line_len, bare_lf).await?;
+            self.remaining = parse_chunk_size(&line)? as u64;
+            if self.remaining == 0 {
+                self.read_trailers().await?;
+                self.done = true;
+                return Ok(None);
+            }
+        }
+
+        let len = match self.framing {
+            Framing::Close => STREAM_CHUNK_LEN,
+            _ => self.remaining.min(STREAM_CHUNK_LEN),
+        };
+        if len == 0 {
+            self.done = true;
+            return Ok(None);
+        }
+        let mut chunk = vec![0; len as usize];
+        let n = self.reader.read(&mut chunk).await?;
+        if n == 0 {
+            if self.framing != Framing::Close {
+                return Err(ParseRequestError::Io(io::ErrorKind::UnexpectedEof));
+            }
+            self.done = true;
+            return Ok(None);
+        }
+        chunk.truncate(n);
+        if self.framing != Framing::Close {
+            self.remaining -= n as u64;
+        }
+        Ok(Some(chunk))
+    }
+
+    async fn read_trailers(&mut self) -> Result<(), ParseRequestError> {
+        let limits = &self.limits;
+        loop {
+            let line =
+                read_next_line(&mut self.reader, limits.max_line_len, limits.leniency.bare_lf)
+                    .await?;
+            if line.is_empty() {
+                return Ok(());
+            }
+            if self.trailers.len() == limits.max_headers {
+                return Err(ParseRequestError::TooManyHeaders);
+            }
+            self.trailers.push(text_line(&line, limits.leniency.latin1_header_values)?.parse()?);
+        }
+    }
+
+    /// Writes the rest of the body to `writer`, e.g. a file, returns how many bytes that was
+    pub async fn copy_to<W>(&mut self, writer: &mut W) -> Result<u64, ParseRequestError>
+    where
+        W: AsyncWrite + ?Sized + Unpin,
+    {
+        let mut copied = 0;
+        while let Some(chunk) = self.chunk().await? {
+            writer.write_all(&chunk).await?;
+            copied += chunk.len() as u64;
+        }
+        writer.flush().await?;
+        Ok(copied)
+    }
+
+    /// Fields sent after a chunked body, complete once it was read
+    pub fn trailers(&self) -> &Headers {
+        &self.trailers
+    }
+
+    /// Whether the whole body was read
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct RawResponse {
     status_line: StatusLine,
     headers: Headers,
     body: Option<Vec<u8>>,
+    /// in place of `body`
+    stream: Option<Body>,
 }
 
 impl RawResponse {
@@ -30,11 +427,145 @@ impl RawResponse {
             headers.set("Content-Length", body.len().to_string())
         };
 
-        Self { status_line, headers, body }
+        Self { status_line, headers, body, stream: None }
+    }
+
+    /// Response with a body written as it's read, with its `Content-Length` if known and in
+    /// chunked transfer coding otherwise, which the server replaces with closing the
+    /// connection after the body for HTTP/1.0 clients
+    pub fn streaming(status_line: StatusLine, headers: Headers, body: Body) -> Self {
+        let mut headers = headers;
+        match body.len() {
+            Some(len) => headers.set("Content-Length", len.to_string()),
+            None => {
+                headers.retain(|field, _| !field.eq_ignore_ascii_case("Content-Length"));
+                headers.set("Transfer-Encoding", "chunked".to_owned());
+                if let Some(field) = body.trailer_field() {
+                    headers.set("Trailer", field.to_owned());
+                }
+            }
+        }
+        Self { status_line, headers, body: None, stream: Some(body) }
+    }
+
+    /// Redirect to `location`, which is percent-encoded where it couldn't appear in a header as
+    /// is. Use `Location` to put untrusted parts into it.
+    pub fn redirect(status: StatusCode, location: &str) -> Self {
+        let mut headers = Headers::empty();
+        headers.set("Location", percent_encode(location, EncodeSet::HeaderValue).into_owned());
+        Self::new(StatusLine::new(HttpVersion::Http1_1, status), headers, Some(Vec::new()))
     }
 
+    pub fn status(&self) -> StatusCode {
+        self.status_line.status
+    }
+
+    pub fn version(&self) -> HttpVersion {
+        self.status_line.version
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        &mut self.headers
+    }
+
+    /// `None` for streamed bodies too
+    pub fn body(&self) -> Option<&[u8]> {
+        self.body.as_deref()
+    }
+
+    pub fn is_streamed(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Whether there is a body, even an empty one, rather than none as in responses to `HEAD`
+    /// and with 1xx, 204 and 304 statuses
+    pub fn has_body(&self) -> bool {
+        self.body.is_some() || self.stream.is_some()
+    }
+
+    /// Edits the body in place, its `Content-Length` has to be kept in step, see `map_body`
+    pub fn body_mut(&mut self) -> Option<&mut Vec<u8>> {
+        self.body.as_mut()
+    }
+
+    /// Transforms the body, e.g. to compress it, and updates its `Content-Length`
+    pub fn map_body<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(Vec<u8>) -> Vec<u8>,
+    {
+        if let Some(body) = self.body.take() {
+            self.set_body(f(body));
+        }
+        self
+    }
+
+    /// Replaces the body and its `Content-Length`, a streamed body too
+    pub(crate) fn set_body(&mut self, body: Vec<u8>) {
+        if self.stream.take().is_some() {
+            self.headers.retain(|field, _| !field.eq_ignore_ascii_case("Transfer-Encoding"));
+        }
+        self.headers.set("Content-Length", body.len().to_string());
+        self.body = Some(body);
+    }
+
+    /// Gives a response without a body or framing a `Content-Length` of 0 where responses to
+    /// `method` have content, for a connection kept open: there no body could only be told
+    /// from an empty one by closing it
+    pub(crate) fn frame_absent_body(&mut self, method: Method) {
+        let framed = self.headers.get("Content-Length").is_some()
+            || self.headers.get("Transfer-Encoding").is_some();
+        if !self.has_body() && !framed && has_body(method, self.status()) {
+            self.set_body(Vec::new());
+        }
+    }
+
+    /// Drops the body but keeps its `Content-Length`, as in a response to `HEAD`
+    pub(crate) fn without_body(mut self) -> Self {
+        self.body = None;
+        self.stream = None;
+        self
+    }
+
+    /// Checks that the message is framed unambiguously as a response to a `method` request
+    pub fn check_framing(&self, method: Method) -> Result<(), FramingError> {
+        let status = self.status_line.status;
+        let body_len = self.body.as_ref().map_or(0, Vec::len);
+        let mut lengths = self.headers.get_all("Content-Length").peekable();
+        let chunked = self
+            .headers
+            .get_all("Transfer-Encoding")
+            .flat_map(|v| v.split(','))
+            .any(|v| v.trim().eq_ignore_ascii_case("chunked"));
+
+        let no_content = (100..200).contains(&*status) || status == StatusCode::NO_CONTENT;
+        if no_content && lengths.peek().is_some() {
+            return Err(FramingError::ContentLengthNotAllowed(status));
+        }
+        if (body_len > 0 || self.stream.as_ref().is_some_and(|stream| !stream.is_empty()))
+            && (no_content || status == StatusCode::NOT_MODIFIED || method == Method::HEAD)
+        {
+            return Err(FramingError::BodyNotAllowed(status));
+        }
+        if chunked && lengths.peek().is_some() {
+            return Err(FramingError::ChunkedWithContentLength);
+        }
+        // without a body the length describes the representation, e.g. for `HEAD` or 304
+        if self.body.is_some() {
+            let length = body_len.to_string();
+            if let Some(declared) = lengths.find(|v| v.trim() != length) {
+                return Err(FramingError::ContentLengthMismatch(declared.to_owned(), body_len));
+            }
+        }
+        Ok(())
+    }
+
+    /// The message as written, but for a streamed body
     pub fn into_vec(self) -> Vec<u8> {
-        let Self { status_line, headers, body } = self;
+        let Self { status_line, headers, body, .. } = self;
         let buffer = Vec::<u8>::with_capacity(512);
         let mut cursor = Cursor::new(buffer);
 
@@ -52,7 +583,7 @@ impl RawResponse {
 /// response status line
 /// e.g.
 /// HTTP/1.1 200 OK
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct StatusLine {
     version: HttpVersion,
     status: StatusCode,
@@ -63,12 +594,31 @@ impl StatusLine {
         Self { version, status }
     }
 
+    /// `HTTP-version SP status-code SP [ reason-phrase ]`, the reason phrase is not kept since
+    /// `to_http_message` writes the default one
+    pub fn parse_with(s: &str, leniency: &Leniency) -> Result<Self, ParseRequestError> {
+        let invalid = || ParseRequestError::StatusLine(s.to_owned());
+        let mut parts = s.splitn(3, ' ');
+
+        let version =
+            parts.next().and_then(|w| w.parse::<HttpVersion>().ok()).ok_or_else(invalid)?;
+        let status = parts.next().and_then(|w| w.parse::<StatusCode>().ok()).ok_or_else(invalid)?;
+        if parts.next().is_none() && !leniency.missing_reason_phrase {
+            return Err(invalid());
+        }
+
+        Ok(StatusLine { version, status })
+    }
+
     pub fn to_http_message(&self) -> String {
-        format!(
-            "{} {} {}{CRLF}",
-            self.version.as_str(),
-            self.status.0,
-            self.status.default_reason_phrase()
-        )
+        format!("{} {}{CRLF}", self.version.as_str(), self.status)
+    }
+}
+
+impl FromStr for StatusLine {
+    type Err = ParseRequestError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        StatusLine::parse_with(s, &Leniency::STRICT)
     }
 }
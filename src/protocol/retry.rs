@@ -0,0 +1,83 @@
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, SystemTime};
+
+use super::{format_http_date, parse_http_date, Headers};
+
+/// Value of a `Retry-After` header
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RetryAfter {
+    /// sent in whole seconds, rounded up so a retry isn't early
+    Delay(Duration),
+    Date(SystemTime),
+}
+
+impl RetryAfter {
+    /// Delay seconds or an `HTTP-date`
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+            return s.parse().ok().map(|secs| RetryAfter::Delay(Duration::from_secs(secs)));
+        }
+        parse_http_date(s).map(RetryAfter::Date)
+    }
+
+    /// How long to wait from `now`, zero once the date has passed
+    pub fn delay_from(&self, now: SystemTime) -> Duration {
+        match *self {
+            RetryAfter::Delay(delay) => delay,
+            RetryAfter::Date(date) => date.duration_since(now).unwrap_or_default(),
+        }
+    }
+
+    pub fn apply(&self, headers: &mut Headers) {
+        headers.set("Retry-After", self.to_string());
+    }
+}
+
+impl Display for RetryAfter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            RetryAfter::Delay(delay) => write!(f, "{}", ceil_secs(delay)),
+            RetryAfter::Date(date) => write!(f, "{}", format_http_date(date)),
+        }
+    }
+}
+
+/// The `RateLimit-*` headers of draft-ietf-httpapi-ratelimit-headers, describing the quota of
+/// the client a response is for
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RateLimit {
+    /// units per window
+    pub limit: u32,
+    pub remaining: u32,
+    /// until the quota is renewed
+    pub reset: Duration,
+    pub window: Duration,
+}
+
+impl RateLimit {
+    /// Sets `RateLimit-Limit`, `RateLimit-Remaining`, `RateLimit-Reset` and `RateLimit-Policy`
+    pub fn apply(&self, headers: &mut Headers) {
+        headers.set("RateLimit-Limit", self.limit.to_string());
+        headers.set("RateLimit-Remaining", self.remaining.to_string());
+        headers.set("RateLimit-Reset", ceil_secs(self.reset).to_string());
+        headers.set("RateLimit-Policy", format!("{};w={}", self.limit, ceil_secs(self.window)));
+    }
+
+    /// The quota a response declares, `None` unless limit, remaining and reset are all given
+    pub fn from_headers(headers: &Headers) -> Option<Self> {
+        let limit = headers.get_parsed("RateLimit-Limit")?;
+        let remaining = headers.get_parsed("RateLimit-Remaining")?;
+        let reset = Duration::from_secs(headers.get_parsed("RateLimit-Reset")?);
+        let window = headers
+            .get("RateLimit-Policy")
+            .and_then(|policy| policy.split(';').find_map(|param| param.trim().strip_prefix("w=")))
+            .and_then(|w| w.parse().ok())
+            .map_or(reset, Duration::from_secs);
+        Some(Self { limit, remaining, reset, window })
+    }
+}
+
+fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
@@ -0,0 +1,191 @@
+use std::error::Error;
+use std::fmt::{Debug, Formatter};
+use std::future::{poll_fn, Future};
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use futures_core::Stream;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::CRLF;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+type BoxReader = Pin<Box<dyn AsyncRead + Send>>;
+type BoxStream = Pin<Box<dyn Stream<Item = Result<Bytes, BoxError>> + Send>>;
+
+/// bytes read from a reader for each write, and each chunk in chunked transfer coding
+const CHUNK_LEN: usize = 16 * 1024;
+
+enum Source {
+    Reader(BoxReader),
+    /// `rest` is what's left of the last piece after `max` bytes were taken from it
+    Stream {
+        stream: BoxStream,
+        rest: Bytes,
+    },
+}
+
+impl Source {
+    /// The next piece of at most `max` bytes, `None` at the end
+    async fn next(&mut self, buf: &mut [u8], max: usize) -> io::Result<Option<Bytes>> {
+        match self {
+            Source::Reader(reader) => match reader.read(&mut buf[..max]).await? {
+                0 => Ok(None),
+                n => Ok(Some(Bytes::copy_from_slice(&buf[..n]))),
+            },
+            Source::Stream { stream, rest } => loop {
+                if !rest.is_empty() {
+                    return Ok(Some(rest.split_to(max.min(rest.len()))));
+                }
+                match poll_fn(|cx| stream.as_mut().poll_next(cx)).await {
+                    // an empty chunk would end the chunked body
+                    Some(Ok(piece)) if piece.is_empty() => continue,
+                    Some(Ok(piece)) => *rest = piece,
+                    Some(Err(err)) => return Err(io::Error::other(err)),
+                    None => return Ok(None),
+                }
+            },
+        }
+    }
+}
+
+/// A response body read from its source while the response is written, see
+/// `RawResponse::streaming`.
+///
+/// Clones of a response share the source, only the first of them written sends the body.
+#[derive(Clone)]
+pub struct Body {
+    source: Arc<Mutex<Option<Source>>>,
+    len: Option<u64>,
+    error_trailer: Option<String>,
+}
+
+impl Body {
+    /// `len` bytes of `reader`, or all of it until it ends when `None`
+    pub fn from_reader<R>(reader: R, len: Option<u64>) -> Self
+    where
+        R: AsyncRead + Send + 'static,
+    {
+        Self::new(Source::Reader(Box::pin(reader)), len)
+    }
+
+    /// The pieces of `stream` until it ends, each sent as a chunk as soon as it's produced
+    pub fn from_stream<S, E>(stream: S) -> Self
+    where
+        S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+        E: Into<BoxError>,
+    {
+        let stream = Box::pin(ErrInto(Box::pin(stream)));
+        Self::new(Source::Stream { stream, rest: Bytes::new() }, None)
+    }
+
+    fn new(source: Source, len: Option<u64>) -> Self {
+        Self { source: Arc::new(Mutex::new(Some(source))), len, error_trailer: None }
+    }
+
+    /// When the source fails, ends a chunked body with the error message in trailer `field`,
+    /// announced in `Trailer`. Without it, or when the body isn't chunked, the connection is
+    /// closed before the body is complete, which is all clients can tell the error by.
+    pub fn error_trailer(mut self, field: &str) -> Self {
+        self.error_trailer = Some(field.to_owned());
+        self
+    }
+
+    pub fn len(&self) -> Option<u64> {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == Some(0)
+    }
+
+    pub(crate) fn trailer_field(&self) -> Option<&str> {
+        self.error_trailer.as_deref()
+    }
+
+    /// Copies the body to `writer`, framed as chunks or else as exactly `len` bytes. `writer` is
+    /// flushed whenever the source has nothing ready, so a buffered writer doesn't hold back
+    /// what a slow producer sent.
+    pub(crate) async fn write_to<W>(&self, writer: &mut W, chunked: bool) -> io::Result<u64>
+    where
+        W: AsyncWrite + ?Sized + Unpin,
+    {
+        let source = self.source.lock().unwrap().take();
+        let mut source = source.ok_or_else(|| io::Error::other("response body already sent"))?;
+        let mut buf = vec![0; CHUNK_LEN];
+        let mut written = 0;
+        let failure = loop {
+            let max = match self.len {
+                Some(len) if !chunked => (len - written).min(CHUNK_LEN as u64) as usize,
+                _ => CHUNK_LEN,
+            };
+            if max == 0 {
+                break None;
+            }
+            let next = source.next(&mut buf, max);
+            tokio::pin!(next);
+            let next = match poll_fn(|cx| Poll::Ready(next.as_mut().poll(cx))).await {
+                Poll::Ready(next) => next,
+                // nothing more for now, what's buffered so far, the head included, goes out
+                Poll::Pending => {
+                    writer.flush().await?;
+                    next.await
+                }
+            };
+            let piece = match next {
+                Ok(Some(piece)) => piece,
+                Ok(None) => break None,
+                Err(err) => break Some(err),
+            };
+            if chunked {
+                writer.write_all(format!("{:X}{CRLF}", piece.len()).as_bytes()).await?;
+                writer.write_all(&piece).await?;
+                writer.write_all(CRLF.as_bytes()).await?;
+            } else {
+                writer.write_all(&piece).await?;
+            }
+            written += piece.len() as u64;
+        };
+
+        match (failure, self.error_trailer.as_deref()) {
+            (Some(err), Some(field)) if chunked => {
+                let message = err.to_string().replace(|c: char| c.is_ascii_control(), " ");
+                writer
+                    .write_all(format!("0{CRLF}{field}: {message}{CRLF}{CRLF}").as_bytes())
+                    .await?;
+            }
+            (Some(err), _) => return Err(err),
+            (None, _) if chunked => writer.write_all(format!("0{CRLF}{CRLF}").as_bytes()).await?,
+            // the head promised more, the connection can't carry another response
+            (None, _) if self.len.is_some_and(|len| len != written) => {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            (None, _) => {}
+        }
+        Ok(written)
+    }
+}
+
+impl Debug for Body {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Body").field("len", &self.len).finish_non_exhaustive()
+    }
+}
+
+/// Boxes the errors of a stream
+struct ErrInto<S>(Pin<Box<S>>);
+
+impl<S, E> Stream for ErrInto<S>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+    E: Into<BoxError>,
+{
+    type Item = Result<Bytes, BoxError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0.as_mut().poll_next(cx).map(|item| item.map(|item| item.map_err(Into::into)))
+    }
+}
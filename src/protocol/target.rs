@@ -0,0 +1,165 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use super::{Host, HostError, ParseRequestError};
+
+/// `scheme://authority[/path][?query]`, the absolute form of a request target
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Uri {
+    /// lowercase
+    scheme: String,
+    authority: String,
+    path: String,
+    query: Option<String>,
+}
+
+impl Uri {
+    pub fn scheme(&self) -> &str {
+        &self.scheme
+    }
+
+    /// As sent, possibly with userinfo
+    pub fn authority(&self) -> &str {
+        &self.authority
+    }
+
+    pub fn host(&self) -> Result<Host, HostError> {
+        let authority = self.authority.rsplit_once('@').map_or(&*self.authority, |(_, host)| host);
+        Host::parse(authority)
+    }
+
+    /// `/` when empty
+    pub fn path(&self) -> &str {
+        match self.path.as_str() {
+            "" => "/",
+            path => path,
+        }
+    }
+
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+}
+
+impl Display for Uri {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}://{}{}", self.scheme, self.authority, self.path)?;
+        match self.query {
+            Some(ref query) => write!(f, "?{query}"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl FromStr for Uri {
+    type Err = ParseRequestError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseRequestError::RequestLine(s.to_owned());
+        let (scheme, rest) = s.split_once("://").ok_or_else(invalid)?;
+        let valid_scheme = scheme.bytes().next().is_some_and(|b| b.is_ascii_alphabetic())
+            && scheme.bytes().all(|b| b.is_ascii_alphanumeric() || b"+-.".contains(&b));
+        if !valid_scheme || s.contains('#') {
+            return Err(invalid());
+        }
+        let end = rest.find(['/', '?']).unwrap_or(rest.len());
+        let (authority, rest) = rest.split_at(end);
+        if authority.is_empty() {
+            return Err(invalid());
+        }
+        let (path, query) = split_query(rest);
+        Ok(Self {
+            scheme: scheme.to_ascii_lowercase(),
+            authority: authority.to_owned(),
+            path: path.to_owned(),
+            query: query.map(str::to_owned),
+        })
+    }
+}
+
+/// The four forms of a request target (RFC 9112, section 3.2)
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RequestTarget {
+    /// `/path[?query]`, as sent to origin servers
+    OriginForm { path: String, query: Option<String> },
+    /// as sent to proxies
+    AbsoluteForm(Uri),
+    /// `host:port` of `CONNECT`, the host as `Host::hostname` gives it
+    AuthorityForm(String, u16),
+    /// `*` of a server-wide `OPTIONS`
+    AsteriskForm,
+}
+
+impl RequestTarget {
+    /// The origin form of a path with an optional query, neither of which is checked
+    pub fn origin(path_and_query: &str) -> Self {
+        let (path, query) = split_query(path_and_query);
+        RequestTarget::OriginForm { path: path.to_owned(), query: query.map(str::to_owned) }
+    }
+
+    /// The path of the origin and absolute forms, `*` of the asterisk form and empty for the
+    /// authority form
+    pub fn path(&self) -> &str {
+        match self {
+            RequestTarget::OriginForm { path, .. } => path,
+            RequestTarget::AbsoluteForm(uri) => uri.path(),
+            RequestTarget::AuthorityForm(..) => "",
+            RequestTarget::AsteriskForm => "*",
+        }
+    }
+
+    pub fn query(&self) -> Option<&str> {
+        match self {
+            RequestTarget::OriginForm { query, .. } => query.as_deref(),
+            RequestTarget::AbsoluteForm(uri) => uri.query(),
+            _ => None,
+        }
+    }
+}
+
+impl Display for RequestTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestTarget::OriginForm { path, query: Some(query) } => write!(f, "{path}?{query}"),
+            RequestTarget::OriginForm { path, query: None } => write!(f, "{path}"),
+            RequestTarget::AbsoluteForm(uri) => write!(f, "{uri}"),
+            RequestTarget::AuthorityForm(host, port) if host.contains(':') => {
+                write!(f, "[{host}]:{port}")
+            }
+            RequestTarget::AuthorityForm(host, port) => write!(f, "{host}:{port}"),
+            RequestTarget::AsteriskForm => write!(f, "*"),
+        }
+    }
+}
+
+/// Any of the forms, whether it suits the method is up to the request line
+impl FromStr for RequestTarget {
+    type Err = ParseRequestError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseRequestError::RequestLine(s.to_owned());
+        if s == "*" {
+            return Ok(RequestTarget::AsteriskForm);
+        }
+        // a fragment is for the user agent only, never part of a request target
+        if s.starts_with('/') {
+            return match s.contains('#') {
+                true => Err(invalid()),
+                false => Ok(RequestTarget::origin(s)),
+            };
+        }
+        if s.contains("://") {
+            return s.parse().map(RequestTarget::AbsoluteForm);
+        }
+        let host = Host::parse(s).map_err(|_| invalid())?;
+        let port = host.port().ok_or_else(invalid)?;
+        Ok(RequestTarget::AuthorityForm(host.hostname(), port))
+    }
+}
+
+fn split_query(s: &str) -> (&str, Option<&str>) {
+    match s.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (s, None),
+    }
+}
@@ -11,6 +11,30 @@ pub fn test_parse_http_method() {
     assert_eq!(ParseRequestError::UnknownMethod("another".to_string()), method);
 }
 
+#[test]
+pub fn test_method_properties() {
+    let methods = [
+        Method::GET,
+        Method::HEAD,
+        Method::POST,
+        Method::PUT,
+        Method::DELETE,
+        Method::PATCH,
+        Method::OPTIONS,
+        Method::TRACE,
+        Method::CONNECT,
+    ];
+    let with = |property: fn(&Method) -> bool| {
+        methods.iter().filter(|m| property(m)).map(Method::as_str).collect::<Vec<_>>()
+    };
+    assert_eq!(vec!["GET", "HEAD", "OPTIONS", "TRACE"], with(Method::is_safe));
+    assert_eq!(
+        vec!["GET", "HEAD", "PUT", "DELETE", "OPTIONS", "TRACE"],
+        with(Method::is_idempotent)
+    );
+    assert_eq!(vec!["GET", "HEAD"], with(Method::is_cacheable));
+}
+
 #[test]
 pub fn test_parse_http_version() {
     let source = "HTTP/1.1";
@@ -26,6 +50,21 @@ pub fn test_parse_http_header() {
 
     assert_eq!("Content-Type", header.field());
     assert_eq!("application/json", header.value());
+
+    let header = "X-Empty:".parse::<Header>().unwrap();
+    assert_eq!(("X-Empty", ""), (header.field(), header.value()));
+    let header = "Host:\texample.com:8080 ".parse::<Header>().unwrap();
+    assert_eq!("example.com:8080", header.value());
+
+    assert_eq!(
+        Err(ParseRequestError::InvalidHeaderName("Host ".to_owned())),
+        "Host : example.com".parse::<Header>().map(|h| h.field().to_owned())
+    );
+    assert!(matches!(
+        "Bad(Name): 1".parse::<Header>(),
+        Err(ParseRequestError::InvalidHeaderName(_))
+    ));
+    assert!(matches!("no colon".parse::<Header>(), Err(ParseRequestError::InvalidHeader(_))));
 }
 
 #[tokio::test]
@@ -35,7 +74,43 @@ pub async fn test_parse_request_line() {
 
     assert_eq!(Method::GET, request_line.method);
     assert_eq!(HttpVersion::Http1_1, request_line.version);
-    assert_eq!("/some", request_line.uri);
+    assert_eq!("/some", request_line.target.path());
+}
+
+#[test]
+pub fn test_parse_request_targets() {
+    let target = |line: &str| line.parse::<RequestLine>().map(|line| line.target);
+
+    assert_eq!(
+        Ok(RequestTarget::OriginForm { path: "/a".to_owned(), query: Some("b=1".to_owned()) }),
+        target("GET /a?b=1 HTTP/1.1")
+    );
+    let absolute = target("GET HTTP://example.com:8080?q HTTP/1.1").unwrap();
+    let RequestTarget::AbsoluteForm(ref uri) = absolute else { panic!("{absolute:?}") };
+    assert_eq!(
+        ("http", "example.com:8080", "/", Some("q")),
+        (uri.scheme(), uri.authority(), uri.path(), uri.query())
+    );
+    assert_eq!(Some(8080), uri.host().unwrap().port());
+    assert_eq!("http://example.com:8080?q", absolute.to_string());
+    assert_eq!(
+        Ok(RequestTarget::AuthorityForm("::1".to_owned(), 443)),
+        target("CONNECT [::1]:443 HTTP/1.1")
+    );
+    assert_eq!("[::1]:443", target("CONNECT [::1]:443 HTTP/1.1").unwrap().to_string());
+    assert_eq!(Ok(RequestTarget::AsteriskForm), target("OPTIONS * HTTP/1.1"));
+
+    for invalid in [
+        "GET * HTTP/1.1",
+        "GET example.com:80 HTTP/1.1",
+        "CONNECT /a HTTP/1.1",
+        "CONNECT example.com HTTP/1.1",
+        "GET /a#b HTTP/1.1",
+        "GET ://a HTTP/1.1",
+        "GET http:///a HTTP/1.1",
+    ] {
+        assert!(matches!(target(invalid), Err(ParseRequestError::RequestLine(_))), "{invalid}");
+    }
 }
 
 #[tokio::test]
@@ -46,7 +121,7 @@ pub async fn test_read_http_request() {
 
     assert_eq!(Method::GET, request.request_line.method);
     assert_eq!(HttpVersion::Http1_1, request.request_line.version);
-    assert_eq!("/foo/bar", request.request_line.uri);
+    assert_eq!("/foo/bar", request.request_line.target.path());
     assert_eq!(1, request.headers.len());
     assert_eq!(Some("application/json"), request.headers.get("Content-Type"));
     assert_eq!(None, request.body);
@@ -70,3 +145,1033 @@ pub fn test_header_to_http_message() {
     let expected = "hello: world\r\n";
     assert_eq!(expected, actual);
 }
+
+#[test]
+pub fn test_headers_conveniences() {
+    let mut headers =
+        [("Content-Length", "12"), ("Vary", "Accept")].into_iter().collect::<Headers>();
+    headers.extend([("vary", "Origin".to_owned())]);
+    assert!(headers.contains("content-length"));
+    assert!(!headers.contains("Content-Type"));
+    assert_eq!(vec!["Accept", "Origin"], headers.get_all("Vary").collect::<Vec<_>>());
+
+    assert_eq!(Some(12), headers.get_parsed::<usize>("Content-Length"));
+    assert_eq!(None, headers.get_parsed::<usize>("Vary"));
+    assert_eq!(None, headers.get_parsed::<usize>("Age"));
+
+    headers.get_or_insert_with("Cache-Control", || "no-store".to_owned()).push_str(", private");
+    *headers.get_or_insert_with("vary", String::new) += ", Cookie";
+    assert_eq!(Some("no-store, private"), headers.get("Cache-Control"));
+    assert_eq!(Some("Accept, Cookie"), headers.get("Vary"));
+
+    headers.retain(|field, value| !field.eq_ignore_ascii_case("Vary") || value != "Origin");
+    assert_eq!(
+        "Content-Length: 12\r\nVary: Accept, Cookie\r\nCache-Control: no-store, private\r\n",
+        headers.to_http_message()
+    );
+}
+
+#[tokio::test]
+pub async fn test_read_http_request_limits() {
+    let limits =
+        RequestLimits { max_line_len: 32, max_headers: 1, max_body_len: 4, ..Default::default() };
+
+    let mut source: &[u8] = b"GET /a/very/long/path/exceeding/the/limit HTTP/1.1\r\n\r\n";
+    let err = read_http_request_with(&mut source, &limits).await.unwrap_err();
+    assert_eq!(ParseRequestError::LineTooLong, err);
+
+    let mut source: &[u8] = b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\n\r\n";
+    let err = read_http_request_with(&mut source, &limits).await.unwrap_err();
+    assert_eq!(ParseRequestError::TooManyHeaders, err);
+
+    let mut source: &[u8] = b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello";
+    let err = read_http_request_with(&mut source, &limits).await.unwrap_err();
+    assert_eq!(ParseRequestError::BodyTooLarge(5), err);
+}
+
+#[tokio::test]
+pub async fn test_content_length_framing() {
+    let read = |length: &'static str| async move {
+        let message = format!("POST / HTTP/1.1\r\n{length}\r\n\r\nhello, world");
+        let mut source = message.as_bytes();
+        read_http_request(&mut source).await.map(|request| request.body.unwrap())
+    };
+    assert_eq!(Ok(b"hello".to_vec()), read("Content-Length: 5").await);
+    assert_eq!(Ok(b"hello".to_vec()), read("Content-Length: 5, 5").await);
+    assert_eq!(Ok(b"hello".to_vec()), read("Content-Length: 5\r\nContent-Length: 5").await);
+    assert_eq!(Ok(b"hello".to_vec()), read("Content-Length: 005").await);
+    for invalid in [
+        "Content-Length: +5",
+        "Content-Length: -5",
+        "Content-Length: 5, 6",
+        "Content-Length: 5,",
+        "Content-Length: 5\r\nContent-Length: 6",
+        "Content-Length: abc",
+        "Content-Length: 0x5",
+        "Content-Length: ",
+        "Content-Length: 99999999999999999999999",
+    ] {
+        let err = read(invalid).await.unwrap_err();
+        assert!(matches!(err, ParseRequestError::InvalidHeader(_)), "{invalid}");
+        assert_eq!(Some(StatusCode::BAD_REQUEST), err.status());
+    }
+
+    let limits = RequestLimits::default();
+    let mut source: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: +2\r\n\r\nok";
+    assert!(read_http_response(&mut source, Method::GET, &limits).await.is_err());
+    let source: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 2, 3\r\n\r\nok";
+    assert!(read_http_response_head(source, Method::GET, &limits).await.is_err());
+}
+
+#[tokio::test]
+pub async fn test_read_http_response() {
+    let limits = RequestLimits::default();
+    let message = |response: RawResponse| String::from_utf8(response.into_vec()).unwrap();
+
+    let mut source: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+    let response = read_http_response(&mut source, Method::GET, &limits).await.unwrap();
+    assert_eq!("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello", message(response));
+
+    let mut source: &[u8] =
+        b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3;x=y\r\nhel\r\n2\r\nlo\r\n0\r\nT: 1\r\n\r\n";
+    let response = read_http_response(&mut source, Method::GET, &limits).await.unwrap();
+    assert_eq!("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello", message(response));
+
+    let mut source: &[u8] = b"HTTP/1.0 404 Not Found\r\n\r\nuntil eof";
+    let response = read_http_response(&mut source, Method::GET, &limits).await.unwrap();
+    assert_eq!(
+        "HTTP/1.0 404 Not Found\r\nConnection: close\r\nContent-Length: 9\r\n\r\nuntil eof",
+        message(response)
+    );
+
+    let mut source: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n";
+    let response = read_http_response(&mut source, Method::HEAD, &limits).await.unwrap();
+    assert_eq!("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n", message(response));
+
+    let mut source: &[u8] = b"HTTP/1.1 20 OK\r\n\r\n";
+    let err = read_http_response(&mut source, Method::GET, &limits).await.unwrap_err();
+    assert_eq!(ParseRequestError::StatusLine("HTTP/1.1 20 OK".to_owned()), err);
+}
+
+#[test]
+pub fn test_response_check_framing() {
+    let response = |status: u16, headers: &[(&str, &str)], body: Option<&[u8]>| {
+        let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::from(status));
+        let mut response =
+            RawResponse::new(status_line, Headers::empty(), body.map(<[u8]>::to_vec));
+        // appended after `new`, which would otherwise correct `Content-Length`
+        for (field, value) in headers {
+            response.headers_mut().push(Header::new(field, value));
+        }
+        response
+    };
+
+    assert_eq!(Ok(()), response(200, &[], Some(b"ok")).check_framing(Method::GET));
+    assert_eq!(
+        Ok(()),
+        response(200, &[("Content-Length", "10")], None).check_framing(Method::HEAD)
+    );
+    assert_eq!(
+        Err(FramingError::ContentLengthMismatch("10".to_owned(), 2)),
+        response(200, &[("Content-Length", "10")], Some(b"ok")).check_framing(Method::GET)
+    );
+    assert_eq!(
+        Err(FramingError::BodyNotAllowed(StatusCode::NOT_MODIFIED)),
+        response(304, &[], Some(b"ok")).check_framing(Method::GET)
+    );
+    assert_eq!(
+        Err(FramingError::BodyNotAllowed(StatusCode::OK)),
+        response(200, &[], Some(b"ok")).check_framing(Method::HEAD)
+    );
+    assert_eq!(
+        Err(FramingError::ContentLengthNotAllowed(StatusCode::NO_CONTENT)),
+        response(204, &[], Some(b"")).check_framing(Method::GET)
+    );
+    assert_eq!(
+        Err(FramingError::ChunkedWithContentLength),
+        response(200, &[("Transfer-Encoding", "chunked")], Some(b"ok")).check_framing(Method::GET)
+    );
+}
+
+#[test]
+pub fn test_response_accessors() {
+    let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::OK);
+    let mut response = RawResponse::new(status_line, Headers::empty(), Some(b"hello".to_vec()));
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(Some("5"), response.headers().get("Content-Length"));
+
+    response.headers_mut().set("Content-Type", "text/plain".to_owned());
+    response.body_mut().unwrap().make_ascii_uppercase();
+    assert_eq!(Some(&b"HELLO"[..]), response.body());
+
+    let response = response.map_body(|body| [&body[..], b", world"].concat());
+    assert_eq!(Ok(()), response.check_framing(Method::GET));
+    assert_eq!(
+        "HTTP/1.1 200 OK\r\nContent-Length: 12\r\nContent-Type: text/plain\r\n\r\nHELLO, world",
+        String::from_utf8(response.into_vec()).unwrap()
+    );
+}
+
+#[test]
+pub fn test_parse_with_leniency() {
+    assert!("GET  /  HTTP/1.1".parse::<RequestLine>().is_err());
+    assert!("GET / HTTP/1.1 extra".parse::<RequestLine>().is_err());
+    let unknown = |s: &str| s.parse::<RequestLine>().unwrap_err().status().map(|s| *s);
+    assert_eq!(Some(501), unknown("BREW / HTTP/1.1"));
+    assert_eq!(Some(505), unknown("GET / HTTP/3.0"));
+    assert_eq!(Some(400), unknown("G(ET / HTTP/1.1"));
+    assert_eq!(Some(400), unknown("GET / HTTP/1.1.1"));
+    let request_line = RequestLine::parse_with("GET  /a\tHTTP/1.1 ", &Leniency::LENIENT).unwrap();
+    assert_eq!("GET /a HTTP/1.1", request_line.to_string());
+
+    assert!("HTTP/1.1 200 ".parse::<StatusLine>().is_ok());
+    assert!("HTTP/1.1 200".parse::<StatusLine>().is_err());
+    let status_line = StatusLine::parse_with("HTTP/1.1 200", &Leniency::LENIENT).unwrap();
+    assert_eq!("HTTP/1.1 200 OK\r\n", status_line.to_http_message());
+}
+
+#[tokio::test]
+pub async fn test_bare_lf() {
+    let mut source: &[u8] = b"GET / HTTP/1.1\nHost: a\n\n";
+    let err = read_http_request(&mut source).await.unwrap_err();
+    assert_eq!(ParseRequestError::BareLineFeed, err);
+
+    let limits = RequestLimits {
+        leniency: Leniency { bare_lf: true, ..Leniency::STRICT },
+        ..Default::default()
+    };
+    let mut source: &[u8] = b"GET / HTTP/1.1\nHost: a\r\n\n";
+    let request = read_http_request_with(&mut source, &limits).await.unwrap();
+    assert_eq!(Some("a"), request.headers.get("Host"));
+}
+
+#[tokio::test]
+pub async fn test_invalid_bytes() {
+    let mut source: &[u8] = b"GET /caf\xc3\xa9 HTTP/1.1\r\n\r\n";
+    let err = read_http_request(&mut source).await.unwrap_err();
+    assert_eq!(ParseRequestError::InvalidByte(0xc3), err);
+
+    let mut source: &[u8] = b"GET / HTTP/1.1\r\nX-Name: Ren\xe9\r\n\r\n";
+    let err = read_http_request(&mut source).await.unwrap_err();
+    assert_eq!(ParseRequestError::InvalidByte(0xe9), err);
+
+    let limits = RequestLimits {
+        leniency: Leniency { latin1_header_values: true, ..Leniency::STRICT },
+        ..Default::default()
+    };
+    let mut source: &[u8] = b"GET / HTTP/1.1\r\nX-Name: Ren\xe9\r\n\r\n";
+    let request = read_http_request_with(&mut source, &limits).await.unwrap();
+    assert_eq!(Some("Ren\u{e9}"), request.headers.get("X-Name"));
+
+    let mut source: &[u8] = b"GET / HTTP/1.1\r\nX-Name: a\x00b\r\n\r\n";
+    let err = read_http_request_with(&mut source, &limits).await.unwrap_err();
+    assert_eq!(ParseRequestError::InvalidByte(0), err);
+}
+
+#[test]
+pub fn test_percent_encoding() {
+    assert_eq!("a%20b%2Fc%3F", percent_encode("a b/c?", EncodeSet::PathSegment));
+    assert_eq!("x%26y%3D1%2B2", percent_encode("x&y=1+2", EncodeSet::QueryComponent));
+    assert_eq!(
+        "/a%0D%0ASet-Cookie:%20x",
+        percent_encode("/a\r\nSet-Cookie: x", EncodeSet::HeaderValue)
+    );
+    assert_eq!("caf%C3%A9", percent_encode("caf\u{e9}", EncodeSet::PathSegment));
+
+    assert_eq!("caf\u{e9} /", percent_decode("caf%c3%A9%20%2F").unwrap());
+    assert_eq!(Err(PercentDecodeError::InvalidEscape(1)), percent_decode("a%2"));
+    assert_eq!(Err(PercentDecodeError::NotUtf8), percent_decode("%ff"));
+
+    let location = Location::new("/users").segment("a/b").query("next", "/x?y&z");
+    assert_eq!("/users/a%2Fb?next=/x?y%26z", location.to_string());
+
+    let response = RawResponse::redirect(StatusCode::FOUND, "/a\r\nX: 1");
+    let message = String::from_utf8(response.into_vec()).unwrap();
+    assert_eq!(
+        "HTTP/1.1 302 Found\r\nLocation: /a%0D%0AX:%201\r\nContent-Length: 0\r\n\r\n",
+        message
+    );
+}
+
+#[test]
+pub fn test_parse_host() {
+    let host = Host::parse("Example.COM:8080").unwrap();
+    assert_eq!(("example.com".to_owned(), Some(8080)), (host.hostname(), host.port()));
+    assert!(host.matches("EXAMPLE.com.") && !host.matches("example.org"));
+    assert_eq!("example.com:8080", host.to_string());
+
+    let host = Host::parse("[::1]:443").unwrap();
+    assert_eq!(("::1".to_owned(), 443), (host.hostname(), host.port_or(80)));
+    assert!(host.is_ip() && host.matches("[0:0::1]"));
+    assert_eq!("[::1]:443", host.to_string());
+    assert_eq!(Some(80), Host::parse("10.0.0.1:").map(|h| h.port_or(80)).ok());
+
+    for invalid in ["", "::1", "[::1", "[::1]x", "a..b", "-a.com", "a_b.com", "1.2.3", "a:99999"] {
+        assert_eq!(Err(HostError::Invalid(invalid.to_owned())), Host::parse(invalid), "{invalid}");
+    }
+    #[cfg(not(feature = "idna"))]
+    assert!(Host::parse("bücher.example").is_err());
+    #[cfg(feature = "idna")]
+    assert_eq!("xn--bcher-kva.example", Host::parse("Bücher.example").unwrap().hostname());
+
+    let mut request = RawRequest {
+        request_line: "GET / HTTP/1.1".parse().unwrap(),
+        headers: Headers::empty(),
+        body: None,
+        extensions: Extensions::new(),
+    };
+    assert_eq!(Err(HostError::Missing), request.host());
+    request.headers.push(Header::new("Host", "a.example"));
+    assert_eq!(Ok(Some(80)), request.host().map(|h| Some(h.port_or(80))));
+    request.headers.push(Header::new("Host", "b.example"));
+    assert!(request.host().is_err());
+}
+
+#[cfg(feature = "idna")]
+#[test]
+pub fn test_punycode() {
+    // RFC 3492, section 7.1 (A) and (L)
+    assert_eq!(
+        Some("egbpdaj6bu4bxfgehfvwxn".to_owned()),
+        host::punycode_encode("\u{644}\u{64a}\u{647}\u{645}\u{627}\u{628}\u{62a}\u{643}\u{644}\u{645}\u{648}\u{634}\u{639}\u{631}\u{628}\u{64a}\u{61f}")
+    );
+    assert_eq!(
+        Some("3B-ww4c5e180e575a65lsy2b".to_owned()),
+        host::punycode_encode("3年B組金八先生")
+    );
+}
+
+#[test]
+pub fn test_parse_media_type() {
+    let media = MediaType::parse("Text/HTML; Charset=\"UTF-8\"; boundary=\"a b\\\"c\"").unwrap();
+    assert_eq!(("text", "html", "text/html"), (media.kind(), media.subtype(), media.essence()));
+    assert_eq!(Some("utf-8"), media.charset());
+    assert_eq!(Some("a b\"c"), media.param("BOUNDARY"));
+    assert_eq!("text/html; charset=utf-8; boundary=\"a b\\\"c\"", media.to_string());
+    assert_eq!(media, media.to_string().parse().unwrap());
+
+    assert!(media.matches("text/*") && media.matches("*/*") && media.matches("TEXT/html;q=0.5"));
+    assert!(!media.matches("text/plain") && !media.matches("image/*") && !media.matches("text"));
+
+    let problem = MediaType::parse("application/problem+json").unwrap();
+    assert!(problem.is_json());
+    assert_eq!(Some("json"), problem.suffix());
+    assert!(!MediaType::parse("application/jsonl").unwrap().is_json());
+    let with_charset = problem.with_param("charset", "UTF-8");
+    assert_eq!("application/problem+json; charset=utf-8", with_charset.to_string());
+
+    for invalid in ["text", "text/", "te xt/html", "text/html; charset", "text/html; a=\"open"] {
+        assert_eq!(Err(MediaTypeError(invalid.to_owned())), MediaType::parse(invalid));
+    }
+}
+
+#[tokio::test]
+pub async fn test_charsets() {
+    assert_eq!(Some(Charset::Latin1), Charset::from_label("ISO-8859-1"));
+    assert_eq!(None, Charset::from_label("shift_jis"));
+    assert_eq!("café", Charset::Latin1.decode(b"caf\xe9").unwrap());
+    assert_eq!(Err(CharsetError::Malformed(3)), Charset::Utf8.decode(b"caf\xe9"));
+    assert_eq!(Err(CharsetError::Malformed(3)), Charset::Ascii.decode(b"caf\xe9"));
+    assert_eq!(b"caf\xe9", &*Charset::Latin1.encode("café").unwrap());
+    assert_eq!(Err(CharsetError::Unmappable('€')), Charset::Latin1.encode("5 €"));
+
+    let preferred = |accept: &str| {
+        let mut headers = Headers::empty();
+        headers.set("Accept-Charset", accept.to_owned());
+        Charset::preferred(&headers)
+    };
+    assert_eq!(Charset::Utf8, Charset::preferred(&Headers::empty()));
+    assert_eq!(Charset::Latin1, preferred("iso-8859-1, utf-8;q=0.5"));
+    assert_eq!(Charset::Latin1, preferred("utf-8;q=0, *;q=0.3"));
+    assert_eq!(Charset::Utf8, preferred("shift_jis"));
+
+    let mut source: &[u8] = b"POST / HTTP/1.1\r\nContent-Type: text/plain; charset=latin1\r\n\
+                              Content-Length: 4\r\n\r\ncaf\xe9";
+    let mut request = read_http_request(&mut source).await.unwrap();
+    assert_eq!("caf\u{e9}", request.text_body().unwrap());
+    request.headers.set("Content-Type", "text/plain; charset=koi8-r".to_owned());
+    assert_eq!(Err(CharsetError::Unsupported("koi8-r".to_owned())), request.text_body());
+
+    let media_type = MediaType::parse("text/plain; charset=ISO-8859-1").unwrap();
+    let response = RawResponse::text(StatusCode::OK, media_type, "café").unwrap();
+    assert_eq!(
+        b"HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=iso-8859-1\r\n\
+          Content-Length: 4\r\n\r\ncaf\xe9",
+        &response.into_vec()[..]
+    );
+    let response = RawResponse::text(StatusCode::OK, "text/html".parse().unwrap(), "é").unwrap();
+    assert_eq!(Some("text/html; charset=utf-8"), response.headers().get("Content-Type"));
+}
+
+#[test]
+pub fn test_link_header() {
+    let links = Links::new().first("/items?page=1").next("/items?page=3");
+    assert_eq!("</items?page=1>; rel=first, </items?page=3>; rel=next", links.to_string());
+
+    let hints = Links::new()
+        .link(Link::preload("/app.css", "style"))
+        .link(Link::new("https://cdn.example").rel("preconnect dns-prefetch"));
+    assert_eq!(
+        "HTTP/1.1 103 Early Hints\r\nLink: </app.css>; rel=preload; as=style, \
+         <https://cdn.example>; rel=\"preconnect dns-prefetch\"\r\n\r\n",
+        String::from_utf8(hints.early_hints().into_vec()).unwrap()
+    );
+
+    let parsed = Links::parse(
+        "<https://a.example/?q=1,2>; REL=\"next\"; rel=ignored; title=\"a; b, c\" , \
+         </b>;rel=\"preconnect Prev\";crossorigin",
+    )
+    .unwrap();
+    assert_eq!(2, parsed.0.len());
+    let next = parsed.find("next").unwrap();
+    assert_eq!(("https://a.example/?q=1,2", Some("a; b, c")), (next.target(), next.get("title")));
+    assert_eq!(Some("next"), next.get("rel"));
+    let prev = parsed.find("prev").unwrap();
+    assert_eq!(("/b", Some("")), (prev.target(), prev.get("crossorigin")));
+    assert_eq!(vec!["preconnect", "Prev"], prev.rels().collect::<Vec<_>>());
+
+    let mut headers = Headers::empty();
+    headers.push(Header::new("Link", links.to_string()));
+    headers.push(Header::new("Link", "</c>; rel=last"));
+    let all = Links::from_headers(&headers).unwrap();
+    assert_eq!(Some("/c"), all.find("last").map(Link::target));
+    assert_eq!(links, Links::parse(&links.to_string()).unwrap());
+
+    for invalid in ["/no-brackets", "<open", "</a> junk", "</a>; =x", "</a>; t=\"open"] {
+        assert_eq!(Err(LinkError(invalid.to_owned())), Links::parse(invalid));
+    }
+}
+
+#[test]
+pub fn test_http_dates() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let time = UNIX_EPOCH + Duration::from_secs(784111777);
+    assert_eq!("Sun, 06 Nov 1994 08:49:37 GMT", format_http_date(time));
+    assert_eq!("Thu, 01 Jan 1970 00:00:00 GMT", format_http_date(UNIX_EPOCH));
+    assert_eq!(
+        "Thu, 29 Feb 2024 23:59:59 GMT",
+        format_http_date(UNIX_EPOCH + Duration::from_secs(1709251199))
+    );
+    for date in [
+        "Sun, 06 Nov 1994 08:49:37 GMT",
+        "Sunday, 06-Nov-94 08:49:37 GMT",
+        "Sun Nov  6 08:49:37 1994",
+    ] {
+        assert_eq!(Some(time), parse_http_date(date), "{date}");
+    }
+    for invalid in [
+        "Sun, 06 Nov 1994 08:49:37 UTC",
+        "Sun, 32 Nov 1994 08:49:37 GMT",
+        "Sun, 06 Nov 1994 8:49:37 GMT",
+        "",
+    ] {
+        assert_eq!(None, parse_http_date(invalid), "{invalid}");
+    }
+
+    assert_eq!(Some(RetryAfter::Delay(Duration::from_secs(120))), RetryAfter::parse("120"));
+    assert_eq!(Some(RetryAfter::Date(time)), RetryAfter::parse("Sun, 06 Nov 1994 08:49:37 GMT"));
+    assert_eq!(None, RetryAfter::parse("-1"));
+    assert_eq!("2", RetryAfter::Delay(Duration::from_millis(1500)).to_string());
+    let later = RetryAfter::Date(time + Duration::from_secs(30));
+    assert_eq!(Duration::from_secs(30), later.delay_from(time));
+    assert_eq!(Duration::ZERO, later.delay_from(time + Duration::from_secs(60)));
+
+    let mut headers = Headers::empty();
+    let quota = RateLimit {
+        limit: 100,
+        remaining: 40,
+        reset: Duration::from_millis(200),
+        window: Duration::from_secs(60),
+    };
+    quota.apply(&mut headers);
+    assert_eq!(
+        "RateLimit-Limit: 100\r\nRateLimit-Remaining: 40\r\nRateLimit-Reset: 1\r\nRateLimit-Policy: 100;w=60\r\n",
+        headers.to_http_message()
+    );
+    let parsed = RateLimit::from_headers(&headers).unwrap();
+    assert_eq!(
+        (100, 40, Duration::from_secs(1), Duration::from_secs(60)),
+        (parsed.limit, parsed.remaining, parsed.reset, parsed.window)
+    );
+}
+
+#[test]
+pub fn test_challenge() {
+    assert_eq!("Basic realm=\"admin\", charset=\"UTF-8\"", Challenge::basic("admin").to_string());
+
+    let bearer = Challenge::bearer("api \"v2\"")
+        .error("invalid_token")
+        .error_description("expired\r\nat 12:00")
+        .scope("read write");
+    assert_eq!(
+        "Bearer realm=\"api \\\"v2\\\"\", error=\"invalid_token\", \
+         error_description=\"expired  at 12:00\", scope=\"read write\"",
+        bearer.to_string()
+    );
+    assert_eq!(("Bearer", Some("invalid_token")), (bearer.scheme(), bearer.get("ERROR")));
+
+    let mut headers = Headers::empty();
+    Challenge::new("Negotiate").apply(&mut headers);
+    bearer.realm("api").apply(&mut headers);
+    assert_eq!(
+        vec![
+            "Negotiate",
+            "Bearer realm=\"api\", error=\"invalid_token\", \
+              error_description=\"expired  at 12:00\", scope=\"read write\""
+        ],
+        headers.get_all("www-authenticate").collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+pub async fn test_stream_response_body() {
+    let limits = RequestLimits { max_body_len: 4, ..Default::default() };
+
+    let source: &[u8] =
+        b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nhel\r\n4\r\nlo, \r\n0\r\n\r\nnext";
+    let (response, mut body) = read_http_response_head(source, Method::GET, &limits).await.unwrap();
+    assert_eq!(Some("chunked"), response.headers().get("Transfer-Encoding"));
+    assert_eq!(Some(b"hel".to_vec()), body.chunk().await.unwrap());
+    assert_eq!(Some(b"lo, ".to_vec()), body.chunk().await.unwrap());
+    assert_eq!(None, body.chunk().await.unwrap());
+    assert!(body.is_done());
+    assert_eq!(b"next", body.into_inner());
+
+    let source: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nhello!next";
+    let (_, mut body) = read_http_response_head(source, Method::GET, &limits).await.unwrap();
+    let mut copied = Vec::new();
+    assert_eq!(6, body.copy_to(&mut copied).await.unwrap());
+    assert_eq!(b"hello!", &copied[..]);
+
+    let source: &[u8] = b"HTTP/1.0 200 OK\r\n\r\nuntil eof";
+    let (_, mut body) = read_http_response_head(source, Method::GET, &limits).await.unwrap();
+    let mut copied = Vec::new();
+    assert_eq!(9, body.copy_to(&mut copied).await.unwrap());
+
+    let source: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nhel";
+    let (_, mut body) = read_http_response_head(source, Method::GET, &limits).await.unwrap();
+    assert_eq!(Some(b"hel".to_vec()), body.chunk().await.unwrap());
+    assert_eq!(
+        ParseRequestError::Io(std::io::ErrorKind::UnexpectedEof),
+        body.chunk().await.unwrap_err()
+    );
+
+    let source: &[u8] = b"HTTP/1.1 304 Not Modified\r\nContent-Length: 6\r\n\r\n";
+    let (_, mut body) = read_http_response_head(source, Method::GET, &limits).await.unwrap();
+    assert_eq!(None, body.chunk().await.unwrap());
+}
+
+#[tokio::test]
+pub async fn test_raw_body() {
+    let zlib = b"\x78\x9c\x4b\x4e\x4c\x3b\xbc\x52\x21\x19\x44\x02\x00\x1d\xf7\x05\x4d";
+    let head = "POST /events HTTP/1.1\r\nContent-Encoding: deflate\r\nContent-Length: 17\r\n\r\n";
+    let source = [head.as_bytes(), zlib].concat();
+
+    let mut request = read_http_request(&mut &source[..]).await.unwrap();
+    assert_eq!(None, request.raw_body());
+    request.keep_raw_body(16);
+    assert_eq!(None, request.raw_body());
+    request.keep_raw_body(17);
+    let request = request.decoded(1024).unwrap();
+    assert_eq!(Some("café café".as_bytes()), request.body.as_deref());
+    assert_eq!(Some("11"), request.headers.get("Content-Length"));
+    assert_eq!(None, request.headers.get("Content-Encoding"));
+    assert_eq!(Some(&zlib[..]), request.raw_body());
+
+    // kept once, later bodies don't replace it
+    let mut request = request;
+    request.keep_raw_body(1024);
+    assert_eq!(Some(&zlib[..]), request.raw_body());
+
+    let mut request = read_http_request(&mut &source[..]).await.unwrap();
+    request.headers.set("Content-Encoding", "br".to_owned());
+    assert_eq!(BodyError::UnsupportedEncoding("br".to_owned()), request.decoded(1024).unwrap_err());
+}
+
+#[tokio::test]
+pub async fn test_absent_and_empty_bodies() {
+    let read = |source: &'static str| async move {
+        read_http_request(&mut source.as_bytes()).await.unwrap()
+    };
+    assert_eq!(None, read("POST / HTTP/1.1\r\n\r\n").await.body);
+    let empty = read("POST / HTTP/1.1\r\nContent-Length: 0\r\n\r\n").await;
+    assert_eq!(Some(Vec::new()), empty.body);
+    let chunked = read("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n").await;
+    assert_eq!(Some(Vec::new()), chunked.body);
+    assert_eq!(Some("0"), chunked.headers.get("Content-Length"));
+
+    // written as they were read, and framed when built without headers
+    let absent = read("POST / HTTP/1.1\r\n\r\n").await;
+    assert_eq!(b"POST / HTTP/1.1\r\n\r\n".to_vec(), absent.into_vec());
+    let mut request = read("POST / HTTP/1.1\r\n\r\n").await;
+    request.body = Some(Vec::new());
+    assert_eq!(b"POST / HTTP/1.1\r\nContent-Length: 0\r\n\r\n".to_vec(), request.into_vec());
+    let mut request = read("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n").await;
+    request.body = Some(b"abc".to_vec());
+    assert_eq!(b"POST / HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc".to_vec(), request.into_vec());
+
+    let mut request = read("PUT / HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc").await;
+    request.set_body(Some(Vec::new()));
+    assert_eq!(Some("0"), request.headers.get("Content-Length"));
+    request.set_body(None);
+    assert_eq!((None, None), (request.body.as_ref(), request.headers.get("Content-Length")));
+
+    let limits = RequestLimits::default();
+    let read = |source: &'static str, method: Method| async move {
+        read_http_response(&mut source.as_bytes(), method, &limits).await.unwrap()
+    };
+    let empty = read("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n", Method::GET).await;
+    assert!(empty.has_body());
+    assert_eq!(Some(&b""[..]), empty.body());
+    assert!(!read("HTTP/1.1 204 No Content\r\n\r\n", Method::DELETE).await.has_body());
+    let head = read("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n", Method::HEAD).await;
+    assert!(!head.has_body());
+    assert_eq!(Some("5"), head.headers().get("Content-Length"));
+
+    let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::OK);
+    let mut absent = RawResponse::new(status_line, Headers::empty(), None);
+    absent.frame_absent_body(Method::HEAD);
+    assert_eq!((false, None), (absent.has_body(), absent.headers().get("Content-Length")));
+    absent.frame_absent_body(Method::GET);
+    assert_eq!((true, Some("0")), (absent.has_body(), absent.headers().get("Content-Length")));
+}
+
+#[test]
+pub fn test_decode_response_body() {
+    let response = |encoding: &str, content_type: &str, body: &[u8]| {
+        let mut headers = Headers::empty();
+        if !encoding.is_empty() {
+            headers.set("Content-Encoding", encoding.to_owned());
+        }
+        headers.set("Content-Type", content_type.to_owned());
+        RawResponse::new(
+            StatusLine::new(HttpVersion::Http1_1, StatusCode::OK),
+            headers,
+            Some(body.to_vec()),
+        )
+    };
+
+    // a dynamic Huffman block
+    let gzip = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03\xd5\xc9\xdb\x15\x80\x20\x08\x00\xd0\x55\x18\xa0\xd3\x24\x2e\x61\x4a\x46\x89\x98\x60\xaf\xe9\xdb\xa1\xbf\xee\xef\x75\xd2\x90\x81\xaa\x76\x86\x28\x59\x1a\x28\x19\x78\x46\x1b\xc0\x7d\xbc\x20\x45\x31\x18\x5a\x6f\xe0\x23\x55\xd2\x40\x25\x01\x66\xb2\x11\xfc\x22\x47\x58\xf7\x0b\xb3\x3e\xa9\xf4\x89\xea\x19\xb7\x76\xcf\x6c\xff\x9a\x17\x28\x42\xa9\x4b\x39\x01\x00\x00";
+    let expected = "Lorem ipsum dolor sit amet, ".repeat(3)
+        + "consectetur adipiscing elit. "
+        + &(0..200).map(|i| (b'a' + (i * 7 % 26) as u8) as char).collect::<String>();
+    let decoded = response("gzip", "text/plain", gzip).decoded(1024).unwrap();
+    assert_eq!(None, decoded.headers().get("Content-Encoding"));
+    assert_eq!(Some("313"), decoded.headers().get("Content-Length"));
+    assert_eq!(expected, decoded.into_string().unwrap());
+    let err = response("gzip", "text/plain", gzip).decoded(100).unwrap_err();
+    assert_eq!(BodyError::Corrupt("decoded data too large".to_owned()), err);
+    let mut corrupt = gzip.to_vec();
+    corrupt[95] ^= 1;
+    assert!(matches!(
+        response("gzip", "text/plain", &corrupt).into_bytes(),
+        Err(BodyError::Corrupt(_))
+    ));
+
+    // zlib with a fixed Huffman block, and raw DEFLATE with a stored block
+    let zlib = b"\x78\x9c\x4b\x4e\x4c\x3b\xbc\x52\x21\x19\x44\x02\x00\x1d\xf7\x05\x4d";
+    let body = response("deflate", "text/plain; charset=utf-8", zlib).into_string();
+    assert_eq!("café café", body.unwrap());
+    let raw = b"\x01\x06\x00\xf9\xff\x73\x74\x6f\x72\x65\x64";
+    assert_eq!(b"stored".to_vec(), response("identity, deflate", "", raw).into_bytes().unwrap());
+
+    assert_eq!(
+        "caf\u{e9}",
+        response("", "text/plain; charset=latin1", b"caf\xe9").into_string().unwrap()
+    );
+    assert_eq!(
+        BodyError::UnsupportedEncoding("br".to_owned()),
+        response("br", "text/plain", b"").into_bytes().unwrap_err()
+    );
+
+    #[cfg(feature = "config")]
+    {
+        let json = response("", "application/json", br#"{"id": 7}"#).json::<serde_json::Value>();
+        assert_eq!(serde_json::json!({"id": 7}), json.unwrap());
+        assert!(matches!(
+            response("", "", b"{").json::<serde_json::Value>(),
+            Err(BodyError::Json(_))
+        ));
+    }
+}
+
+#[test]
+pub fn test_deflate_round_trip() {
+    let text: Vec<u8> = (0..5000).map(|i| b"toot, toot! "[i % 12] ^ (i / 1000) as u8).collect();
+    for data in [&b""[..], b"a", b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa", &text] {
+        let compressed = deflate_sync(data, 1 << 15);
+        assert!(compressed.ends_with(&[0x00, 0x00, 0xFF, 0xFF]));
+        let terminated = [&compressed[..], &[0x03, 0x00]].concat();
+        assert_eq!(data, &inflate::inflate(&terminated, data.len()).unwrap().0[..]);
+    }
+    assert!(deflate_sync(&text, 1 << 15).len() < text.len() / 10);
+
+    // "Hello" again, after "Hello" (RFC 7692 section 7.2.3.2)
+    let data = b"\xf2\x00\x11\x00\x00\x00\x00\xff\xff\x03\x00";
+    assert_eq!(b"Hello".to_vec(), inflate_after(b"Hello", data, 5).unwrap().0);
+    assert_eq!(Err(TOO_LARGE), inflate_after(b"Hello", data, 4).map(|_| ()));
+    assert!(inflate::inflate(data, 5).is_err());
+}
+
+#[tokio::test]
+pub async fn test_relay_streamed_response() {
+    let limits = RequestLimits::default();
+    let relay = |source: &'static [u8], method: Method, version: HttpVersion| async move {
+        let (response, mut body) = read_http_response_head(source, method, &limits).await.unwrap();
+        let mut relayed = Vec::new();
+        write_streaming_response(&mut relayed, response, &mut body, version).await.unwrap();
+        (String::from_utf8(relayed).unwrap(), body)
+    };
+
+    let source = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nTrailer: Digest\r\n\r\n\
+                   5;ext=1\r\nhello\r\n0\r\nDigest: sha-256=abc\r\n\r\n";
+    let (relayed, body) = relay(source, Method::GET, HttpVersion::Http1_1).await;
+    assert_eq!(Some("sha-256=abc"), body.trailers().get("Digest"));
+    assert_eq!(
+        "HTTP/1.1 200 OK\r\nTrailer: Digest\r\nTransfer-Encoding: chunked\r\n\r\n\
+         5\r\nhello\r\n0\r\nDigest: sha-256=abc\r\n\r\n",
+        relayed
+    );
+    let (relayed, _) = relay(source, Method::GET, HttpVersion::Http1_0).await;
+    assert_eq!("HTTP/1.1 200 OK\r\nTrailer: Digest\r\n\r\nhello", relayed);
+
+    let source = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+    let (relayed, _) = relay(source, Method::GET, HttpVersion::Http1_1).await;
+    assert_eq!("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok", relayed);
+    let source = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n";
+    let (relayed, _) = relay(source, Method::HEAD, HttpVersion::Http1_1).await;
+    assert_eq!("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n", relayed);
+}
+
+#[test]
+pub fn test_status_code_text() {
+    assert_eq!("404 Not Found", StatusCode::NOT_FOUND.to_string());
+    assert_eq!("299 Success", StatusCode::from(299).to_string());
+    assert_eq!("599 Server Error", StatusCode::from(599).to_string());
+    assert_eq!("Unknown", StatusCode::from(600).default_reason_phrase());
+
+    assert_eq!(Ok(StatusCode::NOT_FOUND), "404".parse());
+    assert_eq!(Ok(StatusCode::from(299)), StatusCode::from(299).to_string().parse());
+    for invalid in ["", "99", "600", "+20", "2000", "20x"] {
+        assert!(invalid.parse::<StatusCode>().is_err(), "{invalid}");
+    }
+
+    assert_eq!(Some(StatusClass::Informational), StatusCode::CONTINUE.class());
+    assert_eq!(Some(StatusClass::Redirection), StatusCode::FOUND.class());
+    assert_eq!(Some(StatusClass::ClientError), StatusCode::TOO_MANY_REQUESTS.class());
+    assert_eq!(None, StatusCode::from(42).class());
+}
+
+#[test]
+pub fn test_headers_from_pairs() {
+    let length = 3;
+    let headers = crate::headers! {
+        "Content-Type" => "text/html",
+        "Content-Length" => length,
+        "Vary" => " Accept ",
+    };
+    assert_eq!(
+        "Content-Type: text/html\r\nContent-Length: 3\r\nVary: Accept\r\n",
+        headers.to_http_message()
+    );
+    assert!(crate::headers! {}.is_empty());
+
+    assert!(matches!(
+        Headers::from_pairs([("Bad Name", "x")]),
+        Err(ParseRequestError::InvalidHeaderName(_))
+    ));
+    assert!(matches!(
+        Headers::from_pairs([("X-Split", "a\r\nInjected: 1")]),
+        Err(ParseRequestError::InvalidByte(b'\r'))
+    ));
+    assert_eq!(1, Headers::from_pairs([("X-Tab", "a\tb")]).unwrap().len());
+}
+
+#[tokio::test]
+pub async fn test_stream_response_from_pieces() {
+    struct Pieces(std::collections::VecDeque<Result<bytes::Bytes, std::io::Error>>);
+
+    impl futures_core::Stream for Pieces {
+        type Item = Result<bytes::Bytes, std::io::Error>;
+
+        fn poll_next(
+            mut self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Option<Self::Item>> {
+            std::task::Poll::Ready(self.0.pop_front())
+        }
+    }
+
+    let write = |pieces: Vec<Result<&'static str, &'static str>>, trailer: Option<&str>| {
+        let pieces = pieces
+            .into_iter()
+            .map(|piece| piece.map(bytes::Bytes::from).map_err(std::io::Error::other))
+            .collect();
+        let mut body = Body::from_stream(Pieces(pieces));
+        if let Some(field) = trailer {
+            body = body.error_trailer(field);
+        }
+        let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::OK);
+        let response = RawResponse::streaming(status_line, Headers::empty(), body);
+        async move {
+            let mut written = Vec::new();
+            let result = write_http_response(&mut written, response).await;
+            (result.is_ok(), String::from_utf8(written).unwrap())
+        }
+    };
+
+    let (ok, written) = write(vec![Ok("ab"), Ok(""), Ok("cde")], None).await;
+    assert!(ok);
+    assert_eq!(
+        "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nab\r\n3\r\ncde\r\n0\r\n\r\n",
+        written
+    );
+    let (ok, written) = write(vec![Ok("ab"), Err("db gone\r\n")], None).await;
+    assert!(!ok);
+    assert!(written.ends_with("2\r\nab\r\n"));
+    let (ok, written) = write(vec![Ok("ab"), Err("db gone\r\n")], Some("X-Error")).await;
+    assert!(ok);
+    assert_eq!(
+        "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nTrailer: X-Error\r\n\r\n\
+         2\r\nab\r\n0\r\nX-Error: db gone  \r\n\r\n",
+        written
+    );
+
+    // a piece longer than a chunk is split, not cut short
+    let piece: Vec<u8> = (0..64 * 1024).map(|i| (i % 251) as u8).collect();
+    let body = Body::from_stream(Pieces([Ok(bytes::Bytes::from(piece.clone()))].into()));
+    let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::OK);
+    let mut written = Vec::new();
+    write_http_response(&mut written, RawResponse::streaming(status_line, Headers::empty(), body))
+        .await
+        .unwrap();
+    let mut rest = &written[written.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4..];
+    let mut received = Vec::new();
+    loop {
+        let line_end = rest.windows(2).position(|w| w == b"\r\n").unwrap();
+        let size = std::str::from_utf8(&rest[..line_end]).unwrap();
+        let size = usize::from_str_radix(size, 16).unwrap();
+        rest = &rest[line_end + 2..];
+        if size == 0 {
+            break;
+        }
+        received.extend_from_slice(&rest[..size]);
+        rest = &rest[size + 2..];
+    }
+    assert_eq!(piece, received);
+    assert_eq!(b"\r\n", rest);
+}
+
+#[test]
+pub fn test_http_server_codec() {
+    let mut codec = HttpServerCodec::default();
+    let mut buf = bytes::BytesMut::new();
+    let input = b"POST /a HTTP/1.1\r\nContent-Length: 3\r\n\r\nabcGET /b HTTP/1.1\r\n\r\nGET /c";
+    let mut decoded = Vec::new();
+    for byte in input {
+        buf.extend_from_slice(&[*byte]);
+        while let Some(request) = codec.decode(&mut buf).unwrap() {
+            decoded.push((request.request_line.to_string(), request.body));
+        }
+    }
+    assert_eq!(
+        vec![
+            ("POST /a HTTP/1.1".to_owned(), Some(b"abc".to_vec())),
+            ("GET /b HTTP/1.1".to_owned(), None)
+        ],
+        decoded
+    );
+    assert_eq!(&b"GET /c"[..], &buf[..]);
+
+    let mut codec = HttpServerCodec::new(RequestLimits { max_line_len: 8, ..Default::default() });
+    let mut buf = bytes::BytesMut::from(&b"GET /longer HTTP/1.1"[..]);
+    assert_eq!(Err(ParseRequestError::LineTooLong), codec.decode(&mut buf).map(|_| ()));
+    let mut buf = bytes::BytesMut::from(&b"GET / HTTP/1.1\nHost: a\n\n"[..]);
+    let decoded = HttpServerCodec::default().decode(&mut buf).map(|_| ());
+    assert_eq!(Err(ParseRequestError::BareLineFeed), decoded);
+
+    let mut buf = bytes::BytesMut::new();
+    let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::OK);
+    codec
+        .encode(RawResponse::new(status_line, Headers::empty(), Some(b"ok".to_vec())), &mut buf)
+        .unwrap();
+    assert_eq!(&b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok"[..], &buf[..]);
+}
+
+#[test]
+pub fn test_http_server_codec_chunked() {
+    let mut codec = HttpServerCodec::default();
+    let mut buf = bytes::BytesMut::new();
+    let input = b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                  5\r\nhello\r\n1;ext=1\r\n!\r\n0\r\nX-Trailer: 1\r\n\r\nGET /b HTTP/1.1\r\n\r\n";
+    let mut decoded = Vec::new();
+    for byte in input {
+        buf.extend_from_slice(&[*byte]);
+        while let Some(request) = codec.decode(&mut buf).unwrap() {
+            let length = request.headers.get("Content-Length").map(str::to_owned);
+            decoded.push((request.request_line.to_string(), length, request.body));
+        }
+    }
+    assert_eq!(
+        vec![
+            ("POST /a HTTP/1.1".to_owned(), Some("6".to_owned()), Some(b"hello!".to_vec())),
+            ("GET /b HTTP/1.1".to_owned(), None, None)
+        ],
+        decoded
+    );
+    assert!(buf.is_empty());
+
+    for invalid in [
+        &b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n"[..],
+        b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n",
+        b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n",
+        b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r\nab\r\n",
+    ] {
+        let mut buf = bytes::BytesMut::from(invalid);
+        let decoded = HttpServerCodec::default().decode(&mut buf);
+        assert!(matches!(decoded, Err(ParseRequestError::InvalidHeader(_))), "{decoded:?}");
+    }
+    let limits = RequestLimits { max_body_len: 4, ..Default::default() };
+    let mut buf = bytes::BytesMut::from(
+        &b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n3\r\n"[..],
+    );
+    let decoded = HttpServerCodec::new(limits).decode(&mut buf).map(|_| ());
+    assert_eq!(Err(ParseRequestError::BodyTooLarge(6)), decoded);
+}
+
+#[cfg(feature = "codec")]
+#[tokio::test]
+pub async fn test_http_server_codec_framed() {
+    use futures_core::Stream;
+    use tokio::io::AsyncWriteExt;
+    use tokio_util::codec::{Encoder, Framed};
+
+    let (client, server) = tokio::io::duplex(1024);
+    let mut framed = Framed::new(server, HttpServerCodec::default());
+    let mut client = client;
+    client
+        .write_all(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nok\r\n0\r\n\r\n")
+        .await
+        .unwrap();
+    let next = std::future::poll_fn(|cx| std::pin::Pin::new(&mut framed).poll_next(cx));
+    let request = next.await.unwrap().unwrap();
+    assert_eq!(Some(b"ok".to_vec()), request.body);
+
+    let mut buf = bytes::BytesMut::new();
+    let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::NO_CONTENT);
+    let response = RawResponse::new(status_line, Headers::empty(), None);
+    Encoder::encode(framed.codec_mut(), response, &mut buf).unwrap();
+    assert_eq!(&b"HTTP/1.1 204 No Content\r\n\r\n"[..], &buf[..]);
+}
+
+#[test]
+pub fn test_parse_buffers() {
+    let (request_line, headers, len) =
+        parse_request_head(b"GET / HTTP/1.1\r\nHost: a\r\n\r\nbody").unwrap().unwrap();
+    assert_eq!("GET / HTTP/1.1", request_line.to_string());
+    assert_eq!(Some("a"), headers.get("Host"));
+    assert_eq!(27, len);
+    assert!(parse_request_head(b"GET / HTTP/1.1\r\nHost: a\r\n").unwrap().is_none());
+
+    assert_eq!(Ok(0x1f), parse_chunk_size(b"1F"));
+    assert_eq!(Ok(10), parse_chunk_size(b"a ;ext=\"x;y\""));
+    for invalid in [&b""[..], b"+1", b" 1", b"0x1", b"1 2", b"ffffffffffffffffff"] {
+        assert!(parse_chunk_size(invalid).is_err(), "{invalid:?}");
+    }
+}
+
+#[test]
+pub fn test_parse_borrowed_head() {
+    let buf = b"POST /p?q HTTP/1.1\r\nHost: a\r\nX-Latin: caf\xe9 \r\nContent-Length: 2\r\n\r\nok";
+    let mut headers = [EMPTY_HEADER; 4];
+    let limits = RequestLimits::default();
+    assert_eq!(
+        Err(ParseRequestError::InvalidByte(0xe9)),
+        parse_request_head_borrowed(buf, &mut headers, &limits)
+    );
+
+    let limits = RequestLimits { leniency: Leniency::LENIENT, ..Default::default() };
+    let (head, len) = parse_request_head_borrowed(buf, &mut headers, &limits).unwrap().unwrap();
+    assert_eq!(
+        (Method::POST, "/p?q", HttpVersion::Http1_1),
+        (head.method, head.target, head.version)
+    );
+    assert_eq!(3, head.headers.len());
+    assert_eq!(Some(&b"caf\xe9"[..]), head.header("x-latin"));
+    assert_eq!(Some(&b"2"[..]), head.header("Content-Length"));
+    assert_eq!(buf.len() - 2, len);
+
+    let mut headers = [EMPTY_HEADER; 2];
+    assert_eq!(
+        Err(ParseRequestError::TooManyHeaders),
+        parse_request_head_borrowed(buf, &mut headers, &limits)
+    );
+    assert_eq!(Ok(None), parse_request_head_borrowed(&buf[..20], &mut headers, &limits));
+}
+
+#[test]
+pub fn test_byte_ranges() {
+    let parse = |value: &str| ByteRanges::parse(value, 100);
+    let one = |range: std::ops::Range<u64>| ByteRanges::Satisfiable(vec![range]);
+    assert_eq!(one(0..10), parse("bytes=0-9"));
+    assert_eq!(one(90..100), parse("Bytes=90-"));
+    assert_eq!(one(80..100), parse("bytes=-20"));
+    assert_eq!(one(0..100), parse("bytes=-200"));
+    assert_eq!(one(50..100), parse("bytes=50-999"));
+    assert_eq!(ByteRanges::Satisfiable(vec![20..30, 0..10]), parse("bytes=20-29, 0-9"));
+    // unsatisfiable ranges are left out
+    assert_eq!(one(0..1), parse("bytes=0-0,200-300"));
+    assert_eq!(ByteRanges::Unsatisfiable, parse("bytes=100-"));
+    assert_eq!(ByteRanges::Unsatisfiable, parse("bytes=-0"));
+
+    assert_eq!(ByteRanges::Whole, parse("items=0-9"));
+    assert_eq!(ByteRanges::Whole, parse("bytes=9-0"));
+    assert_eq!(ByteRanges::Whole, parse("bytes=a-9"));
+    assert_eq!(ByteRanges::Whole, parse("bytes=,"));
+    assert_eq!(ByteRanges::Whole, parse("bytes=0-9,5-20"));
+    let many = (0..17).map(|i| format!("{i}-{i}")).collect::<Vec<_>>().join(",");
+    assert_eq!(ByteRanges::Whole, parse(&format!("bytes={many}")));
+
+    assert_eq!("bytes 0-9/100", content_range(&(0..10), 100));
+    let body = ByteRangesBody::new().boundary("XyZ");
+    assert_eq!("multipart/byteranges; boundary=XyZ", body.content_type());
+    assert_eq!(
+        "--XyZ\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/6\r\n\r\nab\r\n\
+         --XyZ\r\nContent-Type: text/plain\r\nContent-Range: bytes 4-5/6\r\n\r\nef\r\n\
+         --XyZ--\r\n",
+        String::from_utf8(body.encode(b"abcdef", &[0..2, 4..6], "text/plain")).unwrap()
+    );
+}
+
+#[test]
+pub fn test_content_disposition() {
+    let download = ContentDisposition::attachment("report.pdf");
+    assert_eq!("attachment; filename=report.pdf", download.to_string());
+    let download = ContentDisposition::attachment("r\u{e9}sum\u{e9} 2024.pdf");
+    assert_eq!(
+        "attachment; filename=\"r_sum_ 2024.pdf\"; filename*=UTF-8''r%C3%A9sum%C3%A9%202024.pdf",
+        download.to_string()
+    );
+    assert_eq!(download, download.to_string().parse().unwrap());
+    assert_eq!("inline", ContentDisposition::inline().to_string());
+
+    let parsed = ContentDisposition::parse("Attachment; FILENAME=\"a \\\"b\\\".txt\"").unwrap();
+    assert!(parsed.is_attachment());
+    assert_eq!(Some("a \"b\".txt"), parsed.filename());
+    let parsed =
+        ContentDisposition::parse("attachment; filename*=iso-8859-1'en'%A3%20rates; filename=x")
+            .unwrap();
+    assert_eq!(Some("\u{a3} rates"), parsed.filename());
+    let parsed = ContentDisposition::parse("x-unknown; filename*=koi8-r''x; filename=y").unwrap();
+    assert_eq!((DispositionType::Attachment, Some("y")), (parsed.kind(), parsed.filename()));
+    assert!(ContentDisposition::parse("attachment; filename").is_err());
+    assert!(ContentDisposition::parse("attach ment").is_err());
+
+    let field = ContentDisposition::form_data("up\"load").with_filename("caf\u{e9}.txt");
+    assert_eq!("form-data; name=\"up%22load\"; filename=\"caf\u{e9}.txt\"", field.to_string());
+    let parsed = ContentDisposition::parse("form-data; name=\"file\"; filename=\"a.png\"").unwrap();
+    assert_eq!(DispositionType::FormData, parsed.kind());
+    assert_eq!((Some("file"), Some("a.png")), (parsed.name(), parsed.filename()));
+}
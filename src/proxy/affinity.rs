@@ -0,0 +1,138 @@
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use super::{Health, Upstream, UpstreamPool};
+use crate::protocol::RawRequest;
+
+/// virtual nodes per upstream on the hash ring
+const VIRTUAL_NODES: usize = 64;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum HashKey {
+    ClientIp,
+    /// value of this request header, requests without it fall back to the client ip
+    Header(String),
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub enum Affinity {
+    #[default]
+    RoundRobin,
+    /// pin clients to an upstream with a routing cookie of this name
+    Cookie {
+        name: String,
+    },
+    ConsistentHash(HashKey),
+}
+
+#[derive(Debug, Clone)]
+pub struct Selection {
+    pub upstream: Arc<Upstream>,
+    /// `Set-Cookie` value to add to the response when cookie affinity picked a new upstream
+    pub set_cookie: Option<String>,
+}
+
+/// Picks an upstream from the healthy members of a pool according to an `Affinity`
+pub struct Balancer {
+    pool: UpstreamPool,
+    affinity: Affinity,
+    /// sorted (hash, upstream index)
+    ring: Vec<(u64, usize)>,
+    next: AtomicUsize,
+}
+
+impl Balancer {
+    pub fn new(pool: UpstreamPool, affinity: Affinity) -> Self {
+        let mut ring = Vec::new();
+        if let Affinity::ConsistentHash(_) = affinity {
+            for (index, upstream) in pool.upstreams().iter().enumerate() {
+                for node in 0..VIRTUAL_NODES {
+                    ring.push((fnv1a(format!("{}#{node}", upstream.addr()).as_bytes()), index));
+                }
+            }
+            ring.sort_unstable();
+        }
+        Self { pool, affinity, ring, next: AtomicUsize::new(0) }
+    }
+
+    pub fn pool(&self) -> &UpstreamPool {
+        &self.pool
+    }
+
+    /// `None` when no upstream is healthy
+    pub fn select(&self, request: &RawRequest, peer: IpAddr) -> Option<Selection> {
+        match self.affinity {
+            Affinity::RoundRobin => {
+                self.round_robin().map(|upstream| Selection { upstream, set_cookie: None })
+            }
+            Affinity::Cookie { ref name } => {
+                let pinned = cookie(request, name)
+                    .and_then(|value| self.healthy().find(|u| route_id(u) == value).cloned());
+                match pinned {
+                    Some(upstream) => Some(Selection { upstream, set_cookie: None }),
+                    None => self.round_robin().map(|upstream| {
+                        let set_cookie =
+                            format!("{name}={}; Path=/; HttpOnly", route_id(&upstream));
+                        Selection { upstream, set_cookie: Some(set_cookie) }
+                    }),
+                }
+            }
+            Affinity::ConsistentHash(ref key) => {
+                let key = match key {
+                    HashKey::Header(field) => request.headers.get(field).map(str::to_owned),
+                    HashKey::ClientIp => None,
+                }
+                .unwrap_or_else(|| peer.to_string());
+                self.ring_lookup(fnv1a(key.as_bytes()))
+                    .map(|upstream| Selection { upstream, set_cookie: None })
+            }
+        }
+    }
+
+    fn healthy(&self) -> impl Iterator<Item = &Arc<Upstream>> + '_ {
+        self.pool.healthy()
+    }
+
+    fn round_robin(&self) -> Option<Arc<Upstream>> {
+        let healthy = self.healthy().collect::<Vec<_>>();
+        if healthy.is_empty() {
+            return None;
+        }
+        let next = self.next.fetch_add(1, Ordering::Relaxed);
+        Some(healthy[next % healthy.len()].clone())
+    }
+
+    /// first healthy upstream clockwise from `hash`
+    fn ring_lookup(&self, hash: u64) -> Option<Arc<Upstream>> {
+        let start = self.ring.partition_point(|(h, _)| *h < hash);
+        let upstreams = self.pool.upstreams();
+        (start..self.ring.len())
+            .chain(0..start)
+            .map(|i| &upstreams[self.ring[i].1])
+            .find(|u| self.pool.health(u) == Health::Healthy)
+            .cloned()
+    }
+}
+
+fn cookie<'a>(request: &'a RawRequest, name: &str) -> Option<&'a str> {
+    request
+        .headers
+        .get_all("Cookie")
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v)
+}
+
+/// routing cookie value, so the upstream address itself isn't exposed to clients
+fn route_id(upstream: &Upstream) -> String {
+    format!("{:016x}", fnv1a(upstream.addr().as_bytes()))
+}
+
+/// FNV-1a, stable across processes so every proxy instance hashes keys the same way
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+use tokio::time::sleep;
+
+use crate::protocol::{Host, HostName};
+use crate::server::BoxFuture;
+
+/// Addresses a name resolved to, `ttl` is how long they may be used for if the source knows
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Resolved {
+    pub addrs: Vec<IpAddr>,
+    pub ttl: Option<Duration>,
+}
+
+/// Looks up the addresses of a domain name.
+///
+/// There is no DNS client in here: `SystemResolver` asks the OS, other backends, e.g. one
+/// reporting record TTLs, plug in by implementing this.
+pub trait Resolver: Send + Sync {
+    fn resolve<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<Resolved>>;
+}
+
+/// `getaddrinfo` on the blocking pool, which doesn't tell TTLs
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<Resolved>> {
+        Box::pin(async move {
+            let mut addrs = Vec::new();
+            for addr in tokio::net::lookup_host((name, 0)).await? {
+                if !addrs.contains(&addr.ip()) {
+                    addrs.push(addr.ip());
+                }
+            }
+            Ok(Resolved { addrs, ttl: None })
+        })
+    }
+}
+
+/// Fixed addresses for some names, like `/etc/hosts`, other names go to the fallback if any
+#[derive(Default)]
+pub struct StaticHosts {
+    hosts: HashMap<String, Vec<IpAddr>>,
+    fallback: Option<Arc<dyn Resolver>>,
+}
+
+impl StaticHosts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn host(mut self, name: &str, addrs: &[IpAddr]) -> Self {
+        self.hosts.insert(normalize(name), addrs.to_vec());
+        self
+    }
+
+    pub fn fallback<R: Resolver + 'static>(mut self, resolver: R) -> Self {
+        self.fallback = Some(Arc::new(resolver));
+        self
+    }
+}
+
+impl Resolver for StaticHosts {
+    fn resolve<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<Resolved>> {
+        if let Some(addrs) = self.hosts.get(&normalize(name)) {
+            let resolved = Resolved { addrs: addrs.clone(), ttl: None };
+            return Box::pin(async move { Ok(resolved) });
+        }
+        match self.fallback {
+            Some(ref fallback) => fallback.resolve(name),
+            None => Box::pin(async move { Err(not_found(name)) }),
+        }
+    }
+}
+
+struct CacheEntry {
+    addrs: Vec<IpAddr>,
+    expires: Instant,
+    /// where the next answer starts, to spread connections over all addresses
+    next: usize,
+}
+
+/// Caches answers of another resolver for their TTL, or `default_ttl` if it has none, capped at
+/// `max_ttl`. Each answer from the cache is rotated by one address, round-robin.
+pub struct DnsCache<R> {
+    resolver: R,
+    default_ttl: Duration,
+    max_ttl: Duration,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+}
+
+impl<R: Resolver> DnsCache<R> {
+    pub fn new(resolver: R) -> Self {
+        Self {
+            resolver,
+            default_ttl: Duration::from_secs(30),
+            max_ttl: Duration::from_secs(300),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn default_ttl(mut self, ttl: Duration) -> Self {
+        self.default_ttl = ttl;
+        self
+    }
+
+    pub fn max_ttl(mut self, ttl: Duration) -> Self {
+        self.max_ttl = ttl;
+        self
+    }
+
+    /// Forgets every answer, e.g. after a failover
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    fn cached(&self, name: &str) -> Option<Resolved> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get_mut(name)?;
+        let ttl = entry.expires.checked_duration_since(Instant::now()).filter(|ttl| !ttl.is_zero());
+        let Some(ttl) = ttl else {
+            entries.remove(name);
+            return None;
+        };
+        let mut addrs = entry.addrs.clone();
+        let len = addrs.len().max(1);
+        addrs.rotate_left(entry.next % len);
+        entry.next = entry.next.wrapping_add(1);
+        Some(Resolved { addrs, ttl: Some(ttl) })
+    }
+}
+
+impl<R: Resolver> Resolver for DnsCache<R> {
+    fn resolve<'a>(&'a self, name: &'a str) -> BoxFuture<'a, io::Result<Resolved>> {
+        Box::pin(async move {
+            let name = normalize(name);
+            if let Some(resolved) = self.cached(&name) {
+                return Ok(resolved);
+            }
+            let resolved = self.resolver.resolve(&name).await?;
+            let ttl = resolved.ttl.unwrap_or(self.default_ttl).min(self.max_ttl);
+            let entry = CacheEntry {
+                addrs: resolved.addrs.clone(),
+                expires: Instant::now() + ttl,
+                next: 1,
+            };
+            self.entries.lock().unwrap().insert(name, entry);
+            Ok(Resolved { addrs: resolved.addrs, ttl: Some(ttl) })
+        })
+    }
+}
+
+/// Connects to `addr`, a `host:port` pair, trying its addresses as in Happy Eyeballs
+/// (RFC 8305).
+///
+/// IPv6 and IPv4 addresses are tried alternately, each attempt `attempt_delay` after the
+/// previous one or right after it failed. The first connection established wins, the attempts
+/// still pending are dropped.
+pub async fn connect(
+    resolver: &dyn Resolver,
+    addr: &str,
+    attempt_delay: Duration,
+) -> io::Result<TcpStream> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("invalid address {addr}"));
+    let host = Host::parse(addr).map_err(|_| invalid())?;
+    let port = host.port().ok_or_else(invalid)?;
+    let ips = match host.name() {
+        HostName::Ip(ip) => vec![*ip],
+        HostName::Domain(name) => resolver.resolve(name).await?.addrs,
+    };
+
+    let mut pending = interleave(ips).into_iter().map(|ip| SocketAddr::new(ip, port));
+    let mut attempts = JoinSet::new();
+    let mut error = not_found(addr);
+    loop {
+        match pending.next() {
+            Some(addr) => drop(attempts.spawn(TcpStream::connect(addr))),
+            None if attempts.is_empty() => return Err(error),
+            None => {}
+        }
+        let finished = if pending.len() > 0 {
+            tokio::select! {
+                finished = attempts.join_next() => finished,
+                _ = sleep(attempt_delay) => continue,
+            }
+        } else {
+            attempts.join_next().await
+        };
+        match finished {
+            Some(Ok(Ok(stream))) => return Ok(stream),
+            Some(Ok(Err(err))) => error = err,
+            Some(Err(err)) => error = io::Error::other(err),
+            None => {}
+        }
+    }
+}
+
+/// Alternates address families, starting with the one of the first address
+fn interleave(ips: Vec<IpAddr>) -> Vec<IpAddr> {
+    let Some(first) = ips.first() else {
+        return ips;
+    };
+    let first_v6 = first.is_ipv6();
+    let (preferred, other): (Vec<_>, Vec<_>) = ips.iter().partition(|ip| ip.is_ipv6() == first_v6);
+    let mut other = other.into_iter();
+    let mut interleaved = Vec::with_capacity(ips.len());
+    for ip in preferred {
+        interleaved.push(ip);
+        interleaved.extend(other.next());
+    }
+    interleaved.extend(other);
+    interleaved
+}
+
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+fn not_found(name: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("no addresses for {name}"))
+}
@@ -0,0 +1,249 @@
+use std::ffi::OsString;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+use super::dns::{connect, Resolver, SystemResolver};
+use super::forward::{open, parse_error};
+use crate::protocol::{
+    Headers, HttpVersion, Method, RawRequest, RequestLimits, RequestLine, RequestTarget, StatusCode,
+};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DownloadError {
+    Io(io::ErrorKind),
+    /// the server answered neither 200 nor 206
+    Status(StatusCode),
+    /// a 206 response for other bytes than asked for
+    InvalidRange(String),
+}
+
+impl Display for DownloadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DownloadError::Io(err) => write!(f, "download failure: {err}"),
+            DownloadError::Status(status) => write!(f, "download answered {}", **status),
+            DownloadError::InvalidRange(range) => write!(f, "unexpected content range: {range}"),
+        }
+    }
+}
+
+impl From<io::Error> for DownloadError {
+    fn from(value: io::Error) -> Self {
+        DownloadError::Io(value.kind())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Progress {
+    /// bytes in the file so far, including those of earlier attempts
+    pub downloaded: u64,
+    /// size of the whole resource, if the server told
+    pub total: Option<u64>,
+}
+
+/// Downloads one resource into a file, resuming with `Range` requests where an earlier attempt
+/// stopped.
+///
+/// The validator of the resource, its strong `ETag` or else its `Last-Modified` date, is kept
+/// next to the file with a `.resume` suffix until the download completes, and sent as
+/// `If-Range` so a changed resource is downloaded from the start again. This works across
+/// processes too: a later download to the same path picks up what an interrupted one left.
+pub struct RangeDownload {
+    addr: String,
+    target: String,
+    headers: Headers,
+    max_attempts: u32,
+    retry_delay: Duration,
+    resolver: Arc<dyn Resolver>,
+    limits: RequestLimits,
+}
+
+enum Outcome {
+    Complete(Progress),
+    /// the partial file doesn't belong to the resource, download all of it again
+    Restart,
+}
+
+impl RangeDownload {
+    /// `GET target` from `addr`, a `host:port` pair also sent as `Host`
+    pub fn new(addr: &str, target: &str) -> Self {
+        let mut headers = Headers::empty();
+        headers.set("Host", addr.to_owned());
+        Self {
+            addr: addr.to_owned(),
+            target: target.to_owned(),
+            headers,
+            max_attempts: 3,
+            retry_delay: Duration::from_secs(1),
+            resolver: Arc::new(SystemResolver),
+            limits: RequestLimits::default(),
+        }
+    }
+
+    pub fn header(mut self, field: &str, value: &str) -> Self {
+        self.headers.set(field, value.to_owned());
+        self
+    }
+
+    /// Attempts before an I/O error is given up on, each resuming the previous one
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    pub fn retry_delay(mut self, delay: Duration) -> Self {
+        self.retry_delay = delay;
+        self
+    }
+
+    pub fn resolver(mut self, resolver: Arc<dyn Resolver>) -> Self {
+        self.resolver = resolver;
+        self
+    }
+
+    /// Downloads into `path`, calling `progress` after every piece written
+    pub async fn to_file<P, F>(&self, path: P, progress: F) -> Result<Progress, DownloadError>
+    where
+        P: AsRef<Path>,
+        F: FnMut(Progress),
+    {
+        let path = path.as_ref();
+        let resume = resume_path(path);
+        let mut progress = progress;
+        let mut attempt = 1;
+        loop {
+            let err = match self.attempt(path, &resume, &mut progress).await {
+                Ok(Outcome::Complete(done)) => {
+                    remove_if_exists(&resume).await?;
+                    return Ok(done);
+                }
+                Ok(Outcome::Restart) => {
+                    File::create(path).await?;
+                    remove_if_exists(&resume).await?;
+                    DownloadError::InvalidRange("range not satisfiable".to_owned())
+                }
+                Err(err @ DownloadError::Io(_)) => err,
+                Err(err) => return Err(err),
+            };
+            if attempt >= self.max_attempts {
+                return Err(err);
+            }
+            attempt += 1;
+            tokio::time::sleep(self.retry_delay).await;
+        }
+    }
+
+    async fn attempt<F>(
+        &self,
+        path: &Path,
+        resume: &Path,
+        progress: &mut F,
+    ) -> Result<Outcome, DownloadError>
+    where
+        F: FnMut(Progress),
+    {
+        let offset = match fs::metadata(path).await {
+            Ok(metadata) => metadata.len(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(err.into()),
+        };
+        let validator = match offset {
+            0 => None,
+            _ => fs::read_to_string(resume).await.ok().filter(|v| !v.is_empty()),
+        };
+
+        let mut headers = self.headers.clone();
+        if let Some(ref validator) = validator {
+            headers.set("Range", format!("bytes={offset}-"));
+            headers.set("If-Range", validator.clone());
+        }
+        let request_line = RequestLine {
+            method: Method::GET,
+            target: RequestTarget::origin(&self.target),
+            version: HttpVersion::Http1_1,
+        };
+        let request =
+            RawRequest { request_line, headers, body: None, extensions: Default::default() };
+
+        let stream = connect(&*self.resolver, &self.addr, Duration::from_millis(250)).await?;
+        let (response, mut body) = open(stream, request, &self.limits).await?;
+        let content_range = response.headers().get("Content-Range").map(str::to_owned);
+
+        let (mut file, mut done) = match *response.status() {
+            206 if validator.is_some() => {
+                let range = content_range.unwrap_or_default();
+                let (start, total) = parse_content_range(&range)
+                    .ok_or_else(|| DownloadError::InvalidRange(range.clone()))?;
+                if start != offset {
+                    return Err(DownloadError::InvalidRange(range));
+                }
+                let file = OpenOptions::new().append(true).open(path).await?;
+                (file, Progress { downloaded: offset, total })
+            }
+            200 => {
+                let total = response.headers().get_parsed::<u64>("Content-Length");
+                fs::write(resume, validator_of(response.headers()).unwrap_or_default()).await?;
+                (File::create(path).await?, Progress { downloaded: 0, total })
+            }
+            416 if validator.is_some() => {
+                let total =
+                    content_range.as_deref().and_then(|range| range.strip_prefix("bytes */"));
+                return match total.and_then(|total| total.trim().parse::<u64>().ok()) {
+                    Some(total) if total == offset => {
+                        Ok(Outcome::Complete(Progress { downloaded: offset, total: Some(total) }))
+                    }
+                    _ => Ok(Outcome::Restart),
+                };
+            }
+            _ => return Err(DownloadError::Status(response.status())),
+        };
+
+        progress(done);
+        while let Some(chunk) = body.chunk().await.map_err(parse_error)? {
+            file.write_all(&chunk).await?;
+            done.downloaded += chunk.len() as u64;
+            progress(done);
+        }
+        file.flush().await?;
+        if done.total.is_some_and(|total| total != done.downloaded) {
+            return Err(DownloadError::Io(io::ErrorKind::UnexpectedEof));
+        }
+        Ok(Outcome::Complete(done))
+    }
+}
+
+/// `ETag` unless weak, which `If-Range` doesn't allow, or `Last-Modified`
+fn validator_of(headers: &Headers) -> Option<String> {
+    let etag = headers.get("ETag").map(str::trim).filter(|etag| !etag.starts_with("W/"));
+    etag.or_else(|| headers.get("Last-Modified")).map(str::to_owned)
+}
+
+/// `bytes first-last/total` with an unknown total as `*`
+fn parse_content_range(range: &str) -> Option<(u64, Option<u64>)> {
+    let (span, total) = range.trim().strip_prefix("bytes ")?.split_once('/')?;
+    let (first, _) = span.split_once('-')?;
+    let total = match total {
+        "*" => None,
+        total => Some(total.parse().ok()?),
+    };
+    Some((first.parse().ok()?, total))
+}
+
+fn resume_path(path: &Path) -> PathBuf {
+    let mut resume = OsString::from(path.as_os_str());
+    resume.push(".resume");
+    PathBuf::from(resume)
+}
+
+async fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path).await {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,288 @@
+use std::io;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time::{timeout, timeout_at};
+
+use super::Hop;
+use crate::middleware::{Deadline, REQUEST_TIMEOUT_HEADER};
+use crate::protocol::{
+    read_http_response, read_http_response_head, BodyStream, HttpVersion, Method,
+    ParseRequestError, RawRequest, RawResponse, RequestLimits,
+};
+
+/// Sends `request` to `addr` over a fresh connection and reads back the whole response,
+/// unless `hop` answers it here, e.g. for a loop.
+///
+/// The connection is closed after the exchange, `limits` bound the upstream response.
+pub async fn forward(
+    addr: &str,
+    request: RawRequest,
+    hop: &Hop,
+    limits: &RequestLimits,
+) -> io::Result<RawResponse> {
+    let mut request = request;
+    if let Some(response) = hop.on_request(&mut request) {
+        return Ok(response);
+    }
+    let mut response = connect_and_send(addr, request, limits).await?;
+    hop.on_response(&mut response);
+    Ok(response)
+}
+
+async fn connect_and_send(
+    addr: &str,
+    request: RawRequest,
+    limits: &RequestLimits,
+) -> io::Result<RawResponse> {
+    let connect = TcpStream::connect(addr);
+    let stream = match request.extensions.get::<Deadline>() {
+        Some(deadline) => {
+            timeout_at(deadline.0, connect).await.map_err(|_| deadline_exceeded())??
+        }
+        None => connect.await?,
+    };
+    send(stream, request, limits).await
+}
+
+/// Like `forward`, over an already established connection, e.g. a TLS stream.
+///
+/// Hop-by-hop headers are removed from the request and the response. A `Deadline` in the
+/// request extensions is passed on as `X-Request-Timeout` and bounds the whole exchange.
+pub async fn send<S>(
+    stream: S,
+    request: RawRequest,
+    limits: &RequestLimits,
+) -> io::Result<RawResponse>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut request = request;
+    request.headers.remove_hop_by_hop();
+    request.headers.set("Connection", "close".to_owned());
+
+    let Some(deadline) = request.extensions.get::<Deadline>().copied() else {
+        return exchange(stream, request, limits).await;
+    };
+    request.headers.set(REQUEST_TIMEOUT_HEADER, deadline.header_value());
+    timeout_at(deadline.0, exchange(stream, request, limits))
+        .await
+        .map_err(|_| deadline_exceeded())?
+}
+
+/// Like `send`, with the request body read from `body` while it is written instead of from
+/// the request: `length` bytes of it if known up front, otherwise all of it in chunked
+/// transfer coding.
+///
+/// Only the response head is read, its body is left to the returned `BodyStream`. A `Deadline`
+/// bounds the exchange up to the response head.
+pub async fn send_streaming<S, B>(
+    stream: S,
+    request: RawRequest,
+    body: B,
+    length: Option<u64>,
+    limits: &RequestLimits,
+) -> io::Result<(RawResponse, BodyStream<BufReader<S>>)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    B: AsyncRead + Unpin,
+{
+    stream_request(stream, request, body, length, None, limits).await
+}
+
+/// Like `send_streaming`, with `Expect: 100-continue`: the body is only sent once the upstream
+/// asks for it, or hasn't answered within `wait`. A response to the head alone, e.g. 413 or
+/// 401, is returned without sending any of the body.
+pub async fn send_expecting_continue<S, B>(
+    stream: S,
+    request: RawRequest,
+    body: B,
+    length: Option<u64>,
+    wait: Duration,
+    limits: &RequestLimits,
+) -> io::Result<(RawResponse, BodyStream<BufReader<S>>)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    B: AsyncRead + Unpin,
+{
+    let mut request = request;
+    request.headers.set("Expect", "100-continue".to_owned());
+    stream_request(stream, request, body, length, Some(wait), limits).await
+}
+
+async fn stream_request<S, B>(
+    stream: S,
+    request: RawRequest,
+    body: B,
+    length: Option<u64>,
+    continue_wait: Option<Duration>,
+    limits: &RequestLimits,
+) -> io::Result<(RawResponse, BodyStream<BufReader<S>>)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    B: AsyncRead + Unpin,
+{
+    let mut request = request;
+    request.body = None;
+    request.headers.remove_hop_by_hop();
+    request.headers.retain(|field, _| !field.eq_ignore_ascii_case("Content-Length"));
+    request.headers.set("Connection", "close".to_owned());
+    match length {
+        Some(length) => request.headers.set("Content-Length", length.to_string()),
+        None => request.headers.set("Transfer-Encoding", "chunked".to_owned()),
+    }
+
+    let body = Some((body, length));
+    let Some(deadline) = request.extensions.get::<Deadline>().copied() else {
+        return exchange_streaming(stream, request, body, continue_wait, limits).await;
+    };
+    request.headers.set(REQUEST_TIMEOUT_HEADER, deadline.header_value());
+    timeout_at(deadline.0, exchange_streaming(stream, request, body, continue_wait, limits))
+        .await
+        .map_err(|_| deadline_exceeded())?
+}
+
+/// Like `send`, but only the response head is read, its body is left to the returned
+/// `BodyStream`, e.g. for a download too large to buffer
+pub async fn open<S>(
+    stream: S,
+    request: RawRequest,
+    limits: &RequestLimits,
+) -> io::Result<(RawResponse, BodyStream<BufReader<S>>)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut request = request;
+    request.headers.remove_hop_by_hop();
+    request.headers.set("Connection", "close".to_owned());
+
+    let body = None::<(tokio::io::Empty, _)>;
+    let Some(deadline) = request.extensions.get::<Deadline>().copied() else {
+        return exchange_streaming(stream, request, body, None, limits).await;
+    };
+    request.headers.set(REQUEST_TIMEOUT_HEADER, deadline.header_value());
+    timeout_at(deadline.0, exchange_streaming(stream, request, body, None, limits))
+        .await
+        .map_err(|_| deadline_exceeded())?
+}
+
+async fn exchange_streaming<S, B>(
+    stream: S,
+    request: RawRequest,
+    body: Option<(B, Option<u64>)>,
+    continue_wait: Option<Duration>,
+    limits: &RequestLimits,
+) -> io::Result<(RawResponse, BodyStream<BufReader<S>>)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    B: AsyncRead + Unpin,
+{
+    let method = request.request_line.method;
+    let mut stream = BufReader::new(stream);
+    stream.write_all(&request.into_vec()).await?;
+
+    if let (Some(wait), Some(_)) = (continue_wait, &body) {
+        stream.flush().await?;
+        // `fill_buf` only looks ahead, giving up on the wait loses nothing of a late answer
+        if let Ok(filled) = timeout(wait, stream.fill_buf()).await {
+            filled?;
+            let (mut response, rest) =
+                read_http_response_head(stream, method, limits).await.map_err(parse_error)?;
+            if !is_interim(&response) {
+                response.headers_mut().remove_hop_by_hop();
+                return Ok((response, rest));
+            }
+            stream = rest.into_inner();
+        }
+    }
+
+    match body {
+        None => {}
+        Some((mut body, Some(length))) => {
+            let copied = tokio::io::copy(&mut (&mut body).take(length), &mut stream).await?;
+            if copied < length {
+                let message = format!("request body ended after {copied} of {length} bytes");
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, message));
+            }
+        }
+        Some((mut body, None)) => {
+            let mut chunk = vec![0; 16 * 1024];
+            loop {
+                let n = body.read(&mut chunk).await?;
+                if n == 0 {
+                    break;
+                }
+                stream.write_all(format!("{n:X}\r\n").as_bytes()).await?;
+                stream.write_all(&chunk[..n]).await?;
+                stream.write_all(b"\r\n").await?;
+            }
+            stream.write_all(b"0\r\n\r\n").await?;
+        }
+    }
+    stream.flush().await?;
+
+    loop {
+        let (mut response, rest) =
+            read_http_response_head(stream, method, limits).await.map_err(parse_error)?;
+        if !is_interim(&response) {
+            response.headers_mut().remove_hop_by_hop();
+            return Ok((response, rest));
+        }
+        stream = rest.into_inner();
+    }
+}
+
+/// 1xx responses other than 101, which come before the final one, e.g. a late 100 Continue
+fn is_interim(response: &RawResponse) -> bool {
+    let status = *response.status();
+    (100..200).contains(&status) && status != 101
+}
+
+async fn exchange<S>(
+    stream: S,
+    request: RawRequest,
+    limits: &RequestLimits,
+) -> io::Result<RawResponse>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let method = request.request_line.method;
+    let mut reader = BufReader::new(stream);
+    let (response, _) = roundtrip(&mut reader, &request.into_vec(), method, limits).await?;
+    Ok(response)
+}
+
+/// Writes `message` and reads the response to it, stripped of hop-by-hop headers. Also tells
+/// whether the upstream keeps the connection open for another request.
+pub(super) async fn roundtrip<S>(
+    stream: &mut BufReader<S>,
+    message: &[u8],
+    method: Method,
+    limits: &RequestLimits,
+) -> io::Result<(RawResponse, bool)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(message).await?;
+    stream.flush().await?;
+
+    let mut response = read_http_response(stream, method, limits).await.map_err(parse_error)?;
+    let reusable = match response.version() {
+        HttpVersion::Http1_1 => !response.headers().has_token("Connection", "close"),
+        _ => response.headers().has_token("Connection", "keep-alive"),
+    };
+    response.headers_mut().remove_hop_by_hop();
+    Ok((response, reusable))
+}
+
+pub(super) fn parse_error(err: ParseRequestError) -> io::Error {
+    match err {
+        ParseRequestError::Io(kind) => io::Error::from(kind),
+        err => io::Error::new(io::ErrorKind::InvalidData, err.to_string()),
+    }
+}
+
+pub(super) fn deadline_exceeded() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "request deadline exceeded")
+}
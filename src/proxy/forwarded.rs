@@ -0,0 +1,109 @@
+use std::collections::hash_map::RandomState;
+use std::fmt::{Display, Formatter};
+use std::hash::BuildHasher;
+use std::net::{IpAddr, SocketAddr};
+
+use crate::protocol::RawRequest;
+use crate::server::PeerAddr;
+
+/// A node of a `Forwarded` element (RFC 7239, section 6)
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Node {
+    Ip(IpAddr),
+    Addr(SocketAddr),
+    /// `_` followed by letters, digits, `.`, `_` or `-`, e.g. `_proxy1`
+    Obfuscated(String),
+    Unknown,
+}
+
+impl Display for Node {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Node::Ip(IpAddr::V4(ip)) => write!(f, "{ip}"),
+            // brackets and colons need quoting
+            Node::Ip(IpAddr::V6(ip)) => write!(f, "\"[{ip}]\""),
+            Node::Addr(addr) => write!(f, "\"{addr}\""),
+            Node::Obfuscated(name) => write!(f, "{name}"),
+            Node::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// Adds the hop to a request about to be proxied: a `Forwarded` element (RFC 7239) and,
+/// unless turned off, the legacy `X-Forwarded-For`, `-Proto` and `-Host` headers.
+///
+/// The client is the `PeerAddr` of the request, and the host its `Host` header. Elements of
+/// earlier proxies are kept, the new one is appended.
+#[derive(Debug, Clone)]
+pub struct Forwarding {
+    proto: &'static str,
+    by: Option<Node>,
+    obfuscate: Option<RandomState>,
+    legacy: bool,
+}
+
+impl Forwarding {
+    /// `proto` is the scheme the client used, `http` or `https`
+    pub fn new(proto: &'static str) -> Self {
+        Self { proto, by: None, obfuscate: None, legacy: true }
+    }
+
+    /// The interface the request came in on, left out unless given
+    pub fn by(mut self, node: Node) -> Self {
+        self.by = Some(node);
+        self
+    }
+
+    /// Names clients by an obfuscated identifier instead of their address. Identifiers are
+    /// stable for the lifetime of this value, so upstreams can still tell clients apart.
+    /// `X-Forwarded-For` is left out then, as it has no room for them.
+    pub fn obfuscate(mut self, obfuscate: bool) -> Self {
+        self.obfuscate = obfuscate.then(RandomState::new);
+        self
+    }
+
+    pub fn legacy_headers(mut self, legacy: bool) -> Self {
+        self.legacy = legacy;
+        self
+    }
+
+    /// the node naming the client of `request`
+    pub fn client(&self, request: &RawRequest) -> Node {
+        let Some(PeerAddr(peer)) = request.extensions.get::<PeerAddr>() else {
+            return Node::Unknown;
+        };
+        match self.obfuscate {
+            Some(ref state) => Node::Obfuscated(format!("_{:016x}", state.hash_one(peer.ip()))),
+            None => Node::Ip(peer.ip()),
+        }
+    }
+
+    /// The `Forwarded` element describing this hop of `request`
+    pub fn element(&self, request: &RawRequest) -> String {
+        let mut element = format!("for={};proto={}", self.client(request), self.proto);
+        if let Some(ref by) = self.by {
+            element.push_str(&format!(";by={by}"));
+        }
+        if let Some(host) = request.headers.get("Host") {
+            let host = host.replace('\\', "\\\\").replace('"', "\\\"");
+            element.push_str(&format!(";host=\"{host}\""));
+        }
+        element
+    }
+
+    pub fn apply(&self, request: &mut RawRequest) {
+        let element = self.element(request);
+        request.headers.append_list("Forwarded", &element);
+        if !self.legacy {
+            return;
+        }
+        if let (None, Some(PeerAddr(peer))) = (&self.obfuscate, request.extensions.get()) {
+            let ip = peer.ip().to_string();
+            request.headers.append_list("X-Forwarded-For", &ip);
+        }
+        request.headers.set("X-Forwarded-Proto", self.proto.to_owned());
+        if let Some(host) = request.headers.get("Host").map(str::to_owned) {
+            request.headers.set("X-Forwarded-Host", host);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::time::Duration;
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+use super::forward::parse_error;
+use crate::protocol::{read_http_response, Method, RawResponse, RequestLimits, StatusCode, CRLF};
+
+#[derive(Debug, Clone)]
+pub struct HealthCheckConfig {
+    /// request target of the probe, e.g. `/healthz`
+    pub path: String,
+    pub interval: Duration,
+    pub timeout: Duration,
+    pub expected_status: StatusCode,
+    /// the probe response body must contain this
+    pub expected_body: Option<String>,
+    /// consecutive successful probes before an unhealthy upstream is used again
+    pub healthy_threshold: u32,
+    /// consecutive failed probes before an upstream is taken out
+    pub unhealthy_threshold: u32,
+    /// consecutive failed proxied requests before an upstream is ejected
+    pub passive_failures: u32,
+    /// how long a passively ejected upstream stays out
+    pub passive_cooldown: Duration,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            path: "/".to_owned(),
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(2),
+            expected_status: StatusCode::OK,
+            expected_body: None,
+            healthy_threshold: 2,
+            unhealthy_threshold: 3,
+            passive_failures: 5,
+            passive_cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
+/// probe response bodies are read up to this many bytes, decoded as well
+const MAX_PROBE_BODY: usize = 64 * 1024;
+
+/// Sends one `GET` to `addr` and checks the status (and decoded body) against `config`
+pub async fn probe(addr: &str, config: &HealthCheckConfig) -> bool {
+    let exchange = async {
+        let mut stream = TcpStream::connect(addr).await?;
+        let request = format!(
+            "GET {} HTTP/1.1{CRLF}Host: {addr}{CRLF}Connection: close{CRLF}{CRLF}",
+            config.path
+        );
+        stream.write_all(request.as_bytes()).await?;
+
+        let limits = RequestLimits { max_body_len: MAX_PROBE_BODY, ..Default::default() };
+        read_http_response(&mut stream, Method::GET, &limits).await.map_err(parse_error)
+    };
+
+    let Ok(Ok(response)) = timeout(config.timeout, exchange).await else {
+        return false;
+    };
+    if response.status() != config.expected_status {
+        return false;
+    }
+    match config.expected_body {
+        Some(ref expected) => response
+            .decoded(MAX_PROBE_BODY)
+            .and_then(RawResponse::into_string)
+            .is_ok_and(|body| body.contains(expected.as_str())),
+        None => true,
+    }
+}
@@ -0,0 +1,58 @@
+use super::{Forwarding, MaxForwards, Via};
+use crate::protocol::{RawRequest, RawResponse};
+
+/// What a proxy adds to the messages it passes on besides forwarding them: `Via` with loop
+/// detection, `Max-Forwards` and `Forwarded`, each left out unless given.
+#[derive(Debug, Clone, Default)]
+pub struct Hop {
+    via: Option<Via>,
+    max_forwards: Option<MaxForwards>,
+    forwarding: Option<Forwarding>,
+}
+
+impl Hop {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn via(mut self, via: Via) -> Self {
+        self.via = Some(via);
+        self
+    }
+
+    pub fn max_forwards(mut self, max_forwards: MaxForwards) -> Self {
+        self.max_forwards = Some(max_forwards);
+        self
+    }
+
+    pub fn forwarding(mut self, forwarding: Forwarding) -> Self {
+        self.forwarding = Some(forwarding);
+        self
+    }
+
+    /// Prepares `request` to be forwarded, or answers it here when it must not be
+    pub fn on_request(&self, request: &mut RawRequest) -> Option<RawResponse> {
+        // `TRACE` echoes the request as it arrived, before this hop is added to it
+        if let Some(ref max_forwards) = self.max_forwards {
+            if let Some(response) = max_forwards.on_request(request) {
+                return Some(response);
+            }
+        }
+        if let Some(ref via) = self.via {
+            if let Some(response) = via.on_request(request) {
+                return Some(response);
+            }
+        }
+        if let Some(ref forwarding) = self.forwarding {
+            forwarding.apply(request);
+        }
+        None
+    }
+
+    /// Adds this hop to the upstream's response
+    pub fn on_response(&self, response: &mut RawResponse) {
+        if let Some(ref via) = self.via {
+            via.on_response(response);
+        }
+    }
+}
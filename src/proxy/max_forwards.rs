@@ -0,0 +1,66 @@
+use std::future::Future;
+
+use crate::protocol::{
+    Headers, HttpVersion, Method, RawRequest, RawResponse, StatusCode, StatusLine,
+};
+use crate::server::trace_echo;
+
+/// `Max-Forwards` handling of `TRACE` and `OPTIONS` requests on the way through the proxy
+/// (RFC 9110, section 7.6.2).
+///
+/// A request arriving with `Max-Forwards: 0` is answered here as its final recipient, `TRACE`
+/// with an echo and `OPTIONS` with 204 and the `allow`ed methods. Otherwise the value is
+/// decremented before forwarding. Other methods, and values which aren't a number, pass as is.
+#[derive(Debug, Clone, Default)]
+pub struct MaxForwards {
+    allow: Vec<Method>,
+}
+
+impl MaxForwards {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Methods listed in `Allow` when `OPTIONS` is answered here
+    pub fn allow(mut self, methods: &[Method]) -> Self {
+        self.allow = methods.to_vec();
+        self
+    }
+
+    /// Answers `request` when it arrived with `Max-Forwards: 0`, otherwise counts it down
+    pub fn on_request(&self, request: &mut RawRequest) -> Option<RawResponse> {
+        let method = request.request_line.method;
+        if !matches!(method, Method::TRACE | Method::OPTIONS) {
+            return None;
+        }
+        match request.headers.get_parsed::<u32>("Max-Forwards") {
+            Some(0) if method == Method::TRACE => Some(trace_echo(request)),
+            Some(0) => {
+                let mut headers = Headers::empty();
+                if !self.allow.is_empty() {
+                    let allow = self.allow.iter().map(Method::as_str).collect::<Vec<_>>();
+                    headers.set("Allow", allow.join(", "));
+                }
+                let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::NO_CONTENT);
+                Some(RawResponse::new(status_line, headers, None))
+            }
+            Some(hops) => {
+                request.headers.set("Max-Forwards", (hops - 1).to_string());
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub async fn call<F, Fut>(&self, request: RawRequest, proxy: F) -> RawResponse
+    where
+        F: FnOnce(RawRequest) -> Fut,
+        Fut: Future<Output = RawResponse>,
+    {
+        let mut request = request;
+        match self.on_request(&mut request) {
+            Some(response) => response,
+            None => proxy(request).await,
+        }
+    }
+}
@@ -0,0 +1,29 @@
+pub use self::affinity::{Affinity, Balancer, HashKey, Selection};
+pub use self::dns::{connect, DnsCache, Resolved, Resolver, StaticHosts, SystemResolver};
+pub use self::download::{DownloadError, Progress, RangeDownload};
+pub use self::forward::{forward, open, send, send_expecting_continue, send_streaming};
+pub use self::forwarded::{Forwarding, Node};
+pub use self::health::{probe, HealthCheckConfig};
+pub use self::hop::Hop;
+pub use self::max_forwards::MaxForwards;
+pub use self::multipart::{Multipart, MultipartReader, Part};
+pub use self::pool::{ConnectionPool, PoolConfig, PoolStats};
+pub use self::upgrade::{forward_upgrade, is_upgrade};
+pub use self::upstream::{Health, Upstream, UpstreamPool, UpstreamStats};
+pub use self::via::Via;
+
+mod affinity;
+mod dns;
+mod download;
+mod forward;
+mod forwarded;
+mod health;
+mod hop;
+mod max_forwards;
+mod multipart;
+mod pool;
+#[cfg(test)]
+mod tests;
+mod upgrade;
+mod upstream;
+mod via;
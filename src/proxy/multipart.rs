@@ -0,0 +1,197 @@
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::hash::BuildHasher;
+use std::io::{self, Cursor};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+
+use crate::files::content_type;
+use crate::protocol::ContentDisposition;
+
+type BoxReader = Box<dyn AsyncRead + Send + Unpin>;
+
+enum Body {
+    Bytes(Vec<u8>),
+    Reader(BoxReader, Option<u64>),
+}
+
+/// One field of a `Multipart` form
+pub struct Part {
+    filename: Option<String>,
+    content_type: Option<String>,
+    body: Body,
+}
+
+impl Part {
+    pub fn text(value: &str) -> Self {
+        Self::bytes(value.as_bytes().to_vec())
+    }
+
+    pub fn bytes(data: Vec<u8>) -> Self {
+        Self { filename: None, content_type: None, body: Body::Bytes(data) }
+    }
+
+    /// Read while the form is sent, `length` bytes of it if known, which makes the form's
+    /// length known too
+    pub fn reader<R>(reader: R, length: Option<u64>) -> Self
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+    {
+        let reader: BoxReader = match length {
+            Some(length) => Box::new(reader.take(length)),
+            None => Box::new(reader),
+        };
+        Self { filename: None, content_type: None, body: Body::Reader(reader, length) }
+    }
+
+    /// The file at `path`, named after it and with a media type guessed from its extension
+    pub async fn file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let file = tokio::fs::File::open(path).await?;
+        let length = file.metadata().await?.len();
+        let part = Self::reader(file, Some(length)).content_type(content_type(path));
+        match path.file_name() {
+            Some(name) => Ok(part.filename(&name.to_string_lossy())),
+            None => Ok(part),
+        }
+    }
+
+    pub fn filename(mut self, filename: &str) -> Self {
+        self.filename = Some(filename.to_owned());
+        self
+    }
+
+    pub fn content_type(mut self, content_type: &str) -> Self {
+        self.content_type = Some(content_type.to_owned());
+        self
+    }
+
+    fn length(&self) -> Option<u64> {
+        match self.body {
+            Body::Bytes(ref data) => Some(data.len() as u64),
+            Body::Reader(_, length) => length,
+        }
+    }
+}
+
+/// `multipart/form-data` request body (RFC 7578), sent by `send_streaming` from `into_reader`
+/// with the `content_type` and `content_length` of the form
+pub struct Multipart {
+    boundary: String,
+    parts: Vec<(String, Part)>,
+}
+
+impl Default for Multipart {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Multipart {
+    /// With a random boundary, which is not checked against the contents of the parts
+    pub fn new() -> Self {
+        let random = || RandomState::new().hash_one(0u8);
+        Self { boundary: format!("toot-{:016x}{:016x}", random(), random()), parts: Vec::new() }
+    }
+
+    pub fn boundary(mut self, boundary: &str) -> Self {
+        self.boundary = boundary.to_owned();
+        self
+    }
+
+    pub fn text(self, name: &str, value: &str) -> Self {
+        self.part(name, Part::text(value))
+    }
+
+    pub fn part(mut self, name: &str, part: Part) -> Self {
+        self.parts.push((name.to_owned(), part));
+        self
+    }
+
+    /// Value of the `Content-Type` header to send the form with
+    pub fn content_type(&self) -> String {
+        format!("multipart/form-data; boundary={}", self.boundary)
+    }
+
+    /// Length of the whole body, unless a part's length is unknown
+    pub fn content_length(&self) -> Option<u64> {
+        let mut length = self.closing().len() as u64;
+        for (name, part) in self.parts.iter() {
+            length += self.head(name, part).len() as u64 + part.length()? + 2;
+        }
+        Some(length)
+    }
+
+    pub fn into_reader(self) -> MultipartReader {
+        let heads = self.parts.iter().map(|(name, part)| self.head(name, part)).collect::<Vec<_>>();
+        let closing = self.closing();
+        let mut segments = VecDeque::new();
+        for (head, (_, part)) in heads.into_iter().zip(self.parts) {
+            segments.push_back(Segment::Bytes(Cursor::new(head.into_bytes())));
+            segments.push_back(match part.body {
+                Body::Bytes(data) => Segment::Bytes(Cursor::new(data)),
+                Body::Reader(reader, _) => Segment::Reader(reader),
+            });
+            segments.push_back(Segment::Bytes(Cursor::new(b"\r\n".to_vec())));
+        }
+        segments.push_back(Segment::Bytes(Cursor::new(closing.into_bytes())));
+        MultipartReader { segments }
+    }
+
+    /// Everything in front of the part's body
+    fn head(&self, name: &str, part: &Part) -> String {
+        let mut disposition = ContentDisposition::form_data(name);
+        if let Some(ref filename) = part.filename {
+            disposition = disposition.with_filename(filename);
+        }
+        let mut head = format!("--{}\r\nContent-Disposition: {disposition}\r\n", self.boundary);
+        if let Some(ref content_type) = part.content_type {
+            head.push_str(&format!("Content-Type: {content_type}\r\n"));
+        }
+        head.push_str("\r\n");
+        head
+    }
+
+    fn closing(&self) -> String {
+        format!("--{}--\r\n", self.boundary)
+    }
+}
+
+enum Segment {
+    Bytes(Cursor<Vec<u8>>),
+    Reader(BoxReader),
+}
+
+/// The encoded form, read part after part
+pub struct MultipartReader {
+    segments: VecDeque<Segment>,
+}
+
+impl AsyncRead for MultipartReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        while let Some(segment) = self.segments.front_mut() {
+            let filled = buf.filled().len();
+            let poll = match segment {
+                Segment::Bytes(bytes) => Pin::new(bytes).poll_read(cx, buf),
+                Segment::Reader(reader) => Pin::new(reader).poll_read(cx, buf),
+            };
+            match poll {
+                Poll::Ready(Ok(())) if buf.filled().len() == filled => {
+                    self.segments.pop_front();
+                }
+                poll => return poll,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
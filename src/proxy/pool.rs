@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::io::BufReader;
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::time::timeout_at;
+
+use super::dns::{connect, Resolver, SystemResolver};
+use super::forward::{deadline_exceeded, roundtrip};
+use crate::middleware::{Deadline, REQUEST_TIMEOUT_HEADER};
+use crate::protocol::{RawRequest, RawResponse, RequestLimits};
+
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    /// concurrent requests to one upstream, more wait for one of them to finish
+    pub max_per_origin: u32,
+    /// idle connections are closed after this
+    pub idle_timeout: Duration,
+    /// connections are not reused once this old, however busy
+    pub max_lifetime: Duration,
+    /// between connection attempts to the addresses of an upstream, see `connect`
+    pub attempt_delay: Duration,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            max_per_origin: 32,
+            idle_timeout: Duration::from_secs(90),
+            max_lifetime: Duration::from_secs(600),
+            attempt_delay: Duration::from_millis(250),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct PoolStats {
+    pub idle: usize,
+    /// requests currently in flight, each on its own connection
+    pub in_use: usize,
+    pub dialed: u64,
+    /// requests sent over an already open connection
+    pub reused: u64,
+    /// connections closed for being idle or open too long, or found closed by the upstream
+    pub expired: u64,
+}
+
+struct Conn {
+    stream: BufReader<TcpStream>,
+    created: Instant,
+    idle_since: Instant,
+}
+
+struct Origin {
+    idle: Vec<Conn>,
+    permits: Arc<Semaphore>,
+}
+
+struct Inner {
+    config: PoolConfig,
+    resolver: Arc<dyn Resolver>,
+    origins: Mutex<HashMap<String, Origin>>,
+    draining: AtomicBool,
+    dialed: AtomicU64,
+    reused: AtomicU64,
+    expired: AtomicU64,
+}
+
+/// Keep-alive connections to upstreams, shared by clones.
+///
+/// Unlike `forward`, which dials every request, connections are handed back after the exchange
+/// unless either side asked to close them.
+#[derive(Clone)]
+pub struct ConnectionPool {
+    inner: Arc<Inner>,
+}
+
+impl ConnectionPool {
+    pub fn new(config: PoolConfig) -> Self {
+        Self::with_resolver(config, Arc::new(SystemResolver))
+    }
+
+    /// Upstream names are looked up with `resolver`, e.g. a `DnsCache`
+    pub fn with_resolver(config: PoolConfig, resolver: Arc<dyn Resolver>) -> Self {
+        let inner = Inner {
+            config,
+            resolver,
+            origins: Mutex::new(HashMap::new()),
+            draining: AtomicBool::new(false),
+            dialed: AtomicU64::new(0),
+            reused: AtomicU64::new(0),
+            expired: AtomicU64::new(0),
+        };
+        Self { inner: Arc::new(inner) }
+    }
+
+    /// Sends `request` to `addr` over a pooled connection, see `send` for what is done to it.
+    ///
+    /// A request which fails on a reused connection is sent once more over a new one if its
+    /// method is idempotent, the upstream may have closed the connection meanwhile.
+    pub async fn send(
+        &self,
+        addr: &str,
+        request: RawRequest,
+        limits: &RequestLimits,
+    ) -> io::Result<RawResponse> {
+        let mut request = request;
+        request.headers.remove_hop_by_hop();
+
+        let Some(deadline) = request.extensions.get::<Deadline>().copied() else {
+            return self.exchange(addr, request, limits).await;
+        };
+        request.headers.set(REQUEST_TIMEOUT_HEADER, deadline.header_value());
+        timeout_at(deadline.0, self.exchange(addr, request, limits))
+            .await
+            .map_err(|_| deadline_exceeded())?
+    }
+
+    async fn exchange(
+        &self,
+        addr: &str,
+        request: RawRequest,
+        limits: &RequestLimits,
+    ) -> io::Result<RawResponse> {
+        let permits = self.origin(addr, |origin| origin.permits.clone());
+        let _permit = permits.acquire_owned().await.expect("pool semaphores are never closed");
+
+        let method = request.request_line.method;
+        let message = request.into_vec();
+        if let Some(mut conn) = self.checkout(addr) {
+            self.inner.reused.fetch_add(1, Ordering::Relaxed);
+            match roundtrip(&mut conn.stream, &message, method, limits).await {
+                Ok((response, reusable)) => {
+                    if reusable {
+                        self.checkin(addr, conn);
+                    }
+                    return Ok(response);
+                }
+                Err(err) if !method.is_idempotent() => return Err(err),
+                Err(_) => self.inner.expired.fetch_add(1, Ordering::Relaxed),
+            };
+        }
+
+        let stream = connect(&*self.inner.resolver, addr, self.inner.config.attempt_delay).await?;
+        self.inner.dialed.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        let mut conn = Conn { stream: BufReader::new(stream), created: now, idle_since: now };
+        let (response, reusable) = roundtrip(&mut conn.stream, &message, method, limits).await?;
+        if reusable {
+            self.checkin(addr, conn);
+        }
+        Ok(response)
+    }
+
+    fn origin<T>(&self, addr: &str, f: impl FnOnce(&mut Origin) -> T) -> T {
+        let mut origins = self.inner.origins.lock().unwrap();
+        let origin = origins.entry(addr.to_owned()).or_insert_with(|| Origin {
+            idle: Vec::new(),
+            permits: Arc::new(Semaphore::new(self.inner.config.max_per_origin as usize)),
+        });
+        f(origin)
+    }
+
+    /// The most recently used connection which is still open
+    fn checkout(&self, addr: &str) -> Option<Conn> {
+        let config = &self.inner.config;
+        self.origin(addr, |origin| {
+            let before = origin.idle.len();
+            origin.idle.retain(|conn| {
+                conn.idle_since.elapsed() < config.idle_timeout
+                    && conn.created.elapsed() < config.max_lifetime
+            });
+            let mut expired = before - origin.idle.len();
+
+            let mut found = None;
+            while let Some(conn) = origin.idle.pop() {
+                // a closed connection is readable, as is one with unexpected data on it
+                match conn.stream.get_ref().try_read(&mut [0; 1]) {
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                        found = Some(conn);
+                        break;
+                    }
+                    _ => expired += 1,
+                }
+            }
+            self.inner.expired.fetch_add(expired as u64, Ordering::Relaxed);
+            found
+        })
+    }
+
+    fn checkin(&self, addr: &str, conn: Conn) {
+        if self.inner.draining.load(Ordering::Relaxed) {
+            return;
+        }
+        if conn.created.elapsed() >= self.inner.config.max_lifetime {
+            self.inner.expired.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let max = self.inner.config.max_per_origin as usize;
+        self.origin(addr, |origin| {
+            if origin.idle.len() < max {
+                origin.idle.push(Conn { idle_since: Instant::now(), ..conn });
+            }
+        });
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let max = self.inner.config.max_per_origin as usize;
+        let origins = self.inner.origins.lock().unwrap();
+        PoolStats {
+            idle: origins.values().map(|origin| origin.idle.len()).sum(),
+            in_use: origins.values().map(|origin| max - origin.permits.available_permits()).sum(),
+            dialed: self.inner.dialed.load(Ordering::Relaxed),
+            reused: self.inner.reused.load(Ordering::Relaxed),
+            expired: self.inner.expired.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Closes idle connections and stops reusing any, e.g. before a shutdown. Requests still go
+    /// through, each over a new connection. Resolves once those in flight have finished.
+    pub async fn drain(&self) {
+        self.inner.draining.store(true, Ordering::Relaxed);
+        let permits = {
+            let mut origins = self.inner.origins.lock().unwrap();
+            origins.values_mut().for_each(|origin| origin.idle.clear());
+            origins.values().map(|origin| origin.permits.clone()).collect::<Vec<_>>()
+        };
+        for permits in permits {
+            let all = self.inner.config.max_per_origin;
+            drop(permits.acquire_many(all).await.expect("pool semaphores are never closed"));
+        }
+    }
+}
@@ -0,0 +1,931 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use super::*;
+
+async fn serve_once(response: impl AsRef<[u8]> + Send + 'static) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buf = [0u8; 1024];
+        let _ = stream.read(&mut buf).await;
+        let _ = stream.write_all(response.as_ref()).await;
+    });
+    addr
+}
+
+#[tokio::test]
+pub async fn test_probe_checks_status_and_body() {
+    let config = HealthCheckConfig { expected_body: Some("ok".to_owned()), ..Default::default() };
+
+    let addr = serve_once("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok").await;
+    assert!(probe(&addr, &config).await);
+
+    let addr = serve_once("HTTP/1.1 503 Service Unavailable\r\n\r\n").await;
+    assert!(!probe(&addr, &config).await);
+}
+
+#[tokio::test]
+pub async fn test_probe_matches_decoded_body() {
+    let config = HealthCheckConfig { expected_body: Some("ok".to_owned()), ..Default::default() };
+
+    let chunked =
+        "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n1\r\no\r\n1\r\nk\r\n0\r\n\r\n";
+    let addr = serve_once(chunked).await;
+    assert!(probe(&addr, &config).await);
+
+    // "all ok"
+    let gzip = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03\x4b\xcc\xc9\x51\xc8\xcf\x06\x00\xf3\x26\x10\x5f\x06\x00\x00\x00";
+    let mut response =
+        b"HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: 26\r\n\r\n".to_vec();
+    response.extend_from_slice(gzip);
+    let addr = serve_once(response).await;
+    assert!(probe(&addr, &config).await);
+}
+
+#[tokio::test]
+pub async fn test_check_now_probes_concurrently() {
+    // each upstream answers only once both were probed
+    let barrier = Arc::new(tokio::sync::Barrier::new(2));
+    let mut addrs = Vec::new();
+    for _ in 0..2 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        addrs.push(listener.local_addr().unwrap().to_string());
+        let barrier = barrier.clone();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf).await;
+            barrier.wait().await;
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await;
+        });
+    }
+    let config = HealthCheckConfig {
+        unhealthy_threshold: 1,
+        timeout: Duration::from_millis(500),
+        ..Default::default()
+    };
+    let pool = UpstreamPool::new(addrs.iter().map(String::as_str), config);
+
+    pool.check_now().await;
+    assert_eq!(2, pool.healthy().count());
+    assert!(pool.healthy().all(|upstream| upstream.stats().probe_failures == 0));
+}
+
+#[tokio::test]
+pub async fn test_pool_transitions() {
+    let config = HealthCheckConfig {
+        unhealthy_threshold: 1,
+        passive_failures: 2,
+        passive_cooldown: Duration::from_millis(10),
+        timeout: Duration::from_millis(200),
+        ..Default::default()
+    };
+    // nothing listens on port 1
+    let pool = UpstreamPool::new(["127.0.0.1:1", "127.0.0.1:2"], config);
+    let transitions = Arc::new(Mutex::new(Vec::new()));
+    let seen = transitions.clone();
+    pool.on_transition(move |upstream, health| {
+        seen.lock().unwrap().push((upstream.addr().to_owned(), health))
+    });
+
+    let second = pool.get("127.0.0.1:2").unwrap().clone();
+    pool.report_failure(&second);
+    assert_eq!(Health::Healthy, pool.health(&second));
+    pool.report_failure(&second);
+    assert_eq!(Health::Unhealthy, pool.health(&second));
+    assert_eq!(1, pool.healthy().count());
+
+    tokio::time::sleep(Duration::from_millis(15)).await;
+    assert_eq!(Health::Healthy, pool.health(&second));
+
+    let first = pool.get("127.0.0.1:1").unwrap().clone();
+    pool.check_now().await;
+    assert_eq!(Health::Unhealthy, pool.health(&first));
+    assert_eq!(1, first.stats().probe_failures);
+
+    let expected = vec![
+        ("127.0.0.1:2".to_owned(), Health::Unhealthy),
+        ("127.0.0.1:2".to_owned(), Health::Healthy),
+        ("127.0.0.1:1".to_owned(), Health::Unhealthy),
+        ("127.0.0.1:2".to_owned(), Health::Unhealthy),
+    ];
+    assert_eq!(expected, *transitions.lock().unwrap());
+}
+
+/// Answers every probe with `200 OK` until the test ends
+async fn serve_healthy() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf).await;
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await;
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+pub async fn test_pool_cooldown_keeps_probe_verdict() {
+    let config = HealthCheckConfig {
+        unhealthy_threshold: 1,
+        passive_failures: 1,
+        passive_cooldown: Duration::from_millis(10),
+        timeout: Duration::from_millis(200),
+        ..Default::default()
+    };
+    let pool = UpstreamPool::new(["127.0.0.1:1"], config);
+    let upstream = pool.get("127.0.0.1:1").unwrap().clone();
+
+    pool.report_failure(&upstream);
+    pool.check_now().await;
+    tokio::time::sleep(Duration::from_millis(15)).await;
+    assert_eq!(Health::Unhealthy, pool.health(&upstream));
+    assert_eq!(1, upstream.stats().transitions);
+}
+
+#[tokio::test]
+pub async fn test_pool_probe_success_keeps_passive_ejection() {
+    let config = HealthCheckConfig {
+        healthy_threshold: 1,
+        passive_failures: 1,
+        passive_cooldown: Duration::from_millis(50),
+        ..Default::default()
+    };
+    let addr = serve_healthy().await;
+    let pool = UpstreamPool::new([addr.as_str()], config);
+    let upstream = pool.get(&addr).unwrap().clone();
+
+    pool.report_failure(&upstream);
+    pool.check_now().await;
+    assert_eq!(0, upstream.stats().probe_failures);
+    assert_eq!(Health::Unhealthy, pool.health(&upstream));
+
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    assert_eq!(Health::Healthy, pool.health(&upstream));
+}
+
+#[tokio::test]
+pub async fn test_pool_checks_count_failures_apart() {
+    let config = HealthCheckConfig {
+        unhealthy_threshold: 2,
+        passive_failures: 2,
+        timeout: Duration::from_millis(200),
+        ..Default::default()
+    };
+    let pool = UpstreamPool::new(["127.0.0.1:1"], config);
+    let upstream = pool.get("127.0.0.1:1").unwrap().clone();
+
+    pool.report_failure(&upstream);
+    pool.check_now().await;
+    assert_eq!(Health::Healthy, pool.health(&upstream));
+    // a successful request doesn't undo a failed probe
+    pool.report_success(&upstream);
+    pool.check_now().await;
+    assert_eq!(Health::Unhealthy, pool.health(&upstream));
+}
+
+async fn request(source: &str) -> crate::protocol::RawRequest {
+    crate::protocol::read_http_request(&mut source.as_bytes()).await.unwrap()
+}
+
+#[tokio::test]
+pub async fn test_cookie_affinity() {
+    let pool = UpstreamPool::new(["10.0.0.1:80", "10.0.0.2:80"], HealthCheckConfig::default());
+    let balancer = Balancer::new(pool, Affinity::Cookie { name: "route".to_owned() });
+    let peer = "192.0.2.1".parse().unwrap();
+
+    let first = balancer.select(&request("GET / HTTP/1.1\r\n\r\n").await, peer).unwrap();
+    let set_cookie = first.set_cookie.unwrap();
+    assert!(set_cookie.starts_with("route="));
+
+    let cookie = set_cookie.split(';').next().unwrap();
+    let source = format!("GET / HTTP/1.1\r\nCookie: theme=dark; {cookie}\r\n\r\n");
+    for _ in 0..3 {
+        let pinned = balancer.select(&request(&source).await, peer).unwrap();
+        assert_eq!(first.upstream.addr(), pinned.upstream.addr());
+        assert_eq!(None, pinned.set_cookie);
+    }
+}
+
+#[tokio::test]
+pub async fn test_consistent_hash_affinity() {
+    let config = HealthCheckConfig { passive_failures: 1, ..Default::default() };
+    let pool = UpstreamPool::new(["10.0.0.1:80", "10.0.0.2:80", "10.0.0.3:80"], config);
+    let balancer =
+        Balancer::new(pool, Affinity::ConsistentHash(HashKey::Header("X-User".to_owned())));
+    let peer = "192.0.2.1".parse().unwrap();
+    let get = request("GET / HTTP/1.1\r\nX-User: alice\r\n\r\n").await;
+
+    let chosen = balancer.select(&get, peer).unwrap().upstream;
+    assert_eq!(chosen.addr(), balancer.select(&get, peer).unwrap().upstream.addr());
+
+    balancer.pool().report_failure(&chosen);
+    let fallback = balancer.select(&get, peer).unwrap().upstream;
+    assert_ne!(chosen.addr(), fallback.addr());
+}
+
+/// A server answering upgrade requests with `forward_upgrade` to `backend`, 502 when it fails
+async fn upgrade_proxy(backend: std::net::SocketAddr) -> std::net::SocketAddr {
+    use crate::protocol::{Headers, HttpVersion, StatusCode, StatusLine};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handler = move |request| async move {
+        let limits = crate::protocol::RequestLimits::default();
+        let upstream = TcpStream::connect(backend).await.unwrap();
+        forward_upgrade(upstream, request, &limits).await.unwrap_or_else(|_| {
+            let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::BAD_GATEWAY);
+            crate::protocol::RawResponse::new(status_line, Headers::empty(), None)
+        })
+    };
+    tokio::spawn(crate::server::Server::new(handler).serve(vec![listener]));
+    addr
+}
+
+/// Reads a request head from `stream` and answers it with `response`
+async fn answer_head(stream: &mut TcpStream, response: &str) -> String {
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        head.push(stream.read_u8().await.unwrap());
+    }
+    stream.write_all(response.as_bytes()).await.unwrap();
+    String::from_utf8(head).unwrap()
+}
+
+#[tokio::test]
+pub async fn test_forward_upgrade_splices_streams() {
+    let backend = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = upgrade_proxy(backend.local_addr().unwrap()).await;
+    let handshake =
+        "GET /chat HTTP/1.1\r\nHost: a\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\r\n";
+    assert!(is_upgrade(&request(handshake).await));
+
+    // the first bytes of the new protocol come right behind the handshake
+    let mut client = TcpStream::connect(addr).await.unwrap();
+    client.write_all(format!("{handshake}ping").as_bytes()).await.unwrap();
+
+    let (mut upstream, _) = backend.accept().await.unwrap();
+    let switching = "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\r\n";
+    let head = answer_head(&mut upstream, &format!("{switching}hello")).await;
+    assert!(head.starts_with("GET /chat HTTP/1.1\r\n"));
+    assert!(head.contains("Upgrade: websocket\r\n"));
+
+    let mut buf = vec![0; switching.len() + 5];
+    client.read_exact(&mut buf).await.unwrap();
+    assert_eq!(format!("{switching}hello"), String::from_utf8(buf).unwrap());
+
+    let mut ping = [0u8; 4];
+    upstream.read_exact(&mut ping).await.unwrap();
+    assert_eq!(b"ping", &ping);
+    upstream.write_all(b"pong").await.unwrap();
+    drop(upstream);
+
+    let mut rest = String::new();
+    client.read_to_string(&mut rest).await.unwrap();
+    assert_eq!("pong", rest);
+}
+
+#[tokio::test]
+pub async fn test_forward_upgrade_relays_refusal_body() {
+    let cases = [
+        (
+            "HTTP/1.1 403 Forbidden\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nnope!\r\n0\r\n\r\n",
+            "HTTP/1.1 403 Forbidden\r\nContent-Length: 5\r\n\r\nnope!",
+        ),
+        (
+            "HTTP/1.1 400 Bad Request\r\nContent-Length: 4\r\n\r\nnope",
+            "HTTP/1.1 400 Bad Request\r\nContent-Length: 4\r\n\r\nnope",
+        ),
+        (
+            "HTTP/1.1 426 Upgrade Required\r\n\r\nuntil close",
+            "HTTP/1.1 426 Upgrade Required\r\nContent-Length: 11\r\n\r\nuntil close",
+        ),
+    ];
+    for (upstream_response, expected) in cases {
+        let backend = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = upgrade_proxy(backend.local_addr().unwrap()).await;
+        tokio::spawn(async move {
+            let (mut upstream, _) = backend.accept().await.unwrap();
+            answer_head(&mut upstream, upstream_response).await;
+        });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let handshake = "GET /chat HTTP/1.1\r\nHost: a\r\nConnection: Upgrade, close\r\n\
+                         Upgrade: websocket\r\n\r\n";
+        client.write_all(handshake.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert_eq!(expected, response);
+    }
+}
+
+#[tokio::test]
+pub async fn test_forward_upgrade_needs_upgrade_request() {
+    let (upstream, _) = tokio::io::duplex(64);
+    let limits = crate::protocol::RequestLimits::default();
+    let get = request("GET / HTTP/1.1\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\r\n");
+    let err = forward_upgrade(upstream, get.await, &limits).await.unwrap_err();
+    assert_eq!(std::io::ErrorKind::InvalidInput, err.kind());
+}
+
+#[tokio::test]
+pub async fn test_forward() {
+    let addr = serve_once(
+        "HTTP/1.1 201 Created\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nok\r\n0\r\n\r\n",
+    )
+    .await;
+    let limits = crate::protocol::RequestLimits::default();
+
+    let post = request("POST /items HTTP/1.1\r\n\r\n").await;
+    let response = forward(&addr, post, &Hop::new(), &limits).await;
+    let response = String::from_utf8(response.unwrap().into_vec()).unwrap();
+    assert_eq!("HTTP/1.1 201 Created\r\nContent-Length: 2\r\n\r\nok", response);
+}
+
+/// A server forwarding everything to `upstream` through `hop`, proxying to itself when
+/// `upstream` is `None`
+async fn hop_proxy(upstream: Option<std::net::SocketAddr>, hop: Hop) -> std::net::SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let upstream = upstream.unwrap_or(addr).to_string();
+    let hop = Arc::new(hop);
+    let handler = move |request| {
+        let (upstream, hop) = (upstream.clone(), hop.clone());
+        async move {
+            let limits = crate::protocol::RequestLimits::default();
+            forward(&upstream, request, &hop, &limits).await.unwrap()
+        }
+    };
+    tokio::spawn(crate::server::Server::new(handler).serve(vec![listener]));
+    addr
+}
+
+/// A server answering every request with the request as it arrived
+async fn echo_backend() -> std::net::SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handler = |request| async move { crate::server::trace_echo(&request) };
+    tokio::spawn(crate::server::Server::new(handler).serve(vec![listener]));
+    addr
+}
+
+async fn send_to(addr: std::net::SocketAddr, message: &str) -> crate::protocol::RawResponse {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(message.as_bytes()).await.unwrap();
+    let method = message.split(' ').next().unwrap().parse().unwrap();
+    let limits = crate::protocol::RequestLimits::default();
+    crate::protocol::read_http_response(&mut stream, method, &limits).await.unwrap()
+}
+
+#[tokio::test]
+pub async fn test_forward_adds_hop() {
+    use crate::protocol::StatusCode;
+
+    let backend = echo_backend().await;
+    let hop = Hop::new()
+        .via(Via::new("edge-1"))
+        .max_forwards(MaxForwards::new())
+        .forwarding(Forwarding::new("http").legacy_headers(false));
+    let proxy = hop_proxy(Some(backend), hop).await;
+
+    let response = send_to(
+        proxy,
+        "GET / HTTP/1.1\r\nHost: example.com\r\nVia: 1.1 a\r\nMax-Forwards: 3\r\n\r\n",
+    )
+    .await;
+    assert_eq!(StatusCode::OK, response.status());
+    assert_eq!(Some("1.1 edge-1"), response.headers().get("Via"));
+    let received = String::from_utf8(response.body().unwrap().to_vec()).unwrap();
+    assert!(received.contains("\r\nVia: 1.1 a, 1.1 edge-1\r\n"), "{received}");
+    assert!(received.contains("\r\nMax-Forwards: 2\r\n"), "{received}");
+    assert!(
+        received.contains("\r\nForwarded: for=127.0.0.1;proto=http;host=\"example.com\"\r\n"),
+        "{received}"
+    );
+}
+
+#[tokio::test]
+pub async fn test_forward_answers_loops_and_exhausted_hops() {
+    use crate::protocol::StatusCode;
+
+    let backend = echo_backend().await;
+    let hop = Hop::new().via(Via::new("edge-1")).max_forwards(MaxForwards::new());
+    let proxy = hop_proxy(Some(backend), hop).await;
+
+    let looping = send_to(proxy, "GET / HTTP/1.1\r\nVia: 1.1 edge-1\r\n\r\n").await;
+    assert_eq!(StatusCode::LOOP_DETECTED, looping.status());
+    let exhausted = send_to(proxy, "GET / HTTP/1.1\r\nMax-Forwards: 0\r\n\r\n").await;
+    assert_eq!(StatusCode::GATEWAY_TIMEOUT, exhausted.status());
+
+    // answered by the proxy as its final recipient, without its own `Via`
+    let trace = send_to(proxy, "TRACE / HTTP/1.1\r\nMax-Forwards: 0\r\n\r\n").await;
+    assert_eq!(None, trace.headers().get("Via"));
+    let echoed = String::from_utf8(trace.body().unwrap().to_vec()).unwrap();
+    assert!(echoed.starts_with("TRACE / HTTP/1.1\r\n"), "{echoed}");
+    assert!(echoed.contains("\r\nMax-Forwards: 0\r\n"), "{echoed}");
+    let trace = send_to(proxy, "TRACE / HTTP/1.1\r\nMax-Forwards: 1\r\n\r\n").await;
+    let echoed = String::from_utf8(trace.body().unwrap().to_vec()).unwrap();
+    assert!(echoed.contains("\r\nMax-Forwards: 0\r\n"), "{echoed}");
+    assert_eq!(Some("1.1 edge-1"), trace.headers().get("Via"));
+}
+
+#[tokio::test]
+pub async fn test_forward_to_itself_stops() {
+    use crate::protocol::StatusCode;
+
+    let proxy = hop_proxy(None, Hop::new().via(Via::new("edge-1"))).await;
+    let response = send_to(proxy, "GET / HTTP/1.1\r\n\r\n").await;
+    assert_eq!(StatusCode::LOOP_DETECTED, response.status());
+    assert_eq!(Some("1.1 edge-1"), response.headers().get("Via"));
+}
+
+#[tokio::test]
+pub async fn test_forward_passes_deadline_on() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let upstream = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buf = [0u8; 1024];
+        let n = stream.read(&mut buf).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await;
+        String::from_utf8_lossy(&buf[..n]).into_owned()
+    });
+
+    let mut source: &[u8] = b"GET / HTTP/1.1\r\n\r\n";
+    let mut request = crate::protocol::read_http_request(&mut source).await.unwrap();
+    request.extensions.insert(crate::middleware::Deadline::after(Duration::from_millis(50)));
+    let err = forward(&addr, request, &Hop::new(), &Default::default()).await.unwrap_err();
+    assert_eq!(std::io::ErrorKind::TimedOut, err.kind());
+
+    let sent = upstream.await.unwrap();
+    let timeout = sent.split("X-Request-Timeout: ").nth(1).unwrap().split("\r\n").next().unwrap();
+    assert!(("0.000".."0.051").contains(&timeout), "{timeout}");
+}
+
+#[tokio::test]
+pub async fn test_forwarding_headers() {
+    use crate::server::PeerAddr;
+
+    let mut first = request("GET / HTTP/1.1\r\nHost: example.com\r\n\r\n").await;
+    first.extensions.insert(PeerAddr("192.0.2.43:4711".parse().unwrap()));
+    Forwarding::new("https").by(Node::Obfuscated("_edge".to_owned())).apply(&mut first);
+    assert_eq!(
+        Some("for=192.0.2.43;proto=https;by=_edge;host=\"example.com\""),
+        first.headers.get("Forwarded")
+    );
+    assert_eq!(Some("192.0.2.43"), first.headers.get("X-Forwarded-For"));
+    assert_eq!(Some("https"), first.headers.get("X-Forwarded-Proto"));
+    assert_eq!(Some("example.com"), first.headers.get("X-Forwarded-Host"));
+
+    // a second hop appends to the chain
+    first.extensions.insert(PeerAddr("[2001:db8::1]:443".parse().unwrap()));
+    Forwarding::new("http").apply(&mut first);
+    assert_eq!(
+        Some(
+            "for=192.0.2.43;proto=https;by=_edge;host=\"example.com\", \
+             for=\"[2001:db8::1]\";proto=http;host=\"example.com\""
+        ),
+        first.headers.get("Forwarded")
+    );
+    assert_eq!(Some("192.0.2.43, 2001:db8::1"), first.headers.get("X-Forwarded-For"));
+    assert_eq!(Some("http"), first.headers.get("X-Forwarded-Proto"));
+
+    let obfuscating = Forwarding::new("http").obfuscate(true).legacy_headers(false);
+    let mut second = request("GET / HTTP/1.1\r\n\r\n").await;
+    second.extensions.insert(PeerAddr("192.0.2.43:5000".parse().unwrap()));
+    let client = obfuscating.client(&second);
+    assert!(
+        matches!(client, Node::Obfuscated(ref name) if name.starts_with('_') && name.len() == 17)
+    );
+    assert_eq!(client, obfuscating.client(&second));
+    obfuscating.apply(&mut second);
+    assert_eq!(
+        Some(format!("for={client};proto=http")).as_deref(),
+        second.headers.get("Forwarded")
+    );
+    assert!(
+        !second.headers.contains("X-Forwarded-For")
+            && !second.headers.contains("X-Forwarded-Proto")
+    );
+
+    let mut unknown = request("GET / HTTP/1.1\r\n\r\n").await;
+    Forwarding::new("http").apply(&mut unknown);
+    assert_eq!(Some("for=unknown;proto=http"), unknown.headers.get("Forwarded"));
+    assert!(!unknown.headers.contains("X-Forwarded-For"));
+}
+
+#[tokio::test]
+pub async fn test_send_strips_hop_by_hop_headers() {
+    let (client, mut upstream) = tokio::io::duplex(4096);
+    let upstream = tokio::spawn(async move {
+        let mut buf = [0u8; 1024];
+        let n = upstream.read(&mut buf).await.unwrap();
+        let response =
+            "HTTP/1.1 200 OK\r\nKeep-Alive: timeout=5\r\nConnection: keep-alive, X-Trace\r\n\
+                        X-Trace: 1\r\nContent-Length: 0\r\n\r\n";
+        upstream.write_all(response.as_bytes()).await.unwrap();
+        String::from_utf8_lossy(&buf[..n]).into_owned()
+    });
+
+    let sent = request(
+        "GET / HTTP/1.1\r\nHost: a\r\nConnection: keep-alive, X-Secret\r\nX-Secret: s\r\n\
+         Proxy-Authorization: Basic eDp5\r\nTE: trailers\r\nAccept: */*\r\n\r\n",
+    )
+    .await;
+    let response = send(client, sent, &Default::default()).await.unwrap();
+    assert_eq!(
+        "GET / HTTP/1.1\r\nHost: a\r\nAccept: */*\r\nConnection: close\r\n\r\n",
+        upstream.await.unwrap()
+    );
+    assert_eq!(
+        "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n",
+        String::from_utf8(response.into_vec()).unwrap()
+    );
+}
+
+#[tokio::test]
+pub async fn test_via_loop_detection() {
+    use crate::protocol::{Headers, HttpVersion, RawResponse, StatusCode, StatusLine};
+
+    let via = Via::new("edge-1").max_hops(3);
+    assert_eq!("1.0 edge-1", via.entry(HttpVersion::Http1_0));
+    let upstream = |request: crate::protocol::RawRequest| async move {
+        let mut headers = Headers::empty();
+        headers.set("X-Seen-Via", request.headers.get("Via").unwrap_or_default().to_owned());
+        let status_line = StatusLine::new(HttpVersion::Http1_0, StatusCode::OK);
+        RawResponse::new(status_line, headers, Some(Vec::new()))
+    };
+
+    let forwarded = request("GET / HTTP/1.1\r\nVia: 1.0 fred, 1.1 p.example.net\r\n\r\n").await;
+    let response = via.call(forwarded, upstream).await;
+    assert_eq!(
+        Some("1.0 fred, 1.1 p.example.net, 1.1 edge-1"),
+        response.headers().get("X-Seen-Via")
+    );
+    assert_eq!(Some("1.0 edge-1"), response.headers().get("Via"));
+
+    let looping = request("GET / HTTP/1.1\r\nVia: 1.1 a\r\nVia: 1.1 EDGE-1 (toot)\r\n\r\n").await;
+    assert_eq!(StatusCode::LOOP_DETECTED, via.call(looping, upstream).await.status());
+    let long = request("GET / HTTP/1.1\r\nVia: 1.1 a, 1.1 b, 1.1 c\r\n\r\n").await;
+    assert_eq!(StatusCode::LOOP_DETECTED, via.call(long, upstream).await.status());
+
+    let exhausted = request("GET / HTTP/1.1\r\nMax-Forwards: 0\r\n\r\n").await;
+    assert_eq!(StatusCode::GATEWAY_TIMEOUT, via.call(exhausted, upstream).await.status());
+}
+
+#[tokio::test]
+pub async fn test_max_forwards() {
+    use crate::protocol::{Method, RawResponse, StatusCode};
+
+    let limit = MaxForwards::new().allow(&[Method::GET, Method::OPTIONS]);
+    let upstream = |request: crate::protocol::RawRequest| async move {
+        let hops = request.headers.get("Max-Forwards").unwrap_or("none").to_owned();
+        RawResponse::redirect(StatusCode::FOUND, &format!("/forwarded/{hops}"))
+    };
+    let location = |response: RawResponse| response.headers().get("Location").map(str::to_owned);
+
+    let trace = request("TRACE / HTTP/1.1\r\nMax-Forwards: 0\r\nX-A: b\r\n\r\n").await;
+    let response = limit.call(trace, upstream).await;
+    assert_eq!(
+        "TRACE / HTTP/1.1\r\nMax-Forwards: 0\r\nX-A: b\r\n\r\n",
+        std::str::from_utf8(response.body().unwrap()).unwrap()
+    );
+    let options = request("OPTIONS * HTTP/1.1\r\nMax-Forwards: 0\r\n\r\n").await;
+    let response = limit.call(options, upstream).await;
+    assert_eq!(StatusCode::NO_CONTENT, response.status());
+    assert_eq!(Some("GET, OPTIONS"), response.headers().get("Allow"));
+
+    let options = request("OPTIONS * HTTP/1.1\r\nMax-Forwards: 5\r\n\r\n").await;
+    assert_eq!(Some("/forwarded/4".to_owned()), location(limit.call(options, upstream).await));
+    let get = request("GET / HTTP/1.1\r\nMax-Forwards: 0\r\n\r\n").await;
+    assert_eq!(Some("/forwarded/0".to_owned()), location(limit.call(get, upstream).await));
+    let trace = request("TRACE / HTTP/1.1\r\n\r\n").await;
+    assert_eq!(Some("/forwarded/none".to_owned()), location(limit.call(trace, upstream).await));
+}
+
+#[tokio::test]
+pub async fn test_connection_pool_reuse() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        for accepted in 0.. {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut stream = tokio::io::BufReader::new(stream);
+                while let Ok(request) = crate::protocol::read_http_request(&mut stream).await {
+                    let close = request.request_line.target.path() == "/close";
+                    let connection = if close { "Connection: close\r\n" } else { "" };
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\n{connection}Content-Length: 1\r\n\r\n{accepted}"
+                    );
+                    stream.write_all(response.as_bytes()).await.unwrap();
+                    if close {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+
+    let pool = ConnectionPool::new(PoolConfig::default());
+    let limits = Default::default();
+    for _ in 0..3 {
+        let response = pool.send(&addr, request("GET / HTTP/1.1\r\n\r\n").await, &limits).await;
+        assert_eq!(Some(&b"0"[..]), response.unwrap().body());
+    }
+    let stats = pool.stats();
+    assert_eq!((1, 2, 1, 0), (stats.dialed, stats.reused, stats.idle, stats.in_use));
+
+    let response = pool.send(&addr, request("GET /close HTTP/1.1\r\n\r\n").await, &limits).await;
+    assert_eq!(None, response.unwrap().headers().get("Connection"));
+    assert_eq!(0, pool.stats().idle);
+    let response = pool.send(&addr, request("GET / HTTP/1.1\r\n\r\n").await, &limits).await;
+    assert_eq!(Some(&b"1"[..]), response.unwrap().body());
+    assert_eq!(2, pool.stats().dialed);
+
+    pool.drain().await;
+    let response = pool.send(&addr, request("GET / HTTP/1.1\r\n\r\n").await, &limits).await;
+    assert_eq!(Some(&b"2"[..]), response.unwrap().body());
+    assert_eq!(PoolStats { idle: 0, in_use: 0, dialed: 3, reused: 3, expired: 0 }, pool.stats());
+}
+
+#[tokio::test]
+pub async fn test_dns_resolution() {
+    use std::net::IpAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Counting(Arc<AtomicUsize>);
+
+    impl Resolver for Counting {
+        fn resolve<'a>(
+            &'a self,
+            _name: &'a str,
+        ) -> crate::server::BoxFuture<'a, std::io::Result<Resolved>> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            let addrs = vec!["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()];
+            Box::pin(async move { Ok(Resolved { addrs, ttl: Some(Duration::from_secs(5)) }) })
+        }
+    }
+
+    let lookups = Arc::new(AtomicUsize::new(0));
+    let cache = DnsCache::new(Counting(lookups.clone())).max_ttl(Duration::from_secs(1));
+    let first = cache.resolve("Api.Example.").await.unwrap();
+    assert_eq!(Some(Duration::from_secs(1)), first.ttl);
+    let second = cache.resolve("api.example").await.unwrap();
+    assert_eq!(
+        first.addrs.iter().rev().collect::<Vec<_>>(),
+        second.addrs.iter().collect::<Vec<_>>()
+    );
+    assert_eq!(first.addrs, cache.resolve("api.example").await.unwrap().addrs);
+    assert_eq!(1, lookups.load(Ordering::Relaxed));
+    cache.clear();
+    cache.resolve("api.example").await.unwrap();
+    assert_eq!(2, lookups.load(Ordering::Relaxed));
+
+    // nothing listens on 127.0.0.2, the next address is tried right after it refuses
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let ips: [IpAddr; 2] = ["127.0.0.2".parse().unwrap(), "127.0.0.1".parse().unwrap()];
+    let hosts = StaticHosts::new().host("upstream.internal", &ips);
+    let addr = format!("upstream.internal:{port}");
+    let stream = connect(&hosts, &addr, Duration::from_secs(30));
+    let stream = tokio::time::timeout(Duration::from_secs(5), stream).await.unwrap().unwrap();
+    assert_eq!(listener.local_addr().unwrap(), stream.peer_addr().unwrap());
+
+    let err = connect(&hosts, "elsewhere.internal:80", Duration::ZERO).await.unwrap_err();
+    assert_eq!(std::io::ErrorKind::NotFound, err.kind());
+}
+
+#[tokio::test]
+pub async fn test_send_streaming() {
+    let (client, mut upstream) = tokio::io::duplex(64);
+    let upstream = tokio::spawn(async move {
+        let mut received = Vec::new();
+        let mut buf = [0u8; 64];
+        while !received.ends_with(b"0\r\n\r\n") {
+            let n = upstream.read(&mut buf).await.unwrap();
+            received.extend_from_slice(&buf[..n]);
+        }
+        let response = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                        5\r\nfirst\r\n6\r\nsecond\r\n0\r\n\r\n";
+        upstream.write_all(response.as_bytes()).await.unwrap();
+        String::from_utf8(received).unwrap()
+    });
+
+    let sent = request("POST /upload HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\n\r\nold").await;
+    let upload = &b"streamed body"[..];
+    let (response, mut body) =
+        send_streaming(client, sent, upload, None, &Default::default()).await.unwrap();
+    assert_eq!(
+        "POST /upload HTTP/1.1\r\nHost: a\r\nConnection: close\r\nTransfer-Encoding: chunked\r\n\r\n\
+         D\r\nstreamed body\r\n0\r\n\r\n",
+        upstream.await.unwrap()
+    );
+    assert_eq!(None, response.headers().get("Transfer-Encoding"));
+    let mut downloaded = Vec::new();
+    assert_eq!(11, body.copy_to(&mut downloaded).await.unwrap());
+    assert_eq!(b"firstsecond", &downloaded[..]);
+
+    let (client, _upstream) = tokio::io::duplex(64);
+    let sent = request("PUT / HTTP/1.1\r\n\r\n").await;
+    let err = send_streaming(client, sent, &b"short"[..], Some(10), &Default::default())
+        .await
+        .unwrap_err();
+    assert_eq!(std::io::ErrorKind::UnexpectedEof, err.kind());
+}
+
+#[tokio::test]
+pub async fn test_multipart_form() {
+    let csv = std::env::temp_dir().join(format!("toot-multipart-{}.csv", std::process::id()));
+    tokio::fs::write(&csv, "a,b\n").await.unwrap();
+
+    let form = Multipart::new()
+        .boundary("XyZ")
+        .text("title", "Q3 \"final\"")
+        .part("data", Part::file(&csv).await.unwrap().content_type("text/csv"))
+        .part("raw", Part::reader(&b"streamed"[..], None).filename("raw.bin"));
+    assert_eq!("multipart/form-data; boundary=XyZ", form.content_type());
+    assert_eq!(None, form.content_length());
+
+    let mut encoded = String::new();
+    form.into_reader().read_to_string(&mut encoded).await.unwrap();
+    let file_name = csv.file_name().unwrap().to_str().unwrap();
+    assert_eq!(
+        format!(
+            "--XyZ\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nQ3 \"final\"\r\n\
+             --XyZ\r\nContent-Disposition: form-data; name=\"data\"; filename=\"{file_name}\"\r\n\
+             Content-Type: text/csv\r\n\r\na,b\n\r\n\
+             --XyZ\r\nContent-Disposition: form-data; name=\"raw\"; filename=\"raw.bin\"\r\n\r\n\
+             streamed\r\n--XyZ--\r\n"
+        ),
+        encoded
+    );
+
+    let form = Multipart::new().text("say", "hi").part("file", Part::file(&csv).await.unwrap());
+    assert!(form.content_type().starts_with("multipart/form-data; boundary=toot-"));
+    let length = form.content_length().unwrap();
+    let mut encoded = Vec::new();
+    form.into_reader().read_to_end(&mut encoded).await.unwrap();
+    assert_eq!(length, encoded.len() as u64);
+    assert!(String::from_utf8(encoded).unwrap().contains("Content-Type: application/octet-stream"));
+    tokio::fs::remove_file(&csv).await.unwrap();
+}
+
+#[tokio::test]
+pub async fn test_range_download_resumes() {
+    // serves 1000 bytes under the ETag in `etag`, cutting off responses after `cut` bytes
+    async fn serve(etag: Arc<Mutex<&'static str>>, cut: Arc<Mutex<Option<usize>>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let content = (0..1000).map(|i| b'a' + (i % 26) as u8).collect::<Vec<_>>();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut stream = tokio::io::BufReader::new(stream);
+                let request = crate::protocol::read_http_request(&mut stream).await.unwrap();
+                let etag = *etag.lock().unwrap();
+                let start = match request.headers.get("If-Range") {
+                    Some(validator) if validator == etag => request
+                        .headers
+                        .get("Range")
+                        .and_then(|range| range.strip_prefix("bytes="))
+                        .and_then(|range| range.trim_end_matches('-').parse::<usize>().ok()),
+                    _ => None,
+                };
+                let head = match start {
+                    Some(start) => format!(
+                        "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {start}-999/1000\r\n\
+                         Content-Length: {}\r\n\r\n",
+                        1000 - start
+                    ),
+                    None => {
+                        format!("HTTP/1.1 200 OK\r\nETag: {etag}\r\nContent-Length: 1000\r\n\r\n")
+                    }
+                };
+                let body = &content[start.unwrap_or(0)..];
+                let body = &body[..cut.lock().unwrap().take().unwrap_or(body.len())];
+                stream.write_all(head.as_bytes()).await.unwrap();
+                stream.write_all(body).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    let etag = Arc::new(Mutex::new("\"v1\""));
+    let cut = Arc::new(Mutex::new(Some(300)));
+    let addr = serve(etag.clone(), cut.clone()).await;
+    let path = std::env::temp_dir().join(format!("toot-download-{}.bin", std::process::id()));
+    let resume = std::path::PathBuf::from(format!("{}.resume", path.display()));
+    let expected = (0..1000).map(|i| b'a' + (i % 26) as u8).collect::<Vec<_>>();
+
+    let download = RangeDownload::new(&addr, "/file").max_attempts(1);
+    let err = download.to_file(&path, |_| {}).await.unwrap_err();
+    assert_eq!(DownloadError::Io(std::io::ErrorKind::UnexpectedEof), err);
+    assert_eq!(300, tokio::fs::metadata(&path).await.unwrap().len());
+    assert_eq!("\"v1\"", tokio::fs::read_to_string(&resume).await.unwrap());
+
+    // resumed with a range, then cut again and resumed by the second attempt
+    *cut.lock().unwrap() = Some(200);
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let seen = reports.clone();
+    let download = download.max_attempts(2).retry_delay(Duration::ZERO);
+    let done = download.to_file(&path, move |p| seen.lock().unwrap().push(p)).await.unwrap();
+    assert_eq!(Progress { downloaded: 1000, total: Some(1000) }, done);
+    assert_eq!(
+        Some(&Progress { downloaded: 300, total: Some(1000) }),
+        reports.lock().unwrap().first()
+    );
+    assert_eq!(expected, tokio::fs::read(&path).await.unwrap());
+    assert!(!resume.exists());
+
+    // a changed resource is downloaded from the start
+    tokio::fs::write(&path, &expected[..500]).await.unwrap();
+    tokio::fs::write(&resume, "\"v1\"").await.unwrap();
+    *etag.lock().unwrap() = "\"v2\"";
+    let done = download.to_file(&path, |_| {}).await.unwrap();
+    assert_eq!(Progress { downloaded: 1000, total: Some(1000) }, done);
+    assert_eq!(expected, tokio::fs::read(&path).await.unwrap());
+    tokio::fs::remove_file(&path).await.unwrap();
+}
+
+#[tokio::test]
+pub async fn test_expect_continue() {
+    use crate::protocol::StatusCode;
+
+    async fn upstream(
+        answer: &'static str,
+    ) -> (tokio::io::DuplexStream, tokio::task::JoinHandle<String>) {
+        let (client, mut upstream) = tokio::io::duplex(1024);
+        let handle = tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            let n = upstream.read(&mut buf).await.unwrap();
+            let mut received = buf[..n].to_vec();
+            upstream.write_all(answer.as_bytes()).await.unwrap();
+            if answer.starts_with("HTTP/1.1 100") {
+                while !received.ends_with(b"upload") {
+                    let n = upstream.read(&mut buf).await.unwrap();
+                    received.extend_from_slice(&buf[..n]);
+                }
+                upstream
+                    .write_all(b"HTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n")
+                    .await
+                    .unwrap();
+            }
+            String::from_utf8(received).unwrap()
+        });
+        (client, handle)
+    }
+    let limits = Default::default();
+    let wait = Duration::from_secs(30);
+
+    let (client, received) = upstream("HTTP/1.1 100 Continue\r\n\r\n").await;
+    let sent = request("PUT /big HTTP/1.1\r\n\r\n").await;
+    let (response, _) =
+        send_expecting_continue(client, sent, &b"upload"[..], Some(6), wait, &limits)
+            .await
+            .unwrap();
+    assert_eq!(StatusCode::CREATED, response.status());
+    assert_eq!(
+        "PUT /big HTTP/1.1\r\nExpect: 100-continue\r\nConnection: close\r\nContent-Length: 6\r\n\r\nupload",
+        received.await.unwrap()
+    );
+
+    let (client, received) =
+        upstream("HTTP/1.1 413 Content Too Large\r\nContent-Length: 0\r\n\r\n").await;
+    let sent = request("PUT /big HTTP/1.1\r\n\r\n").await;
+    let (response, _) =
+        send_expecting_continue(client, sent, &b"upload"[..], Some(6), wait, &limits)
+            .await
+            .unwrap();
+    assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, response.status());
+    assert!(received.await.unwrap().ends_with("Content-Length: 6\r\n\r\n"));
+
+    // no answer in time, the body is sent anyway and a late 100 is skipped
+    let (client, mut upstream) = tokio::io::duplex(1024);
+    let late = tokio::spawn(async move {
+        let mut received = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !received.ends_with(b"upload") {
+            let n = upstream.read(&mut buf).await.unwrap();
+            received.extend_from_slice(&buf[..n]);
+        }
+        let answer = "HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 204 No Content\r\n\r\n";
+        upstream.write_all(answer.as_bytes()).await.unwrap();
+    });
+    let sent = request("PUT /big HTTP/1.1\r\n\r\n").await;
+    let wait = Duration::from_millis(20);
+    let (response, _) =
+        send_expecting_continue(client, sent, &b"upload"[..], Some(6), wait, &limits)
+            .await
+            .unwrap();
+    assert_eq!(StatusCode::NO_CONTENT, response.status());
+    late.await.unwrap();
+}
@@ -0,0 +1,53 @@
+use std::io;
+
+use tokio::io::{copy_bidirectional, AsyncRead, AsyncWrite, AsyncWriteExt};
+
+use super::forward::parse_error;
+use crate::protocol::{read_http_response, RawRequest, RawResponse, RequestLimits};
+use crate::server::OnUpgrade;
+
+/// `Connection: upgrade` together with an `Upgrade` header
+pub fn is_upgrade(request: &RawRequest) -> bool {
+    let headers = &request.headers;
+    headers.has_token("Connection", "upgrade") && headers.get("Upgrade").is_some()
+}
+
+/// Forwards an upgrade handshake to `upstream` and returns the response for the handler to
+/// answer with.
+///
+/// When the upstream switches protocols the 101 is returned as it is, and once the server has
+/// written it the client connection, taken from the request's `OnUpgrade`, is spliced with
+/// `upstream` in a spawned task until either side closes. Any other response is read whole,
+/// without its hop-by-hop headers. Fails with `InvalidInput` on requests the server can't hand
+/// the connection over for.
+pub async fn forward_upgrade<U>(
+    upstream: U,
+    request: RawRequest,
+    limits: &RequestLimits,
+) -> io::Result<RawResponse>
+where
+    U: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let (mut upstream, mut request) = (upstream, request);
+    let Some(on_upgrade) = request.extensions.remove::<OnUpgrade>() else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "not an upgrade request"));
+    };
+    let method = request.request_line.method;
+    upstream.write_all(&request.into_vec()).await?;
+    upstream.flush().await?;
+
+    // read unbuffered, so no bytes of the upgraded protocol are consumed with the head
+    let mut response =
+        read_http_response(&mut upstream, method, limits).await.map_err(parse_error)?;
+    if *response.status() != 101 {
+        response.headers_mut().remove_hop_by_hop();
+        return Ok(response);
+    }
+
+    tokio::spawn(async move {
+        if let Ok(mut client) = on_upgrade.await {
+            let _ = copy_bidirectional(&mut client, &mut upstream).await;
+        }
+    });
+    Ok(response)
+}
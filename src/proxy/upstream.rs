@@ -0,0 +1,232 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use futures_util::future::join_all;
+use tokio::task::JoinHandle;
+
+use super::health::{probe, HealthCheckConfig};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Health {
+    Healthy,
+    Unhealthy,
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct UpstreamStats {
+    pub probes: u64,
+    pub probe_failures: u64,
+    pub passive_failures: u64,
+    pub transitions: u64,
+}
+
+/// Passive and active checks keep their own state, an upstream is healthy only when neither
+/// has taken it out
+#[derive(Debug)]
+struct Tracker {
+    /// last verdict of both checks, as reported to the transition hooks
+    health: Health,
+    /// verdict of the active probes
+    probed: Health,
+    probe_failures: u32,
+    probe_successes: u32,
+    /// consecutive failed proxied requests
+    passive_failures: u32,
+    /// set while ejected by passive checks
+    ejected_at: Option<Instant>,
+}
+
+impl Tracker {
+    fn current(&mut self, cooldown: Duration) -> Health {
+        if self.ejected_at.is_some_and(|at| at.elapsed() >= cooldown) {
+            self.ejected_at = None;
+            self.passive_failures = 0;
+        }
+        match (self.probed, self.ejected_at) {
+            (Health::Healthy, None) => Health::Healthy,
+            _ => Health::Unhealthy,
+        }
+    }
+}
+
+/// One backend address, e.g. `10.0.0.2:8080`
+#[derive(Debug)]
+pub struct Upstream {
+    addr: String,
+    tracker: Mutex<Tracker>,
+    probes: AtomicU64,
+    probe_failures: AtomicU64,
+    passive_failures: AtomicU64,
+    transitions: AtomicU64,
+}
+
+impl Upstream {
+    fn new(addr: String) -> Self {
+        let tracker = Tracker {
+            health: Health::Healthy,
+            probed: Health::Healthy,
+            probe_failures: 0,
+            probe_successes: 0,
+            passive_failures: 0,
+            ejected_at: None,
+        };
+        Self {
+            addr,
+            tracker: Mutex::new(tracker),
+            probes: AtomicU64::new(0),
+            probe_failures: AtomicU64::new(0),
+            passive_failures: AtomicU64::new(0),
+            transitions: AtomicU64::new(0),
+        }
+    }
+
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    pub fn stats(&self) -> UpstreamStats {
+        UpstreamStats {
+            probes: self.probes.load(Ordering::Relaxed),
+            probe_failures: self.probe_failures.load(Ordering::Relaxed),
+            passive_failures: self.passive_failures.load(Ordering::Relaxed),
+            transitions: self.transitions.load(Ordering::Relaxed),
+        }
+    }
+}
+
+type TransitionHook = Box<dyn Fn(&Upstream, Health) + Send + Sync>;
+
+struct Inner {
+    upstreams: Vec<Arc<Upstream>>,
+    config: HealthCheckConfig,
+    hooks: RwLock<Vec<TransitionHook>>,
+}
+
+/// Set of upstreams with active (periodic probe) and passive (reported failure) health checks
+#[derive(Clone)]
+pub struct UpstreamPool {
+    inner: Arc<Inner>,
+}
+
+impl UpstreamPool {
+    pub fn new<I, S>(addrs: I, config: HealthCheckConfig) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: ToString,
+    {
+        let upstreams =
+            addrs.into_iter().map(|addr| Arc::new(Upstream::new(addr.to_string()))).collect();
+        let inner = Inner { upstreams, config, hooks: RwLock::new(Vec::new()) };
+        Self { inner: Arc::new(inner) }
+    }
+
+    pub fn upstreams(&self) -> &[Arc<Upstream>] {
+        &self.inner.upstreams
+    }
+
+    pub fn get(&self, addr: &str) -> Option<&Arc<Upstream>> {
+        self.inner.upstreams.iter().find(|u| u.addr == addr)
+    }
+
+    pub fn health(&self, upstream: &Upstream) -> Health {
+        self.update(upstream, |_| {})
+    }
+
+    pub fn healthy(&self) -> impl Iterator<Item = &Arc<Upstream>> + '_ {
+        self.inner.upstreams.iter().filter(|u| self.health(u) == Health::Healthy)
+    }
+
+    /// Called on every health state change, e.g. to log or export metrics
+    pub fn on_transition<F>(&self, hook: F)
+    where
+        F: Fn(&Upstream, Health) + Send + Sync + 'static,
+    {
+        self.inner.hooks.write().unwrap().push(Box::new(hook));
+    }
+
+    /// Passive check: a proxied request to `upstream` succeeded
+    pub fn report_success(&self, upstream: &Upstream) {
+        upstream.tracker.lock().unwrap().passive_failures = 0;
+    }
+
+    /// Passive check: a proxied request to `upstream` failed or timed out
+    pub fn report_failure(&self, upstream: &Upstream) {
+        upstream.passive_failures.fetch_add(1, Ordering::Relaxed);
+        let threshold = self.inner.config.passive_failures;
+        self.update(upstream, |tracker| {
+            tracker.passive_failures += 1;
+            if tracker.passive_failures >= threshold && tracker.ejected_at.is_none() {
+                tracker.ejected_at = Some(Instant::now());
+            }
+        });
+    }
+
+    fn record_probe(&self, upstream: &Upstream, success: bool) {
+        let config = &self.inner.config;
+        upstream.probes.fetch_add(1, Ordering::Relaxed);
+        if !success {
+            upstream.probe_failures.fetch_add(1, Ordering::Relaxed);
+        }
+
+        self.update(upstream, |tracker| {
+            if success {
+                tracker.probe_successes += 1;
+                tracker.probe_failures = 0;
+                if tracker.probe_successes >= config.healthy_threshold {
+                    tracker.probed = Health::Healthy;
+                }
+            } else {
+                tracker.probe_failures += 1;
+                tracker.probe_successes = 0;
+                if tracker.probe_failures >= config.unhealthy_threshold {
+                    tracker.probed = Health::Unhealthy;
+                }
+            }
+        });
+    }
+
+    /// Applies `change` and calls the hooks when the combined verdict changes
+    fn update<F>(&self, upstream: &Upstream, change: F) -> Health
+    where
+        F: FnOnce(&mut Tracker),
+    {
+        let health = {
+            let mut tracker = upstream.tracker.lock().unwrap();
+            change(&mut tracker);
+            let health = tracker.current(self.inner.config.passive_cooldown);
+            if health == tracker.health {
+                return health;
+            }
+            tracker.health = health;
+            health
+        };
+        upstream.transitions.fetch_add(1, Ordering::Relaxed);
+        for hook in self.inner.hooks.read().unwrap().iter() {
+            hook(upstream, health);
+        }
+        health
+    }
+
+    /// Probes every upstream once, all at the same time
+    pub async fn check_now(&self) {
+        let config = &self.inner.config;
+        let probes = self.inner.upstreams.iter().map(|upstream| async move {
+            let success = probe(&upstream.addr, config).await;
+            self.record_probe(upstream, success);
+        });
+        join_all(probes).await;
+    }
+
+    /// Probes all upstreams every `interval` until the returned task is aborted
+    pub fn spawn_health_checks(&self) -> JoinHandle<()> {
+        let pool = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(pool.inner.config.interval);
+            loop {
+                ticker.tick().await;
+                pool.check_now().await;
+            }
+        })
+    }
+}
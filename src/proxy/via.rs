@@ -0,0 +1,92 @@
+use std::future::Future;
+
+use crate::protocol::{
+    Headers, HttpVersion, Method, RawRequest, RawResponse, StatusCode, StatusLine,
+};
+
+/// Adds this proxy's entry to the `Via` header of forwarded requests and their responses, and
+/// answers requests which already passed through it with 508 instead of forwarding them in a
+/// loop. Requests whose `Max-Forwards` runs out are answered with 504.
+///
+/// The pseudonym should be unique among the proxies of a chain, e.g. the host name.
+#[derive(Debug, Clone)]
+pub struct Via {
+    pseudonym: String,
+    max_hops: Option<usize>,
+}
+
+impl Via {
+    pub fn new(pseudonym: &str) -> Self {
+        Self { pseudonym: pseudonym.to_owned(), max_hops: None }
+    }
+
+    /// Also treats requests as looping once their `Via` lists this many proxies
+    pub fn max_hops(mut self, hops: usize) -> Self {
+        self.max_hops = Some(hops);
+        self
+    }
+
+    /// `received-protocol received-by` of a message of `version`, e.g. `1.1 edge-1`
+    pub fn entry(&self, version: HttpVersion) -> String {
+        let protocol = version.as_str().trim_start_matches("HTTP/");
+        format!("{protocol} {}", self.pseudonym)
+    }
+
+    /// Whether `headers` show the message already passed through this proxy, or through more
+    /// than the allowed number of proxies
+    pub fn is_looping(&self, headers: &Headers) -> bool {
+        let received_by = headers
+            .get_all("Via")
+            .flat_map(|v| v.split(','))
+            .filter_map(|entry| entry.split_ascii_whitespace().nth(1))
+            .collect::<Vec<_>>();
+        received_by.iter().any(|by| by.eq_ignore_ascii_case(&self.pseudonym))
+            || self.max_hops.is_some_and(|max| received_by.len() >= max)
+    }
+
+    /// Answers `request` with 508 when it is looping, or with 504 when its `Max-Forwards` ran
+    /// out, otherwise adds the `Via` entry and counts `Max-Forwards` down. `TRACE` and
+    /// `OPTIONS` keep their `Max-Forwards`, they are `MaxForwards`' to answer.
+    pub fn on_request(&self, request: &mut RawRequest) -> Option<RawResponse> {
+        if self.is_looping(&request.headers) {
+            return Some(status_response(StatusCode::LOOP_DETECTED));
+        }
+        let method = request.request_line.method;
+        if !matches!(method, Method::TRACE | Method::OPTIONS) {
+            match request.headers.get_parsed::<u32>("Max-Forwards") {
+                Some(0) => return Some(status_response(StatusCode::GATEWAY_TIMEOUT)),
+                Some(hops) => request.headers.set("Max-Forwards", (hops - 1).to_string()),
+                None => {}
+            }
+        }
+        let entry = self.entry(request.request_line.version);
+        request.headers.append_list("Via", &entry);
+        None
+    }
+
+    /// Adds the `Via` entry to a response on its way back
+    pub fn on_response(&self, response: &mut RawResponse) {
+        let entry = self.entry(response.version());
+        response.headers_mut().append_list("Via", &entry);
+    }
+
+    /// Forwards `request` through `proxy` with `Via` entries added on the way there and back
+    pub async fn call<F, Fut>(&self, request: RawRequest, proxy: F) -> RawResponse
+    where
+        F: FnOnce(RawRequest) -> Fut,
+        Fut: Future<Output = RawResponse>,
+    {
+        let mut request = request;
+        if let Some(response) = self.on_request(&mut request) {
+            return response;
+        }
+        let mut response = proxy(request).await;
+        self.on_response(&mut response);
+        response
+    }
+}
+
+fn status_response(status: StatusCode) -> RawResponse {
+    let status_line = StatusLine::new(HttpVersion::Http1_1, status);
+    RawResponse::new(status_line, Headers::empty(), Some(Vec::new()))
+}
@@ -0,0 +1,103 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use tokio::io::AsyncWrite;
+use tokio::time::{sleep, Sleep};
+
+/// Caps how fast bytes are written with a token bucket refilled at `rate` bytes per second,
+/// holding at most a second's worth so an idle connection can't save up for a burst
+pub(crate) struct Throttle<W> {
+    inner: W,
+    rate: Option<u64>,
+    tokens: u64,
+    /// when the tokens were last topped up, behind now by the time a partial token took
+    refilled: Instant,
+    wait: Option<Pin<Box<Sleep>>>,
+}
+
+impl<W: AsyncWrite + Unpin> Throttle<W> {
+    /// Unthrottled until `set_rate` is called
+    pub fn new(inner: W) -> Self {
+        Self { inner, rate: None, tokens: 0, refilled: Instant::now(), wait: None }
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// Bytes per second written from now on, `None` for no limit
+    pub fn set_rate(&mut self, rate: Option<u64>) {
+        let rate = rate.filter(|rate| *rate > 0);
+        if rate != self.rate {
+            self.rate = rate;
+            self.tokens = rate.unwrap_or_default();
+            self.refilled = Instant::now();
+        }
+    }
+
+    fn refill(&mut self, rate: u64) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_nanos();
+        let added = (elapsed * rate as u128 / 1_000_000_000) as u64;
+        if self.tokens + added >= rate {
+            self.tokens = rate;
+            self.refilled = now;
+        } else if added > 0 {
+            self.tokens += added;
+            self.refilled += nanos_for(added, rate);
+        }
+    }
+}
+
+fn nanos_for(bytes: u64, rate: u64) -> Duration {
+    Duration::from_nanos((bytes as u128 * 1_000_000_000 / rate as u128) as u64)
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Throttle<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let Some(rate) = this.rate else {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        };
+        loop {
+            if let Some(ref mut wait) = this.wait {
+                match wait.as_mut().poll(cx) {
+                    Poll::Ready(()) => this.wait = None,
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+            this.refill(rate);
+            if this.tokens > 0 || buf.is_empty() {
+                break;
+            }
+            // until enough for the whole write or a full bucket, whichever is less
+            let wanted = (buf.len() as u64).min(rate);
+            this.wait = Some(Box::pin(sleep(nanos_for(wanted, rate))));
+        }
+        let allowed = buf.len().min(this.tokens as usize);
+        let poll = Pin::new(&mut this.inner).poll_write(cx, &buf[..allowed]);
+        if let Poll::Ready(Ok(n)) = poll {
+            this.tokens -= n as u64;
+        }
+        poll
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
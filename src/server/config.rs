@@ -0,0 +1,390 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::protocol::{Leniency, RequestLimits};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ConfigError {
+    Io(io::ErrorKind),
+    Parse(String),
+    /// environment variable name and its unparsable value
+    Env(String, String),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "read failure: {err}"),
+            ConfigError::Parse(reason) => write!(f, "invalid config: {reason}"),
+            ConfigError::Env(var, value) => write!(f, "invalid value for {var}: {value}"),
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(value: io::Error) -> Self {
+        ConfigError::Io(value.kind())
+    }
+}
+
+/// Operational settings of a `Server`.
+///
+/// Loaded from TOML or JSON with the `config` feature, environment variables prefixed with
+/// `TOOT_` override either.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default, deny_unknown_fields))]
+pub struct Config {
+    pub bind: Vec<SocketAddr>,
+    /// listening sockets bound per address with `SO_REUSEPORT`, each served by its own accept
+    /// loop; `0` binds one per runtime worker thread
+    pub accept_shards: usize,
+    pub tls: Option<TlsFiles>,
+    pub limits: Limits,
+    pub timeouts: Timeouts,
+    /// one line per request on stderr, in `log_format`
+    pub access_log: bool,
+    pub log_format: LogFormat,
+    pub static_mounts: Vec<StaticMount>,
+    /// caps on how fast responses below some paths are written, under `Limits::bandwidth`
+    pub route_bandwidth: Vec<RouteBandwidth>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: vec![SocketAddr::from(([127, 0, 0, 1], 8080))],
+            accept_shards: 1,
+            tls: None,
+            limits: Limits::default(),
+            timeouts: Timeouts::default(),
+            access_log: false,
+            log_format: LogFormat::default(),
+            static_mounts: Vec::new(),
+            route_bandwidth: Vec::new(),
+        }
+    }
+}
+
+/// PEM files of the certificate chain and its private key
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(deny_unknown_fields))]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// accept TLS 1.3 0-RTT data, requests sent in it are marked with `EarlyData`
+    #[cfg_attr(feature = "config", serde(default))]
+    pub early_data: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default, deny_unknown_fields))]
+pub struct Limits {
+    pub max_connections: usize,
+    pub max_line_len: usize,
+    pub max_headers: usize,
+    pub max_body_len: usize,
+    /// requests served on a keep-alive connection before it's closed
+    pub max_requests: usize,
+    /// bytes a connection's read buffer starts with, and shrinks back to between small requests
+    pub min_read_buffer: usize,
+    /// bytes a connection's read buffer grows to at most while requests fill it
+    pub read_buffer: usize,
+    /// bytes of a request body over `max_body_len` discarded to answer it with 413 and keep the
+    /// connection open, larger ones are answered with `Connection: close`
+    pub max_drain: usize,
+    /// bytes of a request body kept as `RawBody` after it's read, none unless set
+    pub max_raw_body: usize,
+    /// bytes of a response buffered per connection before writing waits for the peer
+    pub write_buffer: usize,
+    /// bytes per second written to each connection, unlimited unless set
+    pub bandwidth: Option<u64>,
+    /// sizes of the buffers in the server's `BufferPool`, read and write buffers are leased in
+    /// the smallest size that fits
+    pub buffer_sizes: Vec<usize>,
+    /// bytes of buffers leased by all connections at once, connections beyond it are answered
+    /// with 503 and closed
+    pub memory_budget: usize,
+    /// strict unless configured otherwise
+    pub leniency: Leniency,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        let request = RequestLimits::default();
+        Self {
+            max_connections: 10_000,
+            max_line_len: request.max_line_len,
+            max_headers: request.max_headers,
+            max_body_len: request.max_body_len,
+            max_requests: 1000,
+            min_read_buffer: 1024,
+            read_buffer: 64 * 1024,
+            max_drain: 64 * 1024,
+            max_raw_body: 0,
+            write_buffer: 64 * 1024,
+            bandwidth: None,
+            buffer_sizes: vec![1024, 4 * 1024, 16 * 1024, 64 * 1024],
+            memory_budget: 1024 * 1024 * 1024,
+            leniency: Leniency::STRICT,
+        }
+    }
+}
+
+impl Limits {
+    pub fn request_limits(&self) -> RequestLimits {
+        RequestLimits {
+            max_line_len: self.max_line_len,
+            max_headers: self.max_headers,
+            max_body_len: self.max_body_len,
+            leniency: self.leniency,
+        }
+    }
+}
+
+/// Durations are given in (fractional) seconds in config files
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default, deny_unknown_fields))]
+pub struct Timeouts {
+    /// from the first byte of a request until its head and body are read
+    #[cfg_attr(feature = "config", serde(deserialize_with = "seconds"))]
+    pub request_read: Duration,
+    /// from accepting a TLS connection until its handshake is complete
+    #[cfg_attr(feature = "config", serde(deserialize_with = "seconds"))]
+    pub tls_handshake: Duration,
+    /// how long a keep-alive connection may wait for its next request
+    #[cfg_attr(feature = "config", serde(deserialize_with = "seconds"))]
+    pub idle: Duration,
+    /// how long writing a response may make no progress before the connection is dropped
+    #[cfg_attr(feature = "config", serde(deserialize_with = "seconds"))]
+    pub write_stall: Duration,
+    /// how long a graceful shutdown waits for open connections to finish
+    #[cfg_attr(feature = "config", serde(deserialize_with = "seconds"))]
+    pub shutdown: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            request_read: Duration::from_secs(30),
+            tls_handshake: Duration::from_secs(10),
+            idle: Duration::from_secs(60),
+            write_stall: Duration::from_secs(30),
+            shutdown: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(rename_all = "lowercase"))]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// serves files below `dir` under the url path `prefix`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(deny_unknown_fields))]
+pub struct StaticMount {
+    pub prefix: String,
+    pub dir: PathBuf,
+}
+
+/// writes responses to requests below the url path `prefix` at most `bytes_per_sec` fast
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(deny_unknown_fields))]
+pub struct RouteBandwidth {
+    pub prefix: String,
+    pub bytes_per_sec: u64,
+}
+
+impl Config {
+    /// How fast the response to a request for `path` may be written, the lower of
+    /// `Limits::bandwidth` and the cap of the longest matching `route_bandwidth` prefix
+    pub fn bandwidth_for(&self, path: &str) -> Option<u64> {
+        let route = self
+            .route_bandwidth
+            .iter()
+            .filter(|route| {
+                let prefix = route.prefix.trim_end_matches('/');
+                path.strip_prefix(prefix)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+            .max_by_key(|route| route.prefix.trim_end_matches('/').len())
+            .map(|route| route.bytes_per_sec);
+        match (self.limits.bandwidth, route) {
+            (Some(limit), Some(route)) => Some(limit.min(route)),
+            (limit, route) => limit.or(route),
+        }
+    }
+}
+
+#[cfg(feature = "config")]
+fn seconds<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::Deserialize;
+
+    let secs = f64::deserialize(deserializer)?;
+    Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom)
+}
+
+impl Config {
+    #[cfg(feature = "config")]
+    pub fn from_toml_str(s: &str) -> Result<Self, ConfigError> {
+        toml::from_str(s).map_err(|err| ConfigError::Parse(err.to_string()))
+    }
+
+    #[cfg(feature = "config")]
+    pub fn from_json_str(s: &str) -> Result<Self, ConfigError> {
+        serde_json::from_str(s).map_err(|err| ConfigError::Parse(err.to_string()))
+    }
+
+    /// TOML or JSON depending on the file extension
+    #[cfg(feature = "config")]
+    pub fn from_file<P: AsRef<std::path::Path>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => Self::from_json_str(&content),
+            _ => Self::from_toml_str(&content),
+        }
+    }
+
+    /// Defaults overridden by `TOOT_*` environment variables
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::default().merge_env()
+    }
+
+    /// Overrides settings from environment variables:
+    /// `TOOT_BIND` (comma separated), `TOOT_ACCEPT_SHARDS`, `TOOT_TLS_CERT` and `TOOT_TLS_KEY`,
+    /// `TOOT_TLS_EARLY_DATA` (`true` or `false`, with TLS configured),
+    /// `TOOT_MAX_CONNECTIONS`, `TOOT_MAX_BODY_LEN`, `TOOT_MAX_REQUESTS`,
+    /// `TOOT_MEMORY_BUDGET` (bytes),
+    /// `TOOT_LENIENT` (`true` or `false`),
+    /// `TOOT_REQUEST_READ_TIMEOUT`, `TOOT_TLS_HANDSHAKE_TIMEOUT`, `TOOT_IDLE_TIMEOUT`,
+    /// `TOOT_WRITE_STALL_TIMEOUT`, `TOOT_SHUTDOWN_TIMEOUT` (seconds), `TOOT_ACCESS_LOG` (`true` or `false`),
+    /// `TOOT_LOG_FORMAT` (`text` or `json`) and `TOOT_STATIC` (`prefix=dir`, comma separated)
+    pub fn merge_env(self) -> Result<Self, ConfigError> {
+        self.merge_vars(|name| std::env::var(name).ok())
+    }
+
+    pub(crate) fn merge_vars<F>(mut self, var: F) -> Result<Self, ConfigError>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        fn parse<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, ConfigError> {
+            value.trim().parse().map_err(|_| ConfigError::Env(name.to_owned(), value.to_owned()))
+        }
+        let seconds = |name: &str, value: &str| {
+            parse::<f64>(name, value).and_then(|secs| {
+                Duration::try_from_secs_f64(secs)
+                    .map_err(|_| ConfigError::Env(name.to_owned(), value.to_owned()))
+            })
+        };
+
+        if let Some(value) = var("TOOT_BIND") {
+            self.bind =
+                value.split(',').map(|addr| parse("TOOT_BIND", addr)).collect::<Result<_, _>>()?;
+        }
+        if let Some(value) = var("TOOT_ACCEPT_SHARDS") {
+            self.accept_shards = parse("TOOT_ACCEPT_SHARDS", &value)?;
+        }
+        match (var("TOOT_TLS_CERT"), var("TOOT_TLS_KEY")) {
+            (Some(cert), Some(key)) => {
+                self.tls = Some(TlsFiles { cert: cert.into(), key: key.into(), early_data: false })
+            }
+            (Some(_), None) => {
+                return Err(ConfigError::Env("TOOT_TLS_KEY".to_owned(), String::new()))
+            }
+            (None, Some(_)) => {
+                return Err(ConfigError::Env("TOOT_TLS_CERT".to_owned(), String::new()))
+            }
+            (None, None) => {}
+        }
+        if let (Some(value), Some(tls)) = (var("TOOT_TLS_EARLY_DATA"), self.tls.as_mut()) {
+            tls.early_data = parse("TOOT_TLS_EARLY_DATA", &value)?;
+        }
+        if let Some(value) = var("TOOT_MAX_CONNECTIONS") {
+            self.limits.max_connections = parse("TOOT_MAX_CONNECTIONS", &value)?;
+        }
+        if let Some(value) = var("TOOT_MAX_BODY_LEN") {
+            self.limits.max_body_len = parse("TOOT_MAX_BODY_LEN", &value)?;
+        }
+        if let Some(value) = var("TOOT_MAX_RAW_BODY") {
+            self.limits.max_raw_body = parse("TOOT_MAX_RAW_BODY", &value)?;
+        }
+        if let Some(value) = var("TOOT_MAX_REQUESTS") {
+            self.limits.max_requests = parse("TOOT_MAX_REQUESTS", &value)?;
+        }
+        if let Some(value) = var("TOOT_BANDWIDTH") {
+            self.limits.bandwidth = Some(parse("TOOT_BANDWIDTH", &value)?);
+        }
+        if let Some(value) = var("TOOT_MEMORY_BUDGET") {
+            self.limits.memory_budget = parse("TOOT_MEMORY_BUDGET", &value)?;
+        }
+        if let Some(value) = var("TOOT_LENIENT") {
+            self.limits.leniency = match parse("TOOT_LENIENT", &value)? {
+                true => Leniency::LENIENT,
+                false => Leniency::STRICT,
+            };
+        }
+        if let Some(value) = var("TOOT_REQUEST_READ_TIMEOUT") {
+            self.timeouts.request_read = seconds("TOOT_REQUEST_READ_TIMEOUT", &value)?;
+        }
+        if let Some(value) = var("TOOT_TLS_HANDSHAKE_TIMEOUT") {
+            self.timeouts.tls_handshake = seconds("TOOT_TLS_HANDSHAKE_TIMEOUT", &value)?;
+        }
+        if let Some(value) = var("TOOT_IDLE_TIMEOUT") {
+            self.timeouts.idle = seconds("TOOT_IDLE_TIMEOUT", &value)?;
+        }
+        if let Some(value) = var("TOOT_WRITE_STALL_TIMEOUT") {
+            self.timeouts.write_stall = seconds("TOOT_WRITE_STALL_TIMEOUT", &value)?;
+        }
+        if let Some(value) = var("TOOT_SHUTDOWN_TIMEOUT") {
+            self.timeouts.shutdown = seconds("TOOT_SHUTDOWN_TIMEOUT", &value)?;
+        }
+        if let Some(value) = var("TOOT_ACCESS_LOG") {
+            self.access_log = parse("TOOT_ACCESS_LOG", &value)?;
+        }
+        if let Some(value) = var("TOOT_LOG_FORMAT") {
+            self.log_format = match value.trim() {
+                "text" => LogFormat::Text,
+                "json" => LogFormat::Json,
+                _ => return Err(ConfigError::Env("TOOT_LOG_FORMAT".to_owned(), value)),
+            };
+        }
+        if let Some(value) = var("TOOT_STATIC") {
+            self.static_mounts = value
+                .split(',')
+                .map(|mount| match mount.split_once('=') {
+                    Some((prefix, dir)) => {
+                        Ok(StaticMount { prefix: prefix.trim().to_owned(), dir: dir.trim().into() })
+                    }
+                    None => Err(ConfigError::Env("TOOT_STATIC".to_owned(), mount.to_owned())),
+                })
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(value) = var("TOOT_ROUTE_BANDWIDTH") {
+            let invalid =
+                |route: &str| ConfigError::Env("TOOT_ROUTE_BANDWIDTH".to_owned(), route.to_owned());
+            self.route_bandwidth = value
+                .split(',')
+                .map(|route| {
+                    let (prefix, rate) = route.split_once('=').ok_or_else(|| invalid(route))?;
+                    let bytes_per_sec = rate.trim().parse().map_err(|_| invalid(route))?;
+                    Ok::<_, ConfigError>(RouteBandwidth {
+                        prefix: prefix.trim().to_owned(),
+                        bytes_per_sec,
+                    })
+                })
+                .collect::<Result<_, _>>()?;
+        }
+
+        Ok(self)
+    }
+}
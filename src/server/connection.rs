@@ -0,0 +1,588 @@
+use std::any::Any;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::{pin, Pin};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Chain,
+    ReadHalf, WriteHalf,
+};
+use tokio::sync::watch;
+use tokio::time::timeout;
+
+use super::bandwidth::Throttle;
+use super::log::{AccessLogEntry, ConnectionLogEntry};
+use super::pool::{PooledReader, PooledWriter};
+use super::stall::StallTimeout;
+use super::upgrade::{OnUpgrade, Upgraded};
+use super::{
+    CloseReason, Config, ConfigHandle, ConnectionRecord, ErrorFormat, ErrorReport, HandlerError,
+    PhaseTimings, RequestRecord, RequestSize, Services,
+};
+use crate::files::StaticFiles;
+use crate::protocol::{
+    read_request_body, read_request_head, write_http_response, Extensions, Headers, HttpVersion,
+    Method, ParseRequestError, RawRequest, RawResponse, RetryAfter, StatusCode, StatusLine,
+};
+
+/// Address of the connected client, in the extensions of every request served over TCP
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct PeerAddr(pub SocketAddr);
+
+/// Which connection of a server a request came in on, numbered from 1 in the order accepted,
+/// and its place among that connection's requests, from 1. In the extensions of every request.
+#[derive(Debug, Clone, Copy, Eq, Ord, PartialEq, PartialOrd)]
+pub struct RequestSeq {
+    pub connection: u64,
+    pub sequence: u64,
+}
+
+impl Display for RequestSeq {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.connection, self.sequence)
+    }
+}
+
+/// In the extensions of requests which arrived, at least partly, in TLS 1.3 early data (0-RTT),
+/// on servers with `TlsFiles::early_data`. `middleware::EarlyDataGate` answers those which
+/// aren't safe to repeat with 425.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct EarlyData;
+
+/// In the extensions of every request the server reads, tells when the client closed the
+/// connection or reading from it failed while the request was handled, e.g. to stop waiting on
+/// a long poll. Works the same over TLS.
+///
+/// Requests pipelined behind this one are buffered meanwhile, the client closing shows only
+/// once they are read.
+#[derive(Debug, Clone)]
+pub struct ClientGone(watch::Receiver<bool>);
+
+impl ClientGone {
+    /// Resolves once the client is gone, or the server is done with the request
+    pub async fn wait(&self) {
+        let mut gone = self.0.clone();
+        let _ = gone.wait_for(|gone| *gone).await;
+    }
+
+    pub fn is_gone(&self) -> bool {
+        *self.0.borrow()
+    }
+}
+
+/// A connection to serve requests on
+struct Accepted {
+    peer: Option<SocketAddr>,
+    connection: u64,
+    /// TLS 0-RTT data, read before the stream
+    early_data: Vec<u8>,
+}
+
+/// Serves requests on one connection until either side closes it or a timeout expires, then
+/// tells observers why it was closed. `early_data` is read before `stream`.
+pub(crate) async fn serve_connection<S>(
+    stream: S,
+    early_data: Vec<u8>,
+    peer: Option<SocketAddr>,
+    services: Arc<Services>,
+    config: ConfigHandle,
+    drain: watch::Receiver<bool>,
+) where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let connection = services.connections.fetch_add(1, Ordering::Relaxed) + 1;
+    let opened = Instant::now();
+    let mut requests = 0;
+    let accepted = Accepted { peer, connection, early_data };
+    let reason = serve_requests(stream, accepted, &mut requests, &services, &config, drain).await;
+
+    let record =
+        ConnectionRecord { peer, connection, requests, elapsed: opened.elapsed(), reason: &reason };
+    notify_closed(&record, &services, &config);
+}
+
+/// Tells observers about a connection closed before a request could be read from it, as when
+/// its TLS handshake failed, `opened` when it was accepted
+#[cfg(feature = "tls")]
+pub(crate) fn close_unserved(
+    peer: Option<SocketAddr>,
+    opened: Instant,
+    reason: CloseReason,
+    services: &Services,
+    config: &ConfigHandle,
+) {
+    let connection = services.connections.fetch_add(1, Ordering::Relaxed) + 1;
+    let elapsed = opened.elapsed();
+    let record = ConnectionRecord { peer, connection, requests: 0, elapsed, reason: &reason };
+    notify_closed(&record, services, config);
+}
+
+fn notify_closed(record: &ConnectionRecord<'_>, services: &Services, config: &ConfigHandle) {
+    let config = config.load();
+    if config.access_log && record.reason.is_error() {
+        ConnectionLogEntry { record }.write(config.log_format, &*services.log_sink);
+    }
+    services.observers.iter().for_each(|observer| observer.closed(record));
+}
+
+async fn serve_requests<S>(
+    stream: S,
+    accepted: Accepted,
+    requests: &mut u64,
+    services: &Services,
+    config: &ConfigHandle,
+    mut drain: watch::Receiver<bool>,
+) -> CloseReason
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let Accepted { peer, connection, early_data } = accepted;
+    let early_data_len = early_data.len() as u64;
+    let (reader, mut writer) = tokio::io::split(stream);
+    let reader = io::Cursor::new(early_data).chain(reader);
+    let (limits, write_stall) = {
+        let config = config.load();
+        (config.limits.clone(), config.timeouts.write_stall)
+    };
+    // shed the connection rather than allocate beyond the memory budget
+    let pool = services.buffers.clone();
+    let reader = PooledReader::new(reader, pool, limits.min_read_buffer, limits.read_buffer);
+    let buffers = reader.zip(services.buffers.lease(limits.write_buffer));
+    let Some((mut reader, write_buffer)) = buffers else {
+        let _ = writer.write_all(&overloaded_response().into_vec()).await;
+        let _ = writer.shutdown().await;
+        return CloseReason::Overloaded;
+    };
+    let writer = Throttle::new(StallTimeout::new(writer, write_stall));
+    let mut writer = PooledWriter::new(writer, write_buffer);
+
+    loop {
+        let config = config.load();
+        let limits = config.limits.request_limits();
+        reader.shrink_to_fit();
+        let ready = Instant::now();
+
+        // wait for the first byte of the next request under the idle timeout, idle connections
+        // are closed right away once the server drains
+        tokio::select! {
+            read = timeout(config.timeouts.idle, reader.fill_buf()) => match read {
+                Ok(Ok(buf)) if !buf.is_empty() => {}
+                Ok(Ok(_)) => return CloseReason::ClientClosed,
+                Ok(Err(err)) => return CloseReason::ReadFailed(err.kind()),
+                Err(_) => return CloseReason::IdleTimeout,
+            },
+            _ = drain.wait_for(|draining| *draining) => return CloseReason::Shutdown,
+        }
+
+        let started = Instant::now();
+        let early = reader.consumed() < early_data_len;
+        let read = async {
+            let start = reader.consumed();
+            let (request_line, mut headers) = read_request_head(&mut reader, &limits).await?;
+            let head_read = Instant::now();
+            let head = reader.consumed() - start;
+            // framed both ways, which an intermediary may have read differently
+            let ambiguous = headers.get("Transfer-Encoding").is_some()
+                && headers.get("Content-Length").is_some();
+            let drainable = !ambiguous
+                && headers.get("Transfer-Encoding").is_none()
+                && keep_alive(request_line.version, &headers);
+            let body = match read_request_body(&mut reader, &mut headers, &limits).await {
+                // rejected before any of it was read, discarding it keeps the connection open
+                Err(ParseRequestError::BodyTooLarge(len))
+                    if drainable && len <= config.limits.max_drain =>
+                {
+                    let mut discarded = (&mut reader).take(len as u64);
+                    if tokio::io::copy(&mut discarded, &mut tokio::io::sink()).await? < len as u64 {
+                        return Err(ParseRequestError::Io(io::ErrorKind::UnexpectedEof));
+                    }
+                    return Ok(Incoming::Discarded(ParseRequestError::BodyTooLarge(len)));
+                }
+                body => body?,
+            };
+            let mut extensions = Extensions::new();
+            extensions.insert(RequestSize { head, body: reader.consumed() - start - head });
+            let mut request = RawRequest { request_line, headers, body, extensions };
+            request.keep_raw_body(config.limits.max_raw_body);
+            Ok(Incoming::Request(request, head_read, ambiguous))
+        };
+        let (mut request, head_read, ambiguous) =
+            match timeout(config.timeouts.request_read, read).await {
+                Ok(Ok(Incoming::Request(request, head_read, ambiguous))) => {
+                    (request, head_read, ambiguous)
+                }
+                Ok(Ok(Incoming::Discarded(err))) => {
+                    *requests += 1;
+                    let status = err.status().expect("body errors have a status");
+                    let mut response = error_response(status, Headers::empty());
+                    if !services.error_pages.is_empty() {
+                        services.error_pages.apply(&mut response, ErrorFormat::Html);
+                    }
+                    let last = *requests >= config.limits.max_requests as u64;
+                    match last {
+                        true => response.headers_mut().set("Connection", "close".to_owned()),
+                        false => advertise_keep_alive(response.headers_mut(), &config, *requests),
+                    }
+                    writer.get_mut().get_mut().set_stall(config.timeouts.write_stall);
+                    writer.get_mut().set_rate(config.limits.bandwidth);
+                    let written = match write_http_response(&mut writer, response).await {
+                        Ok(()) => writer.flush().await,
+                        Err(err) => Err(err),
+                    };
+                    match written {
+                        Ok(()) if last => return CloseReason::MaxRequests,
+                        Ok(()) => continue,
+                        Err(err) => return CloseReason::WriteFailed(err.kind()),
+                    }
+                }
+                Ok(Err(err)) => {
+                    let Some(status) = err.status() else {
+                        return CloseReason::from(err);
+                    };
+                    let mut response = parse_error_response(status);
+                    if !services.error_pages.is_empty() {
+                        services.error_pages.apply(&mut response, ErrorFormat::Html);
+                    }
+                    let _ = write_http_response(&mut writer, response).await;
+                    let _ = writer.shutdown().await;
+                    return CloseReason::from(err);
+                }
+                // part of a request arrived, tell the client it stalled rather than just closing
+                Err(_) => {
+                    let mut response = parse_error_response(StatusCode::REQUEST_TIMEOUT);
+                    if !services.error_pages.is_empty() {
+                        services.error_pages.apply(&mut response, ErrorFormat::Html);
+                    }
+                    writer.get_mut().get_mut().set_stall(config.timeouts.write_stall);
+                    let _ = write_http_response(&mut writer, response).await;
+                    let _ = writer.shutdown().await;
+                    return CloseReason::ReadTimeout;
+                }
+            };
+        *requests += 1;
+        let mut timings = PhaseTimings {
+            first_byte: started - ready,
+            head: head_read - started,
+            body: head_read.elapsed(),
+            ..PhaseTimings::default()
+        };
+        request.extensions.insert(timings);
+        let size = request.extensions.get::<RequestSize>().copied().unwrap_or_default();
+        let seq = RequestSeq { connection, sequence: *requests };
+        request.extensions.insert(seq);
+        if let Some(peer) = peer {
+            request.extensions.insert(PeerAddr(peer));
+        }
+        if early {
+            request.extensions.insert(EarlyData);
+        }
+        let (gone, client_gone) = watch::channel(false);
+        request.extensions.insert(ClientGone(client_gone));
+        // handed the connection if the handler switches protocols
+        let upgrade = wants_upgrade(&request).then(|| {
+            let (sender, on_upgrade) = OnUpgrade::new();
+            request.extensions.insert(on_upgrade);
+            sender
+        });
+
+        let keep_alive = keep_alive(request.request_line.version, &request.headers) && !ambiguous;
+        strip_connection_options(&mut request.headers);
+        let observed = !services.observers.is_empty();
+        let reported = !services.error_observers.is_empty();
+        let request_line = config.access_log.then(|| request.request_line.clone());
+        let uri = (observed || reported).then(|| request.request_line.target.to_string());
+        let route = (observed || reported).then(|| services.handler.matched_route(&request));
+        let route = route.flatten();
+        let request_id =
+            reported.then(|| request.headers.get("X-Request-Id").map(str::to_owned)).flatten();
+        if observed {
+            services.observers.iter().for_each(|observer| observer.started(route));
+        }
+        let method = request.request_line.method;
+        let bandwidth = config.bandwidth_for(request.request_line.target.path());
+        let request_version = request.request_line.version;
+        let format =
+            (!services.error_pages.is_empty()).then(|| ErrorFormat::negotiate(&request.headers));
+        let report = |error: HandlerError<'_>| {
+            let uri = uri.as_deref().unwrap_or_default();
+            let request_id = request_id.as_deref();
+            let report = ErrorReport { peer, seq, method, uri, route, request_id, error };
+            services.error_observers.iter().for_each(|observer| observer.report(&report));
+        };
+        let dispatched = pin!(dispatch(request, services, &config));
+        let handler_started = Instant::now();
+        let response = watch_client(CatchUnwind(dispatched), &mut reader, &gone).await;
+        timings.handler = handler_started.elapsed();
+        let mut response = match response {
+            Ok(response) if *response.status() == 101 && upgrade.is_none() => {
+                report(HandlerError::InvalidResponse("switching protocols without an upgrade"));
+                internal_server_error()
+            }
+            Ok(response) => match response.check_framing(method) {
+                Ok(()) if *response.status() >= 500 => {
+                    report(HandlerError::Status(response.status()));
+                    response
+                }
+                Ok(()) => response,
+                Err(err) => {
+                    report(HandlerError::InvalidResponse(&err.to_string()));
+                    internal_server_error()
+                }
+            },
+            Err(payload) => {
+                report(HandlerError::Panic(panic_message(&*payload)));
+                internal_server_error()
+            }
+        };
+        if let Some(format) = format {
+            services.error_pages.apply(&mut response, format);
+            if method == Method::HEAD {
+                response = response.without_body();
+            }
+        }
+        // chunked transfer coding is HTTP/1.1, older clients read a body of unknown length until
+        // the connection closes
+        let until_close = request_version != HttpVersion::Http1_1
+            && response.is_streamed()
+            && response.headers().has_token("Transfer-Encoding", "chunked");
+        if until_close {
+            response.headers_mut().retain(|field, _| {
+                !field.eq_ignore_ascii_case("Transfer-Encoding")
+                    && !field.eq_ignore_ascii_case("Trailer")
+            });
+        }
+        let upgrade = upgrade.filter(|_| *response.status() == 101);
+        let draining = *drain.borrow();
+        let last = *requests >= config.limits.max_requests as u64;
+        let close = !keep_alive
+            || draining
+            || until_close
+            || last
+            || response.headers().has_token("Connection", "close");
+        // after switching protocols the connection is no longer HTTP's to frame or close
+        if upgrade.is_none() {
+            if !close {
+                response.frame_absent_body(method);
+            }
+            if keep_alive && close {
+                response.headers_mut().set("Connection", "close".to_owned());
+            } else if keep_alive {
+                if request_version == HttpVersion::Http1_0 {
+                    response.headers_mut().set("Connection", "keep-alive".to_owned());
+                }
+                advertise_keep_alive(response.headers_mut(), &config, *requests);
+            }
+        }
+        let (status, elapsed) = (response.status(), started.elapsed());
+        writer.get_mut().get_mut().set_stall(config.timeouts.write_stall);
+        writer.get_mut().set_rate(bandwidth);
+        let (write_started, written_before) = (Instant::now(), writer.written());
+        let written = match write_http_response(&mut writer, response).await {
+            Ok(_) => writer.flush().await,
+            Err(err) => Err(err),
+        };
+        timings.write = write_started.elapsed();
+        let bytes_written = writer.written() - written_before;
+        if let Some(ref request_line) = request_line {
+            let entry = AccessLogEntry {
+                peer,
+                seq,
+                request_line,
+                status,
+                elapsed,
+                read: size.total(),
+                written: bytes_written,
+            };
+            entry.write(config.log_format, &*services.log_sink);
+        }
+        if observed {
+            let uri = uri.as_deref().unwrap_or_default();
+            let record = RequestRecord {
+                peer,
+                seq,
+                method,
+                uri,
+                route,
+                status,
+                elapsed,
+                timings,
+                size,
+                written: bytes_written,
+            };
+            services.observers.iter().for_each(|observer| observer.observe(&record));
+        }
+        if let (Ok(()), Some(upgrade)) = (&written, upgrade) {
+            // fails only when the handler gave up on the connection, which then just closes
+            let _ = upgrade.send(take_over(reader, writer));
+            return CloseReason::Upgraded;
+        }
+        match written {
+            Err(err) => return CloseReason::WriteFailed(err.kind()),
+            Ok(()) if draining => return CloseReason::Shutdown,
+            Ok(()) if last => return CloseReason::MaxRequests,
+            Ok(()) if close => return CloseReason::Requested,
+            Ok(()) => {}
+        }
+    }
+}
+
+/// Reunites the halves of the connection after `101 Switching Protocols`, reading first the
+/// bytes which arrived past the request
+fn take_over<S>(
+    reader: PooledReader<Chain<io::Cursor<Vec<u8>>, ReadHalf<S>>>,
+    writer: PooledWriter<Throttle<StallTimeout<WriteHalf<S>>>>,
+) -> Upgraded
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let (reader, mut buffered) = reader.into_parts();
+    let (early_data, reader) = reader.into_inner();
+    let pos = early_data.position() as usize;
+    buffered.extend_from_slice(early_data.get_ref().get(pos..).unwrap_or_default());
+    let writer = writer.into_inner().into_inner().into_inner();
+    Upgraded::new(reader.unsplit(writer), buffered)
+}
+
+/// Requests below a static mount are answered from its directory, all others by `handler`
+async fn dispatch(request: RawRequest, services: &Services, config: &Config) -> RawResponse {
+    let path = request.request_line.target.path();
+    let mount = config.static_mounts.iter().find_map(|mount| {
+        let prefix = mount.prefix.trim_end_matches('/');
+        let rest = path.strip_prefix(prefix)?;
+        (rest.is_empty() || rest.starts_with('/')).then_some((mount, rest))
+    });
+    match mount {
+        Some((mount, rest)) => {
+            let files = StaticFiles::new(&mount.dir).cache(services.file_cache.clone());
+            files.respond_path(&request, rest).await
+        }
+        None => services.handler.call(request).await,
+    }
+}
+
+/// Runs `handle` while reading ahead on the connection to notice the client going away, which
+/// `gone` is told. What is read stays buffered for the next request.
+async fn watch_client<F, R>(handle: F, reader: &mut R, gone: &watch::Sender<bool>) -> F::Output
+where
+    F: Future,
+    R: AsyncBufRead + Unpin,
+{
+    let watch = async {
+        // with bytes already buffered nothing more is read, so this learns nothing new
+        let closed = reader.fill_buf().await.map_or(true, |buf| buf.is_empty());
+        if closed {
+            gone.send_replace(true);
+        }
+        std::future::pending().await
+    };
+    tokio::select! {
+        output = handle => output,
+        output = watch => output,
+    }
+}
+
+/// Resolves to the panic payload instead of unwinding into the connection task
+struct CatchUnwind<F>(F);
+
+impl<F: Future + Unpin> Future for CatchUnwind<F> {
+    type Output = Result<F::Output, Box<dyn Any + Send>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let inner = &mut self.0;
+        match panic::catch_unwind(AssertUnwindSafe(|| Pin::new(inner).poll(cx))) {
+            Ok(poll) => poll.map(Ok),
+            Err(payload) => Poll::Ready(Err(payload)),
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> Option<&str> {
+    let message = payload.downcast_ref::<&str>().copied();
+    message.or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+}
+
+/// Answers a request which couldn't be read, the connection is closed after it
+fn parse_error_response(status: StatusCode) -> RawResponse {
+    let mut headers = Headers::empty();
+    headers.set("Connection", "close".to_owned());
+    error_response(status, headers)
+}
+
+fn error_response(status: StatusCode, headers: Headers) -> RawResponse {
+    RawResponse::new(StatusLine::new(HttpVersion::Http1_1, status), headers, Some(Vec::new()))
+}
+
+/// Answers a connection the server has no memory left for, before reading from it
+fn overloaded_response() -> RawResponse {
+    let mut response = parse_error_response(StatusCode::ServiceUnavailable);
+    RetryAfter::Delay(std::time::Duration::from_secs(1)).apply(response.headers_mut());
+    response
+}
+
+fn internal_server_error() -> RawResponse {
+    let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::INTERNAL_SERVER_ERROR);
+    RawResponse::new(status_line, Headers::empty(), Some(Vec::new()))
+}
+
+/// `Keep-Alive` with how long the connection waits for the next request, and how many more it
+/// serves after the `served` ones
+fn advertise_keep_alive(headers: &mut Headers, config: &Config, served: u64) {
+    let timeout = config.timeouts.idle.as_secs();
+    let max = (config.limits.max_requests as u64).saturating_sub(served);
+    headers.set("Keep-Alive", format!("timeout={timeout}, max={max}"));
+}
+
+/// An HTTP/1.1 request to switch protocols, `Connection: upgrade` with an `Upgrade` header
+fn wants_upgrade(request: &RawRequest) -> bool {
+    let headers = &request.headers;
+    request.request_line.version == HttpVersion::Http1_1
+        && headers.has_token("Connection", "upgrade")
+        && headers.get("Upgrade").is_some()
+}
+
+/// HTTP/1.1 keeps connections open unless asked not to, HTTP/1.0 only when asked to
+fn keep_alive(version: HttpVersion, headers: &Headers) -> bool {
+    match version {
+        HttpVersion::Http1_1 => !headers.has_token("Connection", "close"),
+        _ => headers.has_token("Connection", "keep-alive"),
+    }
+}
+
+/// How reading a request ended, unless it failed
+enum Incoming {
+    /// with the time its head was read, and whether its body was framed ambiguously
+    Request(RawRequest, Instant, bool),
+    /// rejected with the error, its body discarded
+    Discarded(ParseRequestError),
+}
+
+/// Removes what the client meant for this connection only: the fields nominated in
+/// `Connection`, except `upgrade` which tells handlers the request may switch protocols (see
+/// `OnUpgrade`), and every transfer coding of `TE` but `trailers`, the only one responses are
+/// sent with
+fn strip_connection_options(headers: &mut Headers) {
+    let nominated = headers
+        .get_all("Connection")
+        .flat_map(|v| v.split(','))
+        .map(|v| v.trim().to_ascii_lowercase())
+        .filter(|v| v != "upgrade")
+        .collect::<Vec<_>>();
+    let trailers = headers
+        .get_all("TE")
+        .flat_map(|v| v.split(','))
+        .any(|v| v.split(';').next().unwrap_or_default().trim().eq_ignore_ascii_case("trailers"));
+    headers.retain(|field, _| {
+        !field.eq_ignore_ascii_case("TE")
+            && !nominated.iter().any(|n| n.eq_ignore_ascii_case(field))
+    });
+    if trailers {
+        headers.set("TE", "trailers".to_owned());
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::protocol::{Headers, MediaType, RawResponse, StatusCode};
+
+/// Body format of an error page, negotiated from the `Accept` header of the request
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ErrorFormat {
+    Html,
+    Json,
+}
+
+impl ErrorFormat {
+    /// JSON when the client prefers it over HTML, HTML otherwise
+    pub fn negotiate(headers: &Headers) -> Self {
+        let accept = headers.get_all("Accept").collect::<Vec<_>>().join(",");
+        if accept.is_empty() {
+            return ErrorFormat::Html;
+        }
+        match quality(&accept, "application/json") > quality(&accept, "text/html") {
+            true => ErrorFormat::Json,
+            false => ErrorFormat::Html,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ErrorFormat::Html => "text/html; charset=utf-8",
+            ErrorFormat::Json => "application/json",
+        }
+    }
+}
+
+/// `q` of the most specific media range in `accept` matching `media`, 0 when none does
+fn quality(accept: &str, media: &str) -> f32 {
+    let media = MediaType::parse(media).expect("valid media type");
+    let mut best = (0, 0.0);
+    for range in accept.split(',') {
+        let Ok(range) = MediaType::parse(range) else {
+            continue;
+        };
+        if !media.matches(range.essence()) {
+            continue;
+        }
+        let specificity = match (range.kind(), range.subtype()) {
+            ("*", _) => 1,
+            (_, "*") => 2,
+            _ => 3,
+        };
+        let q = range.param("q").and_then(|q| q.parse::<f32>().ok()).unwrap_or(1.0);
+        if specificity > best.0 {
+            best = (specificity, q);
+        }
+    }
+    best.1
+}
+
+/// Renders the body of an error page, see `Server::error_page`.
+///
+/// Closures over the status and format are renderers too.
+pub trait ErrorRenderer: Send + Sync + 'static {
+    fn render(&self, status: StatusCode, format: ErrorFormat) -> String;
+}
+
+impl<F> ErrorRenderer for F
+where
+    F: Fn(StatusCode, ErrorFormat) -> String + Send + Sync + 'static,
+{
+    fn render(&self, status: StatusCode, format: ErrorFormat) -> String {
+        self(status, format)
+    }
+}
+
+/// Error pages of a `Server` by status code
+#[derive(Clone, Default)]
+pub(crate) struct ErrorPages(BTreeMap<StatusCode, Arc<dyn ErrorRenderer>>);
+
+impl ErrorPages {
+    pub fn insert(&mut self, status: StatusCode, renderer: Arc<dyn ErrorRenderer>) {
+        self.0.insert(status, renderer);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Renders the page for the status of `response` into it, unless it has a body of its own
+    pub fn apply(&self, response: &mut RawResponse, format: ErrorFormat) {
+        if !response.body().is_some_and(<[u8]>::is_empty) {
+            return;
+        }
+        if let Some(renderer) = self.0.get(&response.status()) {
+            let body = renderer.render(response.status(), format);
+            response.headers_mut().set("Content-Type", format.content_type().to_owned());
+            response.set_body(body.into_bytes());
+        }
+    }
+}
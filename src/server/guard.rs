@@ -0,0 +1,84 @@
+use crate::protocol::RawRequest;
+
+/// Predicate deciding whether a request may reach a routed handler, see `Router::guard`.
+///
+/// Guards run after authentication middleware and typically inspect what it attached to the
+/// request extensions. Closures over `&RawRequest` are guards too.
+pub trait Guard: Send + Sync + 'static {
+    fn check(&self, request: &RawRequest) -> bool;
+
+    fn and<G: Guard>(self, other: G) -> And<Self, G>
+    where
+        Self: Sized,
+    {
+        And(self, other)
+    }
+
+    fn or<G: Guard>(self, other: G) -> Or<Self, G>
+    where
+        Self: Sized,
+    {
+        Or(self, other)
+    }
+
+    fn not(self) -> Not<Self>
+    where
+        Self: Sized,
+    {
+        Not(self)
+    }
+}
+
+impl<F> Guard for F
+where
+    F: Fn(&RawRequest) -> bool + Send + Sync + 'static,
+{
+    fn check(&self, request: &RawRequest) -> bool {
+        self(request)
+    }
+}
+
+pub struct And<A, B>(A, B);
+
+impl<A: Guard, B: Guard> Guard for And<A, B> {
+    fn check(&self, request: &RawRequest) -> bool {
+        self.0.check(request) && self.1.check(request)
+    }
+}
+
+pub struct Or<A, B>(A, B);
+
+impl<A: Guard, B: Guard> Guard for Or<A, B> {
+    fn check(&self, request: &RawRequest) -> bool {
+        self.0.check(request) || self.1.check(request)
+    }
+}
+
+pub struct Not<A>(A);
+
+impl<A: Guard> Guard for Not<A> {
+    fn check(&self, request: &RawRequest) -> bool {
+        !self.0.check(request)
+    }
+}
+
+/// Roles of the authenticated principal, inserted into the request extensions by
+/// authentication middleware
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Roles(pub Vec<String>);
+
+impl Roles {
+    pub fn contains(&self, role: &str) -> bool {
+        self.0.iter().any(|r| r == role)
+    }
+}
+
+/// Passes requests whose `Roles` extension contains the role
+#[derive(Debug, Clone)]
+pub struct RequireRole(pub &'static str);
+
+impl Guard for RequireRole {
+    fn check(&self, request: &RawRequest) -> bool {
+        request.extensions.get::<Roles>().is_some_and(|roles| roles.contains(self.0))
+    }
+}
@@ -0,0 +1,128 @@
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+
+use tokio::net::TcpListener;
+use tokio::process::{Child, Command};
+use tokio::signal::unix::{signal, SignalKind};
+
+use super::{activated_or_bound, Server};
+
+/// Number of listening sockets handed to this process, starting at descriptor 3
+pub const LISTEN_FDS_ENV: &str = "TOOT_LISTEN_FDS";
+/// pid of the process which started this one as its upgrade
+const UPGRADE_FROM_ENV: &str = "TOOT_UPGRADE_FROM";
+
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Takes the listening sockets handed over by the previous process, empty when there are none.
+///
+/// The environment variable is removed so child processes don't pick the sockets up again.
+pub fn inherited_listeners() -> io::Result<Vec<std::net::TcpListener>> {
+    let count = inherited_fd_count(std::env::var(LISTEN_FDS_ENV).ok().as_deref())?;
+    std::env::remove_var(LISTEN_FDS_ENV);
+
+    (LISTEN_FDS_START..LISTEN_FDS_START + count as RawFd)
+        .map(|fd| {
+            // SAFETY: the previous process placed exactly `count` sockets from descriptor 3 on
+            let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+            listener.set_nonblocking(true)?;
+            Ok(listener)
+        })
+        .collect()
+}
+
+pub(super) fn inherited_fd_count(value: Option<&str>) -> io::Result<usize> {
+    value.map_or(Ok(0), |count| {
+        count
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid TOOT_LISTEN_FDS"))
+    })
+}
+
+/// Spawns `command` with `listeners` as descriptors 3, 4, ... and `TOOT_LISTEN_FDS` set
+pub fn spawn_with_listeners(mut command: Command, listeners: &[RawFd]) -> io::Result<Child> {
+    let mut fds = listeners.to_vec();
+    let end = LISTEN_FDS_START + fds.len() as RawFd;
+    command.env(LISTEN_FDS_ENV, fds.len().to_string());
+
+    // SAFETY: only async-signal-safe calls between fork and exec, `fds` is not reallocated
+    unsafe {
+        command.pre_exec(move || {
+            // move every socket above the target range first so no `dup2` clobbers a source,
+            // the intermediate copies close on exec
+            for fd in fds.iter_mut() {
+                *fd = libc::fcntl(*fd, libc::F_DUPFD_CLOEXEC, end);
+                if *fd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            for (target, fd) in (LISTEN_FDS_START..).zip(fds.iter()) {
+                if libc::dup2(*fd, target) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    command.spawn()
+}
+
+/// Tells the process this one replaces to drain, `Ok(false)` when not started as an upgrade
+pub fn notify_upgraded() -> io::Result<bool> {
+    let from = std::env::var(UPGRADE_FROM_ENV).ok().and_then(|pid| pid.parse::<libc::pid_t>().ok());
+    std::env::remove_var(UPGRADE_FROM_ENV);
+
+    // SAFETY: plain syscalls without memory arguments
+    let parent = unsafe { libc::getppid() };
+    match from {
+        Some(pid) if pid == parent => match unsafe { libc::kill(pid, libc::SIGTERM) } {
+            0 => Ok(true),
+            _ => Err(io::Error::last_os_error()),
+        },
+        _ => Ok(false),
+    }
+}
+
+impl Server {
+    /// Serves like `run` with zero-downtime binary upgrades.
+    ///
+    /// On `SIGUSR2` the current executable is started again with the same arguments and the
+    /// listening sockets. Once the new process serves them it sends `SIGTERM` to this one, which
+    /// stops accepting and drains like on any `SIGTERM` or `SIGINT`. Sockets handed over by a
+    /// previous process, accept shards included, are used instead of binding `Config::bind`, as
+    /// are the sockets of systemd socket activation on the first start.
+    pub async fn run_upgradable(self) -> io::Result<()> {
+        let inherited = inherited_listeners()?;
+        let listeners = if inherited.is_empty() {
+            activated_or_bound(&self.config.load())?
+        } else {
+            inherited.into_iter().map(TcpListener::from_std).collect::<io::Result<_>>()?
+        };
+
+        let fds = listeners.iter().map(AsRawFd::as_raw_fd).collect::<Vec<_>>();
+        let mut upgrades = signal(SignalKind::user_defined2())?;
+        let mut terminate = signal(SignalKind::terminate())?;
+        let shutdown = async move {
+            loop {
+                tokio::select! {
+                    _ = terminate.recv() => return,
+                    _ = tokio::signal::ctrl_c() => return,
+                    _ = upgrades.recv() => {
+                        // a failed upgrade leaves this process serving
+                        let _ = std::env::current_exe().and_then(|exe| {
+                            let mut command = Command::new(exe);
+                            command
+                                .args(std::env::args_os().skip(1))
+                                .env(UPGRADE_FROM_ENV, std::process::id().to_string());
+                            spawn_with_listeners(command, &fds)
+                        });
+                    }
+                }
+            }
+        };
+
+        notify_upgraded()?;
+        self.serve_with_shutdown(listeners, shutdown).await
+    }
+}
@@ -0,0 +1,244 @@
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::time::Duration;
+
+use super::{ConnectionRecord, LogFormat, RequestSeq};
+use crate::protocol::{RequestLine, StatusCode};
+
+/// One served request as written to the access log
+pub(crate) struct AccessLogEntry<'a> {
+    pub peer: Option<SocketAddr>,
+    pub seq: RequestSeq,
+    pub request_line: &'a RequestLine,
+    pub status: StatusCode,
+    pub elapsed: Duration,
+    /// bytes of the request and of the response
+    pub read: u64,
+    pub written: u64,
+}
+
+impl AccessLogEntry<'_> {
+    pub fn format(&self, format: LogFormat) -> String {
+        let peer = self.peer.map(|peer| peer.to_string()).unwrap_or_else(|| "-".to_owned());
+        let RequestLine { method, target, version } = self.request_line;
+        let micros = self.elapsed.as_micros();
+        let RequestSeq { connection, sequence } = self.seq;
+        let (read, written) = (self.read, self.written);
+
+        match format {
+            LogFormat::Text => {
+                format!(
+                    "{peer} #{connection}.{sequence} \"{} {target} {version}\" {} {micros}us \
+                     {read}B in {written}B out",
+                    method.as_str(),
+                    *self.status
+                )
+            }
+            LogFormat::Json => format!(
+                "{{\"peer\":\"{peer}\",\"connection\":{connection},\"sequence\":{sequence},\
+                 \"method\":\"{}\",\"uri\":\"{}\",\"version\":\"{version}\",\
+                 \"status\":{},\"micros\":{micros},\"bytes_in\":{read},\"bytes_out\":{written}}}",
+                method.as_str(),
+                json_escape(&target.to_string()),
+                *self.status
+            ),
+        }
+    }
+
+    pub fn write(&self, format: LogFormat, sink: &dyn LogSink) {
+        sink.write(&self.format(format));
+    }
+}
+
+/// A connection closed on an error, written to the access log after its requests
+pub(crate) struct ConnectionLogEntry<'a> {
+    pub record: &'a ConnectionRecord<'a>,
+}
+
+impl ConnectionLogEntry<'_> {
+    pub fn format(&self, format: LogFormat) -> String {
+        let ConnectionRecord { peer, connection, requests, elapsed, reason } = self.record;
+        let peer = peer.map(|peer| peer.to_string()).unwrap_or_else(|| "-".to_owned());
+        let micros = elapsed.as_micros();
+
+        match format {
+            LogFormat::Text => {
+                format!(
+                    "{peer} #{connection} closed: {reason} after {requests} requests {micros}us"
+                )
+            }
+            LogFormat::Json => format!(
+                "{{\"peer\":\"{peer}\",\"connection\":{connection},\"requests\":{requests},\
+                 \"close\":\"{}\",\"micros\":{micros}}}",
+                json_escape(&reason.to_string())
+            ),
+        }
+    }
+
+    pub fn write(&self, format: LogFormat, sink: &dyn LogSink) {
+        sink.write(&self.format(format));
+    }
+}
+
+/// Destination of access log lines.
+///
+/// `write` is called on the request path and must not wait on I/O, wrap blocking sinks in a
+/// `ChannelSink`. Failures are the sink's to report, they never fail the request.
+pub trait LogSink: Send + Sync + 'static {
+    /// one line, without its line break
+    fn write(&self, line: &str);
+}
+
+/// Blocking, wrap in a `ChannelSink` unless lines are few
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdoutSink;
+
+impl LogSink for StdoutSink {
+    fn write(&self, line: &str) {
+        let _ = writeln!(std::io::stdout().lock(), "{line}");
+    }
+}
+
+/// Blocking, wrap in a `ChannelSink` unless lines are few
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StderrSink;
+
+impl LogSink for StderrSink {
+    fn write(&self, line: &str) {
+        let _ = writeln!(std::io::stderr().lock(), "{line}");
+    }
+}
+
+/// Hands lines to a dedicated thread writing them to `inner`.
+///
+/// Lines arriving while `capacity` lines are queued are dropped and counted rather than
+/// making requests wait.
+#[derive(Debug)]
+pub struct ChannelSink {
+    lines: mpsc::SyncSender<String>,
+    dropped: AtomicU64,
+}
+
+impl ChannelSink {
+    pub fn new<S: LogSink>(inner: S, capacity: usize) -> Self {
+        let (lines, queued) = mpsc::sync_channel::<String>(capacity);
+        std::thread::Builder::new()
+            .name("toot-access-log".to_owned())
+            .spawn(move || queued.iter().for_each(|line| inner.write(&line)))
+            .expect("spawning the access log thread");
+        Self { lines, dropped: AtomicU64::new(0) }
+    }
+
+    /// lines lost to a full queue so far
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl LogSink for ChannelSink {
+    fn write(&self, line: &str) {
+        if self.lines.try_send(line.to_owned()).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Appends to a file, renaming it to `path.1` (and older ones to `path.2`, ...) once it would
+/// grow beyond `max_len` bytes, keeping `keep` old files.
+///
+/// Blocking, meant to be wrapped in a `ChannelSink`. Write failures such as a full disk are
+/// counted and reported on stderr once until a write succeeds again.
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    max_len: u64,
+    keep: usize,
+    state: Mutex<RotatingState>,
+    errors: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct RotatingState {
+    file: Option<File>,
+    len: u64,
+    failing: bool,
+}
+
+impl RotatingFile {
+    pub fn new<P: Into<PathBuf>>(path: P, max_len: u64, keep: usize) -> Self {
+        let state = Mutex::new(RotatingState::default());
+        Self { path: path.into(), max_len, keep, state, errors: AtomicU64::new(0) }
+    }
+
+    /// failed writes so far
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{n}"));
+        path.into()
+    }
+
+    fn append(&self, state: &mut RotatingState, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if state.file.is_some() && state.len > 0 && state.len + len > self.max_len {
+            state.file = None;
+            if self.keep == 0 {
+                std::fs::remove_file(&self.path)?;
+            } else {
+                for n in (1..self.keep).rev() {
+                    let _ = std::fs::rename(self.rotated(n), self.rotated(n + 1));
+                }
+                std::fs::rename(&self.path, self.rotated(1))?;
+            }
+        }
+        if state.file.is_none() {
+            let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+            state.len = file.metadata()?.len();
+            state.file = Some(file);
+        }
+        let file = state.file.as_mut().expect("opened above");
+        file.write_all(format!("{line}\n").as_bytes())?;
+        state.len += len;
+        Ok(())
+    }
+}
+
+impl LogSink for RotatingFile {
+    fn write(&self, line: &str) {
+        let mut state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match self.append(&mut state, line) {
+            Ok(()) => state.failing = false,
+            Err(err) => {
+                self.errors.fetch_add(1, Ordering::Relaxed);
+                // reopened on the next write, e.g. once space has been freed
+                state.file = None;
+                if !std::mem::replace(&mut state.failing, true) {
+                    eprintln!("toot: access log {} failed: {err}", self.path.display());
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,413 @@
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+
+use tokio::net::{TcpListener, TcpSocket};
+use tokio::sync::{watch, Semaphore};
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+
+pub use self::config::{
+    Config, ConfigError, Limits, LogFormat, RouteBandwidth, StaticMount, Timeouts, TlsFiles,
+};
+#[cfg(feature = "tls")]
+use self::connection::close_unserved;
+use self::connection::serve_connection;
+pub use self::connection::{ClientGone, EarlyData, PeerAddr, RequestSeq};
+pub(crate) use self::error_page::ErrorPages;
+pub use self::error_page::{ErrorFormat, ErrorRenderer};
+pub use self::guard::{And, Guard, Not, Or, RequireRole, Roles};
+#[cfg(unix)]
+pub use self::handoff::{
+    inherited_listeners, notify_upgraded, spawn_with_listeners, LISTEN_FDS_ENV,
+};
+pub(crate) use self::log::json_escape;
+pub use self::log::{ChannelSink, LogSink, RotatingFile, StderrSink, StdoutSink};
+pub use self::observe::{
+    CloseReason, ConnectionRecord, ErrorObserver, ErrorReport, HandlerError, HandshakeFailure,
+    PhaseTimings, RequestObserver, RequestRecord, RequestSize,
+};
+pub use self::pool::{BufferPool, Lease};
+#[cfg(unix)]
+pub use self::prefork::{worker_id, WORKER_ENV};
+pub use self::reload::ConfigHandle;
+pub use self::router::{trace_echo, Router, TRACE_REDACTED_HEADERS};
+pub use self::upgrade::{OnUpgrade, Upgraded};
+use crate::files::FileCache;
+use crate::metrics::StatsRegistry;
+use crate::protocol::{RawRequest, RawResponse, StatusCode};
+
+mod bandwidth;
+mod config;
+mod connection;
+mod error_page;
+mod guard;
+#[cfg(unix)]
+mod handoff;
+mod log;
+mod observe;
+mod pool;
+#[cfg(unix)]
+mod prefork;
+mod reload;
+mod router;
+mod stall;
+#[cfg(test)]
+mod tests;
+mod upgrade;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+pub trait Handler: Send + Sync + 'static {
+    fn call(&self, request: RawRequest) -> BoxFuture<'static, RawResponse>;
+
+    /// The pattern of the route `request` will be dispatched to, for statistics
+    fn matched_route(&self, _request: &RawRequest) -> Option<&str> {
+        None
+    }
+}
+
+impl<F, Fut> Handler for F
+where
+    F: Fn(RawRequest) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = RawResponse> + Send + 'static,
+{
+    fn call(&self, request: RawRequest) -> BoxFuture<'static, RawResponse> {
+        Box::pin(self(request))
+    }
+}
+
+pub struct Server {
+    config: ConfigHandle,
+    handler: Arc<dyn Handler>,
+    log_sink: Option<Arc<dyn LogSink>>,
+    observers: Vec<Arc<dyn RequestObserver>>,
+    error_observers: Vec<Arc<dyn ErrorObserver>>,
+    error_pages: ErrorPages,
+    stats: StatsRegistry,
+    buffer_pool: Option<BufferPool>,
+    file_cache: Option<FileCache>,
+}
+
+/// What every connection of a `Server` needs besides its configuration
+pub(crate) struct Services {
+    pub handler: Arc<dyn Handler>,
+    pub log_sink: Arc<dyn LogSink>,
+    pub observers: Vec<Arc<dyn RequestObserver>>,
+    pub error_observers: Vec<Arc<dyn ErrorObserver>>,
+    pub error_pages: ErrorPages,
+    pub buffers: BufferPool,
+    pub file_cache: Option<FileCache>,
+    /// connections accepted so far
+    pub connections: AtomicU64,
+}
+
+impl Server {
+    pub fn new<H: Handler>(handler: H) -> Self {
+        Self::from_config(Config::default(), handler)
+    }
+
+    pub fn from_config<H: Handler>(config: Config, handler: H) -> Self {
+        Self {
+            config: ConfigHandle::new(config),
+            handler: Arc::new(handler),
+            log_sink: None,
+            observers: Vec::new(),
+            error_observers: Vec::new(),
+            error_pages: ErrorPages::default(),
+            stats: StatsRegistry::new(),
+            buffer_pool: None,
+            file_cache: None,
+        }
+    }
+
+    /// Where access log lines go when `Config::access_log` is set, stderr through a
+    /// `ChannelSink` unless set
+    pub fn log_sink<S: LogSink>(mut self, sink: S) -> Self {
+        self.log_sink = Some(Arc::new(sink));
+        self
+    }
+
+    /// Per-route statistics of this server, which are only collected once this was called
+    pub fn stats(&self) -> StatsRegistry {
+        self.stats.enable();
+        self.stats.clone()
+    }
+
+    /// Adds an observer notified of every answered request
+    pub fn observer<O: RequestObserver>(mut self, observer: O) -> Self {
+        self.observers.push(Arc::new(observer));
+        self
+    }
+
+    /// Adds an observer notified of every handler panic and server error
+    pub fn error_observer<O: ErrorObserver>(mut self, observer: O) -> Self {
+        self.error_observers.push(Arc::new(observer));
+        self
+    }
+
+    /// Renders the body of `status` responses which come without one, such as router misses
+    /// and middleware rejections, as HTML or JSON depending on the request's `Accept` header
+    pub fn error_page<R: ErrorRenderer>(mut self, status: StatusCode, renderer: R) -> Self {
+        self.error_pages.insert(status, Arc::new(renderer));
+        self
+    }
+
+    /// Where connections lease their buffers from, one of `Limits::buffer_sizes` and
+    /// `Limits::memory_budget` unless set. Share a pool to put several servers on one budget.
+    pub fn buffer_pool(mut self, pool: BufferPool) -> Self {
+        self.buffer_pool = Some(pool);
+        self
+    }
+
+    /// Where `Config::static_mounts` keep the files they opened, they open them for every
+    /// request unless set
+    pub fn file_cache(mut self, cache: FileCache) -> Self {
+        self.file_cache = Some(cache);
+        self
+    }
+
+    pub fn config(&self) -> Arc<Config> {
+        self.config.load()
+    }
+
+    /// Handle for swapping the configuration while the server runs
+    pub fn config_handle(&self) -> ConfigHandle {
+        self.config.clone()
+    }
+
+    /// Binds every address in `Config::bind`, or takes the sockets of systemd socket
+    /// activation instead, and serves until a listener fails
+    pub async fn run(self) -> io::Result<()> {
+        self.run_with_shutdown(std::future::pending()).await
+    }
+
+    /// Same as `run`, shutting down gracefully once `shutdown` completes
+    pub async fn run_with_shutdown<F>(self, shutdown: F) -> io::Result<()>
+    where
+        F: Future<Output = ()> + Send,
+    {
+        let listeners = activated_or_bound(&self.config.load())?;
+        self.serve_with_shutdown(listeners, shutdown).await
+    }
+
+    /// Serves connections accepted from already bound `listeners`
+    pub async fn serve(self, listeners: Vec<TcpListener>) -> io::Result<()> {
+        self.serve_with_shutdown(listeners, std::future::pending()).await
+    }
+
+    /// Serves connections until `shutdown` completes, then stops accepting, closes idle
+    /// connections and waits up to `Timeouts::shutdown` for in-flight requests to finish.
+    ///
+    /// Under systemd the service manager is told `READY=1` once connections are accepted and
+    /// `STOPPING=1` when shutting down.
+    pub async fn serve_with_shutdown<F>(
+        self,
+        listeners: Vec<TcpListener>,
+        shutdown: F,
+    ) -> io::Result<()>
+    where
+        F: Future<Output = ()> + Send,
+    {
+        let config = self.config.load();
+        let acceptor = Acceptor::new(&config)?;
+        let connections = Arc::new(Semaphore::new(config.limits.max_connections));
+        let (draining, drain) = watch::channel(false);
+        let log_sink = match self.log_sink {
+            Some(ref sink) => sink.clone(),
+            None => Arc::new(ChannelSink::new(StderrSink, 1024)),
+        };
+        let mut observers = self.observers.clone();
+        if self.stats.is_enabled() {
+            observers.push(Arc::new(self.stats.clone()));
+        }
+        let services = Arc::new(Services {
+            handler: self.handler.clone(),
+            log_sink,
+            observers,
+            error_observers: self.error_observers.clone(),
+            error_pages: self.error_pages.clone(),
+            buffers: match self.buffer_pool {
+                Some(ref pool) => pool.clone(),
+                None => BufferPool::new(&config.limits.buffer_sizes, config.limits.memory_budget),
+            },
+            file_cache: self.file_cache.clone(),
+            connections: AtomicU64::new(0),
+        });
+
+        let mut accept_loops = JoinSet::new();
+        for listener in listeners {
+            let (config, services) = (self.config.clone(), services.clone());
+            let (acceptor, connections, drain) =
+                (acceptor.clone(), connections.clone(), drain.clone());
+
+            accept_loops.spawn(async move {
+                loop {
+                    let permit = connections
+                        .clone()
+                        .acquire_owned()
+                        .await
+                        .expect("semaphore is never closed");
+                    let (stream, peer) = listener.accept().await?;
+                    let (config, services, acceptor, drain) =
+                        (config.clone(), services.clone(), acceptor.clone(), drain.clone());
+
+                    tokio::spawn(async move {
+                        acceptor.serve(stream, peer, services, config, drain).await;
+                        drop(permit);
+                    });
+                }
+            });
+        }
+
+        #[cfg(unix)]
+        notify_service_manager(crate::systemd::NotifyState::Ready);
+        let result = tokio::select! {
+            Some(result) = accept_loops.join_next() => result.map_err(io::Error::other).and_then(|r| r),
+            _ = shutdown => Ok(()),
+        };
+        #[cfg(unix)]
+        notify_service_manager(crate::systemd::NotifyState::Stopping);
+        accept_loops.shutdown().await;
+
+        let _ = draining.send(true);
+        let all = config.limits.max_connections.min(Semaphore::MAX_PERMITS) as u32;
+        let _ = timeout(self.config.load().timeouts.shutdown, connections.acquire_many(all)).await;
+        result
+    }
+}
+
+/// The sockets passed by systemd socket activation, else every address in `Config::bind` bound
+fn activated_or_bound(config: &Config) -> io::Result<Vec<TcpListener>> {
+    #[cfg(unix)]
+    {
+        let activated = crate::systemd::listen_fds()?;
+        if !activated.is_empty() {
+            return activated.into_iter().map(TcpListener::from_std).collect();
+        }
+    }
+    bind_listeners(config, false)
+}
+
+/// Tells systemd about the server's lifecycle, prefork workers leave that to their supervisor
+#[cfg(unix)]
+fn notify_service_manager(state: crate::systemd::NotifyState) {
+    if worker_id().is_none() {
+        // the server runs just as well when the service manager can't be told
+        let _ = crate::systemd::notify(&[state]);
+    }
+}
+
+/// Binds every address in `Config::bind`, `Config::accept_shards` times when sharding.
+///
+/// Shards and `reuse_port` bind with `SO_REUSEPORT`, sharding only applies on unix.
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn bind_listeners(config: &Config, reuse_port: bool) -> io::Result<Vec<TcpListener>> {
+    let shards = match config.accept_shards {
+        0 => tokio::runtime::Handle::current().metrics().num_workers(),
+        shards => shards,
+    };
+
+    let mut listeners = Vec::with_capacity(config.bind.len() * shards);
+    for addr in config.bind.iter() {
+        #[cfg(unix)]
+        if reuse_port || shards > 1 {
+            let first = bind_reuse_port(*addr)?;
+            // a port 0 address is resolved once so all shards share it
+            let addr = first.local_addr()?;
+            listeners.push(first);
+            for _ in 1..shards {
+                listeners.push(bind_reuse_port(addr)?);
+            }
+            continue;
+        }
+
+        let listener = std::net::TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        listeners.push(TcpListener::from_std(listener)?);
+    }
+    Ok(listeners)
+}
+
+/// Binds `addr` with `SO_REUSEPORT` so several sockets, also of other processes, accept on it
+#[cfg(unix)]
+pub fn bind_reuse_port(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = if addr.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+    socket.set_reuseaddr(true)?;
+    socket.set_reuseport(true)?;
+    socket.bind(addr)?;
+    socket.listen(1024)
+}
+
+/// bytes of 0-RTT data accepted per connection with `TlsFiles::early_data`
+#[cfg(feature = "tls")]
+const MAX_EARLY_DATA: u32 = 16 * 1024;
+
+/// Plaintext or TLS, depending on `Config::tls`
+#[derive(Clone)]
+enum Acceptor {
+    Plain,
+    #[cfg(feature = "tls")]
+    Tls(crate::tls::TlsAcceptor),
+}
+
+impl Acceptor {
+    fn new(config: &Config) -> io::Result<Self> {
+        match config.tls {
+            None => Ok(Acceptor::Plain),
+            #[cfg(feature = "tls")]
+            Some(ref files) => {
+                let key = crate::tls::load_certified_key(&files.cert, &files.key)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+                let certificate = crate::tls::ReloadableCertificate::new(key);
+                let max_early_data = if files.early_data { MAX_EARLY_DATA } else { 0 };
+                let acceptor = crate::tls::TlsAcceptor::builder(certificate)
+                    .early_data(max_early_data)
+                    .build()
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+                Ok(Acceptor::Tls(acceptor))
+            }
+            #[cfg(not(feature = "tls"))]
+            Some(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "tls configured but the tls feature is disabled",
+            )),
+        }
+    }
+
+    async fn serve(
+        &self,
+        stream: tokio::net::TcpStream,
+        peer: std::net::SocketAddr,
+        services: Arc<Services>,
+        config: ConfigHandle,
+        drain: watch::Receiver<bool>,
+    ) {
+        match self {
+            Acceptor::Plain => {
+                serve_connection(stream, Vec::new(), Some(peer), services, config, drain).await
+            }
+            #[cfg(feature = "tls")]
+            Acceptor::Tls(acceptor) => {
+                let accepted = std::time::Instant::now();
+                let handshake_timeout = config.load().timeouts.tls_handshake;
+                let handshake = tokio::time::timeout(handshake_timeout, acceptor.accept(stream));
+                let failure = match handshake.await {
+                    Ok(Ok(mut stream)) => {
+                        let early_data = crate::tls::read_early_data(&mut stream);
+                        let peer = Some(peer);
+                        return serve_connection(stream, early_data, peer, services, config, drain)
+                            .await;
+                    }
+                    Ok(Err(err)) => HandshakeFailure::from(err),
+                    Err(_) => HandshakeFailure::Timeout,
+                };
+                let reason = CloseReason::TlsHandshake(failure);
+                close_unserved(Some(peer), accepted, reason, &services, &config);
+            }
+        }
+    }
+}
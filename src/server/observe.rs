@@ -0,0 +1,257 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use super::RequestSeq;
+use crate::protocol::{Method, ParseRequestError, StatusCode};
+
+/// One served request, as seen by `RequestObserver`s
+#[derive(Debug, Clone)]
+pub struct RequestRecord<'a> {
+    pub peer: Option<SocketAddr>,
+    pub seq: RequestSeq,
+    pub method: Method,
+    pub uri: &'a str,
+    /// the route pattern which matched, see `Handler::matched_route`
+    pub route: Option<&'a str>,
+    pub status: StatusCode,
+    /// from the first byte of the request until the response was handed to the connection
+    pub elapsed: Duration,
+    pub timings: PhaseTimings,
+    pub size: RequestSize,
+    /// bytes of the response written to the connection, fewer than it has if writing failed
+    pub written: u64,
+}
+
+/// Where the time of one request went, to tell slow clients from slow handlers.
+///
+/// In the extensions of every request the server reads, with the phases before the handler.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct PhaseTimings {
+    /// from when the connection was ready for the request until its first byte arrived, which
+    /// includes the idle time of keep-alive connections
+    pub first_byte: Duration,
+    /// reading and parsing the request line and headers
+    pub head: Duration,
+    pub body: Duration,
+    pub handler: Duration,
+    /// writing the response until it was flushed
+    pub write: Duration,
+}
+
+/// Bytes of one request as read from the connection, framing such as chunk sizes included.
+///
+/// In the extensions of every request the server reads.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct RequestSize {
+    /// request line and headers, with the empty line ending them
+    pub head: u64,
+    pub body: u64,
+}
+
+impl RequestSize {
+    pub fn total(&self) -> u64 {
+        self.head + self.body
+    }
+}
+
+/// Notified of every request a `Server` answers, e.g. to export metrics, once the response is
+/// written.
+///
+/// Runs on the request path and must not wait on I/O.
+pub trait RequestObserver: Send + Sync + 'static {
+    /// a request for `route` is about to be handled
+    fn started(&self, _route: Option<&str>) {}
+
+    fn observe(&self, record: &RequestRecord<'_>);
+
+    /// a connection was closed, after its last request was observed
+    fn closed(&self, _record: &ConnectionRecord<'_>) {}
+}
+
+/// What went wrong while handling a request, see `ErrorObserver`
+#[derive(Debug, Clone, Copy)]
+pub enum HandlerError<'a> {
+    /// the handler panicked, with the panic message unless its payload wasn't a string
+    Panic(Option<&'a str>),
+    /// the handler answered with a 5xx status
+    Status(StatusCode),
+    /// the response was framed inconsistently and replaced with a 500
+    InvalidResponse(&'a str),
+}
+
+/// One failed request, as seen by `ErrorObserver`s
+#[derive(Debug, Clone)]
+pub struct ErrorReport<'a> {
+    pub peer: Option<SocketAddr>,
+    pub seq: RequestSeq,
+    pub method: Method,
+    pub uri: &'a str,
+    pub route: Option<&'a str>,
+    /// the `X-Request-Id` header
+    pub request_id: Option<&'a str>,
+    pub error: HandlerError<'a>,
+}
+
+/// Notified whenever a handler panics or fails, e.g. to report to an error tracker.
+///
+/// A panicking handler is answered with a 500. Runs on the request path and must not wait on I/O.
+pub trait ErrorObserver: Send + Sync + 'static {
+    fn report(&self, report: &ErrorReport<'_>);
+}
+
+/// Why the server closed a connection, see `RequestObserver::closed`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum CloseReason {
+    /// the client closed the connection between requests
+    ClientClosed,
+    /// reading failed, including the client closing the connection within a request
+    ReadFailed(io::ErrorKind),
+    /// no request arrived within `Timeouts::idle`
+    IdleTimeout,
+    /// a request wasn't read within `Timeouts::request_read`, answered with 408
+    ReadTimeout,
+    /// a malformed request, answered with its error status
+    InvalidRequest(ParseRequestError),
+    /// a request beyond `Limits`, answered with its error status
+    LimitExceeded(ParseRequestError),
+    /// `Connection: close` from either side, an HTTP/1.0 client without keep-alive, or a body
+    /// delimited by closing the connection
+    Requested,
+    /// the connection served `Limits::max_requests`
+    MaxRequests,
+    /// writing a response failed or stalled for `Timeouts::write_stall`
+    WriteFailed(io::ErrorKind),
+    /// the server shut down
+    Shutdown,
+    /// the server's memory budget was exhausted, the connection answered with 503
+    Overloaded,
+    /// the TLS handshake failed, before any request was read
+    TlsHandshake(HandshakeFailure),
+    /// the connection switched protocols and was handed to the handler, see `OnUpgrade`
+    Upgraded,
+}
+
+/// Why a TLS handshake failed, see `CloseReason::TlsHandshake`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum HandshakeFailure {
+    /// not completed within `Timeouts::tls_handshake`
+    Timeout,
+    /// no certificate for the server name the client asked for
+    UnknownServerName(Option<String>),
+    /// no protocol version, cipher suite or ALPN protocol in common, or not TLS at all
+    ProtocolMismatch(String),
+    /// the client aborted the handshake with an alert
+    AlertReceived(String),
+    Io(io::ErrorKind),
+}
+
+impl CloseReason {
+    /// A short snake case name of the variant, for metrics
+    pub fn name(&self) -> &'static str {
+        match self {
+            CloseReason::ClientClosed => "client_closed",
+            CloseReason::ReadFailed(_) => "read_failed",
+            CloseReason::IdleTimeout => "idle_timeout",
+            CloseReason::ReadTimeout => "read_timeout",
+            CloseReason::InvalidRequest(_) => "invalid_request",
+            CloseReason::LimitExceeded(_) => "limit_exceeded",
+            CloseReason::Requested => "requested",
+            CloseReason::MaxRequests => "max_requests",
+            CloseReason::WriteFailed(_) => "write_failed",
+            CloseReason::Shutdown => "shutdown",
+            CloseReason::Overloaded => "overloaded",
+            CloseReason::Upgraded => "upgraded",
+            CloseReason::TlsHandshake(HandshakeFailure::Timeout) => "tls_handshake_timeout",
+            CloseReason::TlsHandshake(HandshakeFailure::UnknownServerName(_)) => {
+                "tls_unknown_server_name"
+            }
+            CloseReason::TlsHandshake(HandshakeFailure::ProtocolMismatch(_)) => {
+                "tls_protocol_mismatch"
+            }
+            CloseReason::TlsHandshake(HandshakeFailure::AlertReceived(_)) => "tls_alert_received",
+            CloseReason::TlsHandshake(HandshakeFailure::Io(_)) => "tls_handshake_failed",
+        }
+    }
+
+    /// Whether a request or response was cut short, as opposed to the connection ending
+    /// between requests
+    pub fn is_error(&self) -> bool {
+        !matches!(
+            self,
+            CloseReason::ClientClosed
+                | CloseReason::IdleTimeout
+                | CloseReason::Requested
+                | CloseReason::MaxRequests
+                | CloseReason::Shutdown
+                | CloseReason::Upgraded
+        )
+    }
+}
+
+impl Display for CloseReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CloseReason::ReadFailed(kind)
+            | CloseReason::WriteFailed(kind)
+            | CloseReason::TlsHandshake(HandshakeFailure::Io(kind)) => {
+                write!(f, "{}: {kind}", self.name())
+            }
+            CloseReason::TlsHandshake(HandshakeFailure::UnknownServerName(name)) => {
+                write!(f, "{}: {}", self.name(), name.as_deref().unwrap_or("-"))
+            }
+            CloseReason::TlsHandshake(
+                HandshakeFailure::ProtocolMismatch(reason)
+                | HandshakeFailure::AlertReceived(reason),
+            ) => write!(f, "{}: {reason}", self.name()),
+            CloseReason::InvalidRequest(err) | CloseReason::LimitExceeded(err) => {
+                write!(f, "{}: {err}", self.name())
+            }
+            _ => write!(f, "{}", self.name()),
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+impl From<crate::tls::TlsError> for HandshakeFailure {
+    fn from(value: crate::tls::TlsError) -> Self {
+        use crate::tls::TlsError;
+
+        match value {
+            TlsError::UnknownServerName(name) => HandshakeFailure::UnknownServerName(name),
+            TlsError::ProtocolMismatch(reason) => HandshakeFailure::ProtocolMismatch(reason),
+            TlsError::AlertReceived(alert) => HandshakeFailure::AlertReceived(alert),
+            TlsError::Io(kind) => HandshakeFailure::Io(kind),
+            // certificates and settings are checked before accepting
+            TlsError::InvalidCertificate(_) | TlsError::InvalidConfig(_) => {
+                HandshakeFailure::Io(io::ErrorKind::InvalidInput)
+            }
+        }
+    }
+}
+
+impl From<ParseRequestError> for CloseReason {
+    fn from(value: ParseRequestError) -> Self {
+        match value {
+            ParseRequestError::Io(kind) => CloseReason::ReadFailed(kind),
+            ParseRequestError::LineTooLong
+            | ParseRequestError::TooManyHeaders
+            | ParseRequestError::BodyTooLarge(_) => CloseReason::LimitExceeded(value),
+            _ => CloseReason::InvalidRequest(value),
+        }
+    }
+}
+
+/// One closed connection, as seen by `RequestObserver::closed`
+#[derive(Debug, Clone)]
+pub struct ConnectionRecord<'a> {
+    pub peer: Option<SocketAddr>,
+    /// see `RequestSeq`
+    pub connection: u64,
+    /// requests read on the connection
+    pub requests: u64,
+    /// from accepting the connection until it was closed
+    pub elapsed: Duration,
+    pub reason: &'a CloseReason,
+}
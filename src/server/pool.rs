@@ -0,0 +1,319 @@
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite, ReadBuf};
+
+/// free buffers kept per size, the rest are deallocated when returned
+const MAX_FREE_PER_SIZE: usize = 256;
+
+/// Read and write buffers of connections, reused across connections, and the memory all the
+/// buffers leased at once may take.
+///
+/// Clones share the pool, so several servers can draw on one budget.
+#[derive(Clone)]
+pub struct BufferPool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    /// ascending
+    sizes: Vec<usize>,
+    /// free buffers of each size
+    free: Vec<Mutex<Vec<Vec<u8>>>>,
+    budget: usize,
+    leased: AtomicUsize,
+}
+
+impl BufferPool {
+    /// Buffers of `sizes`, a lease taking the smallest that fits, with at most `budget` bytes
+    /// leased at once
+    pub fn new(sizes: &[usize], budget: usize) -> Self {
+        let mut sizes = sizes.to_vec();
+        sizes.sort_unstable();
+        sizes.dedup();
+        let free = sizes.iter().map(|_| Mutex::new(Vec::new())).collect();
+        Self { inner: Arc::new(PoolInner { sizes, free, budget, leased: AtomicUsize::new(0) }) }
+    }
+
+    /// A buffer of at least `len` bytes, `None` when it would exceed the budget.
+    ///
+    /// Requests larger than all sizes are allocated to fit, and not kept when returned.
+    pub fn lease(&self, len: usize) -> Option<Lease> {
+        let inner = &self.inner;
+        let class = inner.class(len);
+        let size = class.map_or(len, |class| inner.sizes[class]);
+        inner
+            .leased
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |leased| {
+                leased.checked_add(size).filter(|leased| *leased <= inner.budget)
+            })
+            .ok()?;
+
+        let free = class.and_then(|class| inner.free[class].lock().unwrap().pop());
+        let buf = free.unwrap_or_else(|| Vec::with_capacity(size));
+        Some(Lease { buf, size, class, pool: self.inner.clone() })
+    }
+
+    /// The size a lease of `len` bytes gets
+    pub(crate) fn size_for(&self, len: usize) -> usize {
+        self.inner.class(len).map_or(len, |class| self.inner.sizes[class])
+    }
+
+    /// Bytes currently leased
+    pub fn leased(&self) -> usize {
+        self.inner.leased.load(Ordering::Acquire)
+    }
+
+    pub fn budget(&self) -> usize {
+        self.inner.budget
+    }
+}
+
+impl PoolInner {
+    /// The smallest size `len` bytes fit in
+    fn class(&self, len: usize) -> Option<usize> {
+        self.sizes.iter().position(|size| *size >= len)
+    }
+}
+
+impl Debug for BufferPool {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("sizes", &self.inner.sizes)
+            .field("budget", &self.inner.budget)
+            .field("leased", &self.leased())
+            .finish()
+    }
+}
+
+/// A buffer of a `BufferPool`, given back when dropped. Empty when leased, with the capacity
+/// of its size.
+pub struct Lease {
+    buf: Vec<u8>,
+    size: usize,
+    class: Option<usize>,
+    pool: Arc<PoolInner>,
+}
+
+impl Lease {
+    /// The capacity leased, which the buffer shouldn't grow beyond
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+impl Deref for Lease {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.buf
+    }
+}
+
+impl DerefMut for Lease {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.buf
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.pool.leased.fetch_sub(self.size, Ordering::AcqRel);
+        let Some(class) = self.class else {
+            return;
+        };
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.clear();
+        let mut free = self.pool.free[class].lock().unwrap();
+        if free.len() < MAX_FREE_PER_SIZE && buf.capacity() == self.size {
+            free.push(buf);
+        }
+    }
+}
+
+impl Debug for Lease {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Lease").field("size", &self.size).field("len", &self.buf.len()).finish()
+    }
+}
+
+/// `BufReader` on a leased buffer, which grows while reads fill it and shrinks back to the
+/// messages read, between `min` and `max`
+pub(crate) struct PooledReader<R> {
+    inner: R,
+    pool: BufferPool,
+    /// filled up to its size
+    buf: Lease,
+    pos: usize,
+    filled: usize,
+    /// bytes read from it so far
+    consumed: u64,
+    min: usize,
+    max: usize,
+    /// most bytes a read returned since the last `shrink_to_fit`
+    peak: usize,
+}
+
+impl<R: AsyncRead + Unpin> PooledReader<R> {
+    /// Reads into a buffer of `min` bytes, `None` when the pool can't lease it
+    pub fn new(inner: R, pool: BufferPool, min: usize, max: usize) -> Option<Self> {
+        let mut buf = pool.lease(min)?;
+        let size = buf.size();
+        buf.resize(size, 0);
+        let max = max.max(min);
+        Some(Self { inner, pool, buf, pos: 0, filled: 0, consumed: 0, min, max, peak: 0 })
+    }
+
+    /// Bytes read from it so far, not counting those only buffered
+    pub fn consumed(&self) -> u64 {
+        self.consumed
+    }
+
+    /// The reader with the bytes buffered but not read yet
+    pub fn into_parts(self) -> (R, Vec<u8>) {
+        (self.inner, self.buf[self.pos..self.filled].to_vec())
+    }
+
+    /// Trades an empty buffer for a smaller one when the reads since the last call needed less
+    /// of it, as between requests of a keep-alive connection
+    pub fn shrink_to_fit(&mut self) {
+        let len = std::mem::take(&mut self.peak).max(self.min);
+        if self.pos == self.filled && self.pool.size_for(len) < self.buf.size() {
+            self.replace(len);
+        }
+    }
+
+    /// Swaps the empty buffer for one of `len` bytes, keeping it when that can't be leased
+    fn replace(&mut self, len: usize) {
+        if let Some(mut buf) = self.pool.lease(len) {
+            let size = buf.size();
+            buf.resize(size, 0);
+            self.buf = buf;
+            (self.pos, self.filled) = (0, 0);
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for PooledReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        out: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        // reads at least as large as the buffer skip it
+        if this.pos == this.filled && out.remaining() >= this.buf.len() {
+            let before = out.filled().len();
+            ready!(Pin::new(&mut this.inner).poll_read(cx, out))?;
+            this.consumed += (out.filled().len() - before) as u64;
+            return Poll::Ready(Ok(()));
+        }
+        let available = ready!(Pin::new(&mut *this).poll_fill_buf(cx))?;
+        let n = available.len().min(out.remaining());
+        out.put_slice(&available[..n]);
+        Pin::new(this).consume(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncBufRead for PooledReader<R> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        if this.pos == this.filled {
+            // the last read filled the buffer, the message is larger than it
+            let size = this.buf.size();
+            if this.filled == size && size < this.max {
+                this.replace(size.saturating_mul(2).min(this.max));
+            }
+            let mut read = ReadBuf::new(&mut this.buf[..]);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read))?;
+            this.filled = read.filled().len();
+            this.pos = 0;
+            this.peak = this.peak.max(this.filled);
+        }
+        Poll::Ready(Ok(&this.buf[this.pos..this.filled]))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        let amt = amt.min(this.filled - this.pos);
+        this.pos += amt;
+        this.consumed += amt as u64;
+    }
+}
+
+/// `BufWriter` on a leased buffer
+pub(crate) struct PooledWriter<W> {
+    inner: W,
+    buf: Lease,
+    written: u64,
+}
+
+impl<W: AsyncWrite + Unpin> PooledWriter<W> {
+    pub fn new(inner: W, buf: Lease) -> Self {
+        Self { inner, buf, written: 0 }
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// The writer, dropping what is still buffered unless it was flushed
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// Bytes written to it so far, including those still buffered
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    fn poll_flush_buf(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.buf.is_empty() {
+            match ready!(Pin::new(&mut self.inner).poll_write(cx, &self.buf))? {
+                0 => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                n => drop(self.buf.drain(..n)),
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for PooledWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let size = this.buf.size();
+        if this.buf.len() + data.len() > size {
+            ready!(this.poll_flush_buf(cx))?;
+        }
+        if data.len() >= size {
+            let n = ready!(Pin::new(&mut this.inner).poll_write(cx, data))?;
+            this.written += n as u64;
+            return Poll::Ready(Ok(n));
+        }
+        this.buf.extend_from_slice(data);
+        this.written += data.len() as u64;
+        Poll::Ready(Ok(data.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_flush_buf(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_flush_buf(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
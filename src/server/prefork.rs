@@ -0,0 +1,115 @@
+use std::io;
+use std::process::{ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+use tokio::io::AsyncReadExt;
+use tokio::process::{ChildStdin, Command};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+
+use super::{bind_listeners, Server};
+use crate::systemd::NotifyState;
+
+/// Set to the worker index in the environment of every worker process
+pub const WORKER_ENV: &str = "TOOT_WORKER";
+
+/// workers exiting sooner than this after their start are not restarted
+const MIN_WORKER_UPTIME: Duration = Duration::from_secs(1);
+
+/// Index of this process when it was started as a prefork worker
+pub fn worker_id() -> Option<usize> {
+    std::env::var(WORKER_ENV).ok().and_then(|id| id.parse().ok())
+}
+
+impl Server {
+    /// Runs the server in `workers` processes sharing `Config::bind` through `SO_REUSEPORT`.
+    ///
+    /// The calling process re-executes its own binary with the same arguments for every worker
+    /// and supervises them: a crashed worker is restarted, `SIGINT` or `SIGTERM` shuts all of
+    /// them down gracefully. Workers run the same code path, so everything before this call
+    /// happens once per process.
+    pub async fn run_prefork(self, workers: usize) -> io::Result<()> {
+        if worker_id().is_none() {
+            return supervise(workers, self.config.load().timeouts.shutdown).await;
+        }
+
+        let listeners = bind_listeners(&self.config.load(), true)?;
+        self.serve_with_shutdown(listeners, worker_shutdown()).await
+    }
+}
+
+/// Workers are told to shut down by closing their stdin
+async fn worker_shutdown() {
+    let mut stdin = tokio::io::stdin();
+    let mut buf = [0u8; 64];
+    let closed = async { while matches!(stdin.read(&mut buf).await, Ok(n) if n > 0) {} };
+
+    let terminate = async {
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => terminate.recv().await,
+            Err(_) => std::future::pending().await,
+        }
+    };
+
+    tokio::select! {
+        _ = closed => {}
+        _ = terminate => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+}
+
+type WorkerExit = (usize, Instant, io::Result<ExitStatus>);
+
+fn spawn_worker(
+    id: usize,
+    running: &mut JoinSet<WorkerExit>,
+    stdins: &mut [Option<ChildStdin>],
+) -> io::Result<()> {
+    let mut child = Command::new(std::env::current_exe()?)
+        .args(std::env::args_os().skip(1))
+        .env(WORKER_ENV, id.to_string())
+        .stdin(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    stdins[id] = child.stdin.take();
+
+    let started = Instant::now();
+    running.spawn(async move { (id, started, child.wait().await) });
+    Ok(())
+}
+
+async fn supervise(workers: usize, grace: Duration) -> io::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut running = JoinSet::new();
+    let mut stdins = (0..workers).map(|_| None).collect::<Vec<_>>();
+    for id in 0..workers {
+        spawn_worker(id, &mut running, &mut stdins)?;
+    }
+    let _ = crate::systemd::notify(&[NotifyState::Ready]);
+
+    let result = loop {
+        let exited = tokio::select! {
+            _ = tokio::signal::ctrl_c() => break Ok(()),
+            _ = terminate.recv() => break Ok(()),
+            exited = running.join_next() => exited,
+        };
+        let Some(Ok((id, started, status))) = exited else {
+            break Ok(());
+        };
+        if started.elapsed() < MIN_WORKER_UPTIME {
+            let status = status.map(|status| status.to_string()).unwrap_or_else(|e| e.to_string());
+            break Err(io::Error::other(format!("worker {id} exited on startup: {status}")));
+        }
+        if let Err(err) = spawn_worker(id, &mut running, &mut stdins) {
+            break Err(err);
+        }
+    };
+
+    let _ = crate::systemd::notify(&[NotifyState::Stopping]);
+    // closing the pipes asks every worker to drain, stragglers are killed on drop
+    stdins.clear();
+    let _ = timeout(grace, async { while running.join_next().await.is_some() {} }).await;
+    running.shutdown().await;
+    result
+}
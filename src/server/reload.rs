@@ -0,0 +1,48 @@
+use std::sync::{Arc, RwLock};
+
+use super::Config;
+
+/// Shared, swappable server configuration.
+///
+/// Connections read the current `Config` before every request, so new limits and timeouts apply
+/// without restarting listeners. Bind addresses, TLS files and `max_connections` are only read
+/// when the server starts.
+#[derive(Debug, Clone)]
+pub struct ConfigHandle {
+    current: Arc<RwLock<Arc<Config>>>,
+}
+
+impl ConfigHandle {
+    pub fn new(config: Config) -> Self {
+        Self { current: Arc::new(RwLock::new(Arc::new(config))) }
+    }
+
+    pub fn load(&self) -> Arc<Config> {
+        self.current.read().unwrap().clone()
+    }
+
+    pub fn store(&self, config: Config) {
+        *self.current.write().unwrap() = Arc::new(config);
+    }
+
+    /// Re-reads `path` (plus `TOOT_*` overrides) whenever the process receives `SIGHUP`.
+    ///
+    /// A file which fails to load leaves the current configuration in place.
+    #[cfg(all(unix, feature = "config"))]
+    pub fn reload_on_sighup<P>(&self, path: P) -> std::io::Result<tokio::task::JoinHandle<()>>
+    where
+        P: AsRef<std::path::Path> + Send + 'static,
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangups = signal(SignalKind::hangup())?;
+        let handle = self.clone();
+        Ok(tokio::spawn(async move {
+            while hangups.recv().await.is_some() {
+                if let Ok(config) = Config::from_file(path.as_ref()).and_then(Config::merge_env) {
+                    handle.store(config);
+                }
+            }
+        }))
+    }
+}
@@ -0,0 +1,211 @@
+use std::sync::Arc;
+
+use super::{BoxFuture, Guard, Handler};
+use crate::protocol::{
+    Header, Headers, HttpVersion, Method, RawRequest, RawResponse, StatusCode, StatusLine, CRLF,
+};
+
+struct Route {
+    /// exact path, or a prefix when it ends with `*`
+    path: String,
+    handlers: Vec<Endpoint>,
+}
+
+struct Endpoint {
+    method: Method,
+    handler: Arc<dyn Handler>,
+    guards: Vec<Arc<dyn Guard>>,
+}
+
+impl Endpoint {
+    fn permits(&self, request: &RawRequest) -> bool {
+        self.guards.iter().all(|guard| guard.check(request))
+    }
+}
+
+impl Route {
+    fn matches(&self, path: &str) -> bool {
+        match self.path.strip_suffix('*') {
+            Some(prefix) => path.starts_with(prefix),
+            None => self.path == path,
+        }
+    }
+
+    fn endpoint(&self, method: Method) -> Option<&Endpoint> {
+        self.handlers.iter().find(|endpoint| endpoint.method == method)
+    }
+
+    /// `HEAD` is answered by `GET` handlers and `OPTIONS` by the router when not routed
+    fn allowed(&self) -> Vec<Method> {
+        let mut methods = self.handlers.iter().map(|endpoint| endpoint.method).collect::<Vec<_>>();
+        if methods.contains(&Method::GET) && !methods.contains(&Method::HEAD) {
+            methods.push(Method::HEAD);
+        }
+        if !methods.contains(&Method::OPTIONS) {
+            methods.push(Method::OPTIONS);
+        }
+        methods
+    }
+}
+
+/// Dispatches requests by path and method.
+///
+/// Unrouted paths get 404. A routed path answers methods without a handler with 405 and an
+/// `Allow` header, unless no route of the router handles that method at all, which is answered
+/// with 501. `OPTIONS` lists the allowed methods and `HEAD` runs the `GET` handler without its
+/// body, both unless routed explicitly. Requests rejected by a guard of their handler get 403.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    /// path and method of the last `route`, for `guard`
+    last: Option<(String, Method)>,
+    trace: bool,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Routes `method` requests for `path`, a trailing `*` matches any suffix.
+    /// Earlier routes take precedence.
+    pub fn route<H: Handler>(mut self, method: Method, path: &str, handler: H) -> Self {
+        let endpoint = Endpoint { method, handler: Arc::new(handler), guards: Vec::new() };
+        match self.routes.iter_mut().find(|route| route.path == path) {
+            Some(route) => route.handlers.push(endpoint),
+            None => self.routes.push(Route { path: path.to_owned(), handlers: vec![endpoint] }),
+        }
+        self.last = Some((path.to_owned(), method));
+        self
+    }
+
+    pub fn get<H: Handler>(self, path: &str, handler: H) -> Self {
+        self.route(Method::GET, path, handler)
+    }
+
+    pub fn post<H: Handler>(self, path: &str, handler: H) -> Self {
+        self.route(Method::POST, path, handler)
+    }
+
+    pub fn put<H: Handler>(self, path: &str, handler: H) -> Self {
+        self.route(Method::PUT, path, handler)
+    }
+
+    pub fn delete<H: Handler>(self, path: &str, handler: H) -> Self {
+        self.route(Method::DELETE, path, handler)
+    }
+
+    /// Guards the handler routed last, all of its guards have to pass.
+    ///
+    /// # Panics
+    ///
+    /// When nothing has been routed yet.
+    pub fn guard<G: Guard>(mut self, guard: G) -> Self {
+        let (path, method) = self.last.clone().expect("guard needs a preceding route");
+        let route = self.routes.iter_mut().find(|route| route.path == path);
+        let endpoint =
+            route.and_then(|route| route.handlers.iter_mut().find(|e| e.method == method));
+        endpoint.expect("routed last").guards.push(Arc::new(guard));
+        self
+    }
+
+    /// Answers `TRACE` for any path with `trace_echo`, otherwise it gets 501
+    pub fn enable_trace(mut self) -> Self {
+        self.trace = true;
+        self
+    }
+
+    /// Methods handled by any route
+    fn implements(&self, method: Method) -> bool {
+        matches!(method, Method::HEAD | Method::OPTIONS)
+            || self.routes.iter().any(|route| route.endpoint(method).is_some())
+    }
+}
+
+impl Handler for Router {
+    fn call(&self, request: RawRequest) -> BoxFuture<'static, RawResponse> {
+        let method = request.request_line.method;
+        if method == Method::TRACE && self.trace {
+            let response = trace_echo(&request);
+            return Box::pin(async { response });
+        }
+        let path = request.request_line.target.path();
+        let Some(route) = self.routes.iter().find(|route| route.matches(path)) else {
+            return Box::pin(async { empty_response(StatusCode::NOT_FOUND, Headers::empty()) });
+        };
+
+        let endpoint = match method {
+            Method::HEAD => route.endpoint(method).or_else(|| route.endpoint(Method::GET)),
+            _ => route.endpoint(method),
+        };
+        if let Some(endpoint) = endpoint {
+            if !endpoint.permits(&request) {
+                return Box::pin(async { empty_response(StatusCode::FORBIDDEN, Headers::empty()) });
+            }
+            if endpoint.method == method {
+                return endpoint.handler.call(request);
+            }
+            let mut request = request;
+            request.request_line.method = Method::GET;
+            let response = endpoint.handler.call(request);
+            return Box::pin(async move { response.await.without_body() });
+        }
+        if method == Method::OPTIONS {
+            let headers = allow_headers(route);
+            return Box::pin(async { empty_response(StatusCode::NO_CONTENT, headers) });
+        }
+
+        let response = if self.implements(method) {
+            empty_response(StatusCode::METHOD_NOT_ALLOWED, allow_headers(route))
+        } else {
+            empty_response(StatusCode::NOT_IMPLEMENTED, Headers::empty())
+        };
+        Box::pin(async { response })
+    }
+
+    fn matched_route(&self, request: &RawRequest) -> Option<&str> {
+        if request.request_line.method == Method::TRACE && self.trace {
+            return None;
+        }
+        let path = request.request_line.target.path();
+        self.routes.iter().find(|route| route.matches(path)).map(|route| route.path.as_str())
+    }
+}
+
+/// header values replaced by `trace_echo`
+pub const TRACE_REDACTED_HEADERS: &[&str] =
+    &["Authorization", "Proxy-Authorization", "Cookie", "X-Api-Key"];
+
+/// Echoes the request head back as a `message/http` body, as `TRACE` asks for.
+///
+/// Values of `TRACE_REDACTED_HEADERS` are replaced so credentials don't leak to scripts able
+/// to send `TRACE` on a user's behalf.
+pub fn trace_echo(request: &RawRequest) -> RawResponse {
+    let mut echo = request.request_line.to_http_message();
+    for header in request.headers.iter() {
+        let redacted =
+            TRACE_REDACTED_HEADERS.iter().any(|h| h.eq_ignore_ascii_case(header.field()));
+        let value = if redacted { "[redacted]" } else { header.value() };
+        echo.push_str(&Header::new(header.field(), value).to_http_message());
+    }
+    echo.push_str(CRLF);
+
+    let mut headers = Headers::empty();
+    headers.set("Content-Type", "message/http".to_owned());
+    let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::OK);
+    RawResponse::new(status_line, headers, Some(echo.into_bytes()))
+}
+
+fn allow_headers(route: &Route) -> Headers {
+    let allowed = route.allowed().iter().map(Method::as_str).collect::<Vec<_>>().join(", ");
+    let mut headers = Headers::empty();
+    headers.set("Allow", allowed);
+    headers
+}
+
+fn empty_response(status: StatusCode, headers: Headers) -> RawResponse {
+    let status_line = StatusLine::new(HttpVersion::Http1_1, status);
+    // 204 carries neither a body nor a `Content-Length`
+    let body = (status != StatusCode::NO_CONTENT).then(Vec::new);
+    RawResponse::new(status_line, headers, body)
+}
@@ -0,0 +1,1337 @@
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use super::*;
+use crate::protocol::{
+    Body, Headers, HttpVersion, Method, ParseRequestError, StatusCode, StatusLine,
+};
+
+async fn hello(request: RawRequest) -> RawResponse {
+    let body = format!("hello {}", request.request_line.target);
+    RawResponse::new(
+        StatusLine::new(HttpVersion::Http1_1, StatusCode::OK),
+        Headers::empty(),
+        Some(body.into_bytes()),
+    )
+}
+
+#[tokio::test]
+pub async fn test_server_keep_alive() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(Server::new(hello).serve(vec![listener]));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let expected =
+        "HTTP/1.1 200 OK\r\nContent-Length: 8\r\nKeep-Alive: timeout=60, max=999\r\n\r\n\
+                    hello /a\
+                    HTTP/1.1 200 OK\r\nContent-Length: 8\r\n\r\nhello /b";
+    assert_eq!(expected, response);
+}
+
+#[tokio::test]
+pub async fn test_server_closes_idle_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut config = Config::default();
+    config.timeouts.idle = Duration::from_millis(20);
+    tokio::spawn(Server::from_config(config, hello).serve(vec![listener]));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let mut buf = Vec::new();
+    assert_eq!(0, stream.read_to_end(&mut buf).await.unwrap());
+}
+
+#[test]
+pub fn test_config_merge_vars() {
+    let vars = |name: &str| match name {
+        "TOOT_BIND" => Some("0.0.0.0:80, [::]:80".to_owned()),
+        "TOOT_IDLE_TIMEOUT" => Some("1.5".to_owned()),
+        "TOOT_LOG_FORMAT" => Some("json".to_owned()),
+        _ => None,
+    };
+    let config = Config::default().merge_vars(vars).unwrap();
+
+    assert_eq!(
+        vec!["0.0.0.0:80".parse::<std::net::SocketAddr>().unwrap(), "[::]:80".parse().unwrap()],
+        config.bind
+    );
+    assert_eq!(Duration::from_millis(1500), config.timeouts.idle);
+    assert_eq!(LogFormat::Json, config.log_format);
+
+    let invalid = Config::default()
+        .merge_vars(|name| (name == "TOOT_MAX_CONNECTIONS").then(|| "many".to_owned()));
+    assert_eq!(
+        Err(ConfigError::Env("TOOT_MAX_CONNECTIONS".to_owned(), "many".to_owned())),
+        invalid
+    );
+}
+
+#[cfg(feature = "config")]
+#[test]
+pub fn test_config_from_toml_and_json() {
+    let toml = r#"
+        bind = ["127.0.0.1:3000"]
+        log_format = "json"
+
+        [timeouts]
+        idle = 5
+
+        [[static_mounts]]
+        prefix = "/assets"
+        dir = "./public"
+    "#;
+    let config = Config::from_toml_str(toml).unwrap();
+    assert_eq!(vec!["127.0.0.1:3000".parse::<std::net::SocketAddr>().unwrap()], config.bind);
+    assert_eq!(Duration::from_secs(5), config.timeouts.idle);
+    assert_eq!(Timeouts::default().request_read, config.timeouts.request_read);
+    assert_eq!("/assets", config.static_mounts[0].prefix);
+
+    let json = r#"{"limits": {"max_body_len": 1024}, "tls": {"cert": "c.pem", "key": "k.pem"}}"#;
+    let config = Config::from_json_str(json).unwrap();
+    assert_eq!(1024, config.limits.max_body_len);
+    let tls = TlsFiles { cert: "c.pem".into(), key: "k.pem".into(), early_data: false };
+    assert_eq!(Some(tls), config.tls);
+
+    assert!(matches!(Config::from_toml_str("unknown = 1"), Err(ConfigError::Parse(_))));
+}
+
+#[tokio::test]
+pub async fn test_config_handle_applies_to_new_requests() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::new(hello);
+    let handle = server.config_handle();
+    tokio::spawn(server.serve(vec![listener]));
+
+    let mut config = (*handle.load()).clone();
+    config.limits.max_line_len = 8;
+    handle.store(config);
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"GET /longer-than-eight HTTP/1.1\r\n\r\n").await.unwrap();
+    let mut buf = Vec::new();
+    stream.read_to_end(&mut buf).await.unwrap();
+    assert!(buf.starts_with(b"HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+}
+
+#[tokio::test]
+pub async fn test_parse_error_responses() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(Server::new(hello).serve(vec![listener]));
+
+    let respond = |request: &'static [u8]| async move {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request).await.unwrap();
+        stream.shutdown().await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    };
+    assert_eq!(
+        "HTTP/1.1 501 Not Implemented\r\nConnection: close\r\nContent-Length: 0\r\n\r\n",
+        respond(b"BREW /pot HTTP/1.1\r\n\r\n").await
+    );
+    let status_line = |response: String| response.lines().next().unwrap_or_default().to_owned();
+    assert_eq!(
+        "HTTP/1.1 505 HTTP Version Not Supported",
+        status_line(respond(b"GET / HTTP/2.0\r\n\r\n").await)
+    );
+    assert_eq!("HTTP/1.1 400 Bad Request", status_line(respond(b"GET / HTTX\r\n\r\n").await));
+    assert_eq!(
+        "HTTP/1.1 400 Bad Request",
+        status_line(respond(b"GET / HTTP/1.1\r\nno colon\r\n\r\n").await)
+    );
+    assert_eq!(
+        "HTTP/1.1 413 Payload Too Large",
+        status_line(respond(b"POST / HTTP/1.1\r\nContent-Length: 99999999\r\n\r\n").await)
+    );
+    assert_eq!("", respond(b"GET / HTTP/1.1\r\nHost: a").await);
+}
+
+#[tokio::test]
+pub async fn test_server_static_mounts() {
+    let dir = std::env::temp_dir().join(format!("toot-mount-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("app.js"), "run()").unwrap();
+    let file = std::fs::File::options().write(true).open(dir.join("app.js")).unwrap();
+    file.set_modified(std::time::UNIX_EPOCH + Duration::from_secs(1)).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut config = Config::default();
+    config.static_mounts.push(StaticMount { prefix: "/assets/".to_owned(), dir: dir.clone() });
+    let server = Server::from_config(config, hello).file_cache(FileCache::new());
+    tokio::spawn(server.serve(vec![listener]));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET /assets/app.js HTTP/1.1\r\n\r\nGET /assetsx HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let expected = "HTTP/1.1 200 OK\r\nContent-Type: text/javascript; charset=utf-8\r\n\
+                    Accept-Ranges: bytes\r\nETag: \"5-3b9aca00\"\r\nContent-Length: 5\r\nKeep-Alive: timeout=60, max=999\r\n\r\nrun()\
+                    HTTP/1.1 200 OK\r\nContent-Length: 14\r\n\r\nhello /assetsx";
+    assert_eq!(expected, response);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+pub async fn test_access_log_format() {
+    let request_line = "GET /a?q=\"x\" HTTP/1.1".parse().unwrap();
+    let entry = log::AccessLogEntry {
+        peer: Some("127.0.0.1:4000".parse().unwrap()),
+        seq: RequestSeq { connection: 3, sequence: 2 },
+        request_line: &request_line,
+        status: StatusCode::NOT_FOUND,
+        elapsed: Duration::from_micros(1500),
+        read: 40,
+        written: 96,
+    };
+
+    assert_eq!(
+        "127.0.0.1:4000 #3.2 \"GET /a?q=\"x\" HTTP/1.1\" 404 1500us 40B in 96B out",
+        entry.format(LogFormat::Text)
+    );
+    assert_eq!(
+        r#"{"peer":"127.0.0.1:4000","connection":3,"sequence":2,"method":"GET","uri":"/a?q=\"x\"","version":"HTTP/1.1","status":404,"micros":1500,"bytes_in":40,"bytes_out":96}"#,
+        entry.format(LogFormat::Json)
+    );
+
+    let reason = CloseReason::ReadTimeout;
+    let record = ConnectionRecord {
+        peer: None,
+        connection: 3,
+        requests: 2,
+        elapsed: Duration::from_micros(2500),
+        reason: &reason,
+    };
+    let entry = log::ConnectionLogEntry { record: &record };
+    assert_eq!("- #3 closed: read_timeout after 2 requests 2500us", entry.format(LogFormat::Text));
+    assert_eq!(
+        r#"{"peer":"-","connection":3,"requests":2,"close":"read_timeout","micros":2500}"#,
+        entry.format(LogFormat::Json)
+    );
+}
+
+#[tokio::test]
+pub async fn test_serve_with_shutdown_drains_connections() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (shutdown, signal) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(Server::new(hello).serve_with_shutdown(vec![listener], async {
+        let _ = signal.await;
+    }));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"GET /a HTTP/1.1\r\n\r\n").await.unwrap();
+    let mut buf = [0u8; 128];
+    let n = stream.read(&mut buf).await.unwrap();
+    assert!(buf[..n].ends_with(b"hello /a"));
+
+    shutdown.send(()).unwrap();
+    tokio::time::timeout(Duration::from_secs(1), server).await.unwrap().unwrap().unwrap();
+    assert_eq!(0, stream.read(&mut buf).await.unwrap());
+    assert!(TcpStream::connect(addr).await.is_err());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+pub async fn test_bind_reuse_port() {
+    let first = bind_reuse_port("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = first.local_addr().unwrap();
+    let second = bind_reuse_port(addr).unwrap();
+    assert_eq!(addr, second.local_addr().unwrap());
+    assert!(TcpListener::bind(addr).await.is_err());
+}
+
+#[cfg(unix)]
+#[test]
+pub fn test_inherited_fd_count() {
+    assert_eq!(0, handoff::inherited_fd_count(None).unwrap());
+    assert_eq!(2, handoff::inherited_fd_count(Some("2")).unwrap());
+    assert!(handoff::inherited_fd_count(Some("two")).is_err());
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+pub async fn test_spawn_with_listeners() {
+    use std::os::fd::AsRawFd;
+
+    let first = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let second = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut command = tokio::process::Command::new("sh");
+    command
+        .args(["-c", "echo $TOOT_LISTEN_FDS; readlink /proc/self/fd/3 /proc/self/fd/4"])
+        .stdout(std::process::Stdio::piped());
+
+    let child = spawn_with_listeners(command, &[first.as_raw_fd(), second.as_raw_fd()]).unwrap();
+    let output = child.wait_with_output().await.unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert_eq!("2", lines[0]);
+    assert!(lines[1..].iter().all(|line| line.starts_with("socket:")), "{stdout}");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+pub async fn test_accept_shards() {
+    let config = Config {
+        bind: vec!["127.0.0.1:0".parse().unwrap()],
+        accept_shards: 3,
+        ..Default::default()
+    };
+
+    let listeners = bind_listeners(&config, false).unwrap();
+    assert_eq!(3, listeners.len());
+    let addr = listeners[0].local_addr().unwrap();
+    assert!(listeners.iter().all(|listener| listener.local_addr().unwrap() == addr));
+
+    tokio::spawn(Server::from_config(config, hello).serve(listeners));
+    for _ in 0..6 {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET /s HTTP/1.1\r\nConnection: close\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.ends_with("hello /s"));
+    }
+}
+
+#[tokio::test]
+pub async fn test_stalled_writes_time_out() {
+    let (client, server) = tokio::io::duplex(16);
+    let mut writer = stall::StallTimeout::new(server, Duration::from_millis(30));
+
+    let err = writer.write_all(&[0u8; 64]).await.unwrap_err();
+    assert_eq!(std::io::ErrorKind::TimedOut, err.kind());
+
+    // progress on every write keeps the connection alive
+    let reader = tokio::spawn(async move {
+        let mut client = client;
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.map(|_| buf.len())
+    });
+    writer.set_stall(Duration::from_secs(5));
+    writer.write_all(&[0u8; 64]).await.unwrap();
+    writer.shutdown().await.unwrap();
+    drop(writer);
+    assert!(reader.await.unwrap().unwrap() >= 64);
+}
+
+#[tokio::test]
+pub async fn test_server_replaces_misframed_responses() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let no_content = |_: RawRequest| async {
+        let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::NO_CONTENT);
+        RawResponse::new(status_line, Headers::empty(), Some(b"oops".to_vec()))
+    };
+    tokio::spawn(Server::new(no_content).serve(vec![listener]));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert_eq!("HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n", response);
+}
+
+fn request(request_line: &str) -> RawRequest {
+    RawRequest {
+        request_line: request_line.parse().unwrap(),
+        headers: Headers::empty(),
+        body: None,
+        extensions: Default::default(),
+    }
+}
+
+#[tokio::test]
+pub async fn test_router_methods() {
+    let router = Router::new()
+        .route(Method::GET, "/items", hello)
+        .route(Method::POST, "/items", hello)
+        .route(Method::DELETE, "/admin/*", hello);
+    let call = |request_line: &str| {
+        let response = router.call(request(request_line));
+        async { String::from_utf8(response.await.into_vec()).unwrap() }
+    };
+
+    assert!(call("GET /items?page=2 HTTP/1.1").await.ends_with("hello /items?page=2"));
+    assert!(call("DELETE /admin/users/1 HTTP/1.1").await.starts_with("HTTP/1.1 200 OK"));
+    assert_eq!("HTTP/1.1 200 OK\r\nContent-Length: 12\r\n\r\n", call("HEAD /items HTTP/1.1").await);
+    assert_eq!(
+        "HTTP/1.1 405 Method Not Allowed\r\nAllow: GET, POST, HEAD, OPTIONS\r\nContent-Length: 0\r\n\r\n",
+        call("DELETE /items HTTP/1.1").await
+    );
+    assert_eq!(
+        "HTTP/1.1 204 No Content\r\nAllow: GET, POST, HEAD, OPTIONS\r\n\r\n",
+        call("OPTIONS /items HTTP/1.1").await
+    );
+    assert_eq!(
+        "HTTP/1.1 501 Not Implemented\r\nContent-Length: 0\r\n\r\n",
+        call("TRACE /items HTTP/1.1").await
+    );
+    assert!(call("GET /missing HTTP/1.1").await.starts_with("HTTP/1.1 404 Not Found"));
+}
+
+#[tokio::test]
+pub async fn test_router_trace_echo() {
+    let mut trace = request("TRACE /items HTTP/1.1");
+    trace.headers.set("Host", "example.com".to_owned());
+    trace.headers.set("Cookie", "session=secret".to_owned());
+
+    let response =
+        Router::new().route(Method::GET, "/items", hello).enable_trace().call(trace).await;
+    let expected = "HTTP/1.1 200 OK\r\nContent-Type: message/http\r\nContent-Length: 64\r\n\r\n\
+                    TRACE /items HTTP/1.1\r\nHost: example.com\r\nCookie: [redacted]\r\n\r\n";
+    assert_eq!(expected, String::from_utf8(response.into_vec()).unwrap());
+}
+
+#[tokio::test]
+pub async fn test_router_guards() {
+    let router = Router::new()
+        .get("/reports", hello)
+        .guard(RequireRole("admin").or(RequireRole("auditor")))
+        .delete("/reports", hello)
+        .guard(RequireRole("admin"))
+        .guard(|request: &RawRequest| request.headers.get("X-Confirm").is_some())
+        .post("/feedback", hello)
+        .guard(RequireRole("banned").not());
+    let call = |roles: &[&str], request_line: &str, confirm: bool| {
+        let mut request = request(request_line);
+        request.extensions.insert(Roles(roles.iter().map(|r| r.to_string()).collect()));
+        if confirm {
+            request.headers.set("X-Confirm", "yes".to_owned());
+        }
+        let response = router.call(request);
+        async { String::from_utf8(response.await.into_vec()).unwrap() }
+    };
+    let forbidden = "HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n";
+
+    assert!(call(&["auditor"], "GET /reports HTTP/1.1", false).await.starts_with("HTTP/1.1 200"));
+    assert_eq!(forbidden, call(&["user"], "GET /reports HTTP/1.1", false).await);
+    assert_eq!(forbidden, call(&["user"], "HEAD /reports HTTP/1.1", false).await);
+    assert_eq!(forbidden, call(&["admin"], "DELETE /reports HTTP/1.1", false).await);
+    assert!(call(&["admin"], "DELETE /reports HTTP/1.1", true).await.starts_with("HTTP/1.1 200"));
+    assert!(call(&[], "POST /feedback HTTP/1.1", false).await.starts_with("HTTP/1.1 200"));
+    assert_eq!(forbidden, call(&["banned"], "POST /feedback HTTP/1.1", false).await);
+    assert!(call(&[], "OPTIONS /reports HTTP/1.1", false).await.starts_with("HTTP/1.1 204"));
+}
+
+#[derive(Clone, Default)]
+struct CollectSink(Arc<std::sync::Mutex<Vec<String>>>);
+
+impl LogSink for CollectSink {
+    fn write(&self, line: &str) {
+        self.0.lock().unwrap().push(line.to_owned());
+    }
+}
+
+#[tokio::test]
+pub async fn test_log_sinks() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let sink = CollectSink::default();
+    let config = Config { access_log: true, ..Default::default() };
+    let server = Server::from_config(config, hello).log_sink(ChannelSink::new(sink.clone(), 16));
+    tokio::spawn(server.serve(vec![listener]));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"GET /logged HTTP/1.1\r\nConnection: close\r\n\r\n").await.unwrap();
+    stream.read_to_end(&mut Vec::new()).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    let lines = sink.0.lock().unwrap().clone();
+    assert_eq!(1, lines.len());
+    assert!(lines[0].contains("\"GET /logged HTTP/1.1\" 200 "), "{}", lines[0]);
+
+    let dir = std::env::temp_dir().join(format!("toot-log-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let rotating = RotatingFile::new(dir.join("access.log"), 10, 2);
+    for line in ["one", "two", "three", "four", "five"] {
+        rotating.write(line);
+    }
+    let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+    assert_eq!(
+        ("four\nfive\n", "three\n", "one\ntwo\n"),
+        (&*read("access.log"), &*read("access.log.1"), &*read("access.log.2"))
+    );
+    assert_eq!(0, rotating.errors());
+    std::fs::remove_dir_all(&dir).unwrap();
+    rotating.write("rotates into a removed directory");
+    assert_eq!(1, rotating.errors());
+}
+
+#[derive(Clone, Default)]
+struct CollectStatuses(Arc<std::sync::Mutex<Vec<(String, u16)>>>);
+
+impl RequestObserver for CollectStatuses {
+    fn observe(&self, record: &RequestRecord<'_>) {
+        self.0.lock().unwrap().push((record.uri.to_owned(), *record.status));
+    }
+}
+
+#[tokio::test]
+pub async fn test_request_observers() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let observer = CollectStatuses::default();
+    let router = Router::new().get("/a", hello);
+    tokio::spawn(Server::new(router).observer(observer.clone()).serve(vec![listener]));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    stream.read_to_end(&mut Vec::new()).await.unwrap();
+    let observed = observer.0.lock().unwrap().clone();
+    assert_eq!(vec![("/a".to_owned(), 200), ("/b".to_owned(), 404)], observed);
+}
+
+#[tokio::test]
+pub async fn test_server_stats() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::new(Router::new().get("/users/*", hello));
+    let stats = server.stats();
+    tokio::spawn(server.serve(vec![listener]));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET /users/1 HTTP/1.1\r\n\r\nPOST /users/2 HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    stream.read_to_end(&mut Vec::new()).await.unwrap();
+    let users = stats.route("/users/*").unwrap();
+    assert_eq!((2, 0), (users.requests, users.active));
+    assert_eq!(vec![(200, 1), (501, 1)], users.statuses.into_iter().collect::<Vec<_>>());
+    assert_eq!(Some(&1), stats.route(crate::metrics::UNROUTED).unwrap().statuses.get(&404));
+}
+
+#[derive(Clone, Default)]
+struct CollectErrors(Arc<std::sync::Mutex<Vec<String>>>);
+
+impl ErrorObserver for CollectErrors {
+    fn report(&self, report: &ErrorReport<'_>) {
+        let error = match report.error {
+            HandlerError::Panic(message) => format!("panic {}", message.unwrap_or_default()),
+            HandlerError::Status(status) => format!("status {}", *status),
+            HandlerError::InvalidResponse(err) => format!("invalid {err}"),
+        };
+        let (route, id) = (report.route.unwrap_or("-"), report.request_id.unwrap_or("-"));
+        self.0.lock().unwrap().push(format!("{} {route} {id}: {error}", report.uri));
+    }
+}
+
+async fn failing(request: RawRequest) -> RawResponse {
+    if request.request_line.target.path() == "/fail/panic" {
+        panic!("handler bug");
+    }
+    let status = StatusLine::new(HttpVersion::Http1_1, StatusCode::ServiceUnavailable);
+    RawResponse::new(status, Headers::empty(), Some(Vec::new()))
+}
+
+#[tokio::test]
+pub async fn test_error_observers() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let errors = CollectErrors::default();
+    let router = Router::new().get("/fail/*", failing).get("/ok", hello);
+    tokio::spawn(Server::new(router).error_observer(errors.clone()).serve(vec![listener]));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(
+            b"GET /fail/panic HTTP/1.1\r\nX-Request-Id: r1\r\n\r\nGET /fail/busy HTTP/1.1\r\n\r\n\
+              GET /ok HTTP/1.1\r\nConnection: close\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let statuses = response.lines().filter(|line| line.starts_with("HTTP/1.1")).collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            "HTTP/1.1 500 Internal Server Error",
+            "HTTP/1.1 503 Service Unavailable",
+            "HTTP/1.1 200 OK"
+        ],
+        statuses
+    );
+    assert_eq!(
+        vec!["/fail/panic /fail/* r1: panic handler bug", "/fail/busy /fail/* -: status 503"],
+        errors.0.lock().unwrap().clone()
+    );
+}
+
+#[tokio::test]
+pub async fn test_error_pages() {
+    let accept = |value: &str| {
+        let mut headers = Headers::empty();
+        headers.set("Accept", value.to_owned());
+        ErrorFormat::negotiate(&headers)
+    };
+    assert_eq!(ErrorFormat::Html, ErrorFormat::negotiate(&Headers::empty()));
+    assert_eq!(ErrorFormat::Html, accept("text/html,application/xhtml+xml,*/*;q=0.8"));
+    assert_eq!(ErrorFormat::Json, accept("application/json"));
+    assert_eq!(ErrorFormat::Json, accept("text/*;q=0.5, application/*"));
+    assert_eq!(ErrorFormat::Html, accept("*/*"));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::new(Router::new().get("/a", hello)).error_page(
+        StatusCode::NOT_FOUND,
+        |status: StatusCode, format| match format {
+            ErrorFormat::Html => format!("<h1>{}</h1>", status.default_reason_phrase()),
+            ErrorFormat::Json => format!("{{\"status\":{}}}", *status),
+        },
+    );
+    tokio::spawn(server.serve(vec![listener]));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(
+            b"GET /b HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\nAccept: application/json\r\n\r\n\
+              HEAD /b HTTP/1.1\r\n\r\nPOST /a HTTP/1.1\r\nConnection: close\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let expected = "HTTP/1.1 404 Not Found\r\nContent-Length: 18\r\n\
+                    Content-Type: text/html; charset=utf-8\r\nKeep-Alive: timeout=60, max=999\r\n\
+                    \r\n<h1>Not Found</h1>\
+                    HTTP/1.1 404 Not Found\r\nContent-Length: 14\r\n\
+                    Content-Type: application/json\r\nKeep-Alive: timeout=60, max=998\r\n\
+                    \r\n{\"status\":404}\
+                    HTTP/1.1 404 Not Found\r\nContent-Length: 18\r\n\
+                    Content-Type: text/html; charset=utf-8\r\nKeep-Alive: timeout=60, max=997\r\n\
+                    \r\n\
+                    HTTP/1.1 501 Not Implemented\r\nContent-Length: 0\r\n\r\n";
+    assert_eq!(expected, response);
+}
+
+#[tokio::test]
+pub async fn test_connection_options() {
+    async fn echo_headers(request: RawRequest) -> RawResponse {
+        let mut headers = Headers::empty();
+        if request.request_line.target.path() == "/close" {
+            headers.set("Connection", "close".to_owned());
+        }
+        let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::OK);
+        RawResponse::new(status_line, headers, Some(request.headers.to_http_message().into()))
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(Server::new(echo_headers).serve(vec![listener]));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(
+            b"GET /a HTTP/1.0\r\nConnection: keep-alive\r\nKeep-Alive: timeout=5\r\n\r\n\
+              GET /b HTTP/1.1\r\nConnection: X-Hop, TE\r\nX-Hop: 1\r\nTE: deflate;q=0.5, trailers\r\n\
+              X-End: 2\r\n\r\n\
+              GET /c HTTP/1.1\r\nTE: gzip\r\n\r\n\
+              GET /close HTTP/1.1\r\n\r\nGET /unanswered HTTP/1.1\r\n\r\n",
+        )
+        .await
+        .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let expected = "HTTP/1.1 200 OK\r\nContent-Length: 24\r\nConnection: keep-alive\r\n\
+                    Keep-Alive: timeout=60, max=999\r\n\r\n\
+                    Connection: keep-alive\r\n\
+                    HTTP/1.1 200 OK\r\nContent-Length: 47\r\nKeep-Alive: timeout=60, max=998\r\n\r\n\
+                    Connection: X-Hop, TE\r\nX-End: 2\r\nTE: trailers\r\n\
+                    HTTP/1.1 200 OK\r\nContent-Length: 0\r\nKeep-Alive: timeout=60, max=997\r\n\r\n\
+                    HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 0\r\n\r\n";
+    assert_eq!(expected, response);
+}
+
+#[tokio::test]
+pub async fn test_streamed_response_bodies() {
+    async fn stream(request: RawRequest) -> RawResponse {
+        let len = (request.request_line.target.path() == "/sized").then_some(5);
+        let body = Body::from_reader(&b"hello world"[..], len);
+        RawResponse::streaming(
+            StatusLine::new(HttpVersion::Http1_1, StatusCode::OK),
+            Headers::empty(),
+            body,
+        )
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(Server::new(stream).serve(vec![listener]));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"GET /sized HTTP/1.1\r\n\r\nGET /chunked HTTP/1.1\r\n\r\nGET /old HTTP/1.0\r\nConnection: keep-alive\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let expected =
+        "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nKeep-Alive: timeout=60, max=999\r\n\r\n\
+                    hello\
+                    HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\
+                    Keep-Alive: timeout=60, max=998\r\n\r\nB\r\nhello world\r\n0\r\n\r\n\
+                    HTTP/1.1 200 OK\r\nConnection: close\r\n\r\nhello world";
+    assert_eq!(expected, response);
+}
+
+#[tokio::test]
+pub async fn test_streamed_pieces_sent_as_produced() {
+    struct Channel(tokio::sync::mpsc::Receiver<&'static str>);
+
+    impl futures_core::Stream for Channel {
+        type Item = Result<bytes::Bytes, io::Error>;
+
+        fn poll_next(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Option<Self::Item>> {
+            self.0.poll_recv(cx).map(|piece| piece.map(|piece| Ok(bytes::Bytes::from(piece))))
+        }
+    }
+
+    let (sender, receiver) = tokio::sync::mpsc::channel(4);
+    let receiver = std::sync::Mutex::new(Some(receiver));
+    let events = move |_: RawRequest| {
+        let receiver = receiver.lock().unwrap().take().unwrap();
+        async move {
+            let body = Body::from_stream(Channel(receiver));
+            RawResponse::streaming(
+                StatusLine::new(HttpVersion::Http1_1, StatusCode::OK),
+                Headers::empty(),
+                body,
+            )
+        }
+    };
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(Server::new(events).serve(vec![listener]));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"GET /events HTTP/1.1\r\nConnection: close\r\n\r\n").await.unwrap();
+    sender.send("data: first\n\n").await.unwrap();
+    let expected = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                    D\r\ndata: first\n\n\r\n";
+    let mut received = vec![0; expected.len()];
+    // the stream is still open, the piece must not wait for its end
+    timeout(Duration::from_secs(5), stream.read_exact(&mut received)).await.unwrap().unwrap();
+    assert_eq!(expected.as_bytes(), &received[..]);
+
+    sender.send("data: second\n\n").await.unwrap();
+    drop(sender);
+    let mut rest = String::new();
+    stream.read_to_string(&mut rest).await.unwrap();
+    assert_eq!("E\r\ndata: second\n\n\r\n0\r\n\r\n", rest);
+}
+
+#[tokio::test]
+pub async fn test_switching_protocols_hands_over_connection() {
+    async fn echo(mut request: RawRequest) -> RawResponse {
+        let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::SWITCHING_PROTOCOLS);
+        let mut headers = Headers::empty();
+        headers.set("Connection", "upgrade".to_owned());
+        headers.set("Upgrade", "echo".to_owned());
+        if let Some(on_upgrade) = request.extensions.remove::<OnUpgrade>() {
+            tokio::spawn(async move {
+                let mut upgraded = on_upgrade.await.unwrap();
+                let mut buf = [0; 4];
+                upgraded.read_exact(&mut buf).await.unwrap();
+                upgraded.write_all(&buf).await.unwrap();
+            });
+        }
+        RawResponse::new(status_line, headers, None)
+    }
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(Server::new(echo).serve(vec![listener]));
+
+    // what follows the handshake belongs to the new protocol, even when read with it
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nConnection: upgrade\r\nUpgrade: echo\r\n\r\nping")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let expected =
+        "HTTP/1.1 101 Switching Protocols\r\nConnection: upgrade\r\nUpgrade: echo\r\n\r\nping";
+    assert_eq!(expected, response);
+
+    // a client which didn't ask to switch can't be switched
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+}
+
+#[test]
+pub fn test_buffer_pool() {
+    let pool = BufferPool::new(&[64, 8], 80);
+    let small = pool.lease(5).unwrap();
+    assert_eq!((8, 8), (small.size(), small.capacity()));
+    let large = pool.lease(9).unwrap();
+    assert_eq!(64, large.size());
+    assert_eq!(72, pool.leased());
+    assert!(pool.lease(9).is_none());
+    let small_again = pool.lease(1).unwrap();
+    assert!(pool.lease(1).is_none());
+
+    drop((small, small_again, large));
+    assert_eq!(0, pool.leased());
+    // larger than every size, allocated to fit
+    assert_eq!(70, pool.lease(70).unwrap().size());
+    assert!(pool.lease(81).is_none());
+}
+
+#[tokio::test]
+pub async fn test_sheds_connections_over_memory_budget() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut config = Config::default();
+    config.limits.read_buffer = 1024;
+    config.limits.write_buffer = 1024;
+    let pool = BufferPool::new(&[1024], 2048);
+    tokio::spawn(
+        Server::from_config(config, hello).buffer_pool(pool.clone()).serve(vec![listener]),
+    );
+
+    let mut first = TcpStream::connect(addr).await.unwrap();
+    first.write_all(b"GET /a HTTP/1.1\r\n\r\n").await.unwrap();
+    let mut buf = [0; 64];
+    let n = first.read(&mut buf).await.unwrap();
+    assert!(buf[..n].starts_with(b"HTTP/1.1 200 OK\r\n"));
+    assert_eq!(2048, pool.leased());
+
+    let mut second = TcpStream::connect(addr).await.unwrap();
+    let mut response = String::new();
+    second.read_to_string(&mut response).await.unwrap();
+    assert_eq!(
+        "HTTP/1.1 503 Service Unavailable\r\nConnection: close\r\nContent-Length: 0\r\n\
+         Retry-After: 1\r\n\r\n",
+        response
+    );
+
+    drop(first);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(0, pool.leased());
+}
+
+#[tokio::test]
+pub async fn test_adaptive_read_buffer() {
+    use tokio::io::AsyncBufReadExt;
+
+    let pool = BufferPool::new(&[1024, 4096, 16384], usize::MAX);
+    let message = vec![b'a'; 20_000];
+    let mut reader = pool::PooledReader::new(&message[..], pool.clone(), 1024, 16384).unwrap();
+    assert_eq!(1024, pool.leased());
+
+    let mut read = 0;
+    while read < message.len() {
+        let n = reader.fill_buf().await.unwrap().len();
+        reader.consume(n);
+        read += n;
+    }
+    // doubled while reads filled it, up to the cap
+    assert_eq!(16384, pool.leased());
+    reader.shrink_to_fit();
+    assert_eq!(16384, pool.leased());
+
+    assert!(reader.fill_buf().await.unwrap().is_empty());
+    reader.shrink_to_fit();
+    assert_eq!(1024, pool.leased());
+}
+
+#[derive(Clone, Default)]
+struct CollectTimings(Arc<std::sync::Mutex<Vec<PhaseTimings>>>);
+
+impl RequestObserver for CollectTimings {
+    fn observe(&self, record: &RequestRecord<'_>) {
+        self.0.lock().unwrap().push(record.timings);
+    }
+}
+
+#[tokio::test]
+pub async fn test_request_phase_timings() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let observer = CollectTimings::default();
+    let slow = |request: RawRequest| async move {
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        let timings = request.extensions.get::<PhaseTimings>().copied().unwrap();
+        let body = format!("{}", timings.body >= Duration::from_millis(30));
+        let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::OK);
+        RawResponse::new(status_line, Headers::empty(), Some(body.into_bytes()))
+    };
+    tokio::spawn(Server::new(slow).observer(observer.clone()).serve(vec![listener]));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(30)).await;
+    stream
+        .write_all(b"POST / HTTP/1.1\r\nConnection: close\r\nContent-Length: 2\r\n\r\n")
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(30)).await;
+    stream.write_all(b"ok").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.ends_with("\r\n\r\ntrue"), "{response}");
+
+    let timings = observer.0.lock().unwrap()[0];
+    assert!(timings.first_byte >= Duration::from_millis(30), "{timings:?}");
+    assert!(timings.body >= Duration::from_millis(30), "{timings:?}");
+    assert!(timings.handler >= Duration::from_millis(40), "{timings:?}");
+    assert!(timings.head < Duration::from_millis(30), "{timings:?}");
+}
+
+#[tokio::test]
+pub async fn test_request_sequence_numbers() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let echo_seq = |request: RawRequest| async move {
+        let seq = request.extensions.get::<RequestSeq>().copied().unwrap();
+        let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::OK);
+        RawResponse::new(status_line, Headers::empty(), Some(seq.to_string().into_bytes()))
+    };
+    tokio::spawn(Server::new(echo_seq).serve(vec![listener]));
+
+    for connection in 1..=2 {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let expected = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: 3\r\nKeep-Alive: timeout=60, max=999\r\n\r\n\
+             {connection}.1\
+             HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\n{connection}.2"
+        );
+        assert_eq!(expected, response);
+    }
+}
+
+#[tokio::test]
+pub async fn test_close_reasons() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut config = Config::default();
+    config.timeouts.idle = Duration::from_millis(50);
+    config.limits.max_line_len = 64;
+    let server = Server::from_config(config, hello);
+    let stats = server.stats();
+    tokio::spawn(server.serve(vec![listener]));
+
+    let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(64));
+    let requests = [
+        &b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n"[..],
+        b"GET / HTTP/1.1\r\nbad header\r\n\r\n",
+        long.as_bytes(),
+        b"GET / HTTP/1.1\r\n\r\n",
+    ];
+    for request in requests {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request).await.unwrap();
+        stream.read_to_end(&mut Vec::new()).await.unwrap();
+    }
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+    stream.read_exact(&mut [0; 77]).await.unwrap();
+    drop(stream);
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    let reasons = stats.close_reasons().into_iter().collect::<Vec<_>>();
+    let expected = [
+        ("client_closed", 1),
+        ("idle_timeout", 1),
+        ("invalid_request", 1),
+        ("limit_exceeded", 1),
+        ("requested", 1),
+    ];
+    assert_eq!(expected.to_vec(), reasons);
+    assert_eq!(
+        "invalid_request: invalid characters in header content: bad header",
+        CloseReason::from(ParseRequestError::InvalidHeader("bad header".to_owned())).to_string()
+    );
+    assert!(!CloseReason::IdleTimeout.is_error());
+}
+
+#[tokio::test]
+pub async fn test_unread_request_bodies() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut config = Config::default();
+    config.limits.max_body_len = 4;
+    config.limits.max_drain = 8;
+    let echo = |request: RawRequest| async move {
+        let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::OK);
+        RawResponse::new(status_line, Headers::empty(), request.body)
+    };
+    tokio::spawn(Server::from_config(config, echo).serve(vec![listener]));
+    let call = |request: &'static str| async move {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    };
+
+    // small enough to discard, the next request is still read
+    assert_eq!(
+        "HTTP/1.1 413 Payload Too Large\r\nContent-Length: 0\r\n\
+         Keep-Alive: timeout=60, max=999\r\n\r\n\
+         HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok",
+        call(
+            "POST / HTTP/1.1\r\nContent-Length: 6\r\n\r\nupload\
+              POST / HTTP/1.1\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok"
+        )
+        .await
+    );
+    assert_eq!(
+        "HTTP/1.1 413 Payload Too Large\r\nConnection: close\r\nContent-Length: 0\r\n\r\n",
+        call("POST / HTTP/1.1\r\nContent-Length: 9\r\n\r\nlarge body").await
+    );
+    // chunked bodies are decoded rather than taken for the next request
+    assert_eq!(
+        "HTTP/1.1 200 OK\r\nContent-Length: 3\r\nKeep-Alive: timeout=60, max=999\r\n\r\nabc\
+         HTTP/1.1 200 OK\r\n\r\n",
+        call(
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nab\r\n1\r\nc\r\n0\r\n\r\n\
+              GET / HTTP/1.1\r\nConnection: close\r\n\r\n"
+        )
+        .await
+    );
+    // framed both ways, answered and closed
+    assert_eq!(
+        "HTTP/1.1 200 OK\r\nContent-Length: 1\r\n\r\na",
+        call(
+            "POST / HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n\
+              1\r\na\r\n0\r\n\r\nGET / HTTP/1.1\r\n\r\n"
+        )
+        .await
+    );
+    assert!(call("POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\nbody")
+        .await
+        .starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    // a length read one way here and another way elsewhere is refused, nothing after it is read
+    for request in [
+        "POST / HTTP/1.1\r\nContent-Length: +3\r\n\r\nabcGET / HTTP/1.1\r\n\r\n",
+        "POST / HTTP/1.1\r\nContent-Length: 3, 1\r\n\r\nabcGET / HTTP/1.1\r\n\r\n",
+        "POST / HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 1\r\n\r\nabcGET / HTTP/1.1\r\n\r\n",
+        "POST / HTTP/1.1\r\nContent-Length: abc\r\n\r\nabcGET / HTTP/1.1\r\n\r\n",
+    ] {
+        let response = call(request).await;
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{request}");
+        assert!(response.contains("Connection: close\r\n"), "{request}");
+        assert_eq!(1, response.matches("HTTP/1.1").count(), "{request}");
+    }
+    // the same length repeated frames the body once
+    assert_eq!(
+        "HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\nabc",
+        call("POST / HTTP/1.1\r\nContent-Length: 3, 3\r\nConnection: close\r\n\r\nabc").await
+    );
+}
+
+#[tokio::test]
+pub async fn test_keep_alive_header() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut config = Config::default();
+    config.timeouts.idle = Duration::from_secs(5);
+    config.limits.max_requests = 2;
+    let server = Server::from_config(config, hello);
+    let stats = server.stats();
+    tokio::spawn(server.serve(vec![listener]));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(
+            b"GET /a HTTP/1.0\r\nConnection: keep-alive\r\n\r\n\
+              GET /b HTTP/1.1\r\n\r\nGET /unanswered HTTP/1.1\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let expected = "HTTP/1.1 200 OK\r\nContent-Length: 8\r\nConnection: keep-alive\r\n\
+                    Keep-Alive: timeout=5, max=1\r\n\r\nhello /a\
+                    HTTP/1.1 200 OK\r\nContent-Length: 8\r\nConnection: close\r\n\r\nhello /b";
+    assert_eq!(expected, response);
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(vec![("max_requests", 1)], stats.close_reasons().into_iter().collect::<Vec<_>>());
+}
+
+#[tokio::test]
+pub async fn test_stalled_request_times_out() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut config = Config::default();
+    config.timeouts.request_read = Duration::from_millis(50);
+    let server = Server::from_config(config, hello);
+    let stats = server.stats();
+    tokio::spawn(server.serve(vec![listener]));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\nHost:").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let expected =
+        "HTTP/1.1 200 OK\r\nContent-Length: 8\r\nKeep-Alive: timeout=60, max=999\r\n\r\n\
+                    hello /a\
+                    HTTP/1.1 408 Request Timeout\r\nConnection: close\r\nContent-Length: 0\r\n\r\n";
+    assert_eq!(expected, response);
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(vec![("read_timeout", 1)], stats.close_reasons().into_iter().collect::<Vec<_>>());
+}
+
+#[cfg(feature = "tls")]
+#[tokio::test]
+pub async fn test_tls_handshake_failures() {
+    let dir = std::env::temp_dir().join(format!("toot-handshake-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let generated = rcgen::generate_simple_self_signed(vec!["a.test".to_owned()]).unwrap();
+    let tls = TlsFiles { cert: dir.join("cert.pem"), key: dir.join("key.pem"), early_data: false };
+    std::fs::write(&tls.cert, generated.cert.pem()).unwrap();
+    std::fs::write(&tls.key, generated.key_pair.serialize_pem()).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut config = Config { tls: Some(tls), ..Config::default() };
+    config.timeouts.tls_handshake = Duration::from_millis(50);
+    let server = Server::from_config(config, hello);
+    let stats = server.stats();
+    tokio::spawn(server.serve(vec![listener]));
+
+    // plaintext HTTP on the TLS port
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+    let _ = stream.read_to_end(&mut Vec::new()).await;
+    // silent until the handshake timeout
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.read_to_end(&mut Vec::new()).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    let reasons = stats.close_reasons().into_iter().collect::<Vec<_>>();
+    let expected = [("tls_handshake_timeout", 1), ("tls_protocol_mismatch", 1)];
+    assert_eq!(expected.to_vec(), reasons);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(feature = "tls")]
+#[tokio::test]
+pub async fn test_requests_in_early_data() {
+    use std::sync::Arc;
+
+    use tokio_rustls::rustls::pki_types::ServerName;
+    use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+
+    let dir = std::env::temp_dir().join(format!("toot-early-data-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let generated = rcgen::generate_simple_self_signed(vec!["a.test".to_owned()]).unwrap();
+    let tls = TlsFiles { cert: dir.join("cert.pem"), key: dir.join("key.pem"), early_data: true };
+    std::fs::write(&tls.cert, generated.cert.pem()).unwrap();
+    std::fs::write(&tls.key, generated.key_pair.serialize_pem()).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = Config { tls: Some(tls), ..Config::default() };
+    let early = |request: RawRequest| async move {
+        let early = request.extensions.get::<EarlyData>().is_some();
+        let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::OK);
+        RawResponse::new(status_line, Headers::empty(), Some(early.to_string().into_bytes()))
+    };
+    tokio::spawn(Server::from_config(config, early).serve(vec![listener]));
+
+    let mut roots = RootCertStore::empty();
+    roots.add(generated.cert.der().clone()).unwrap();
+    let mut client = ClientConfig::builder_with_provider(Arc::new(
+        tokio_rustls::rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .unwrap()
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+    client.enable_early_data = true;
+    let connector = tokio_rustls::TlsConnector::from(Arc::new(client)).early_data(true);
+    let name = ServerName::try_from("a.test").unwrap();
+
+    let mut responses = Vec::new();
+    for _ in 0..2 {
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let mut stream = connector.connect(name.clone(), tcp).await.unwrap();
+        // the first connection has no ticket yet, the second sends this as 0-RTT data
+        stream.write_all(b"GET /a HTTP/1.1\r\n\r\n").await.unwrap();
+        stream.flush().await.unwrap();
+        stream.write_all(b"GET /b HTTP/1.1\r\nConnection: close\r\n\r\n").await.unwrap();
+        // closed without close_notify
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response).await;
+        let response = String::from_utf8(response).unwrap();
+        let bodies = response.split("\r\n\r\n").skip(1).map(|body| &body[..4]);
+        responses.push(bodies.collect::<Vec<_>>().join(" "));
+    }
+    assert_eq!(vec!["fals fals", "true fals"], responses);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+pub fn test_bandwidth_for() {
+    let vars = |name: &str| match name {
+        "TOOT_BANDWIDTH" => Some("100000".to_owned()),
+        "TOOT_ROUTE_BANDWIDTH" => Some("/files=50000, /files/big/=1000, /api=500000".to_owned()),
+        _ => None,
+    };
+    let config = Config::default().merge_vars(vars).unwrap();
+
+    assert_eq!(Some(100_000), config.bandwidth_for("/"));
+    assert_eq!(Some(50_000), config.bandwidth_for("/files/a.txt"));
+    assert_eq!(Some(1000), config.bandwidth_for("/files/big/iso"));
+    assert_eq!(Some(100_000), config.bandwidth_for("/filesystem"));
+    assert_eq!(Some(100_000), config.bandwidth_for("/api/users"));
+    assert_eq!(None, Config::default().bandwidth_for("/files"));
+
+    let invalid = Config::default()
+        .merge_vars(|name| (name == "TOOT_ROUTE_BANDWIDTH").then(|| "/a=fast".to_owned()));
+    assert_eq!(
+        Err(ConfigError::Env("TOOT_ROUTE_BANDWIDTH".to_owned(), "/a=fast".to_owned())),
+        invalid
+    );
+}
+
+#[tokio::test]
+pub async fn test_route_bandwidth() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut config = Config::default();
+    config.route_bandwidth.push(RouteBandwidth { prefix: "/slow".to_owned(), bytes_per_sec: 4000 });
+    let handler = |_request: RawRequest| async {
+        let ok = StatusLine::new(HttpVersion::Http1_1, StatusCode::OK);
+        RawResponse::new(ok, Headers::empty(), Some(vec![b'x'; 6000]))
+    };
+    tokio::spawn(Server::from_config(config, handler).serve(vec![listener]));
+
+    let fetch = |path: &'static str| async move {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {path} HTTP/1.1\r\nConnection: close\r\n\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let started = std::time::Instant::now();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        assert!(response.ends_with(&[b'x'; 6000]));
+        started.elapsed()
+    };
+
+    assert!(fetch("/fast").await < Duration::from_millis(250));
+    // a second's worth at once, the rest at 4000 bytes a second
+    assert!(fetch("/slow/file").await >= Duration::from_millis(400));
+}
+
+#[derive(Clone, Default)]
+struct CollectSizes(Arc<std::sync::Mutex<Vec<(RequestSize, u64)>>>);
+
+impl RequestObserver for CollectSizes {
+    fn observe(&self, record: &RequestRecord<'_>) {
+        self.0.lock().unwrap().push((record.size, record.written));
+    }
+}
+
+#[tokio::test]
+pub async fn test_request_sizes() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handler = |request: RawRequest| async move {
+        let size = request.extensions.get::<RequestSize>().copied().unwrap();
+        let ok = StatusLine::new(HttpVersion::Http1_1, StatusCode::OK);
+        let body = format!("{} {}", size.head, size.body);
+        RawResponse::new(ok, Headers::empty(), Some(body.into_bytes()))
+    };
+    let (observer, sink) = (CollectSizes::default(), CollectSink::default());
+    let config = Config { access_log: true, ..Default::default() };
+    let server =
+        Server::from_config(config, handler).observer(observer.clone()).log_sink(sink.clone());
+    tokio::spawn(server.serve(vec![listener]));
+
+    let head = "POST /up HTTP/1.1\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n";
+    let body = "3\r\nabc\r\n0\r\n\r\n";
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(format!("{head}{body}").as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = (head.len() as u64, body.len() as u64);
+    assert!(response.ends_with(&format!("\r\n\r\n{head} {body}")), "{response}");
+
+    let observed = observer.0.lock().unwrap().clone();
+    assert_eq!(vec![(RequestSize { head, body }, response.len() as u64)], observed);
+    let lines = sink.0.lock().unwrap().clone();
+    assert!(lines[0].ends_with(&format!(" {}B in {}B out", head + body, response.len())));
+}
+
+#[tokio::test]
+pub async fn test_keep_raw_body() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handler = |mut request: RawRequest| async move {
+        // replaced as by a handler parsing it, what was sent stays in the extensions
+        request.body = Some(b"parsed".to_vec());
+        let raw = request.raw_body().map(String::from_utf8_lossy).unwrap_or_default().into_owned();
+        let ok = StatusLine::new(HttpVersion::Http1_1, StatusCode::OK);
+        RawResponse::new(ok, Headers::empty(), Some(format!("[{raw}]").into_bytes()))
+    };
+    let mut config = Config::default();
+    config.limits.max_raw_body = 4;
+    tokio::spawn(Server::from_config(config, handler).serve(vec![listener]));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n").await.unwrap();
+    stream.write_all(b"2\r\nab\r\n2\r\ncd\r\n0\r\n\r\n").await.unwrap();
+    stream.write_all(b"POST /b HTTP/1.1\r\nContent-Length: 5\r\n\r\nabcde").await.unwrap();
+    stream.write_all(b"GET /c HTTP/1.1\r\nConnection: close\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let bodies = response.split("HTTP/1.1 ").skip(1).map(|r| r.rsplit("\r\n").next().unwrap());
+    assert_eq!(vec!["[abcd]", "[]", "[]"], bodies.collect::<Vec<_>>());
+}
+
+#[tokio::test]
+pub async fn test_absent_response_bodies() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handler = |request: RawRequest| async move {
+        let status = match request.request_line.target.path() {
+            "/gone" => StatusCode::NO_CONTENT,
+            _ => StatusCode::OK,
+        };
+        RawResponse::new(StatusLine::new(HttpVersion::Http1_1, status), Headers::empty(), None)
+    };
+    tokio::spawn(Server::new(handler).serve(vec![listener]));
+
+    // without a `Content-Length` the first response would run until the connection closes
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"GET /ok HTTP/1.1\r\n\r\nHEAD /ok HTTP/1.1\r\n\r\n").await.unwrap();
+    stream.write_all(b"DELETE /gone HTTP/1.1\r\nConnection: close\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert_eq!(
+        "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nKeep-Alive: timeout=60, max=999\r\n\r\n\
+         HTTP/1.1 200 OK\r\nKeep-Alive: timeout=60, max=998\r\n\r\n\
+         HTTP/1.1 204 No Content\r\n\r\n",
+        response
+    );
+}
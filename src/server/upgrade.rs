@@ -0,0 +1,96 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::oneshot;
+
+/// In the extensions of HTTP/1.1 requests with `Connection: upgrade` and an `Upgrade` header.
+///
+/// A handler switching protocols answers with `101 Switching Protocols` and awaits this, which
+/// resolves to the connection once the response is written. The server reads no more requests
+/// from it. Fails when the response wasn't a 101 or couldn't be written.
+#[derive(Debug)]
+pub struct OnUpgrade {
+    receiver: oneshot::Receiver<Upgraded>,
+}
+
+impl OnUpgrade {
+    pub(crate) fn new() -> (oneshot::Sender<Upgraded>, Self) {
+        let (sender, receiver) = oneshot::channel();
+        (sender, Self { receiver })
+    }
+}
+
+impl Future for OnUpgrade {
+    type Output = io::Result<Upgraded>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.receiver).poll(cx).map_err(|_| {
+            io::Error::new(io::ErrorKind::NotConnected, "the connection didn't switch protocols")
+        })
+    }
+}
+
+trait Io: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Io for T {}
+
+/// A connection taken over after `101 Switching Protocols`, reading first what the client sent
+/// past the request before it was answered
+pub struct Upgraded {
+    read_buf: Vec<u8>,
+    pos: usize,
+    io: Box<dyn Io>,
+}
+
+impl Upgraded {
+    pub(crate) fn new<S>(io: S, read_buf: Vec<u8>) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        Self { read_buf, pos: 0, io: Box::new(io) }
+    }
+}
+
+impl std::fmt::Debug for Upgraded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Upgraded").field("buffered", &(self.read_buf.len() - self.pos)).finish()
+    }
+}
+
+impl AsyncRead for Upgraded {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.pos < this.read_buf.len() {
+            let n = (this.read_buf.len() - this.pos).min(buf.remaining());
+            buf.put_slice(&this.read_buf[this.pos..this.pos + n]);
+            this.pos += n;
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.io).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Upgraded {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().io).poll_write(cx, data)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_shutdown(cx)
+    }
+}
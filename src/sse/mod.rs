@@ -0,0 +1,157 @@
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::broadcast;
+
+use crate::protocol::{
+    write_http_response, Headers, HttpVersion, RawResponse, StatusCode, StatusLine,
+};
+
+#[cfg(test)]
+mod tests;
+
+/// A server-sent event
+/// e.g.
+/// id: 7
+/// event: update
+/// data: {"price": 12}
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Event {
+    pub id: Option<String>,
+    pub event: Option<String>,
+    pub data: String,
+    pub retry: Option<Duration>,
+}
+
+impl Event {
+    pub fn new<S: ToString>(data: S) -> Self {
+        Self { data: data.to_string(), ..Default::default() }
+    }
+
+    pub fn named<S1: ToString, S2: ToString>(event: S1, data: S2) -> Self {
+        Self { event: Some(event.to_string()), ..Self::new(data) }
+    }
+
+    /// Line breaks are stripped from `id` and `event`, which can't span lines, and so is NUL
+    /// from `id`, which clients would ignore it for. `data` takes a field per line.
+    pub fn to_http_message(&self) -> String {
+        let mut message = String::with_capacity(self.data.len() + 32);
+        if let Some(ref id) = self.id {
+            message.push_str(&format!("id: {}\n", id.replace(['\r', '\n', '\0'], "")));
+        }
+        if let Some(ref event) = self.event {
+            message.push_str(&format!("event: {}\n", event.replace(['\r', '\n'], "")));
+        }
+        if let Some(retry) = self.retry {
+            message.push_str(&format!("retry: {}\n", retry.as_millis()));
+        }
+        // `\r\n`, `\r` and `\n` each end a line
+        for line in self.data.split("\r\n").flat_map(|line| line.split(['\r', '\n'])) {
+            message.push_str(&format!("data: {line}\n"));
+        }
+        message.push('\n');
+        message
+    }
+}
+
+/// `200 OK` head of an event stream response, events follow without a Content-Length
+pub fn event_stream_response() -> RawResponse {
+    let mut headers = Headers::empty();
+    headers.set("Content-Type", "text/event-stream".to_owned());
+    headers.set("Cache-Control", "no-cache".to_owned());
+    RawResponse::new(StatusLine::new(HttpVersion::Http1_1, StatusCode::OK), headers, None)
+}
+
+#[derive(Debug)]
+struct Replay {
+    next_id: u64,
+    capacity: usize,
+    events: VecDeque<(u64, Arc<Event>)>,
+}
+
+/// Multi-subscriber event source.
+///
+/// Every event gets an increasing id and is kept in a bounded replay buffer, so a client which
+/// reconnects with `Last-Event-ID` receives what it missed before switching to live events.
+#[derive(Debug, Clone)]
+pub struct Broadcaster {
+    replay: Arc<Mutex<Replay>>,
+    live: broadcast::Sender<Arc<Event>>,
+}
+
+impl Broadcaster {
+    /// `replay_capacity` events are kept for reconnecting clients, and live subscribers may fall
+    /// this far behind before being dropped
+    pub fn new(replay_capacity: usize) -> Self {
+        let capacity = replay_capacity.max(1);
+        let (live, _) = broadcast::channel(capacity);
+        let replay = Replay { next_id: 1, capacity, events: VecDeque::with_capacity(capacity) };
+        Self { replay: Arc::new(Mutex::new(replay)), live }
+    }
+
+    /// Assigns the next id to `event` and publishes it, returns the id
+    pub fn send(&self, mut event: Event) -> u64 {
+        let mut replay = self.replay.lock().unwrap();
+        let id = replay.next_id;
+        replay.next_id += 1;
+
+        event.id = Some(id.to_string());
+        let event = Arc::new(event);
+        if replay.events.len() == replay.capacity {
+            replay.events.pop_front();
+        }
+        replay.events.push_back((id, event.clone()));
+        let _ = self.live.send(event);
+        id
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.live.receiver_count()
+    }
+
+    /// `last_event_id` is the client's `Last-Event-ID` header, if it sent one
+    pub fn subscribe(&self, last_event_id: Option<&str>) -> Subscription {
+        let replay = self.replay.lock().unwrap();
+        let backlog = match last_event_id.and_then(|id| id.trim().parse::<u64>().ok()) {
+            Some(last) => {
+                replay.events.iter().filter(|(id, _)| *id > last).map(|(_, e)| e.clone()).collect()
+            }
+            None => VecDeque::new(),
+        };
+        // subscribing under the lock means nothing falls between the backlog and live events
+        Subscription { backlog, live: self.live.subscribe() }
+    }
+}
+
+pub struct Subscription {
+    backlog: VecDeque<Arc<Event>>,
+    live: broadcast::Receiver<Arc<Event>>,
+}
+
+impl Subscription {
+    /// Next event, `None` once the broadcaster is gone or this subscriber fell too far behind;
+    /// a lagging client should be disconnected so it reconnects with `Last-Event-ID`
+    pub async fn recv(&mut self) -> Option<Arc<Event>> {
+        if let Some(event) = self.backlog.pop_front() {
+            return Some(event);
+        }
+        self.live.recv().await.ok()
+    }
+}
+
+/// Writes the response head followed by events until the subscription ends
+pub async fn write_event_stream<W>(writer: &mut W, mut subscription: Subscription) -> io::Result<()>
+where
+    W: AsyncWrite + ?Sized + Unpin,
+{
+    write_http_response(writer, event_stream_response()).await?;
+    writer.flush().await?;
+    while let Some(event) = subscription.recv().await {
+        writer.write_all(event.to_http_message().as_bytes()).await?;
+        writer.flush().await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use super::*;
+
+#[test]
+pub fn test_event_to_http_message() {
+    let mut event = Event::named("update", "line one\nline two");
+    event.id = Some("3".to_owned());
+
+    let expected = "id: 3\nevent: update\ndata: line one\ndata: line two\n\n";
+    assert_eq!(expected, event.to_http_message());
+}
+
+#[test]
+pub fn test_event_data_line_endings() {
+    let cases = [
+        ("a\r\nb", "data: a\ndata: b\n\n"),
+        ("a\rb", "data: a\ndata: b\n\n"),
+        ("a\nb\r", "data: a\ndata: b\ndata: \n\n"),
+        ("a\n\rb", "data: a\ndata: \ndata: b\n\n"),
+        ("a\r\r\nb", "data: a\ndata: \ndata: b\n\n"),
+    ];
+    for (data, expected) in cases {
+        assert_eq!(expected, Event::new(data).to_http_message());
+    }
+}
+
+#[test]
+pub fn test_event_id_strips_line_breaks_and_nul() {
+    let mut event = Event::new("x");
+    event.id = Some("1\r\ndata: injected\0\r2".to_owned());
+    assert_eq!("id: 1data: injected2\ndata: x\n\n", event.to_http_message());
+}
+
+#[test]
+pub fn test_event_name_strips_line_breaks() {
+    let event = Event::named("up\ndata: injected\r\rdate\0", "x");
+    assert_eq!("event: updata: injecteddate\0\ndata: x\n\n", event.to_http_message());
+}
+
+#[tokio::test]
+pub async fn test_broadcaster_replays_after_last_event_id() {
+    let broadcaster = Broadcaster::new(2);
+    broadcaster.send(Event::new("a"));
+    broadcaster.send(Event::new("b"));
+    broadcaster.send(Event::new("c"));
+
+    let mut subscription = broadcaster.subscribe(Some("1"));
+    broadcaster.send(Event::new("d"));
+
+    let mut received = Vec::new();
+    for _ in 0..3 {
+        let event = subscription.recv().await.unwrap();
+        received.push((event.id.clone().unwrap(), event.data.clone()));
+    }
+    let expected = vec![
+        ("2".to_owned(), "b".to_owned()),
+        ("3".to_owned(), "c".to_owned()),
+        ("4".to_owned(), "d".to_owned()),
+    ];
+    assert_eq!(expected, received);
+}
+
+#[tokio::test]
+pub async fn test_broadcaster_without_last_event_id_is_live_only() {
+    let broadcaster = Broadcaster::new(8);
+    broadcaster.send(Event::new("old"));
+
+    let mut subscription = broadcaster.subscribe(None);
+    broadcaster.send(Event::new("new"));
+
+    assert_eq!("new", subscription.recv().await.unwrap().data);
+    drop(broadcaster);
+    assert_eq!(None, subscription.recv().await);
+}
@@ -0,0 +1,102 @@
+use std::io;
+use std::net::TcpListener;
+use std::os::fd::{FromRawFd, RawFd};
+use std::os::unix::net::UnixDatagram;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(test)]
+mod tests;
+
+/// first file descriptor passed by systemd, `SD_LISTEN_FDS_START`
+const LISTEN_FDS_START: RawFd = 3;
+
+/// set once the passed sockets are owned by listeners, so they are never owned twice
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum NotifyState {
+    Ready,
+    Reloading,
+    Stopping,
+    Watchdog,
+    Status(String),
+}
+
+impl NotifyState {
+    fn as_line(&self) -> String {
+        match self {
+            NotifyState::Ready => "READY=1".to_owned(),
+            NotifyState::Reloading => "RELOADING=1".to_owned(),
+            NotifyState::Stopping => "STOPPING=1".to_owned(),
+            NotifyState::Watchdog => "WATCHDOG=1".to_owned(),
+            NotifyState::Status(status) => format!("STATUS={}", status.replace('\n', " ")),
+        }
+    }
+}
+
+/// Takes the listening sockets passed by systemd socket activation, `Server::run` does so
+/// before binding `Config::bind`.
+///
+/// Returns an empty list when the process wasn't socket activated, and on every call after the
+/// first which returned sockets. The environment is left as it is, child processes ignore it
+/// since `LISTEN_PID` names this one. The listeners are non-blocking, ready for
+/// `tokio::net::TcpListener::from_std`.
+pub fn listen_fds() -> io::Result<Vec<TcpListener>> {
+    let pid = std::env::var("LISTEN_PID").ok();
+    let fds = std::env::var("LISTEN_FDS").ok();
+    let count = passed_fd_count(pid.as_deref(), fds.as_deref(), std::process::id())?;
+    if count == 0 || TAKEN.swap(true, Ordering::AcqRel) {
+        return Ok(Vec::new());
+    }
+
+    (LISTEN_FDS_START..LISTEN_FDS_START + count as RawFd)
+        .map(|fd| {
+            // SAFETY: systemd hands these descriptors to this process, nothing else owns them
+            let listener = unsafe { TcpListener::from_raw_fd(fd) };
+            listener.set_nonblocking(true)?;
+            Ok(listener)
+        })
+        .collect()
+}
+
+fn passed_fd_count(pid: Option<&str>, fds: Option<&str>, own_pid: u32) -> io::Result<usize> {
+    let (Some(pid), Some(fds)) = (pid, fds) else {
+        return Ok(0);
+    };
+    // the variables were meant for another process, e.g. our parent
+    if pid.trim().parse::<u32>().ok() != Some(own_pid) {
+        return Ok(0);
+    }
+    fds.trim()
+        .parse::<usize>()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid LISTEN_FDS"))
+}
+
+/// Sends `states` to the service manager, `Ok(false)` when not running under systemd.
+///
+/// Servers send `Ready` once they accept connections and `Stopping` when they start shutting
+/// down, prefork workers leave that to their supervisor.
+pub fn notify(states: &[NotifyState]) -> io::Result<bool> {
+    match std::env::var("NOTIFY_SOCKET") {
+        Ok(path) => notify_to(&path, states).map(|_| true),
+        Err(_) => Ok(false),
+    }
+}
+
+fn notify_to(path: &str, states: &[NotifyState]) -> io::Result<()> {
+    let message = states.iter().map(NotifyState::as_line).collect::<Vec<_>>().join("\n");
+    let socket = UnixDatagram::unbound()?;
+
+    match path.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(message.as_bytes(), &addr)?;
+        }
+        _ => {
+            socket.send_to(message.as_bytes(), path)?;
+        }
+    }
+    Ok(())
+}
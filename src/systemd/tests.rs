@@ -0,0 +1,26 @@
+use std::os::unix::net::UnixDatagram;
+
+use super::*;
+
+#[test]
+pub fn test_passed_fd_count() {
+    assert_eq!(0, passed_fd_count(None, None, 42).unwrap());
+    assert_eq!(0, passed_fd_count(Some("41"), Some("2"), 42).unwrap());
+    assert_eq!(2, passed_fd_count(Some("42"), Some("2"), 42).unwrap());
+    assert!(passed_fd_count(Some("42"), Some("two"), 42).is_err());
+}
+
+#[test]
+pub fn test_notify_to_socket() {
+    let path = std::env::temp_dir().join(format!("toot-notify-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let receiver = UnixDatagram::bind(&path).unwrap();
+
+    let states = [NotifyState::Ready, NotifyState::Status("serving\non :80".to_owned())];
+    notify_to(path.to_str().unwrap(), &states).unwrap();
+
+    let mut buf = [0u8; 128];
+    let n = receiver.recv(&mut buf).unwrap();
+    assert_eq!("READY=1\nSTATUS=serving on :80", std::str::from_utf8(&buf[..n]).unwrap());
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,210 @@
+use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::rustls::crypto::ring::{default_provider, Ticketer};
+use tokio_rustls::rustls::crypto::CryptoProvider;
+use tokio_rustls::rustls::server::{
+    Acceptor, ClientHello, NoServerSessionStorage, ProducesTickets, ResolvesServerCert,
+    ServerSessionMemoryCache, StoresServerSessions,
+};
+use tokio_rustls::rustls::{version, ServerConfig, SupportedCipherSuite, SupportedProtocolVersion};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::LazyConfigAcceptor;
+
+use super::{CertResolver, CertifiedKey, TlsError};
+
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+pub enum TlsVersion {
+    Tls1_2,
+    Tls1_3,
+}
+
+impl TlsVersion {
+    fn supported(&self) -> &'static SupportedProtocolVersion {
+        match self {
+            TlsVersion::Tls1_2 => &version::TLS12,
+            TlsVersion::Tls1_3 => &version::TLS13,
+        }
+    }
+}
+
+/// Copy of `key` which staples `ocsp_response` to its handshakes
+pub fn with_ocsp_staple(key: &CertifiedKey, ocsp_response: Vec<u8>) -> Arc<CertifiedKey> {
+    let mut key = key.clone();
+    key.ocsp = Some(ocsp_response);
+    Arc::new(key)
+}
+
+pub struct TlsAcceptorBuilder {
+    resolver: Arc<dyn CertResolver>,
+    alpn_protocols: Vec<Vec<u8>>,
+    versions: Vec<TlsVersion>,
+    cipher_suites: Option<Vec<SupportedCipherSuite>>,
+    session_cache_size: usize,
+    session_tickets: bool,
+    max_early_data: u32,
+}
+
+impl TlsAcceptorBuilder {
+    pub fn alpn_protocols(mut self, protocols: &[&str]) -> Self {
+        self.alpn_protocols = protocols.iter().map(|p| p.as_bytes().to_vec()).collect();
+        self
+    }
+
+    /// defaults to TLS 1.2 and 1.3
+    pub fn protocol_versions(mut self, versions: &[TlsVersion]) -> Self {
+        self.versions = versions.to_vec();
+        self
+    }
+
+    /// restricts the provider's cipher suites to these, in this order of preference
+    pub fn cipher_suites(mut self, suites: &[SupportedCipherSuite]) -> Self {
+        self.cipher_suites = Some(suites.to_vec());
+        self
+    }
+
+    /// sessions kept for session ID (and TLS 1.3 stateful) resumption, `0` disables it
+    pub fn session_cache_size(mut self, size: usize) -> Self {
+        self.session_cache_size = size;
+        self
+    }
+
+    /// stateless resumption with session tickets, off by default
+    pub fn session_tickets(mut self, enabled: bool) -> Self {
+        self.session_tickets = enabled;
+        self
+    }
+
+    /// accepts up to `max` bytes of TLS 1.3 0-RTT data from resumed sessions, off by default.
+    /// Tickets are issued for the session cache while it's on, see `read_early_data`.
+    pub fn early_data(mut self, max: u32) -> Self {
+        self.max_early_data = max;
+        self
+    }
+
+    pub fn build(self) -> Result<TlsAcceptor, TlsError> {
+        let invalid = |err: &dyn std::fmt::Display| TlsError::InvalidConfig(err.to_string());
+
+        let mut provider = default_provider();
+        if let Some(suites) = self.cipher_suites {
+            provider.cipher_suites = suites;
+        }
+        let versions = self.versions.iter().map(TlsVersion::supported).collect::<Vec<_>>();
+        // fails early when the versions and suites have nothing in common
+        ServerConfig::builder_with_provider(Arc::new(provider.clone()))
+            .with_protocol_versions(&versions)
+            .map_err(|err| invalid(&err))?;
+
+        let session_storage: Arc<dyn StoresServerSessions> = match self.session_cache_size {
+            0 => Arc::new(NoServerSessionStorage {}),
+            size => ServerSessionMemoryCache::new(size),
+        };
+        let ticketer = match self.session_tickets {
+            true => Some(Ticketer::new().map_err(|err| invalid(&err))?),
+            false => None,
+        };
+
+        Ok(TlsAcceptor {
+            resolver: self.resolver,
+            alpn_protocols: self.alpn_protocols,
+            provider: Arc::new(provider),
+            versions,
+            session_storage,
+            ticketer,
+            max_early_data: self.max_early_data,
+        })
+    }
+}
+
+/// Accepts TLS connections, picking the certificate per handshake through a `CertResolver`
+#[derive(Clone)]
+pub struct TlsAcceptor {
+    resolver: Arc<dyn CertResolver>,
+    alpn_protocols: Vec<Vec<u8>>,
+    provider: Arc<CryptoProvider>,
+    versions: Vec<&'static SupportedProtocolVersion>,
+    /// shared by every handshake so sessions can be resumed across connections
+    session_storage: Arc<dyn StoresServerSessions>,
+    ticketer: Option<Arc<dyn ProducesTickets>>,
+    max_early_data: u32,
+}
+
+impl TlsAcceptor {
+    pub fn new<R>(resolver: R) -> Self
+    where
+        R: CertResolver + 'static,
+    {
+        Self::builder(resolver).build().expect("default tls settings are valid")
+    }
+
+    pub fn builder<R>(resolver: R) -> TlsAcceptorBuilder
+    where
+        R: CertResolver + 'static,
+    {
+        TlsAcceptorBuilder {
+            resolver: Arc::new(resolver),
+            alpn_protocols: vec![b"http/1.1".to_vec()],
+            versions: vec![TlsVersion::Tls1_3, TlsVersion::Tls1_2],
+            cipher_suites: None,
+            session_cache_size: 256,
+            session_tickets: false,
+            max_early_data: 0,
+        }
+    }
+
+    pub async fn accept<S>(&self, stream: S) -> Result<TlsStream<S>, TlsError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let start = LazyConfigAcceptor::new(Acceptor::default(), stream).await?;
+        let server_name = start.client_hello().server_name().map(str::to_owned);
+
+        let key = self
+            .resolver
+            .resolve(server_name.as_deref())
+            .await
+            .ok_or(TlsError::UnknownServerName(server_name))?;
+
+        let stream = start.into_stream(self.server_config(key)).await?;
+        Ok(stream)
+    }
+
+    fn server_config(&self, key: Arc<CertifiedKey>) -> Arc<ServerConfig> {
+        let mut config = ServerConfig::builder_with_provider(self.provider.clone())
+            .with_protocol_versions(&self.versions)
+            .expect("checked by TlsAcceptorBuilder::build")
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(Resolved(key)));
+        config.alpn_protocols = self.alpn_protocols.clone();
+        config.session_storage = self.session_storage.clone();
+        config.max_early_data_size = self.max_early_data;
+        match self.ticketer {
+            Some(ref ticketer) => config.ticketer = ticketer.clone(),
+            // early data comes with resumption, for which the client needs a ticket
+            None if self.max_early_data > 0 => {}
+            None => config.send_tls13_tickets = 0,
+        }
+        Arc::new(config)
+    }
+}
+
+/// Takes the 0-RTT data `stream` accepted, buffered until the handshake was complete. It
+/// precedes what's read from `stream`.
+pub fn read_early_data<S>(stream: &mut TlsStream<S>) -> Vec<u8> {
+    let mut data = Vec::new();
+    if let Some(mut early_data) = stream.get_mut().1.early_data() {
+        // reading from the buffer doesn't fail
+        let _ = std::io::Read::read_to_end(&mut early_data, &mut data);
+    }
+    data
+}
+
+/// the certificate already picked for this handshake
+#[derive(Debug)]
+struct Resolved(Arc<CertifiedKey>);
+
+impl ResolvesServerCert for Resolved {
+    fn resolve(&self, _: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.clone())
+    }
+}
@@ -0,0 +1,584 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use ring::digest::{digest, SHA256};
+use ring::rand::SystemRandom;
+use ring::signature::{
+    EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING, ECDSA_P256_SHA256_FIXED_SIGNING,
+};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::ClientConfig;
+use tokio_rustls::TlsConnector;
+
+use super::der::{self, OCTET_STRING, SET};
+use super::Http01Challenges;
+use crate::protocol::{
+    Headers, HttpVersion, Method, RawRequest, RawResponse, RequestLimits, RequestLine,
+    RequestTarget, StatusClass, StatusCode, Uri,
+};
+use crate::proxy::send;
+use crate::tls::{certified_key_from_pem, load_certified_key, CertifiedKey, ReloadableCertificate};
+
+pub const LETS_ENCRYPT: &str = "https://acme-v02.api.letsencrypt.org/directory";
+/// for trying things out, its certificates aren't trusted but its rate limits are generous
+pub const LETS_ENCRYPT_STAGING: &str = "https://acme-staging-v02.api.letsencrypt.org/directory";
+
+/// how long to wait after a failed renewal before trying again
+const RETRY_DELAY: Duration = Duration::from_secs(3600);
+/// longest the renewal task sleeps before looking at the expiry again, in case the clock jumps
+const MAX_SLEEP: Duration = Duration::from_secs(12 * 3600);
+
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x06, 0x07, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x02, 0x01];
+const OID_P256: &[u8] = &[0x06, 0x08, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x03, 0x01, 0x07];
+const OID_ECDSA_SHA256: &[u8] = &[0x06, 0x08, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x04, 0x03, 0x02];
+const OID_EXTENSION_REQUEST: &[u8] =
+    &[0x06, 0x09, 0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x09, 0x0E];
+const OID_SUBJECT_ALT_NAME: &[u8] = &[0x06, 0x03, 0x55, 0x1D, 0x11];
+/// `[2]` of a `dNSName` in a `GeneralName`
+const DNS_NAME: u8 = 0x82;
+/// `[0]` of the attributes of a certification request
+const ATTRIBUTES: u8 = 0xA0;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AcmeError {
+    Io(io::ErrorKind),
+    InvalidUrl(String),
+    InvalidKey(String),
+    /// status and problem document of a request the CA refused
+    Ca(StatusCode, String),
+    InvalidResponse(String),
+    /// an authorization or the order ended other than `valid`
+    Rejected(String),
+    /// the CA didn't settle an authorization or the order within `AcmeConfig::max_polls`
+    Timeout,
+}
+
+impl Display for AcmeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AcmeError::Io(err) => write!(f, "acme request failure: {err}"),
+            AcmeError::InvalidUrl(url) => write!(f, "invalid acme url: {url}"),
+            AcmeError::InvalidKey(reason) => write!(f, "invalid acme account key: {reason}"),
+            AcmeError::Ca(status, problem) => write!(f, "CA answered {}: {problem}", **status),
+            AcmeError::InvalidResponse(reason) => write!(f, "invalid CA response: {reason}"),
+            AcmeError::Rejected(reason) => write!(f, "certificate order rejected: {reason}"),
+            AcmeError::Timeout => write!(f, "CA didn't validate the order in time"),
+        }
+    }
+}
+
+impl From<io::Error> for AcmeError {
+    fn from(value: io::Error) -> Self {
+        AcmeError::Io(value.kind())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AcmeConfig {
+    /// directory of the CA, e.g. `LETS_ENCRYPT`
+    pub directory_url: String,
+    /// how the CA reaches the account holder, e.g. `mailto:admin@example.com`
+    pub contact: Vec<String>,
+    /// names of the certificate
+    pub domains: Vec<String>,
+    /// how long before its expiry a certificate is renewed
+    pub renew_before: Duration,
+    /// how often authorizations and orders are looked at while the CA works on them
+    pub poll_interval: Duration,
+    pub max_polls: u32,
+    /// where the certificate is kept as `cert.pem` and `key.pem`, so a restart reuses it
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl Default for AcmeConfig {
+    fn default() -> Self {
+        Self {
+            directory_url: LETS_ENCRYPT.to_owned(),
+            contact: Vec::new(),
+            domains: Vec::new(),
+            renew_before: Duration::from_secs(30 * 86400),
+            poll_interval: Duration::from_secs(2),
+            max_polls: 30,
+            cache_dir: None,
+        }
+    }
+}
+
+/// A certificate issued by the CA
+#[derive(Clone)]
+pub struct Issued {
+    pub key: Arc<CertifiedKey>,
+    /// the chain as the CA sent it
+    pub cert_chain_pem: String,
+    /// PKCS #8 private key of the certificate
+    pub private_key_pem: String,
+}
+
+/// Generates an account key, a P-256 key as PKCS #8 DER. Keep it: a new key is a new account.
+pub fn generate_account_key() -> Vec<u8> {
+    EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new())
+        .expect("system randomness")
+        .as_ref()
+        .to_vec()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Order {
+    status: String,
+    #[serde(default)]
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Authorization {
+    status: String,
+    identifier: Identifier,
+    #[serde(default)]
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Identifier {
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    #[serde(default)]
+    token: String,
+    error: Option<Value>,
+}
+
+/// What is learned from the CA along the way
+#[derive(Default)]
+struct Session {
+    directory: Option<Directory>,
+    /// `Replay-Nonce` of the last response, each is good for one request
+    nonce: Option<String>,
+    account_url: Option<String>,
+}
+
+/// Obtains and renews certificates from an ACME CA (RFC 8555), such as Let's Encrypt.
+///
+/// Domains are validated with HTTP-01 challenges, answered by `challenges()` on the plaintext
+/// listener, which must be serving before a certificate is ordered. `certificate` gives a
+/// `ReloadableCertificate` for the TLS acceptor, which `spawn_renewal` keeps fresh.
+pub struct AcmeClient {
+    config: AcmeConfig,
+    account_key: EcdsaKeyPair,
+    jwk: Value,
+    thumbprint: String,
+    challenges: Http01Challenges,
+    tls: Option<Arc<ClientConfig>>,
+    limits: RequestLimits,
+    rng: SystemRandom,
+    session: Mutex<Session>,
+}
+
+impl AcmeClient {
+    /// `account_key` is the PKCS #8 DER of a P-256 key, see `generate_account_key`
+    pub fn new(config: AcmeConfig, account_key: &[u8]) -> Result<Self, AcmeError> {
+        let rng = SystemRandom::new();
+        let account_key =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, account_key, &rng)
+                .map_err(|err| AcmeError::InvalidKey(err.to_string()))?;
+        // uncompressed point, 0x04 then the coordinates
+        let point = account_key.public_key().as_ref();
+        let x = URL_SAFE_NO_PAD.encode(&point[1..33]);
+        let y = URL_SAFE_NO_PAD.encode(&point[33..]);
+        // the members in lexical order and without whitespace (RFC 7638)
+        let canonical = format!(r#"{{"crv":"P-256","kty":"EC","x":"{x}","y":"{y}"}}"#);
+        let thumbprint = URL_SAFE_NO_PAD.encode(digest(&SHA256, canonical.as_bytes()));
+        let jwk = json!({ "crv": "P-256", "kty": "EC", "x": x, "y": y });
+
+        Ok(Self {
+            config,
+            account_key,
+            jwk,
+            thumbprint,
+            challenges: Http01Challenges::new(),
+            tls: None,
+            limits: RequestLimits::default(),
+            rng,
+            session: Mutex::new(Session::default()),
+        })
+    }
+
+    /// Needed for an `https` directory, with the roots trusted for the CA
+    pub fn tls_config(mut self, tls: Arc<ClientConfig>) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    pub fn config(&self) -> &AcmeConfig {
+        &self.config
+    }
+
+    /// The pending challenges, to be answered with `Http01Challenges::respond` on port 80
+    pub fn challenges(&self) -> Http01Challenges {
+        self.challenges.clone()
+    }
+
+    /// The cached certificate unless it is due for renewal, otherwise a new one, which is cached
+    pub async fn certificate(&self) -> Result<ReloadableCertificate, AcmeError> {
+        if let Some(key) = self.cached().filter(|key| self.renew_at(key) > SystemTime::now()) {
+            return Ok(ReloadableCertificate::new(key));
+        }
+        let issued = self.issue().await?;
+        self.store(&issued)?;
+        Ok(ReloadableCertificate::new(issued.key))
+    }
+
+    /// When the certificate is to be renewed, now when its expiry can't be read
+    pub fn renew_at(&self, key: &CertifiedKey) -> SystemTime {
+        let not_after = key.cert.first().and_then(|cert| der::not_after(cert));
+        not_after.and_then(|at| at.checked_sub(self.config.renew_before)).unwrap_or(UNIX_EPOCH)
+    }
+
+    /// Renews `certificate` in place from `renew_before` its expiry on, and caches the new one,
+    /// until the returned task is aborted. Failed renewals are retried an hour later.
+    pub fn spawn_renewal(self: Arc<Self>, certificate: ReloadableCertificate) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                let due = self.renew_at(&certificate.current());
+                if let Ok(wait) = due.duration_since(SystemTime::now()) {
+                    tokio::time::sleep(wait.min(MAX_SLEEP)).await;
+                    continue;
+                }
+                let renewed = match self.issue().await {
+                    Ok(issued) => {
+                        // a failing cache still leaves the server with the new certificate
+                        let _ = self.store(&issued);
+                        certificate.swap(issued.key.clone());
+                        self.renew_at(&issued.key) > SystemTime::now()
+                    }
+                    Err(_) => false,
+                };
+                // don't order again and again when the CA's certificates are too short-lived
+                if !renewed {
+                    tokio::time::sleep(RETRY_DELAY).await;
+                }
+            }
+        })
+    }
+
+    /// Orders a certificate for `AcmeConfig::domains` with a fresh key
+    pub async fn issue(&self) -> Result<Issued, AcmeError> {
+        let directory = self.directory().await?;
+        let account = self.account(&directory).await?;
+
+        let identifiers = self
+            .config
+            .domains
+            .iter()
+            .map(|domain| json!({ "type": "dns", "value": domain }))
+            .collect::<Vec<_>>();
+        let payload = json!({ "identifiers": identifiers });
+        let response = self.post(&directory.new_order, Some(&account), Some(&payload)).await?;
+        let order_url = location(&response)?;
+        let order: Order = parse_json(&response)?;
+        for url in order.authorizations.iter() {
+            self.authorize(url, &account).await?;
+        }
+
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &self.rng)
+            .expect("system randomness");
+        let key =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &self.rng)
+                .expect("freshly generated key");
+        let csr = certification_request(&self.config.domains, &key, &self.rng);
+        let payload = json!({ "csr": URL_SAFE_NO_PAD.encode(csr) });
+        self.post(&order.finalize, Some(&account), Some(&payload)).await?;
+
+        let order: Order = self
+            .poll(&order_url, &account, |order: &Order| {
+                !matches!(order.status.as_str(), "pending" | "ready" | "processing")
+            })
+            .await?;
+        let certificate = match (order.status.as_str(), order.certificate) {
+            ("valid", Some(url)) => url,
+            (status, _) => return Err(AcmeError::Rejected(format!("order is {status}"))),
+        };
+
+        let response = self.post(&certificate, Some(&account), None).await?;
+        let cert_chain_pem = String::from_utf8(response.body().unwrap_or_default().to_vec())
+            .map_err(|_| AcmeError::InvalidResponse("certificate is not PEM".to_owned()))?;
+        let private_key_pem = pem("PRIVATE KEY", pkcs8.as_ref());
+        let key = certified_key_from_pem(cert_chain_pem.as_bytes(), private_key_pem.as_bytes())
+            .map_err(|err| AcmeError::InvalidResponse(err.to_string()))?;
+        Ok(Issued { key, cert_chain_pem, private_key_pem })
+    }
+
+    /// Answers the HTTP-01 challenge of a pending authorization and waits for its verdict
+    async fn authorize(&self, url: &str, account: &str) -> Result<(), AcmeError> {
+        let authorization: Authorization = parse_json(&self.post(url, Some(account), None).await?)?;
+        if authorization.status == "valid" {
+            return Ok(());
+        }
+        let domain = authorization.identifier.value;
+        let Some(challenge) = authorization.challenges.into_iter().find(|c| c.kind == "http-01")
+        else {
+            return Err(AcmeError::Rejected(format!("no http-01 challenge for {domain}")));
+        };
+
+        let key_authorization = format!("{}.{}", challenge.token, self.thumbprint);
+        self.challenges.insert(&challenge.token, &key_authorization);
+        let settled = async {
+            self.post(&challenge.url, Some(account), Some(&json!({}))).await?;
+            self.poll(url, account, |authorization: &Authorization| {
+                authorization.status != "pending"
+            })
+            .await
+        };
+        let settled = settled.await;
+        self.challenges.remove(&challenge.token);
+
+        let authorization = settled?;
+        if authorization.status == "valid" {
+            return Ok(());
+        }
+        let error = authorization
+            .challenges
+            .iter()
+            .find_map(|c| c.error.as_ref())
+            .and_then(|error| error.get("detail"))
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let status = authorization.status;
+        Err(AcmeError::Rejected(format!("authorization of {domain} is {status}: {error}")))
+    }
+
+    /// Fetches `url` until `settled`, `max_polls` times at most
+    async fn poll<T, F>(&self, url: &str, account: &str, settled: F) -> Result<T, AcmeError>
+    where
+        T: DeserializeOwned,
+        F: Fn(&T) -> bool,
+    {
+        for _ in 0..self.config.max_polls {
+            let resource: T = parse_json(&self.post(url, Some(account), None).await?)?;
+            if settled(&resource) {
+                return Ok(resource);
+            }
+            tokio::time::sleep(self.config.poll_interval).await;
+        }
+        Err(AcmeError::Timeout)
+    }
+
+    async fn directory(&self) -> Result<Directory, AcmeError> {
+        if let Some(ref directory) = self.session.lock().unwrap().directory {
+            return Ok(directory.clone());
+        }
+        let response = self.request(&self.config.directory_url, Method::GET, None).await?;
+        if response.status() != StatusCode::OK {
+            return Err(ca_error(&response));
+        }
+        let directory: Directory = parse_json(&response)?;
+        self.session.lock().unwrap().directory = Some(directory.clone());
+        Ok(directory)
+    }
+
+    /// The account URL, the account is created on first use and found again after that
+    async fn account(&self, directory: &Directory) -> Result<String, AcmeError> {
+        if let Some(ref url) = self.session.lock().unwrap().account_url {
+            return Ok(url.clone());
+        }
+        let payload = json!({ "termsOfServiceAgreed": true, "contact": self.config.contact });
+        let response = self.post(&directory.new_account, None, Some(&payload)).await?;
+        let url = location(&response)?;
+        self.session.lock().unwrap().account_url = Some(url.clone());
+        Ok(url)
+    }
+
+    async fn nonce(&self) -> Result<String, AcmeError> {
+        if let Some(nonce) = self.session.lock().unwrap().nonce.take() {
+            return Ok(nonce);
+        }
+        let directory = self.directory().await?;
+        self.request(&directory.new_nonce, Method::HEAD, None).await?;
+        let nonce = self.session.lock().unwrap().nonce.take();
+        nonce.ok_or_else(|| AcmeError::InvalidResponse("no Replay-Nonce".to_owned()))
+    }
+
+    /// Sends `payload` signed with the account key, identified by `account` or else by its
+    /// public key. Without a payload it's a POST-as-GET.
+    async fn post(
+        &self,
+        url: &str,
+        account: Option<&str>,
+        payload: Option<&Value>,
+    ) -> Result<RawResponse, AcmeError> {
+        let payload = payload.map_or_else(String::new, |p| URL_SAFE_NO_PAD.encode(p.to_string()));
+        let mut retried = false;
+        loop {
+            let mut protected = json!({ "alg": "ES256", "nonce": self.nonce().await?, "url": url });
+            match account {
+                Some(account) => protected["kid"] = json!(account),
+                None => protected["jwk"] = self.jwk.clone(),
+            }
+            let protected = URL_SAFE_NO_PAD.encode(protected.to_string());
+            let signature = self
+                .account_key
+                .sign(&self.rng, format!("{protected}.{payload}").as_bytes())
+                .expect("signing with a valid key");
+            let signature = URL_SAFE_NO_PAD.encode(signature);
+            let body =
+                json!({ "protected": protected, "payload": payload, "signature": signature });
+
+            let response = self.request(url, Method::POST, Some(body.to_string())).await?;
+            if response.status().class() == Some(StatusClass::Success) {
+                return Ok(response);
+            }
+            // the response came with a fresh nonce, which is worth one more try
+            let problem = serde_json::from_slice::<Value>(response.body().unwrap_or_default());
+            let bad_nonce = problem
+                .is_ok_and(|problem| problem["type"] == "urn:ietf:params:acme:error:badNonce");
+            if !bad_nonce || retried {
+                return Err(ca_error(&response));
+            }
+            retried = true;
+        }
+    }
+
+    async fn request(
+        &self,
+        url: &str,
+        method: Method,
+        body: Option<String>,
+    ) -> Result<RawResponse, AcmeError> {
+        let invalid = || AcmeError::InvalidUrl(url.to_owned());
+        let uri: Uri = url.parse().map_err(|_| invalid())?;
+        let host = uri.host().map_err(|_| invalid())?;
+        let https = match uri.scheme() {
+            "https" => true,
+            "http" => false,
+            _ => return Err(invalid()),
+        };
+        let (name, port) = (host.hostname(), host.port_or(if https { 443 } else { 80 }));
+
+        let mut headers = Headers::empty();
+        headers.set("Host", uri.authority().to_owned());
+        headers.set("User-Agent", "toot".to_owned());
+        if let Some(ref body) = body {
+            headers.set("Content-Type", "application/jose+json".to_owned());
+            headers.set("Content-Length", body.len().to_string());
+        }
+        let target = match uri.query() {
+            Some(query) => RequestTarget::origin(&format!("{}?{query}", uri.path())),
+            None => RequestTarget::origin(uri.path()),
+        };
+        let request_line = RequestLine { method, target, version: HttpVersion::Http1_1 };
+        let body = body.map(String::into_bytes);
+        let request = RawRequest { request_line, headers, body, extensions: Default::default() };
+
+        let stream = TcpStream::connect((name.as_str(), port));
+        let response = if https {
+            let Some(ref tls) = self.tls else {
+                return Err(AcmeError::InvalidUrl(format!("{url} needs a tls config")));
+            };
+            let server_name = ServerName::try_from(name.clone()).map_err(|_| invalid())?;
+            let stream = TlsConnector::from(tls.clone()).connect(server_name, stream.await?);
+            send(stream.await?, request, &self.limits).await?
+        } else {
+            send(stream.await?, request, &self.limits).await?
+        };
+
+        if let Some(nonce) = response.headers().get("Replay-Nonce") {
+            self.session.lock().unwrap().nonce = Some(nonce.to_owned());
+        }
+        Ok(response)
+    }
+
+    fn cached(&self) -> Option<Arc<CertifiedKey>> {
+        let dir = self.config.cache_dir.as_ref()?;
+        load_certified_key(dir.join("cert.pem"), dir.join("key.pem")).ok()
+    }
+
+    fn store(&self, issued: &Issued) -> io::Result<()> {
+        let Some(ref dir) = self.config.cache_dir else {
+            return Ok(());
+        };
+        std::fs::create_dir_all(dir)?;
+        write_private(&dir.join("key.pem"), issued.private_key_pem.as_bytes())?;
+        std::fs::write(dir.join("cert.pem"), &issued.cert_chain_pem)
+    }
+}
+
+/// A PKCS #10 certification request for `domains` as subject alternative names, with an empty
+/// subject as CAs only look at the former
+fn certification_request(domains: &[String], key: &EcdsaKeyPair, rng: &SystemRandom) -> Vec<u8> {
+    let algorithm = der::sequence(&[OID_EC_PUBLIC_KEY, OID_P256]);
+    let public_key = der::sequence(&[&algorithm, &der::bit_string(key.public_key().as_ref())]);
+
+    let names = domains.iter().flat_map(|d| der::tlv(DNS_NAME, d.as_bytes())).collect::<Vec<_>>();
+    let names = der::tlv(OCTET_STRING, &der::sequence(&[&names]));
+    let extensions = der::sequence(&[&der::sequence(&[OID_SUBJECT_ALT_NAME, &names])]);
+    let request = der::sequence(&[OID_EXTENSION_REQUEST, &der::tlv(SET, &extensions)]);
+
+    let version = [0x02, 0x01, 0x00];
+    let subject = der::sequence(&[]);
+    let attributes = der::tlv(ATTRIBUTES, &request);
+    let info = der::sequence(&[&version, &subject, &public_key, &attributes]);
+    let signature = key.sign(rng, &info).expect("signing with a valid key");
+    let algorithm = der::sequence(&[OID_ECDSA_SHA256]);
+    der::sequence(&[&info, &algorithm, &der::bit_string(signature.as_ref())])
+}
+
+fn pem(label: &str, der: &[u8]) -> String {
+    let encoded = STANDARD.encode(der);
+    let mut pem = format!("-----BEGIN {label}-----\n");
+    for line in encoded.as_bytes().chunks(64) {
+        pem.push_str(std::str::from_utf8(line).expect("base64 is ascii"));
+        pem.push('\n');
+    }
+    pem.push_str(&format!("-----END {label}-----\n"));
+    pem
+}
+
+/// Writes a file only its owner can read
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(contents)
+}
+
+fn location(response: &RawResponse) -> Result<String, AcmeError> {
+    let location = response.headers().get("Location");
+    location.map(str::to_owned).ok_or_else(|| AcmeError::InvalidResponse("no Location".to_owned()))
+}
+
+fn parse_json<T: DeserializeOwned>(response: &RawResponse) -> Result<T, AcmeError> {
+    serde_json::from_slice(response.body().unwrap_or_default())
+        .map_err(|err| AcmeError::InvalidResponse(err.to_string()))
+}
+
+fn ca_error(response: &RawResponse) -> AcmeError {
+    let body = String::from_utf8_lossy(response.body().unwrap_or_default()).into_owned();
+    AcmeError::Ca(response.status(), body)
+}
@@ -0,0 +1,93 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::protocol::days_from_civil;
+
+pub(super) const SEQUENCE: u8 = 0x30;
+pub(super) const SET: u8 = 0x31;
+pub(super) const OCTET_STRING: u8 = 0x04;
+const BIT_STRING: u8 = 0x03;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+/// `[0]` of the optional certificate version
+const VERSION: u8 = 0xA0;
+
+/// One element, `content` prefixed with `tag` and its length
+pub(super) fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes = len.to_be_bytes();
+        let skip = bytes.iter().take_while(|b| **b == 0).count();
+        out.push(0x80 | (bytes.len() - skip) as u8);
+        out.extend_from_slice(&bytes[skip..]);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+pub(super) fn sequence(elements: &[&[u8]]) -> Vec<u8> {
+    tlv(SEQUENCE, &elements.concat())
+}
+
+/// Whole bytes, no unused bits
+pub(super) fn bit_string(bytes: &[u8]) -> Vec<u8> {
+    tlv(BIT_STRING, &[&[0][..], bytes].concat())
+}
+
+/// The first element of `data` as tag and content, and what follows it
+pub(super) fn read(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = data.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = match first {
+        0..=0x7F => (first as usize, rest),
+        0x81..=0x84 => {
+            let (len, rest) = rest.split_at_checked((first & 0x7F) as usize)?;
+            (len.iter().fold(0, |len, b| len << 8 | *b as usize), rest)
+        }
+        _ => return None,
+    };
+    let (content, rest) = rest.split_at_checked(len)?;
+    Some((tag, content, rest))
+}
+
+/// The elements of a sequence's or set's content, up to the first malformed one
+pub(super) fn elements(mut data: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    std::iter::from_fn(move || {
+        let (tag, content, rest) = read(data)?;
+        data = rest;
+        Some((tag, content))
+    })
+}
+
+/// `notAfter` of a DER certificate
+pub(super) fn not_after(certificate: &[u8]) -> Option<SystemTime> {
+    let (_, certificate, _) = read(certificate)?;
+    let (_, tbs, _) = read(certificate)?;
+    // serial number, signature algorithm, issuer, then validity
+    let (_, validity) = elements(tbs).skip_while(|(tag, _)| *tag == VERSION).nth(3)?;
+    let (tag, time) = elements(validity).nth(1)?;
+    parse_time(tag, std::str::from_utf8(time).ok()?)
+}
+
+/// `YYMMDDHHMMSSZ` of a `UTCTime`, `YYYYMMDDHHMMSSZ` of a `GeneralizedTime`
+fn parse_time(tag: u8, time: &str) -> Option<SystemTime> {
+    let time = time.strip_suffix('Z').filter(|time| time.bytes().all(|b| b.is_ascii_digit()))?;
+    let (year, rest) = match (tag, time.len()) {
+        (UTC_TIME, 12) => {
+            let year: i64 = time[..2].parse().ok()?;
+            (if year < 50 { 2000 + year } else { 1900 + year }, &time[2..])
+        }
+        (GENERALIZED_TIME, 14) => (time[..4].parse().ok()?, &time[4..]),
+        _ => return None,
+    };
+    let field = |at: usize| rest[at..at + 2].parse::<u32>().ok();
+    let (month, day) = (field(0)?, field(2)?);
+    let (hour, minute, second) = (field(4)? as i64, field(6)? as i64, field(8)? as i64);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+    let secs = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).ok()?))
+}
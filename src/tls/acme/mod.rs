@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::protocol::{
+    Headers, HttpVersion, Method, RawRequest, RawResponse, StatusCode, StatusLine,
+};
+
+#[cfg(feature = "acme")]
+pub use self::client::{
+    generate_account_key, AcmeClient, AcmeConfig, AcmeError, Issued, LETS_ENCRYPT,
+    LETS_ENCRYPT_STAGING,
+};
+
+#[cfg(feature = "acme")]
+mod client;
+#[cfg(feature = "acme")]
+mod der;
+#[cfg(all(test, feature = "acme"))]
+mod tests;
+
+const CHALLENGE_PREFIX: &str = "/.well-known/acme-challenge/";
+
+/// Pending ACME HTTP-01 challenges, answered on the plaintext listener.
+///
+/// Whatever drives the ACME order, `AcmeClient` with the `acme` feature, registers each token
+/// with its key authorization before telling the CA to validate, and removes it once the
+/// authorization is settled.
+#[derive(Debug, Clone, Default)]
+pub struct Http01Challenges {
+    tokens: Arc<RwLock<HashMap<String, String>>>,
+}
+
+impl Http01Challenges {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&self, token: &str, key_authorization: &str) {
+        self.tokens.write().unwrap().insert(token.to_owned(), key_authorization.to_owned());
+    }
+
+    pub fn remove(&self, token: &str) {
+        self.tokens.write().unwrap().remove(token);
+    }
+
+    /// `Some` for requests to `/.well-known/acme-challenge/`, `None` lets the request through
+    pub fn respond(&self, request: &RawRequest) -> Option<RawResponse> {
+        let line = &request.request_line;
+        let token = line.target.path().strip_prefix(CHALLENGE_PREFIX)?;
+        if line.method != Method::GET {
+            return None;
+        }
+
+        let found = self.tokens.read().unwrap().get(token).cloned();
+        let response = match found {
+            Some(key_authorization) => {
+                let mut headers = Headers::empty();
+                headers.set("Content-Type", "application/octet-stream".to_owned());
+                let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::OK);
+                RawResponse::new(status_line, headers, Some(key_authorization.into_bytes()))
+            }
+            None => {
+                let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::NOT_FOUND);
+                RawResponse::new(status_line, Headers::empty(), None)
+            }
+        };
+        Some(response)
+    }
+}
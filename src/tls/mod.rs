@@ -0,0 +1,80 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+
+pub use tokio_rustls::rustls::sign::CertifiedKey;
+pub use tokio_rustls::rustls::SupportedCipherSuite;
+pub use tokio_rustls::server::TlsStream;
+
+pub use self::acceptor::{
+    read_early_data, with_ocsp_staple, TlsAcceptor, TlsAcceptorBuilder, TlsVersion,
+};
+pub use self::acme::Http01Challenges;
+#[cfg(feature = "acme")]
+pub use self::acme::{
+    generate_account_key, AcmeClient, AcmeConfig, AcmeError, Issued, LETS_ENCRYPT,
+    LETS_ENCRYPT_STAGING,
+};
+pub use self::reload::ReloadableCertificate;
+pub use self::resolver::{
+    certified_key_from_pem, load_certified_key, CertResolver, ResolveFuture, SniCertificates,
+};
+
+mod acceptor;
+mod acme;
+mod reload;
+mod resolver;
+#[cfg(test)]
+mod tests;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TlsError {
+    Io(io::ErrorKind),
+    /// the resolver had no certificate for the requested server name
+    UnknownServerName(Option<String>),
+    /// the client had no protocol version, cipher suite or ALPN protocol in common with the
+    /// acceptor, or broke the handshake protocol
+    ProtocolMismatch(String),
+    /// the client aborted the handshake with an alert, as when it doesn't trust the certificate
+    AlertReceived(String),
+    InvalidCertificate(String),
+    InvalidConfig(String),
+}
+
+impl Display for TlsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TlsError::Io(err) => write!(f, "tls failure: {err}"),
+            TlsError::UnknownServerName(Some(name)) => {
+                write!(f, "no certificate for server name: {name}")
+            }
+            TlsError::UnknownServerName(None) => {
+                write!(f, "no certificate for clients without SNI")
+            }
+            TlsError::ProtocolMismatch(reason) => write!(f, "tls protocol mismatch: {reason}"),
+            TlsError::AlertReceived(alert) => write!(f, "tls alert received: {alert}"),
+            TlsError::InvalidCertificate(reason) => write!(f, "invalid certificate: {reason}"),
+            TlsError::InvalidConfig(reason) => write!(f, "invalid tls config: {reason}"),
+        }
+    }
+}
+
+impl From<io::Error> for TlsError {
+    fn from(value: io::Error) -> Self {
+        use tokio_rustls::rustls::Error;
+
+        // handshakes fail with the rustls error wrapped in an io error
+        match value.get_ref().and_then(|err| err.downcast_ref::<Error>()) {
+            Some(Error::AlertReceived(alert)) => TlsError::AlertReceived(format!("{alert:?}")),
+            Some(
+                err @ (Error::PeerIncompatible(_)
+                | Error::PeerMisbehaved(_)
+                | Error::InappropriateMessage { .. }
+                | Error::InappropriateHandshakeMessage { .. }
+                | Error::InvalidMessage(_)
+                | Error::NoApplicationProtocol
+                | Error::PeerSentOversizedRecord),
+            ) => TlsError::ProtocolMismatch(err.to_string()),
+            _ => TlsError::Io(value.kind()),
+        }
+    }
+}
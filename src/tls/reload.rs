@@ -0,0 +1,68 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use tokio::task::JoinHandle;
+
+use super::{load_certified_key, CertResolver, CertifiedKey, ResolveFuture, TlsError};
+
+/// A certificate which can be replaced while the server runs.
+///
+/// Every handshake picks up the current certificate, connections which are already established
+/// keep the one they were started with.
+#[derive(Clone)]
+pub struct ReloadableCertificate {
+    current: Arc<RwLock<Arc<CertifiedKey>>>,
+}
+
+impl ReloadableCertificate {
+    pub fn new(key: Arc<CertifiedKey>) -> Self {
+        Self { current: Arc::new(RwLock::new(key)) }
+    }
+
+    pub fn current(&self) -> Arc<CertifiedKey> {
+        self.current.read().unwrap().clone()
+    }
+
+    pub fn swap(&self, key: Arc<CertifiedKey>) {
+        *self.current.write().unwrap() = key;
+    }
+
+    /// Re-reads the PEM files, on failure the current certificate stays in place
+    pub fn reload_from<P1, P2>(&self, cert_chain: P1, key: P2) -> Result<(), TlsError>
+    where
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        let key = load_certified_key(cert_chain, key)?;
+        self.swap(key);
+        Ok(())
+    }
+
+    /// Checks the files' modification times every `interval` and reloads when either changed
+    pub fn watch(&self, cert_chain: PathBuf, key: PathBuf, interval: Duration) -> JoinHandle<()> {
+        let certificate = self.clone();
+        tokio::spawn(async move {
+            let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+            let mut last: (Option<SystemTime>, Option<SystemTime>) =
+                (modified(&cert_chain), modified(&key));
+
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let now = (modified(&cert_chain), modified(&key));
+                // a half-written pair fails to load and is retried on the next tick
+                if now != last && certificate.reload_from(&cert_chain, &key).is_ok() {
+                    last = now;
+                }
+            }
+        })
+    }
+}
+
+impl CertResolver for ReloadableCertificate {
+    fn resolve<'a>(&'a self, _: Option<&'a str>) -> ResolveFuture<'a> {
+        let key = self.current();
+        Box::pin(async move { Some(key) })
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use tokio_rustls::rustls::crypto::ring::sign::any_supported_type;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+use super::{CertifiedKey, TlsError};
+
+pub type ResolveFuture<'a> = Pin<Box<dyn Future<Output = Option<Arc<CertifiedKey>>> + Send + 'a>>;
+
+/// Looks up the certificate for a handshake by its SNI server name.
+///
+/// Runs once per handshake before any certificate is sent, so it may do async work such as a
+/// database lookup.
+pub trait CertResolver: Send + Sync {
+    fn resolve<'a>(&'a self, server_name: Option<&'a str>) -> ResolveFuture<'a>;
+}
+
+/// Fixed set of certificates keyed by server name, `*.example.com` entries match one label
+#[derive(Default)]
+pub struct SniCertificates {
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+    fallback: Option<Arc<CertifiedKey>>,
+}
+
+impl SniCertificates {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, server_name: &str, key: Arc<CertifiedKey>) {
+        self.by_name.insert(server_name.to_ascii_lowercase(), key);
+    }
+
+    /// used for clients without SNI or with an unknown server name
+    pub fn set_fallback(&mut self, key: Arc<CertifiedKey>) {
+        self.fallback = Some(key);
+    }
+
+    pub fn lookup(&self, server_name: Option<&str>) -> Option<Arc<CertifiedKey>> {
+        let Some(name) = server_name.map(str::to_ascii_lowercase) else {
+            return self.fallback.clone();
+        };
+        let wildcard = || {
+            name.split_once('.').and_then(|(_, parent)| self.by_name.get(&format!("*.{parent}")))
+        };
+
+        self.by_name.get(&name).or_else(wildcard).or(self.fallback.as_ref()).cloned()
+    }
+}
+
+impl CertResolver for SniCertificates {
+    fn resolve<'a>(&'a self, server_name: Option<&'a str>) -> ResolveFuture<'a> {
+        let key = self.lookup(server_name);
+        Box::pin(async move { key })
+    }
+}
+
+/// Certificate chain and private key from PEM data
+pub fn certified_key_from_pem(
+    cert_chain: &[u8],
+    key: &[u8],
+) -> Result<Arc<CertifiedKey>, TlsError> {
+    let invalid = |err: &dyn std::fmt::Display| TlsError::InvalidCertificate(err.to_string());
+
+    let cert_chain = CertificateDer::pem_slice_iter(cert_chain)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| invalid(&err))?;
+    if cert_chain.is_empty() {
+        return Err(TlsError::InvalidCertificate("no certificate found".to_owned()));
+    }
+    let key = PrivateKeyDer::from_pem_slice(key).map_err(|err| invalid(&err))?;
+    let key = any_supported_type(&key).map_err(|err| invalid(&err))?;
+
+    Ok(Arc::new(CertifiedKey::new(cert_chain, key)))
+}
+
+/// Certificate chain and private key from PEM files
+pub fn load_certified_key<P1, P2>(cert_chain: P1, key: P2) -> Result<Arc<CertifiedKey>, TlsError>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let cert_chain = std::fs::read(cert_chain)?;
+    let key = std::fs::read(key)?;
+    certified_key_from_pem(&cert_chain, &key)
+}
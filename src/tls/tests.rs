@@ -0,0 +1,235 @@
+use std::sync::Arc;
+
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+use super::*;
+
+fn self_signed(name: &str) -> (Arc<CertifiedKey>, CertificateDer<'static>) {
+    let generated = rcgen::generate_simple_self_signed(vec![name.to_owned()]).unwrap();
+    let key = certified_key_from_pem(
+        generated.cert.pem().as_bytes(),
+        generated.key_pair.serialize_pem().as_bytes(),
+    )
+    .unwrap();
+    (key, generated.cert.der().clone())
+}
+
+fn connector(roots: &[CertificateDer<'static>]) -> TlsConnector {
+    let mut store = RootCertStore::empty();
+    for root in roots {
+        store.add(root.clone()).unwrap();
+    }
+    let config = ClientConfig::builder_with_provider(Arc::new(
+        tokio_rustls::rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .unwrap()
+    .with_root_certificates(store)
+    .with_no_client_auth();
+    TlsConnector::from(Arc::new(config))
+}
+
+#[tokio::test]
+pub async fn test_acceptor_selects_certificate_by_sni() {
+    let (a_key, a_der) = self_signed("a.test");
+    let (b_key, b_der) = self_signed("b.test");
+    let mut certificates = SniCertificates::new();
+    certificates.add("a.test", a_key);
+    certificates.add("b.test", b_key);
+    let acceptor = TlsAcceptor::new(certificates);
+
+    let (client, server) = tokio::io::duplex(16 * 1024);
+    let server = tokio::spawn(async move { acceptor.accept(server).await.map(|_| ()) });
+
+    let name = ServerName::try_from("b.test").unwrap();
+    let stream = connector(&[a_der, b_der.clone()]).connect(name, client).await.unwrap();
+    let (_, session) = stream.get_ref();
+
+    assert_eq!(Some(&[b_der][..]), session.peer_certificates());
+    assert_eq!(Ok(()), server.await.unwrap());
+}
+
+#[tokio::test]
+pub async fn test_acceptor_rejects_unknown_server_name() {
+    let (a_key, a_der) = self_signed("a.test");
+    let mut certificates = SniCertificates::new();
+    certificates.add("a.test", a_key);
+    let acceptor = TlsAcceptor::new(certificates);
+
+    let (client, server) = tokio::io::duplex(16 * 1024);
+    let server = tokio::spawn(async move { acceptor.accept(server).await.map(|_| ()) });
+
+    let name = ServerName::try_from("other.test").unwrap();
+    assert!(connector(&[a_der]).connect(name, client).await.is_err());
+    assert_eq!(
+        Err(TlsError::UnknownServerName(Some("other.test".to_owned()))),
+        server.await.unwrap()
+    );
+}
+
+#[test]
+pub fn test_sni_wildcard_lookup() {
+    let (key, _) = self_signed("*.example.com");
+    let mut certificates = SniCertificates::new();
+    certificates.add("*.example.com", key);
+
+    assert!(certificates.lookup(Some("WWW.example.com")).is_some());
+    assert!(certificates.lookup(Some("a.b.example.com")).is_none());
+    assert!(certificates.lookup(None).is_none());
+}
+
+#[tokio::test]
+pub async fn test_reloadable_certificate_picks_up_new_files() {
+    let dir = std::env::temp_dir().join(format!("toot-reload-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+    let write_pair = |name: &str| {
+        let generated = rcgen::generate_simple_self_signed(vec![name.to_owned()]).unwrap();
+        std::fs::write(&cert_path, generated.cert.pem()).unwrap();
+        std::fs::write(&key_path, generated.key_pair.serialize_pem()).unwrap();
+        generated.cert.der().clone()
+    };
+
+    let first = write_pair("first.test");
+    let certificate =
+        ReloadableCertificate::new(load_certified_key(&cert_path, &key_path).unwrap());
+    assert_eq!(first, certificate.current().cert[0]);
+
+    let second = write_pair("second.test");
+    certificate.reload_from(&cert_path, &key_path).unwrap();
+    assert_eq!(second, certificate.current().cert[0]);
+
+    std::fs::write(&key_path, "garbage").unwrap();
+    assert!(certificate.reload_from(&cert_path, &key_path).is_err());
+    assert_eq!(second, certificate.current().cert[0]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+pub async fn test_http01_challenge_response() {
+    let challenges = Http01Challenges::new();
+    challenges.insert("abc", "abc.thumbprint");
+
+    let mut source: &[u8] = b"GET /.well-known/acme-challenge/abc HTTP/1.1\r\nHost: a.test\r\n\r\n";
+    let request = crate::protocol::read_http_request(&mut source).await.unwrap();
+    let response = challenges.respond(&request).unwrap().into_vec();
+    assert!(response.starts_with(b"HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with(b"\r\n\r\nabc.thumbprint"));
+
+    challenges.remove("abc");
+    let response = challenges.respond(&request).unwrap().into_vec();
+    assert!(response.starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+
+    let mut source: &[u8] = b"GET /index.html HTTP/1.1\r\n\r\n";
+    let request = crate::protocol::read_http_request(&mut source).await.unwrap();
+    assert!(challenges.respond(&request).is_none());
+}
+
+#[tokio::test]
+pub async fn test_acceptor_restricts_protocol_version_and_staples_ocsp() {
+    let (key, der) = self_signed("a.test");
+    let key = with_ocsp_staple(&key, b"staple".to_vec());
+    let mut certificates = SniCertificates::new();
+    certificates.add("a.test", key);
+    let acceptor = TlsAcceptor::builder(certificates)
+        .protocol_versions(&[TlsVersion::Tls1_2])
+        .build()
+        .unwrap();
+
+    let (client, server) = tokio::io::duplex(16 * 1024);
+    let server = tokio::spawn(async move { acceptor.accept(server).await.map(|_| ()) });
+
+    let name = ServerName::try_from("a.test").unwrap();
+    let stream = connector(&[der]).connect(name, client).await.unwrap();
+    let (_, session) = stream.get_ref();
+
+    assert_eq!(Some(tokio_rustls::rustls::ProtocolVersion::TLSv1_2), session.protocol_version());
+    assert_eq!(Ok(()), server.await.unwrap());
+}
+
+#[test]
+pub fn test_builder_rejects_versions_without_suites() {
+    use tokio_rustls::rustls::crypto::ring::cipher_suite::TLS13_AES_128_GCM_SHA256;
+
+    let builder = TlsAcceptor::builder(SniCertificates::new())
+        .protocol_versions(&[TlsVersion::Tls1_2])
+        .cipher_suites(&[TLS13_AES_128_GCM_SHA256]);
+
+    assert!(matches!(builder.build(), Err(TlsError::InvalidConfig(_))));
+}
+
+#[tokio::test]
+pub async fn test_acceptor_classifies_handshake_failures() {
+    let (key, _) = self_signed("a.test");
+    let acceptor = TlsAcceptor::new(ReloadableCertificate::new(key));
+
+    let (mut client, server) = tokio::io::duplex(16 * 1024);
+    let accepting = acceptor.clone();
+    let server = tokio::spawn(async move { accepting.accept(server).await.map(|_| ()) });
+    tokio::io::AsyncWriteExt::write_all(&mut client, b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+    assert!(matches!(server.await.unwrap(), Err(TlsError::ProtocolMismatch(_))));
+
+    let (client, server) = tokio::io::duplex(16 * 1024);
+    let server = tokio::spawn(async move { acceptor.accept(server).await.map(|_| ()) });
+    let name = ServerName::try_from("a.test").unwrap();
+    // the client doesn't trust the certificate
+    assert!(connector(&[]).connect(name, client).await.is_err());
+    assert!(matches!(server.await.unwrap(), Err(TlsError::AlertReceived(_))));
+}
+
+#[tokio::test]
+pub async fn test_acceptor_reads_early_data() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (key, der) = self_signed("a.test");
+    let acceptor = TlsAcceptor::builder(ReloadableCertificate::new(key))
+        .protocol_versions(&[TlsVersion::Tls1_3])
+        .early_data(1024)
+        .build()
+        .unwrap();
+    let mut store = RootCertStore::empty();
+    store.add(der).unwrap();
+    let mut config = ClientConfig::builder_with_provider(Arc::new(
+        tokio_rustls::rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .unwrap()
+    .with_root_certificates(store)
+    .with_no_client_auth();
+    config.enable_early_data = true;
+    let connector = TlsConnector::from(Arc::new(config)).early_data(true);
+    let name = ServerName::try_from("a.test").unwrap();
+
+    // the first handshake gets the client a ticket, read along with the response
+    let (client, server) = tokio::io::duplex(16 * 1024);
+    let accepting = acceptor.clone();
+    let server = tokio::spawn(async move {
+        let mut stream = accepting.accept(server).await.unwrap();
+        stream.write_all(b"x").await.unwrap();
+        read_early_data(&mut stream)
+    });
+    let mut stream = connector.connect(name.clone(), client).await.unwrap();
+    stream.read_exact(&mut [0; 1]).await.unwrap();
+    assert_eq!(Vec::<u8>::new(), server.await.unwrap());
+
+    let (client, server) = tokio::io::duplex(16 * 1024);
+    let server = tokio::spawn(async move {
+        let mut stream = acceptor.accept(server).await.unwrap();
+        let early_data = read_early_data(&mut stream);
+        let mut rest = vec![0; 4];
+        stream.read_exact(&mut rest).await.unwrap();
+        (early_data, rest)
+    });
+    let mut stream = connector.connect(name, client).await.unwrap();
+    // written before the handshake is complete
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+    stream.flush().await.unwrap();
+    stream.write_all(b"more").await.unwrap();
+    stream.flush().await.unwrap();
+    let (early_data, rest) = server.await.unwrap();
+    assert_eq!(b"GET / HTTP/1.1\r\n\r\n", &early_data[..]);
+    assert_eq!(b"more", &rest[..]);
+}
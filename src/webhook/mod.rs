@@ -0,0 +1,197 @@
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ring::hmac;
+
+use crate::protocol::{Headers, HttpVersion, RawRequest, RawResponse, StatusCode, StatusLine};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SignatureError {
+    /// no signature header
+    Missing,
+    /// a signature header which doesn't follow the scheme
+    Malformed,
+    /// signed this many seconds further from now than the tolerance allows
+    Expired(u64),
+    /// no signature matches the body
+    Mismatch,
+}
+
+impl Display for SignatureError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignatureError::Missing => write!(f, "missing signature"),
+            SignatureError::Malformed => write!(f, "malformed signature"),
+            SignatureError::Expired(secs) => write!(f, "signature timestamp off by {secs}s"),
+            SignatureError::Mismatch => write!(f, "signature mismatch"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Scheme {
+    /// `prefix` followed by the hex digest of the body
+    Hex { prefix: String },
+    /// `t=<unix seconds>` and one or more `v1=<hex digest>` of `<t>.<body>`
+    Timestamped { tolerance: Duration },
+}
+
+/// Verifies HMAC-SHA256 signatures of webhook deliveries.
+///
+/// Signatures are computed over the body exactly as it was received, so verify before
+/// anything rewrites it. Several secrets may be accepted at once while one is rotated.
+#[derive(Clone, Debug)]
+pub struct WebhookVerifier {
+    header: String,
+    scheme: Scheme,
+    keys: Vec<hmac::Key>,
+}
+
+impl WebhookVerifier {
+    /// `header` holding `prefix` and the hex digest of the body
+    pub fn hex(header: &str, prefix: &str, secret: &[u8]) -> Self {
+        let scheme = Scheme::Hex { prefix: prefix.to_owned() };
+        Self { header: header.to_owned(), scheme, keys: vec![key(secret)] }
+    }
+
+    /// `header` holding a timestamp and digests of it and the body, accepted within 5 minutes
+    /// of it
+    pub fn timestamped(header: &str, secret: &[u8]) -> Self {
+        let scheme = Scheme::Timestamped { tolerance: Duration::from_secs(300) };
+        Self { header: header.to_owned(), scheme, keys: vec![key(secret)] }
+    }
+
+    /// `X-Hub-Signature-256: sha256=<hex>`, as GitHub signs deliveries
+    pub fn github(secret: &[u8]) -> Self {
+        Self::hex("X-Hub-Signature-256", "sha256=", secret)
+    }
+
+    /// `Stripe-Signature: t=<timestamp>,v1=<hex>`, as Stripe signs events
+    pub fn stripe(secret: &[u8]) -> Self {
+        Self::timestamped("Stripe-Signature", secret)
+    }
+
+    /// Also accepts signatures made with `secret`
+    pub fn secret(mut self, secret: &[u8]) -> Self {
+        self.keys.push(key(secret));
+        self
+    }
+
+    /// How far the timestamp of a timestamped signature may be from now, against replays
+    pub fn tolerance(mut self, tolerance: Duration) -> Self {
+        if let Scheme::Timestamped { tolerance: ref mut current } = self.scheme {
+            *current = tolerance;
+        }
+        self
+    }
+
+    pub fn verify(&self, headers: &Headers, body: &[u8]) -> Result<(), SignatureError> {
+        self.verify_at(headers, body, SystemTime::now())
+    }
+
+    /// Same as `verify` with the time it is now
+    pub fn verify_at(
+        &self,
+        headers: &Headers,
+        body: &[u8],
+        now: SystemTime,
+    ) -> Result<(), SignatureError> {
+        let value = headers.get(&self.header).ok_or(SignatureError::Missing)?.trim();
+        match self.scheme {
+            Scheme::Hex { ref prefix } => {
+                let digest =
+                    value.strip_prefix(prefix.as_str()).ok_or(SignatureError::Malformed)?;
+                let digest = decode_hex(digest).ok_or(SignatureError::Malformed)?;
+                match self.keys.iter().any(|key| hmac::verify(key, body, &digest).is_ok()) {
+                    true => Ok(()),
+                    false => Err(SignatureError::Mismatch),
+                }
+            }
+            Scheme::Timestamped { tolerance } => {
+                let mut timestamp = None;
+                let mut digests = Vec::new();
+                for item in value.split(',') {
+                    match item.trim().split_once('=') {
+                        Some(("t", t)) => timestamp = Some(t.parse::<u64>().ok()),
+                        Some(("v1", digest)) => digests.push(decode_hex(digest)),
+                        // other schemes, e.g. Stripe's `v0` test signatures
+                        Some(_) => {}
+                        None => return Err(SignatureError::Malformed),
+                    }
+                }
+                let timestamp = timestamp.flatten().ok_or(SignatureError::Malformed)?;
+                if digests.is_empty() {
+                    return Err(SignatureError::Malformed);
+                }
+                let now = unix_secs(now);
+                if now.abs_diff(timestamp) > tolerance.as_secs() {
+                    let off = now.abs_diff(timestamp) - tolerance.as_secs();
+                    return Err(SignatureError::Expired(off));
+                }
+                let signed = [format!("{timestamp}.").as_bytes(), body].concat();
+                let matches = digests.iter().flatten().any(|digest| {
+                    self.keys.iter().any(|key| hmac::verify(key, &signed, digest).is_ok())
+                });
+                match matches {
+                    true => Ok(()),
+                    false => Err(SignatureError::Mismatch),
+                }
+            }
+        }
+    }
+
+    /// The value of the signature header for `body` sent at `at`, made with the first secret
+    pub fn sign(&self, body: &[u8], at: SystemTime) -> String {
+        match self.scheme {
+            Scheme::Hex { ref prefix } => {
+                format!("{prefix}{}", encode_hex(hmac::sign(&self.keys[0], body).as_ref()))
+            }
+            Scheme::Timestamped { .. } => {
+                let timestamp = unix_secs(at);
+                let signed = [format!("{timestamp}.").as_bytes(), body].concat();
+                let digest = hmac::sign(&self.keys[0], &signed);
+                format!("t={timestamp},v1={}", encode_hex(digest.as_ref()))
+            }
+        }
+    }
+
+    /// Requests without a valid signature over their body get 401, checked against the body
+    /// as received if it was kept as `RawBody`
+    pub async fn call<F, Fut>(&self, request: RawRequest, handler: F) -> RawResponse
+    where
+        F: FnOnce(RawRequest) -> Fut,
+        Fut: Future<Output = RawResponse>,
+    {
+        let body = request.raw_body().or(request.body.as_deref()).unwrap_or_default();
+        match self.verify(&request.headers, body) {
+            Ok(()) => handler(request).await,
+            Err(_) => {
+                let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::UNAUTHORIZED);
+                RawResponse::new(status_line, Headers::empty(), Some(Vec::new()))
+            }
+        }
+    }
+}
+
+fn key(secret: &[u8]) -> hmac::Key {
+    hmac::Key::new(hmac::HMAC_SHA256, secret)
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok()).collect()
+}
@@ -0,0 +1,74 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::*;
+use crate::protocol::read_http_request;
+
+fn headers(name: &str, value: &str) -> Headers {
+    let mut headers = Headers::empty();
+    headers.set(name, value.to_owned());
+    headers
+}
+
+#[test]
+pub fn test_hex_signatures() {
+    // the example of GitHub's documentation on validating deliveries
+    let github = WebhookVerifier::github(b"It's a Secret to Everybody");
+    let signature = "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+    let signed = headers("X-Hub-Signature-256", signature);
+    assert_eq!(Ok(()), github.verify(&signed, b"Hello, World!"));
+    assert_eq!(signature, github.sign(b"Hello, World!", SystemTime::now()));
+
+    assert_eq!(Err(SignatureError::Mismatch), github.verify(&signed, b"Hello, World?"));
+    assert_eq!(Err(SignatureError::Missing), github.verify(&Headers::empty(), b""));
+    let unprefixed = headers("X-Hub-Signature-256", &signature[7..]);
+    assert_eq!(Err(SignatureError::Malformed), github.verify(&unprefixed, b"Hello, World!"));
+
+    let rotated = WebhookVerifier::github(b"new secret").secret(b"It's a Secret to Everybody");
+    assert_eq!(Ok(()), rotated.verify(&signed, b"Hello, World!"));
+}
+
+#[test]
+pub fn test_timestamped_signatures() {
+    let stripe = WebhookVerifier::stripe(b"whsec_test");
+    let sent = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let body = br#"{"id":"evt_1"}"#;
+    let signature = stripe.sign(body, sent);
+    assert!(signature.starts_with("t=1700000000,v1="));
+
+    let signed = headers("Stripe-Signature", &format!("{signature},v0=00"));
+    let later = sent + Duration::from_secs(299);
+    assert_eq!(Ok(()), stripe.verify_at(&signed, body, later));
+    assert_eq!(Err(SignatureError::Mismatch), stripe.verify_at(&signed, b"{}", later));
+    let late = sent + Duration::from_secs(310);
+    assert_eq!(Err(SignatureError::Expired(10)), stripe.verify_at(&signed, body, late));
+    let lenient = stripe.clone().tolerance(Duration::from_secs(600));
+    assert_eq!(Ok(()), lenient.verify_at(&signed, body, late));
+
+    let replayed = signature.replace("t=1700000000", "t=1700000100");
+    let replayed = headers("Stripe-Signature", &replayed);
+    assert_eq!(Err(SignatureError::Mismatch), stripe.verify_at(&replayed, body, later));
+    for malformed in ["v1=00", "t=1700000000", "t=soon,v1=00", "garbage"] {
+        let malformed = headers("Stripe-Signature", malformed);
+        assert_eq!(Err(SignatureError::Malformed), stripe.verify_at(&malformed, body, later));
+    }
+}
+
+#[tokio::test]
+pub async fn test_verify_requests() {
+    let verifier = WebhookVerifier::hex("X-Signature", "", b"secret");
+    let signature = verifier.sign(b"payload", SystemTime::now());
+    let source =
+        format!("POST /hook HTTP/1.1\r\nX-Signature: {signature}\r\nContent-Length: 7\r\n\r\n");
+    let ok = |_| async {
+        RawResponse::new(
+            StatusLine::new(HttpVersion::Http1_1, StatusCode::OK),
+            Headers::empty(),
+            None,
+        )
+    };
+
+    let request = read_http_request(&mut format!("{source}payload").as_bytes()).await.unwrap();
+    assert_eq!(StatusCode::OK, verifier.call(request, ok).await.status());
+    let request = read_http_request(&mut format!("{source}tampers").as_bytes()).await.unwrap();
+    assert_eq!(StatusCode::UNAUTHORIZED, verifier.call(request, ok).await.status());
+}
@@ -0,0 +1,305 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{split, AsyncRead, AsyncWrite, ReadHalf, WriteHalf};
+use tokio::sync::{mpsc, Notify};
+use tokio::time::{interval_at, sleep_until, Instant};
+
+use super::frame::{read_frame, write_frame, Frame, Opcode};
+use super::{CloseCode, CloseFrame, DeflateConfig, Message, WebSocketConfig, WebSocketError};
+use crate::protocol::{deflate_sync, inflate_after, TOO_LARGE};
+
+/// what permessage-deflate strips from the end of each compressed message, followed by an empty
+/// final block for the decoder to stop at
+const DEFLATE_TAIL: [u8; 9] = [0x00, 0x00, 0xFF, 0xFF, 0x01, 0x00, 0x00, 0xFF, 0xFF];
+
+#[derive(Debug)]
+struct Shared {
+    pong: Notify,
+    disconnected: Notify,
+    timed_out: AtomicBool,
+    /// sent ahead of the queue by `Sender::force_close`, which `force` tells the writer about
+    forced: Mutex<Option<CloseFrame>>,
+    force: Notify,
+}
+
+/// Cloneable handle for sending messages from other tasks.
+///
+/// Messages go through a bounded queue, so `send` waits while a slow client catches up.
+#[derive(Debug, Clone)]
+pub struct Sender {
+    queue: mpsc::Sender<Frame>,
+    shared: Arc<Shared>,
+}
+
+impl Sender {
+    pub async fn send(&self, message: Message) -> Result<(), WebSocketError> {
+        self.queue.send(message.into_frame()).await.map_err(|_| WebSocketError::Closed)
+    }
+
+    /// Like `send`, but fails with `QueueFull` instead of waiting
+    pub fn try_send(&self, message: Message) -> Result<(), WebSocketError> {
+        self.queue.try_send(message.into_frame()).map_err(|err| match err {
+            mpsc::error::TrySendError::Full(_) => WebSocketError::QueueFull,
+            mpsc::error::TrySendError::Closed(_) => WebSocketError::Closed,
+        })
+    }
+
+    /// Closes the connection with `frame` without waiting for the queue, whose messages are
+    /// dropped, e.g. for a client too slow to keep up
+    pub fn force_close(&self, frame: CloseFrame) {
+        *self.shared.forced.lock().unwrap() = Some(frame);
+        self.shared.force.notify_one();
+    }
+}
+
+/// A server side websocket connection, created after a successful handshake.
+///
+/// Pings are answered automatically; with `ping_interval` set the connection also sends its own
+/// pings and gives up on the peer when no pong arrives within `pong_timeout`.
+pub struct WebSocket<S> {
+    reader: ReadHalf<S>,
+    sender: Sender,
+    shared: Arc<Shared>,
+    max_message_size: usize,
+    deflate: Option<DeflateConfig>,
+    /// what the client's compressed messages may refer back to, with context takeover
+    window: Vec<u8>,
+    close_sent: bool,
+    closed: bool,
+}
+
+impl<S> WebSocket<S>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    /// Must be called within a tokio runtime, the write side runs on its own task
+    pub fn new(stream: S, config: &WebSocketConfig) -> Self {
+        Self::with_deflate(stream, config, None)
+    }
+
+    /// Like `new`, with the permessage-deflate parameters agreed in `Handshake::accept`, see
+    /// `Negotiated::deflate`: compressed messages are decoded and the ones sent compressed
+    /// when that makes them shorter
+    pub fn with_deflate(
+        stream: S,
+        config: &WebSocketConfig,
+        deflate: Option<DeflateConfig>,
+    ) -> Self {
+        let (reader, writer) = split(stream);
+        let (queue, receiver) = mpsc::channel(config.send_queue.max(1));
+        let shared = Arc::new(Shared {
+            pong: Notify::new(),
+            disconnected: Notify::new(),
+            timed_out: AtomicBool::new(false),
+            forced: Mutex::new(None),
+            force: Notify::new(),
+        });
+
+        tokio::spawn(write_loop(writer, receiver, shared.clone(), config.clone(), deflate));
+
+        Self {
+            reader,
+            sender: Sender { queue, shared: shared.clone() },
+            shared,
+            max_message_size: config.max_message_size,
+            deflate,
+            window: Vec::new(),
+            close_sent: false,
+            closed: false,
+        }
+    }
+}
+
+impl<S> WebSocket<S>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    pub fn sender(&self) -> Sender {
+        self.sender.clone()
+    }
+
+    pub async fn send(&mut self, message: Message) -> Result<(), WebSocketError> {
+        self.close_sent |= matches!(message, Message::Close(_));
+        self.sender.send(message).await
+    }
+
+    /// Starts the closing handshake, keep calling `recv` until the peer's close frame arrives
+    pub async fn close(&mut self, frame: Option<CloseFrame>) -> Result<(), WebSocketError> {
+        if self.close_sent {
+            return Ok(());
+        }
+        self.send(Message::Close(frame)).await
+    }
+
+    /// Receives the next data or close message, ping and pong frames are handled internally
+    pub async fn recv(&mut self) -> Result<Message, WebSocketError> {
+        if self.closed {
+            return Err(WebSocketError::Closed);
+        }
+
+        let shared = self.shared.clone();
+        let result = tokio::select! {
+            message = self.read_message() => message,
+            _ = shared.disconnected.notified() => Err(WebSocketError::PongTimeout),
+        };
+
+        match result {
+            Ok(Message::Close(frame)) => {
+                self.closed = true;
+                if !self.close_sent {
+                    let code = frame.as_ref().map(|f| f.code).unwrap_or(CloseCode::NORMAL);
+                    let _ = self.send(Message::Close(Some(CloseFrame::new(code, "")))).await;
+                }
+                Ok(Message::Close(frame))
+            }
+            Ok(message) => Ok(message),
+            Err(err) => {
+                self.closed = true;
+                if let Some(code) = err.close_code() {
+                    let _ = self.sender.try_send(Message::Close(Some(CloseFrame::new(code, ""))));
+                }
+                Err(err)
+            }
+        }
+    }
+
+    async fn read_message(&mut self) -> Result<Message, WebSocketError> {
+        if self.shared.timed_out.load(Ordering::Acquire) {
+            return Err(WebSocketError::PongTimeout);
+        }
+
+        let mut fragments: Option<(Opcode, bool, Vec<u8>)> = None;
+        loop {
+            let frame =
+                read_frame(&mut self.reader, self.max_message_size, self.deflate.is_some()).await?;
+            if frame.compressed && frame.opcode == Opcode::Continuation {
+                return Err(WebSocketError::Protocol("compressed continuation frame"));
+            }
+
+            match frame.opcode {
+                Opcode::Ping => {
+                    // fails only once our close frame is out, the peer no longer needs a pong then
+                    let _ = self.sender.send(Message::Pong(frame.payload)).await;
+                    continue;
+                }
+                Opcode::Pong => {
+                    self.shared.pong.notify_one();
+                    continue;
+                }
+                Opcode::Close => return CloseFrame::parse(&frame.payload).map(Message::Close),
+                Opcode::Continuation => match fragments {
+                    Some((_, _, ref mut buffer)) => buffer.extend_from_slice(&frame.payload),
+                    None => return Err(WebSocketError::Protocol("unexpected continuation frame")),
+                },
+                Opcode::Text | Opcode::Binary => {
+                    if fragments.is_some() {
+                        return Err(WebSocketError::Protocol("expected continuation frame"));
+                    }
+                    fragments = Some((frame.opcode, frame.compressed, frame.payload));
+                }
+            }
+
+            let (_, _, buffer) = fragments.as_ref().expect("data frame buffered");
+            if buffer.len() > self.max_message_size {
+                return Err(WebSocketError::MessageTooLarge);
+            }
+            if !frame.fin {
+                continue;
+            }
+
+            let (opcode, compressed, mut buffer) = fragments.take().expect("data frame buffered");
+            if compressed {
+                buffer = self.inflate(buffer)?;
+            }
+            return match opcode {
+                Opcode::Text => String::from_utf8(buffer)
+                    .map(Message::Text)
+                    .map_err(|_| WebSocketError::InvalidUtf8),
+                _ => Ok(Message::Binary(buffer)),
+            };
+        }
+    }
+
+    fn inflate(&mut self, mut data: Vec<u8>) -> Result<Vec<u8>, WebSocketError> {
+        let deflate = self.deflate.expect("compressed frames are read only with deflate agreed");
+        data.extend_from_slice(&DEFLATE_TAIL);
+        let (message, _) =
+            inflate_after(&self.window, &data, self.max_message_size).map_err(|err| match err {
+                TOO_LARGE => WebSocketError::MessageTooLarge,
+                _ => WebSocketError::Protocol(err.0),
+            })?;
+        if !deflate.client_no_context_takeover {
+            self.window.extend_from_slice(&message);
+            let excess = self.window.len().saturating_sub(1 << deflate.client_max_window_bits);
+            self.window.drain(..excess);
+        }
+        Ok(message)
+    }
+}
+
+/// Compresses a data frame, unless that doesn't make it shorter. Matches never reach into
+/// earlier messages, which keeps to `server_no_context_takeover` whether it was agreed or not.
+fn compress(frame: &mut Frame, deflate: DeflateConfig) {
+    let mut payload = deflate_sync(&frame.payload, 1 << deflate.server_max_window_bits);
+    payload.truncate(payload.len() - 4);
+    if payload.len() < frame.payload.len() {
+        frame.payload = payload;
+        frame.compressed = true;
+    }
+}
+
+async fn write_loop<W>(
+    mut writer: WriteHalf<W>,
+    mut queue: mpsc::Receiver<Frame>,
+    shared: Arc<Shared>,
+    config: WebSocketConfig,
+    deflate: Option<DeflateConfig>,
+) where
+    W: AsyncWrite,
+{
+    let period = config.ping_interval.unwrap_or(Duration::from_secs(3600));
+    let mut ticker = interval_at(Instant::now() + period, period);
+    let mut pong_deadline: Option<Instant> = None;
+
+    loop {
+        tokio::select! {
+            frame = queue.recv() => {
+                let Some(mut frame) = frame else { break };
+                // forced while this was queued, the close goes out instead
+                let forced = shared.forced.lock().unwrap().take();
+                if let Some(forced) = forced {
+                    let _ = write_frame(&mut writer, &Message::Close(Some(forced)).into_frame()).await;
+                    break;
+                }
+                if let Some(deflate) = deflate.filter(|_| !frame.opcode.is_control()) {
+                    compress(&mut frame, deflate);
+                }
+                let is_close = frame.opcode == Opcode::Close;
+                if write_frame(&mut writer, &frame).await.is_err() || is_close {
+                    break;
+                }
+            }
+            _ = ticker.tick(), if config.ping_interval.is_some() => {
+                if write_frame(&mut writer, &Frame::new(Opcode::Ping, Vec::new())).await.is_err() {
+                    break;
+                }
+                pong_deadline.get_or_insert(Instant::now() + config.pong_timeout);
+            }
+            _ = shared.force.notified() => {
+                let forced = shared.forced.lock().unwrap().take();
+                let _ = write_frame(&mut writer, &Message::Close(forced).into_frame()).await;
+                break;
+            }
+            _ = shared.pong.notified() => pong_deadline = None,
+            _ = sleep_until(pong_deadline.unwrap_or_else(Instant::now)), if pong_deadline.is_some() => {
+                let close = Message::Close(Some(CloseFrame::new(CloseCode::GOING_AWAY, "pong timeout")));
+                let _ = write_frame(&mut writer, &close.into_frame()).await;
+                shared.timed_out.store(true, Ordering::Release);
+                shared.disconnected.notify_one();
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::WebSocketError;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xA => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    fn as_u8(&self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA,
+        }
+    }
+
+    pub(crate) fn is_control(&self) -> bool {
+        matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Frame {
+    pub fin: bool,
+    /// RSV1, set on the first frame of a permessage-deflate compressed message
+    pub compressed: bool,
+    pub opcode: Opcode,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub(crate) fn new(opcode: Opcode, payload: Vec<u8>) -> Self {
+        Self { fin: true, compressed: false, opcode, payload }
+    }
+}
+
+/// Reads one client frame, which must be masked (RFC 6455 section 5.1).
+///
+/// RSV1 is allowed only with `compression` negotiated, the other reserved bits never are.
+pub(crate) async fn read_frame<R>(
+    reader: &mut R,
+    max_payload: usize,
+    compression: bool,
+) -> Result<Frame, WebSocketError>
+where
+    R: AsyncRead + ?Sized + Unpin,
+{
+    let first = reader.read_u8().await?;
+    let second = reader.read_u8().await?;
+
+    let fin = first & 0x80 != 0;
+    let compressed = first & 0x40 != 0;
+    if first & 0x30 != 0 || (compressed && !compression) {
+        return Err(WebSocketError::Protocol("reserved bits set"));
+    }
+    let opcode = Opcode::from_u8(first & 0x0F).ok_or(WebSocketError::Protocol("unknown opcode"))?;
+    if second & 0x80 == 0 {
+        return Err(WebSocketError::Protocol("unmasked client frame"));
+    }
+
+    let length = match second & 0x7F {
+        126 => reader.read_u16().await? as u64,
+        127 => reader.read_u64().await?,
+        n => n as u64,
+    };
+    if opcode.is_control() && (length > 125 || !fin || compressed) {
+        return Err(WebSocketError::Protocol("invalid control frame"));
+    }
+    if length > max_payload as u64 {
+        return Err(WebSocketError::MessageTooLarge);
+    }
+
+    let mut mask = [0u8; 4];
+    reader.read_exact(&mut mask).await?;
+    let mut payload = vec![0; length as usize];
+    reader.read_exact(&mut payload).await?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+
+    Ok(Frame { fin, compressed, opcode, payload })
+}
+
+/// Writes one unmasked server frame
+pub(crate) async fn write_frame<W>(writer: &mut W, frame: &Frame) -> io::Result<()>
+where
+    W: AsyncWrite + ?Sized + Unpin,
+{
+    let mut head = Vec::<u8>::with_capacity(10);
+    let rsv1 = if frame.compressed { 0x40 } else { 0 };
+    head.push(if frame.fin { 0x80 } else { 0 } | rsv1 | frame.opcode.as_u8());
+
+    let length = frame.payload.len();
+    if length < 126 {
+        head.push(length as u8);
+    } else if length <= u16::MAX as usize {
+        head.push(126);
+        head.extend_from_slice(&(length as u16).to_be_bytes());
+    } else {
+        head.push(127);
+        head.extend_from_slice(&(length as u64).to_be_bytes());
+    }
+
+    writer.write_all(&head).await?;
+    writer.write_all(&frame.payload).await?;
+    writer.flush().await
+}
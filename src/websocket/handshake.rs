@@ -0,0 +1,259 @@
+use std::cmp::min;
+use std::fmt::{Display, Formatter};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use sha1_smol::Sha1;
+
+use super::WebSocketConfig;
+use crate::protocol::{
+    Headers, HttpVersion, Method, RawRequest, RawResponse, StatusCode, StatusLine,
+};
+
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum HandshakeError {
+    NotUpgrade,
+    MissingKey,
+    UnsupportedVersion(String),
+}
+
+impl Display for HandshakeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HandshakeError::NotUpgrade => write!(f, "not a websocket upgrade request"),
+            HandshakeError::MissingKey => write!(f, "missing Sec-WebSocket-Key"),
+            HandshakeError::UnsupportedVersion(v) => {
+                write!(f, "unsupported websocket version: {v}")
+            }
+        }
+    }
+}
+
+/// Computes `Sec-WebSocket-Accept` from the client's `Sec-WebSocket-Key`
+pub fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(WEBSOCKET_GUID.as_bytes());
+    STANDARD.encode(sha1.digest().bytes())
+}
+
+/// permessage-deflate parameters (RFC 7692)
+///
+/// Used both as the server's limits and as the parameters agreed with a client.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct DeflateConfig {
+    pub server_max_window_bits: u8,
+    pub client_max_window_bits: u8,
+    pub server_no_context_takeover: bool,
+    pub client_no_context_takeover: bool,
+}
+
+impl Default for DeflateConfig {
+    fn default() -> Self {
+        Self {
+            server_max_window_bits: 15,
+            client_max_window_bits: 15,
+            server_no_context_takeover: false,
+            client_no_context_takeover: false,
+        }
+    }
+}
+
+impl DeflateConfig {
+    fn negotiate(&self, offer: &ExtensionOffer) -> Option<DeflateConfig> {
+        let mut agreed = *self;
+        agreed.client_max_window_bits = 15;
+        let mut client_window_offered = false;
+        let mut seen = Vec::with_capacity(offer.params.len());
+
+        for (name, value) in offer.params.iter() {
+            if seen.contains(&name.as_str()) {
+                return None;
+            }
+            seen.push(name.as_str());
+
+            match (name.as_str(), value) {
+                ("server_no_context_takeover", None) => agreed.server_no_context_takeover = true,
+                ("client_no_context_takeover", None) => agreed.client_no_context_takeover = true,
+                ("server_max_window_bits", Some(bits)) => {
+                    let bits = parse_window_bits(bits)?;
+                    agreed.server_max_window_bits = min(agreed.server_max_window_bits, bits);
+                }
+                ("client_max_window_bits", bits) => {
+                    client_window_offered = true;
+                    if let Some(bits) = bits {
+                        agreed.client_max_window_bits = parse_window_bits(bits)?;
+                    }
+                }
+                _ => return None,
+            }
+        }
+
+        if client_window_offered {
+            agreed.client_max_window_bits =
+                min(agreed.client_max_window_bits, self.client_max_window_bits);
+        } else if self.client_max_window_bits < 15 {
+            // the client can't be told to shrink its window unless it offered to
+            return None;
+        }
+
+        Some(agreed)
+    }
+
+    fn to_header_value(self) -> String {
+        let mut value = String::from("permessage-deflate");
+        if self.server_no_context_takeover {
+            value.push_str("; server_no_context_takeover");
+        }
+        if self.client_no_context_takeover {
+            value.push_str("; client_no_context_takeover");
+        }
+        if self.server_max_window_bits < 15 {
+            value.push_str(&format!("; server_max_window_bits={}", self.server_max_window_bits));
+        }
+        if self.client_max_window_bits < 15 {
+            value.push_str(&format!("; client_max_window_bits={}", self.client_max_window_bits));
+        }
+        value
+    }
+}
+
+fn parse_window_bits(s: &str) -> Option<u8> {
+    s.parse::<u8>().ok().filter(|bits| (8..=15).contains(bits))
+}
+
+/// one element of `Sec-WebSocket-Extensions`
+/// e.g.
+/// permessage-deflate; client_max_window_bits
+#[derive(Debug)]
+struct ExtensionOffer {
+    name: String,
+    params: Vec<(String, Option<String>)>,
+}
+
+impl ExtensionOffer {
+    fn parse_list(s: &str) -> impl Iterator<Item = ExtensionOffer> + '_ {
+        s.split(',').filter(|e| !e.trim().is_empty()).map(|element| {
+            let mut parts = element.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default().to_ascii_lowercase();
+            let params = parts
+                .filter(|p| !p.is_empty())
+                .map(|p| match p.split_once('=') {
+                    Some((k, v)) => {
+                        (k.trim().to_ascii_lowercase(), Some(v.trim().trim_matches('"').to_owned()))
+                    }
+                    None => (p.to_ascii_lowercase(), None),
+                })
+                .collect();
+            ExtensionOffer { name, params }
+        })
+    }
+}
+
+/// What was agreed with the client during the handshake
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Negotiated {
+    pub protocol: Option<String>,
+    pub deflate: Option<DeflateConfig>,
+}
+
+/// A validated websocket opening handshake
+#[derive(Debug)]
+pub struct Handshake {
+    key: String,
+    protocols: Vec<String>,
+    extensions: Vec<ExtensionOffer>,
+}
+
+impl Handshake {
+    pub fn from_request(request: &RawRequest) -> Result<Self, HandshakeError> {
+        let headers = &request.headers;
+        let has_token = |field: &str, token: &str| {
+            headers
+                .get_all(field)
+                .flat_map(|v| v.split(','))
+                .any(|v| v.trim().eq_ignore_ascii_case(token))
+        };
+
+        if request.request_line.method != Method::GET
+            || request.request_line.version != HttpVersion::Http1_1
+            || !has_token("Upgrade", "websocket")
+            || !has_token("Connection", "upgrade")
+        {
+            return Err(HandshakeError::NotUpgrade);
+        }
+
+        match headers.get("Sec-WebSocket-Version") {
+            Some("13") => {}
+            Some(v) => return Err(HandshakeError::UnsupportedVersion(v.to_owned())),
+            None => return Err(HandshakeError::UnsupportedVersion(String::new())),
+        }
+
+        let key = headers
+            .get("Sec-WebSocket-Key")
+            .map(|k| k.trim().to_owned())
+            .filter(|k| !k.is_empty())
+            .ok_or(HandshakeError::MissingKey)?;
+
+        let protocols = headers
+            .get_all("Sec-WebSocket-Protocol")
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(str::to_owned)
+            .collect();
+
+        let extensions = headers
+            .get_all("Sec-WebSocket-Extensions")
+            .flat_map(ExtensionOffer::parse_list)
+            .collect();
+
+        Ok(Self { key, protocols, extensions })
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// subprotocols offered by the client, in its order of preference
+    pub fn protocols(&self) -> &[String] {
+        &self.protocols
+    }
+
+    /// Builds the `101 Switching Protocols` response.
+    ///
+    /// `select` picks one of the offered subprotocols; anything it returns which the client did
+    /// not offer is ignored.
+    pub fn accept<F>(&self, config: &WebSocketConfig, select: F) -> (RawResponse, Negotiated)
+    where
+        F: FnOnce(&[String]) -> Option<&str>,
+    {
+        let protocol = select(&self.protocols)
+            .filter(|selected| self.protocols.iter().any(|p| p == selected))
+            .map(str::to_owned);
+
+        let deflate = config.deflate.and_then(|config| {
+            self.extensions
+                .iter()
+                .filter(|e| e.name == "permessage-deflate")
+                .find_map(|offer| config.negotiate(offer))
+        });
+
+        let mut headers = Headers::empty();
+        headers.set("Upgrade", "websocket".to_owned());
+        headers.set("Connection", "Upgrade".to_owned());
+        headers.set("Sec-WebSocket-Accept", accept_key(&self.key));
+        if let Some(ref protocol) = protocol {
+            headers.set("Sec-WebSocket-Protocol", protocol.clone());
+        }
+        if let Some(deflate) = deflate {
+            headers.set("Sec-WebSocket-Extensions", deflate.to_header_value());
+        }
+
+        let status_line = StatusLine::new(HttpVersion::Http1_1, StatusCode::SWITCHING_PROTOCOLS);
+        let response = RawResponse::new(status_line, headers, None);
+        (response, Negotiated { protocol, deflate })
+    }
+}
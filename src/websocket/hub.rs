@@ -0,0 +1,105 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use super::{CloseCode, CloseFrame, Message, Sender, WebSocketError};
+
+#[derive(Debug, Clone, Copy, Hash, Ord, PartialOrd, Eq, PartialEq)]
+pub struct ClientId(u64);
+
+#[derive(Debug, Default)]
+struct Rooms {
+    next_id: u64,
+    clients: HashMap<ClientId, Sender>,
+    topics: HashMap<String, HashSet<ClientId>>,
+}
+
+impl Rooms {
+    fn evict(&mut self, id: ClientId) {
+        self.clients.remove(&id);
+        self.topics.retain(|_, members| {
+            members.remove(&id);
+            !members.is_empty()
+        });
+    }
+}
+
+/// Fan-out of messages to websocket clients grouped by topic.
+///
+/// Broadcasting never waits: a client whose send queue is full is considered too slow, it is
+/// evicted from the hub and its connection closed with 1008 (policy violation). One whose
+/// connection is already gone is just evicted.
+#[derive(Debug, Clone, Default)]
+pub struct Hub {
+    rooms: Arc<Mutex<Rooms>>,
+}
+
+impl Hub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&self, sender: Sender) -> ClientId {
+        let mut rooms = self.rooms.lock().unwrap();
+        let id = ClientId(rooms.next_id);
+        rooms.next_id += 1;
+        rooms.clients.insert(id, sender);
+        id
+    }
+
+    pub fn unregister(&self, id: ClientId) {
+        self.rooms.lock().unwrap().evict(id);
+    }
+
+    pub fn join(&self, topic: &str, id: ClientId) {
+        let mut rooms = self.rooms.lock().unwrap();
+        if rooms.clients.contains_key(&id) {
+            rooms.topics.entry(topic.to_owned()).or_default().insert(id);
+        }
+    }
+
+    pub fn leave(&self, topic: &str, id: ClientId) {
+        let mut rooms = self.rooms.lock().unwrap();
+        if let Some(members) = rooms.topics.get_mut(topic) {
+            members.remove(&id);
+            if members.is_empty() {
+                rooms.topics.remove(topic);
+            }
+        }
+    }
+
+    pub fn is_registered(&self, id: ClientId) -> bool {
+        self.rooms.lock().unwrap().clients.contains_key(&id)
+    }
+
+    pub fn subscribers(&self, topic: &str) -> usize {
+        self.rooms.lock().unwrap().topics.get(topic).map_or(0, HashSet::len)
+    }
+
+    /// Queues `message` for every client in `topic`, returns how many clients accepted it
+    pub fn broadcast(&self, topic: &str, message: Message) -> usize {
+        let mut rooms = self.rooms.lock().unwrap();
+        let Some(members) = rooms.topics.get(topic) else {
+            return 0;
+        };
+
+        let mut delivered = 0;
+        let mut evicted = Vec::new();
+        for id in members.iter() {
+            let sender = &rooms.clients[id];
+            match sender.try_send(message.clone()) {
+                Ok(()) => delivered += 1,
+                Err(WebSocketError::QueueFull) => {
+                    sender.force_close(CloseFrame::new(CloseCode::POLICY_VIOLATION, "too slow"));
+                    evicted.push(*id);
+                }
+                // already disconnected
+                Err(_) => evicted.push(*id),
+            }
+        }
+
+        for id in evicted {
+            rooms.evict(id);
+        }
+        delivered
+    }
+}
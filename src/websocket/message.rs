@@ -0,0 +1,102 @@
+use std::ops::Deref;
+
+use super::frame::{Frame, Opcode};
+use super::WebSocketError;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close(Option<CloseFrame>),
+}
+
+impl Message {
+    pub(crate) fn into_frame(self) -> Frame {
+        match self {
+            Message::Text(text) => Frame::new(Opcode::Text, text.into_bytes()),
+            Message::Binary(data) => Frame::new(Opcode::Binary, data),
+            Message::Ping(data) => Frame::new(Opcode::Ping, data),
+            Message::Pong(data) => Frame::new(Opcode::Pong, data),
+            Message::Close(None) => Frame::new(Opcode::Close, Vec::new()),
+            Message::Close(Some(CloseFrame { code, reason })) => {
+                let mut payload = code.0.to_be_bytes().to_vec();
+                payload.extend_from_slice(reason.as_bytes());
+                Frame::new(Opcode::Close, payload)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CloseFrame {
+    pub code: CloseCode,
+    pub reason: String,
+}
+
+impl CloseFrame {
+    pub fn new<S: ToString>(code: CloseCode, reason: S) -> Self {
+        Self { code, reason: reason.to_string() }
+    }
+
+    pub(crate) fn parse(payload: &[u8]) -> Result<Option<Self>, WebSocketError> {
+        match payload {
+            [] => Ok(None),
+            [_] => Err(WebSocketError::Protocol("invalid close frame")),
+            [high, low, reason @ ..] => {
+                let code = CloseCode(u16::from_be_bytes([*high, *low]));
+                if !code.is_sendable() {
+                    return Err(WebSocketError::Protocol("invalid close code"));
+                }
+                let reason =
+                    std::str::from_utf8(reason).map_err(|_| WebSocketError::InvalidUtf8)?;
+                Ok(Some(CloseFrame { code, reason: reason.to_owned() }))
+            }
+        }
+    }
+}
+
+/// websocket close status code (RFC 6455 section 7.4)
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
+pub struct CloseCode(u16);
+
+impl Deref for CloseCode {
+    type Target = u16;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl From<u16> for CloseCode {
+    fn from(value: u16) -> Self {
+        Self(value)
+    }
+}
+
+impl CloseCode {
+    /// 1000 Normal Closure
+    pub const NORMAL: CloseCode = CloseCode(1000);
+    /// 1001 Going Away
+    pub const GOING_AWAY: CloseCode = CloseCode(1001);
+    /// 1002 Protocol Error
+    pub const PROTOCOL_ERROR: CloseCode = CloseCode(1002);
+    /// 1003 Unsupported Data
+    pub const UNSUPPORTED_DATA: CloseCode = CloseCode(1003);
+    /// 1007 Invalid Frame Payload Data
+    pub const INVALID_PAYLOAD: CloseCode = CloseCode(1007);
+    /// 1008 Policy Violation
+    pub const POLICY_VIOLATION: CloseCode = CloseCode(1008);
+    /// 1009 Message Too Big
+    pub const MESSAGE_TOO_BIG: CloseCode = CloseCode(1009);
+    /// 1010 Mandatory Extension
+    pub const MANDATORY_EXTENSION: CloseCode = CloseCode(1010);
+    /// 1011 Internal Error
+    pub const INTERNAL_ERROR: CloseCode = CloseCode(1011);
+
+    /// 1005, 1006 and 1015 are reserved for local use and never appear in a close frame
+    fn is_sendable(&self) -> bool {
+        matches!(self.0, 1000..=1003 | 1007..=1011 | 3000..=4999)
+    }
+}
@@ -0,0 +1,85 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::time::Duration;
+
+pub use self::connection::{Sender, WebSocket};
+pub use self::handshake::{accept_key, DeflateConfig, Handshake, HandshakeError, Negotiated};
+pub use self::hub::{ClientId, Hub};
+pub use self::message::{CloseCode, CloseFrame, Message};
+
+mod connection;
+mod frame;
+mod handshake;
+mod hub;
+mod message;
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Clone)]
+pub struct WebSocketConfig {
+    /// offer `permessage-deflate` to clients which ask for it, `None` disables compression.
+    /// What was agreed goes to `WebSocket::with_deflate`.
+    pub deflate: Option<DeflateConfig>,
+    /// how often to ping the client, `None` disables keepalive pings
+    pub ping_interval: Option<Duration>,
+    /// how long to wait for a pong before dropping the client
+    pub pong_timeout: Duration,
+    /// outgoing messages buffered before `Sender::send` starts waiting
+    pub send_queue: usize,
+    pub max_message_size: usize,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        Self {
+            deflate: None,
+            ping_interval: Some(Duration::from_secs(30)),
+            pong_timeout: Duration::from_secs(10),
+            send_queue: 32,
+            max_message_size: 16 << 20,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum WebSocketError {
+    Io(io::ErrorKind),
+    Protocol(&'static str),
+    InvalidUtf8,
+    MessageTooLarge,
+    PongTimeout,
+    QueueFull,
+    Closed,
+}
+
+impl WebSocketError {
+    /// status code to close the connection with when this error is detected on our side
+    fn close_code(&self) -> Option<CloseCode> {
+        match self {
+            WebSocketError::Protocol(_) => Some(CloseCode::PROTOCOL_ERROR),
+            WebSocketError::InvalidUtf8 => Some(CloseCode::INVALID_PAYLOAD),
+            WebSocketError::MessageTooLarge => Some(CloseCode::MESSAGE_TOO_BIG),
+            _ => None,
+        }
+    }
+}
+
+impl Display for WebSocketError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WebSocketError::Io(err) => write!(f, "io failure: {err}"),
+            WebSocketError::Protocol(reason) => write!(f, "protocol error: {reason}"),
+            WebSocketError::InvalidUtf8 => write!(f, "invalid utf-8 in text message"),
+            WebSocketError::MessageTooLarge => write!(f, "message too large"),
+            WebSocketError::PongTimeout => write!(f, "no pong received in time"),
+            WebSocketError::QueueFull => write!(f, "send queue is full"),
+            WebSocketError::Closed => write!(f, "connection closed"),
+        }
+    }
+}
+
+impl From<io::Error> for WebSocketError {
+    fn from(value: io::Error) -> Self {
+        WebSocketError::Io(value.kind())
+    }
+}
@@ -0,0 +1,224 @@
+use super::*;
+use crate::protocol::read_http_request;
+
+async fn upgrade_request(extra: &str) -> crate::protocol::RawRequest {
+    let source = format!(
+        "GET /chat HTTP/1.1\r\nHost: example.com\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n{extra}\r\n"
+    );
+    read_http_request(&mut source.as_bytes()).await.unwrap()
+}
+
+#[test]
+pub fn test_accept_key() {
+    assert_eq!("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=", accept_key("dGhlIHNhbXBsZSBub25jZQ=="));
+}
+
+#[tokio::test]
+pub async fn test_handshake_selects_offered_protocol() {
+    let request = upgrade_request("Sec-WebSocket-Protocol: chat, superchat\r\n").await;
+    let handshake = Handshake::from_request(&request).unwrap();
+    assert_eq!(&["chat".to_owned(), "superchat".to_owned()], handshake.protocols());
+
+    let (response, negotiated) = handshake.accept(&WebSocketConfig::default(), |offered| {
+        offered.iter().find(|p| *p == "superchat").map(String::as_str)
+    });
+    assert_eq!(Some("superchat".to_owned()), negotiated.protocol);
+    assert_eq!(None, negotiated.deflate);
+
+    let message = String::from_utf8(response.into_vec()).unwrap();
+    assert!(message.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+    assert!(message.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+    assert!(message.contains("Sec-WebSocket-Protocol: superchat\r\n"));
+
+    let (_, negotiated) = handshake.accept(&WebSocketConfig::default(), |_| Some("unknown"));
+    assert_eq!(None, negotiated.protocol);
+}
+
+#[tokio::test]
+pub async fn test_handshake_negotiates_permessage_deflate() {
+    let request = upgrade_request(
+        "Sec-WebSocket-Extensions: permessage-deflate; server_max_window_bits=20, permessage-deflate; client_max_window_bits\r\n",
+    )
+    .await;
+    let handshake = Handshake::from_request(&request).unwrap();
+    let deflate = DeflateConfig {
+        server_max_window_bits: 12,
+        client_max_window_bits: 10,
+        ..Default::default()
+    };
+    let config = WebSocketConfig { deflate: Some(deflate), ..Default::default() };
+
+    let (response, negotiated) = handshake.accept(&config, |_| None);
+
+    let agreed = negotiated.deflate.unwrap();
+    assert_eq!(12, agreed.server_max_window_bits);
+    assert_eq!(10, agreed.client_max_window_bits);
+    let message = String::from_utf8(response.into_vec()).unwrap();
+    assert!(message.contains(
+        "Sec-WebSocket-Extensions: permessage-deflate; server_max_window_bits=12; client_max_window_bits=10\r\n"
+    ));
+}
+
+#[tokio::test]
+pub async fn test_handshake_rejects_plain_request() {
+    let mut source: &[u8] = b"GET /chat HTTP/1.1\r\nHost: example.com\r\n\r\n";
+    let request = read_http_request(&mut source).await.unwrap();
+
+    assert_eq!(HandshakeError::NotUpgrade, Handshake::from_request(&request).unwrap_err());
+}
+
+fn client_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mask = [1u8, 2, 3, 4];
+    let mut frame = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    frame
+}
+
+async fn read_server_frame<R: tokio::io::AsyncRead + Unpin>(reader: &mut R) -> (u8, Vec<u8>) {
+    use tokio::io::AsyncReadExt;
+
+    let first = reader.read_u8().await.unwrap();
+    let length = reader.read_u8().await.unwrap();
+    let mut payload = vec![0; length as usize];
+    reader.read_exact(&mut payload).await.unwrap();
+    (first & 0x0F, payload)
+}
+
+#[tokio::test]
+pub async fn test_websocket_answers_ping_and_receives_text() {
+    use tokio::io::AsyncWriteExt;
+
+    let (server, mut client) = tokio::io::duplex(1024);
+    let config = WebSocketConfig { ping_interval: None, ..Default::default() };
+    let mut socket = WebSocket::new(server, &config);
+
+    client.write_all(&client_frame(0x9, b"hi")).await.unwrap();
+    client.write_all(&client_frame(0x1, b"hello")).await.unwrap();
+
+    assert_eq!(Message::Text("hello".to_owned()), socket.recv().await.unwrap());
+    assert_eq!((0xA, b"hi".to_vec()), read_server_frame(&mut client).await);
+}
+
+#[tokio::test]
+pub async fn test_websocket_echoes_close() {
+    use tokio::io::AsyncWriteExt;
+
+    let (server, mut client) = tokio::io::duplex(1024);
+    let config = WebSocketConfig { ping_interval: None, ..Default::default() };
+    let mut socket = WebSocket::new(server, &config);
+
+    client.write_all(&client_frame(0x8, &[0x03, 0xE9, b'b', b'y', b'e'])).await.unwrap();
+
+    let expected = Message::Close(Some(CloseFrame::new(CloseCode::GOING_AWAY, "bye")));
+    assert_eq!(expected, socket.recv().await.unwrap());
+    assert_eq!((0x8, vec![0x03, 0xE9]), read_server_frame(&mut client).await);
+    assert_eq!(WebSocketError::Closed, socket.recv().await.unwrap_err());
+}
+
+#[tokio::test]
+pub async fn test_websocket_permessage_deflate() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (server, mut client) = tokio::io::duplex(1024);
+    let config = WebSocketConfig { ping_interval: None, ..Default::default() };
+    let mut socket = WebSocket::with_deflate(server, &config, Some(DeflateConfig::default()));
+
+    // "Hello", then "Hello" again referring back to the first (RFC 7692 section 7.2.3.2)
+    for payload in
+        [&[0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00][..], &[0xf2, 0x00, 0x11, 0x00, 0x00]]
+    {
+        let mut frame = client_frame(0x1, payload);
+        frame[0] |= 0x40;
+        client.write_all(&frame).await.unwrap();
+        assert_eq!(Message::Text("Hello".to_owned()), socket.recv().await.unwrap());
+    }
+
+    let text = "toot ".repeat(100);
+    socket.send(Message::Text(text.clone())).await.unwrap();
+    socket.send(Message::Text("hi".to_owned())).await.unwrap();
+    let first = client.read_u8().await.unwrap();
+    assert_eq!(0x80 | 0x40 | 0x1, first);
+    let mut payload = vec![0; client.read_u8().await.unwrap() as usize];
+    client.read_exact(&mut payload).await.unwrap();
+    payload.extend_from_slice(&[0x00, 0x00, 0xFF, 0xFF, 0x03, 0x00]);
+    let (decoded, _) = crate::protocol::inflate_after(&[], &payload, text.len()).unwrap();
+    assert_eq!(text.as_bytes(), &decoded[..]);
+    // not worth compressing
+    assert_eq!((0x1, b"hi".to_vec()), read_server_frame(&mut client).await);
+
+    // without deflate agreed, RSV1 is a protocol error
+    let (server, mut client) = tokio::io::duplex(1024);
+    let mut socket = WebSocket::new(server, &config);
+    let mut frame = client_frame(0x1, &[0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00]);
+    frame[0] |= 0x40;
+    client.write_all(&frame).await.unwrap();
+    assert_eq!(WebSocketError::Protocol("reserved bits set"), socket.recv().await.unwrap_err());
+}
+
+#[tokio::test]
+pub async fn test_websocket_pong_timeout() {
+    use std::time::Duration;
+
+    let (server, mut client) = tokio::io::duplex(1024);
+    let config = WebSocketConfig {
+        ping_interval: Some(Duration::from_millis(20)),
+        pong_timeout: Duration::from_millis(20),
+        ..Default::default()
+    };
+    let mut socket = WebSocket::new(server, &config);
+
+    assert_eq!(WebSocketError::PongTimeout, socket.recv().await.unwrap_err());
+    assert_eq!(0x9, read_server_frame(&mut client).await.0);
+    loop {
+        match read_server_frame(&mut client).await {
+            (0x9, _) => continue,
+            (opcode, payload) => {
+                assert_eq!(0x8, opcode);
+                assert_eq!(&[0x03, 0xE9], &payload[..2]);
+                break;
+            }
+        }
+    }
+}
+
+#[tokio::test]
+pub async fn test_hub_broadcast_evicts_slow_clients() {
+    let config = WebSocketConfig { ping_interval: None, send_queue: 1, ..Default::default() };
+    let (fast, mut fast_client) = tokio::io::duplex(1024);
+    let (slow, mut slow_client) = tokio::io::duplex(8);
+    let fast = WebSocket::new(fast, &config);
+    let slow = WebSocket::new(slow, &config);
+
+    let hub = Hub::new();
+    let fast_id = hub.register(fast.sender());
+    let slow_id = hub.register(slow.sender());
+    hub.join("news", fast_id);
+    hub.join("news", slow_id);
+    assert_eq!(2, hub.subscribers("news"));
+
+    let message = Message::Text("a message longer than the slow pipe".to_owned());
+    for _ in 0..4 {
+        hub.broadcast("news", message.clone());
+        let (opcode, _) = read_server_frame(&mut fast_client).await;
+        assert_eq!(0x1, opcode);
+    }
+
+    assert!(hub.is_registered(fast_id));
+    assert!(!hub.is_registered(slow_id));
+    assert_eq!(1, hub.subscribers("news"));
+    assert_eq!(0, hub.broadcast("other", message));
+
+    // whatever was already on its way, then the close jumping the rest of the queue
+    let mut frames = 0;
+    let payload = loop {
+        match read_server_frame(&mut slow_client).await {
+            (0x1, _) => frames += 1,
+            (0x8, payload) => break payload,
+            (opcode, _) => panic!("unexpected opcode {opcode:#x}"),
+        }
+    };
+    assert!(frames < 4);
+    assert_eq!(&1008u16.to_be_bytes(), &payload[..2]);
+    assert_eq!(b"too slow", &payload[2..]);
+}